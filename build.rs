@@ -0,0 +1,25 @@
+use std::{env, process::Command};
+
+/// Embeds the git commit the binary is built from, reported by `--version`.
+/// Builds outside of a git checkout can set `MC_PROXY_GIT_COMMIT` instead.
+fn main() {
+    println!("cargo:rerun-if-env-changed=MC_PROXY_GIT_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+
+    let commit = env::var("MC_PROXY_GIT_COMMIT").ok().or_else(|| {
+        let output = Command::new("git")
+            .args(["rev-parse", "--short=12", "HEAD"])
+            .output()
+            .ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    });
+
+    println!(
+        "cargo:rustc-env=MC_PROXY_GIT_COMMIT={}",
+        commit.as_deref().unwrap_or("unknown")
+    );
+}
@@ -0,0 +1,119 @@
+//! Request authentication for proxies configured with a command secret.
+//!
+//! The `hmac` field of a `CommandRequestMessage` carries the hex encoded
+//! HMAC-SHA256 of the request id (hyphenated, lowercase) immediately followed
+//! by the json of the `command` field exactly as it was sent.
+
+use crate::id::RequestId;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{fmt::Display, str::FromStr};
+
+type HmacSha256 = Hmac<Sha256>;
+
+fn mac(secret: &[u8], id: &RequestId, command: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("hmac accepts keys of any size");
+    mac.update(id.to_string().as_bytes());
+    mac.update(command.as_bytes());
+    mac
+}
+
+/// Computes the `hmac` field of a request.
+pub fn sign(secret: &[u8], id: &RequestId, command: &str) -> String {
+    hex::encode(mac(secret, id, command).finalize().into_bytes())
+}
+
+/// Checks the `hmac` field of a request in constant time.
+pub fn verify(secret: &[u8], id: &RequestId, command: &str, signature: &str) -> bool {
+    match hex::decode(signature) {
+        Ok(signature) => mac(secret, id, command).verify_slice(&signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// What a backend is allowed to do through the command channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    /// Only commands that don't change any state.
+    ReadOnly,
+    /// Every command.
+    Full,
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Permission::ReadOnly => f.write_str("read_only"),
+            Permission::Full => f.write_str("full"),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid permission `{0}`, expected `read_only` or `full`")]
+pub struct ParsePermissionError(String);
+
+impl FromStr for Permission {
+    type Err = ParsePermissionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read_only" => Ok(Permission::ReadOnly),
+            "full" => Ok(Permission::Full),
+            _ => Err(ParsePermissionError(s.to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sign, verify, Permission};
+    use crate::id::RequestId;
+
+    const COMMAND: &str = r#"{"type":"GET_IP_BANS"}"#;
+
+    #[test]
+    fn test_sign_verify() {
+        let id = RequestId::from_u128(1);
+        let signature = sign(b"secret", &id, COMMAND);
+
+        assert!(verify(b"secret", &id, COMMAND, &signature));
+        assert!(!verify(b"other secret", &id, COMMAND, &signature));
+        assert!(!verify(
+            b"secret",
+            &RequestId::from_u128(2),
+            COMMAND,
+            &signature
+        ));
+        assert!(!verify(
+            b"secret",
+            &id,
+            r#"{"type":"GET_PLAYER_BANS"}"#,
+            &signature
+        ));
+        assert!(!verify(b"secret", &id, COMMAND, "not hex"));
+    }
+
+    #[test]
+    fn test_sign_known_value() {
+        let id: RequestId = "67e55044-10b1-426f-9247-bb680e5fe0c8".parse().unwrap();
+
+        assert_eq!(
+            sign(b"key", &id, "{}"),
+            "0d37abfc19b73b0ecd7d457a1172c3cba458db43f5a7562271bae766ac334b91",
+        );
+    }
+
+    #[test]
+    fn test_permission_parse() {
+        assert_eq!(
+            "read_only".parse::<Permission>().unwrap(),
+            Permission::ReadOnly
+        );
+        assert_eq!("full".parse::<Permission>().unwrap(), Permission::Full);
+        assert!("admin".parse::<Permission>().is_err());
+        assert!(Permission::ReadOnly < Permission::Full);
+    }
+}
@@ -0,0 +1,207 @@
+//! Plugin messages sent to the backend can't carry more than [`MAX_MESSAGE_SIZE`]
+//! bytes, so command responses larger than that are split in fragments.
+//!
+//! Every fragment is sent as its own plugin message with the following shape:
+//!
+//! ```json
+//! { "id": "<request id>", "seq": 0, "total": 3, "data": "<part of the response json>" }
+//! ```
+//!
+//! Concatenating the `data` of all the fragments of a request id, ordered by `seq`,
+//! yields the json of the original `CommandResponseMessage`. Fragments can be told
+//! apart from regular responses by the presence of the `total` field. Responses
+//! that fit in a single plugin message are never fragmented.
+
+use crate::id::RequestId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The maximum size of a serverbound plugin message payload.
+pub const MAX_MESSAGE_SIZE: usize = 32767;
+
+/// Room left for the envelope fields around the fragment data.
+const ENVELOPE_OVERHEAD: usize = 256;
+
+/// The maximum amount of response bytes carried by a single fragment.
+///
+/// Halved because, in the worst case, every character of the response json
+/// needs to be escaped when embedded in the `data` string.
+pub const FRAGMENT_SIZE: usize = (MAX_MESSAGE_SIZE - ENVELOPE_OVERHEAD) / 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MessageFragment {
+    pub id: RequestId,
+    pub seq: u32,
+    pub total: u32,
+    pub data: String,
+}
+
+/// Splits `message` in fragments of at most [`FRAGMENT_SIZE`] bytes.
+pub fn split(id: RequestId, message: &str) -> Vec<MessageFragment> {
+    let mut parts = Vec::new();
+    let mut rest = message;
+
+    while !rest.is_empty() {
+        let mut end = rest.len().min(FRAGMENT_SIZE);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let (part, remaining) = rest.split_at(end);
+        parts.push(part);
+        rest = remaining;
+    }
+
+    let total = parts.len() as u32;
+
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(seq, data)| MessageFragment {
+            id,
+            seq: seq as u32,
+            total,
+            data: data.to_owned(),
+        })
+        .collect()
+}
+
+struct PendingMessage {
+    received: u32,
+    parts: Vec<Option<String>>,
+}
+
+/// Reassembles messages split with [`split`], accepting fragments in any order.
+#[derive(Default)]
+pub struct FragmentAssembler {
+    pending: HashMap<RequestId, PendingMessage>,
+}
+
+impl FragmentAssembler {
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Stores the fragment, returning the whole message once all of its
+    /// fragments were received. Malformed or duplicated fragments are ignored.
+    pub fn accept(&mut self, fragment: MessageFragment) -> Option<String> {
+        if fragment.total == 0 || fragment.seq >= fragment.total {
+            return None;
+        }
+
+        let pending = self
+            .pending
+            .entry(fragment.id)
+            .or_insert_with(|| PendingMessage {
+                received: 0,
+                parts: vec![None; fragment.total as usize],
+            });
+
+        let slot = pending.parts.get_mut(fragment.seq as usize)?;
+        if slot.is_some() {
+            return None;
+        }
+
+        *slot = Some(fragment.data);
+        pending.received += 1;
+
+        if pending.received as usize == pending.parts.len() {
+            let pending = self.pending.remove(&fragment.id)?;
+            pending.parts.into_iter().collect()
+        } else {
+            None
+        }
+    }
+
+    /// Drops the fragments received so far for the given id.
+    pub fn discard(&mut self, id: &RequestId) {
+        self.pending.remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split, FragmentAssembler, FRAGMENT_SIZE, MAX_MESSAGE_SIZE};
+    use crate::server::{CommandResponse, CommandResponseMessage, WhitelistGetAllResponse};
+    use crate::{id::RequestId, CommandResult};
+
+    fn large_response(id: RequestId) -> String {
+        // Quoted names force escaping when embedded in the fragments
+        let whitelist = (0..10_000).map(|i| format!("\"Player_{i}\"")).collect();
+
+        let message = CommandResponseMessage {
+            id,
+            result: CommandResult::Success(CommandResponse::WhitelistGetAll(
+                WhitelistGetAllResponse {
+                    whitelist: Some(whitelist),
+                    entries: Vec::new(),
+                },
+            )),
+            took_micros: None,
+            handled_at: None,
+        };
+
+        serde_json::to_string(&message).unwrap()
+    }
+
+    #[test]
+    fn test_large_response_round_trip() {
+        let id = RequestId::from_u128(1);
+        let json = large_response(id);
+        assert!(json.len() > 100_000);
+
+        let fragments = split(id, &json);
+        assert!(fragments.len() > 1);
+
+        let mut assembler = FragmentAssembler::new();
+        let mut result = None;
+
+        for fragment in fragments.into_iter().rev() {
+            let encoded = serde_json::to_vec(&fragment).unwrap();
+            assert!(encoded.len() <= MAX_MESSAGE_SIZE);
+
+            assert!(result.is_none());
+            result = assembler.accept(serde_json::from_slice(&encoded).unwrap());
+        }
+
+        assert_eq!(result.unwrap(), json);
+
+        let decoded: CommandResponseMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.id, id);
+    }
+
+    #[test]
+    fn test_split_respects_char_boundaries() {
+        let message = "é".repeat(FRAGMENT_SIZE);
+        let fragments = split(RequestId::NIL, &message);
+
+        assert!(fragments.iter().all(|v| v.data.len() <= FRAGMENT_SIZE));
+        assert_eq!(
+            fragments
+                .iter()
+                .map(|v| v.data.as_str())
+                .collect::<String>(),
+            message
+        );
+    }
+
+    #[test]
+    fn test_duplicated_fragments_are_ignored() {
+        let id = RequestId::from_u128(1);
+        let fragments = split(id, &large_response(id));
+
+        let mut assembler = FragmentAssembler::new();
+        assert!(assembler.accept(fragments[0].clone()).is_none());
+        assert!(assembler.accept(fragments[0].clone()).is_none());
+
+        let result = fragments
+            .into_iter()
+            .skip(1)
+            .filter_map(|fragment| assembler.accept(fragment))
+            .next();
+
+        assert!(result.is_some());
+    }
+}
@@ -0,0 +1,167 @@
+//! Request ids are uuids, written in their hyphenated lowercase form. Plugins
+//! may generate them however they like, the proxy only echoes them back.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+/// The id a request is correlated with its response by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RequestId(u128);
+
+impl RequestId {
+    /// The all-zero id, used when the id of a request could not be read.
+    pub const NIL: RequestId = RequestId(0);
+
+    #[inline]
+    pub const fn from_u128(value: u128) -> Self {
+        Self(value)
+    }
+
+    #[inline]
+    pub const fn as_u128(&self) -> u128 {
+        self.0
+    }
+}
+
+impl Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let v = self.0;
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            v >> 96,
+            (v >> 80) & 0xffff,
+            (v >> 64) & 0xffff,
+            (v >> 48) & 0xffff,
+            v & 0xffff_ffff_ffff,
+        )
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid request id `{0}`, expected a uuid")]
+pub struct ParseRequestIdError(String);
+
+impl FromStr for RequestId {
+    type Err = ParseRequestIdError;
+
+    /// Accepts the hyphenated and the simple forms, in any case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseRequestIdError(s.to_owned());
+
+        let digits = match s.len() {
+            36 => {
+                let hyphens = [8, 13, 18, 23];
+                if hyphens.iter().any(|&i| s.as_bytes()[i] != b'-') {
+                    return Err(error());
+                }
+                s.bytes()
+                    .enumerate()
+                    .filter(|(i, _)| !hyphens.contains(i))
+                    .map(|(_, b)| b)
+                    .collect()
+            }
+            32 => s.as_bytes().to_vec(),
+            _ => return Err(error()),
+        };
+
+        digits
+            .into_iter()
+            .try_fold(0u128, |acc, b| {
+                let digit = (b as char).to_digit(16)?;
+                Some(acc << 4 | digit as u128)
+            })
+            .map(RequestId)
+            .ok_or_else(error)
+    }
+}
+
+impl Serialize for RequestId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for RequestId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = RequestId;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a uuid string")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl From<uuid::Uuid> for RequestId {
+    #[inline]
+    fn from(value: uuid::Uuid) -> Self {
+        Self(value.as_u128())
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl From<RequestId> for uuid::Uuid {
+    #[inline]
+    fn from(value: RequestId) -> Self {
+        uuid::Uuid::from_u128(value.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RequestId;
+
+    const ID: &str = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+
+    #[test]
+    fn test_parse_and_display() {
+        let id: RequestId = ID.parse().unwrap();
+        assert_eq!(id.as_u128(), 0x67e5504410b1426f9247bb680e5fe0c8);
+        assert_eq!(id.to_string(), ID);
+
+        let upper: RequestId = ID.to_uppercase().parse().unwrap();
+        assert_eq!(upper, id);
+        let simple: RequestId = ID.replace('-', "").parse().unwrap();
+        assert_eq!(simple, id);
+
+        assert_eq!(
+            RequestId::NIL.to_string(),
+            "00000000-0000-0000-0000-000000000000"
+        );
+    }
+
+    #[test]
+    fn test_invalid_ids() {
+        for id in [
+            "",
+            "67e55044",
+            "67e55044-10b1-426f-9247-bb680e5fe0cg",
+            "67e5504410b1-426f-9247-bb680e5fe0c8-",
+            "+7e55044-10b1-426f-9247-bb680e5fe0c8",
+        ] {
+            assert!(id.parse::<RequestId>().is_err(), "{id}");
+        }
+    }
+
+    #[test]
+    fn test_serde() {
+        let json = format!(r#""{ID}""#);
+        let id: RequestId = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&id).unwrap(), json);
+        assert!(serde_json::from_str::<'_, RequestId>("1").is_err());
+    }
+}
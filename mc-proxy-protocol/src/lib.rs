@@ -0,0 +1,205 @@
+use serde::{Deserialize, Serialize};
+
+pub mod auth;
+pub mod fragment;
+pub mod id;
+pub mod server;
+
+/// The command protocol version implemented by this crate.
+///
+/// Bumped whenever the shape of an existing message changes in a way older
+/// peers can't understand. Purely additive changes (new commands) don't bump it.
+pub const PROTOCOL_VERSION: u32 = 5;
+
+/// The oldest command protocol version the proxy still accepts requests from.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Responses larger than a plugin message are split in fragments since this
+/// protocol version, see [`fragment`].
+pub const FRAGMENT_PROTOCOL_VERSION: u32 = 2;
+
+/// Error responses carry an [`ErrorCode`] since this protocol version.
+pub const ERROR_CODE_PROTOCOL_VERSION: u32 = 3;
+
+/// Stats responses carry the connections by close reason since this protocol
+/// version.
+pub const CLOSE_REASONS_PROTOCOL_VERSION: u32 = 5;
+
+/// Whitelist responses carry the entries with their metadata instead of the
+/// usernames since this protocol version.
+pub const WHITELIST_ENTRIES_PROTOCOL_VERSION: u32 = 5;
+
+/// Backend health responses carry when the circuit breaker of each backend
+/// closes since this protocol version.
+pub const CIRCUIT_BREAKER_PROTOCOL_VERSION: u32 = 5;
+
+/// The name of the plugin message channel commands are exchanged on.
+pub const CHANNEL: &str = "basileia:proxy";
+
+/// Negotiates the protocol version to be used with a peer that speaks `version`.
+///
+/// Returns `None` if the peer is too old to be supported.
+pub fn negotiate_version(version: u32) -> Option<u32> {
+    if version < MIN_PROTOCOL_VERSION {
+        None
+    } else {
+        Some(version.min(PROTOCOL_VERSION))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(
+    tag = "type",
+    content = "data",
+    rename_all = "SCREAMING_SNAKE_CASE",
+    deny_unknown_fields
+)]
+pub enum CommandResult<T> {
+    Success(T),
+    Error(ErrorMessage),
+}
+
+impl<T, E> From<Result<T, E>> for CommandResult<T>
+where
+    E: Into<ErrorMessage>,
+{
+    #[inline]
+    fn from(value: Result<T, E>) -> Self {
+        match value {
+            Ok(v) => Self::Success(v),
+            Err(err) => Self::Error(err.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ErrorMessage {
+    /// Human readable description of the error
+    pub error: String,
+    /// Sent since protocol version 3, except when unknown
+    #[serde(default, skip_serializing_if = "ErrorCode::is_unknown")]
+    pub code: ErrorCode,
+}
+
+impl ErrorMessage {
+    #[inline]
+    pub fn new(code: ErrorCode, error: impl ToString) -> Self {
+        Self {
+            error: error.to_string(),
+            code,
+        }
+    }
+
+    /// Leaves the code out for peers speaking `version`, if they predate it.
+    pub fn downgrade(&mut self, version: u32) {
+        if version < ERROR_CODE_PROTOCOL_VERSION {
+            self.code = ErrorCode::Unknown;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// The request `hmac` is missing or doesn't match any accepted secret.
+    Unauthorized,
+    /// The backend is not allowed to run the command.
+    PermissionDenied,
+    /// The request is not a valid command message.
+    DecodeFailed,
+    /// The response could not be serialized.
+    EncodeFailed,
+    /// The request was sent with a protocol version the proxy doesn't support.
+    UnsupportedVersion,
+    /// A duration or expiration in the request is invalid.
+    InvalidDuration,
+    /// The proxy database can't be reached at the moment, the command may be retried.
+    DatabaseUnavailable,
+    /// The proxy database failed to run the command.
+    DatabaseError,
+    /// The record the command refers to doesn't exist.
+    NotFound,
+    /// The command conflicts with a stored record, such as a duplicate entry.
+    Conflict,
+    /// Data stored by the proxy could not be read.
+    InvalidData,
+    /// The username could not be resolved to a Mojang account, either because
+    /// it doesn't exist or because the Mojang API failed or is rate limited.
+    UsernameResolutionFailed,
+    /// A chat component in the request is not valid.
+    InvalidMessage,
+    /// A network in the request is not a valid IP or CIDR.
+    InvalidNetwork,
+    /// A batch contains another batch or too many commands.
+    InvalidBatch,
+    /// The backend sent more commands than it's allowed to, the command may be
+    /// retried later.
+    RateLimited,
+    /// A log level in the request is not valid.
+    InvalidLogLevel,
+    /// A whitelist pattern in the request is not valid.
+    InvalidPattern,
+    /// The backend in the request is not configured in the proxy.
+    UnknownBackend,
+    /// A code this version of the crate doesn't know about.
+    #[default]
+    #[serde(other)]
+    Unknown,
+}
+
+impl ErrorCode {
+    #[inline]
+    pub fn is_unknown(&self) -> bool {
+        *self == ErrorCode::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        negotiate_version, ErrorCode, ErrorMessage, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+    };
+
+    #[test]
+    fn test_error_code_serialization() {
+        let message = ErrorMessage::new(ErrorCode::DatabaseUnavailable, "Pool timed out");
+        let json = serde_json::to_string(&message).unwrap();
+        assert_eq!(
+            json,
+            r#"{"error":"Pool timed out","code":"DATABASE_UNAVAILABLE"}"#
+        );
+    }
+
+    #[test]
+    fn test_error_code_downgrade() {
+        let mut message = ErrorMessage::new(ErrorCode::DatabaseUnavailable, "Pool timed out");
+        message.downgrade(2);
+        let json = serde_json::to_string(&message).unwrap();
+        assert_eq!(json, r#"{"error":"Pool timed out"}"#);
+    }
+
+    #[test]
+    fn test_unknown_error_codes_decode() {
+        let message: ErrorMessage =
+            serde_json::from_str(r#"{"error":"Error","code":"SOME_FUTURE_CODE"}"#).unwrap();
+        assert_eq!(message.code, ErrorCode::Unknown);
+
+        let message: ErrorMessage = serde_json::from_str(r#"{"error":"Error"}"#).unwrap();
+        assert_eq!(message.code, ErrorCode::Unknown);
+    }
+
+    #[test]
+    fn test_negotiate_version() {
+        assert_eq!(negotiate_version(PROTOCOL_VERSION), Some(PROTOCOL_VERSION));
+        assert_eq!(
+            negotiate_version(PROTOCOL_VERSION + 1),
+            Some(PROTOCOL_VERSION)
+        );
+        assert_eq!(
+            negotiate_version(MIN_PROTOCOL_VERSION),
+            Some(MIN_PROTOCOL_VERSION)
+        );
+        assert_eq!(negotiate_version(MIN_PROTOCOL_VERSION - 1), None);
+    }
+}
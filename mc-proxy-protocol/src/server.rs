@@ -0,0 +1,802 @@
+use crate::{
+    auth::Permission, id::RequestId, CommandResult, CIRCUIT_BREAKER_PROTOCOL_VERSION,
+    CLOSE_REASONS_PROTOCOL_VERSION, WHITELIST_ENTRIES_PROTOCOL_VERSION,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, net::IpAddr};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandRequestMessage {
+    pub id: RequestId,
+    /// The protocol version the sender speaks, assumed to be
+    /// [`MIN_PROTOCOL_VERSION`](crate::MIN_PROTOCOL_VERSION) when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+    /// Required by proxies configured with a command secret, see the
+    /// [`auth`](crate::auth) module
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hmac: Option<String>,
+    pub command: CommandRequest,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(
+    tag = "type",
+    content = "data",
+    rename_all = "SCREAMING_SNAKE_CASE",
+    deny_unknown_fields
+)]
+pub enum CommandRequest {
+    Hello(HelloRequest),
+    Ping(PingRequest),
+    GetVersion,
+
+    // User bans
+    BanPlayer(BanPlayerRequest),
+    /// Bans the player and disconnects it with the ban message. A player that
+    /// is logging in is disconnected as soon as it's relayed
+    BanAndKickPlayer(BanPlayerRequest),
+    UnbanPlayer(UsernameMessage),
+    IsPlayerBanned(UsernameMessage),
+    /// The details of the ban of the player, `null` if it's not banned
+    GetPlayerBan(UsernameMessage),
+    GetPlayerBans,
+
+    // IP Bans
+    BanIp(BanIpRequest),
+    UnbanIp(IpMessage),
+    IsIpBanned(IpMessage),
+    /// The details of the ban of the IP, `null` if it's not banned
+    GetIpBan(IpMessage),
+    GetIpBans,
+
+    // User IP bans
+    BanUserIp(BanUserIpRequest),
+    UnbanUserIp(UserIpMessage),
+    GetUserIpBans,
+
+    // Whitelist
+    SetWhitelistEnabled(SetWhitelistEnabled),
+    IsWhitelistEnabled,
+    IsWhitelisted(UsernameMessage),
+    WhitelistAddPlayer(WhitelistAddRequest),
+    WhitelistRemovePlayer(UsernameMessage),
+    WhitelistGetAll,
+    /// Lets a username or uuid through the whitelist until the proxy restarts
+    WhitelistBypassAdd(WhitelistBypassMessage),
+    WhitelistBypassRemove(WhitelistBypassMessage),
+    /// Whitelists the usernames matching a pattern, see [`WhitelistPatternRequest`]
+    WhitelistAddPattern(WhitelistPatternRequest),
+    WhitelistRemovePattern(WhitelistPatternMessage),
+    /// Removes every pattern of the named group
+    WhitelistRemovePatternGroup(WhitelistPatternGroupMessage),
+    WhitelistGetPatterns,
+
+    // Stats
+    GetStats,
+    /// Counters kept in memory since the proxy started or since they were reset
+    GetLiveStats,
+    /// Zeroes the live counters, including the connections by close reason
+    /// of `GET_STATS`. The persisted stats are kept
+    ResetLiveStats,
+
+    // Backends
+    GetBackendHealth,
+
+    // Server list
+    #[cfg(feature = "json")]
+    SetDescription(DescriptionMessage),
+    #[cfg(feature = "json")]
+    GetDescription,
+    /// Goes back to the description configured in the proxy
+    ResetDescription,
+
+    // Sessions
+    ClearSessionLock(UsernameMessage),
+
+    // Players
+    /// Moves an online player to another backend, see [`TransferPlayerRequest`]
+    TransferPlayer(TransferPlayerRequest),
+    /// Drops the packets of an online player, see [`FreezePlayerRequest`]
+    FreezePlayer(FreezePlayerRequest),
+
+    // Logging
+    /// Replaces the log filter of the proxy until it restarts
+    SetLogLevel(LogLevelMessage),
+
+    /// Runs the commands in order, stopping at the first one that fails.
+    /// Batches are not atomic: the commands that ran before a failure stay
+    /// applied, which the results tell apart. Batches can't be nested nor
+    /// hold more than [`MAX_BATCH_SIZE`] commands, and each command counts
+    /// towards the rate limit of the backend.
+    Batch(Vec<CommandRequest>),
+}
+
+/// The most commands a batch can hold.
+pub const MAX_BATCH_SIZE: usize = 64;
+
+impl CommandRequest {
+    /// How many commands the request runs, more than one for batches.
+    pub fn count(&self) -> usize {
+        match self {
+            CommandRequest::Batch(commands) => commands.len(),
+            _ => 1,
+        }
+    }
+
+    /// The permission a backend needs to run this command.
+    pub fn permission(&self) -> Permission {
+        match self {
+            CommandRequest::Hello(_)
+            | CommandRequest::Ping(_)
+            | CommandRequest::GetVersion
+            | CommandRequest::IsPlayerBanned(_)
+            | CommandRequest::GetPlayerBan(_)
+            | CommandRequest::GetPlayerBans
+            | CommandRequest::IsIpBanned(_)
+            | CommandRequest::GetIpBan(_)
+            | CommandRequest::GetIpBans
+            | CommandRequest::GetUserIpBans
+            | CommandRequest::IsWhitelistEnabled
+            | CommandRequest::IsWhitelisted(_)
+            | CommandRequest::WhitelistGetAll
+            | CommandRequest::WhitelistGetPatterns
+            | CommandRequest::GetStats
+            | CommandRequest::GetLiveStats
+            | CommandRequest::GetBackendHealth => Permission::ReadOnly,
+            #[cfg(feature = "json")]
+            CommandRequest::GetDescription => Permission::ReadOnly,
+
+            CommandRequest::BanPlayer(_)
+            | CommandRequest::BanAndKickPlayer(_)
+            | CommandRequest::UnbanPlayer(_)
+            | CommandRequest::BanIp(_)
+            | CommandRequest::UnbanIp(_)
+            | CommandRequest::BanUserIp(_)
+            | CommandRequest::UnbanUserIp(_)
+            | CommandRequest::SetWhitelistEnabled(_)
+            | CommandRequest::WhitelistAddPlayer(_)
+            | CommandRequest::WhitelistRemovePlayer(_)
+            | CommandRequest::ResetDescription
+            | CommandRequest::ClearSessionLock(_)
+            | CommandRequest::WhitelistBypassAdd(_)
+            | CommandRequest::WhitelistBypassRemove(_)
+            | CommandRequest::WhitelistAddPattern(_)
+            | CommandRequest::WhitelistRemovePattern(_)
+            | CommandRequest::WhitelistRemovePatternGroup(_)
+            | CommandRequest::SetLogLevel(_)
+            | CommandRequest::TransferPlayer(_)
+            | CommandRequest::FreezePlayer(_)
+            | CommandRequest::ResetLiveStats => Permission::Full,
+            #[cfg(feature = "json")]
+            CommandRequest::SetDescription(_) => Permission::Full,
+
+            CommandRequest::Batch(commands) => commands
+                .iter()
+                .map(CommandRequest::permission)
+                .max()
+                .unwrap_or(Permission::ReadOnly),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HelloRequest {
+    pub version: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PingRequest {
+    /// Sent back untouched
+    pub payload: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UsernameMessage {
+    pub username: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WhitelistAddRequest {
+    pub username: String,
+    /// The time should be in milliseconds, the entry is permanent when unset
+    #[serde(default)]
+    pub duration: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WhitelistBypassMessage {
+    /// A username or uuid
+    pub entry: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WhitelistPatternRequest {
+    /// Letters, digits and underscores, where `*` matches any characters and
+    /// `?` a single one, like `event_*`. Matched case insensitively
+    pub pattern: String,
+    /// The named group of the pattern, adding a pattern that exists moves it
+    /// to this group
+    #[serde(default)]
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WhitelistPatternMessage {
+    pub pattern: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WhitelistPatternGroupMessage {
+    pub tag: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BanPlayerRequest {
+    pub username: String,
+    /// The time should be in milliseconds
+    pub duration: Option<u64>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BanIpRequest {
+    pub ip: IpAddr,
+    /// The time should be in milliseconds
+    pub duration: Option<u64>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IpMessage {
+    pub ip: IpAddr,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BanUserIpRequest {
+    pub username: String,
+    /// A network in CIDR notation, like `203.0.113.0/24`, or a single IP
+    pub network: String,
+    /// The time should be in milliseconds
+    pub duration: Option<u64>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserIpMessage {
+    pub username: String,
+    /// A network in CIDR notation, like `203.0.113.0/24`, or a single IP
+    pub network: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SetWhitelistEnabled {
+    pub enabled: bool,
+}
+
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DescriptionMessage {
+    /// A chat component, as sent in the `description` of the server list ping,
+    /// or a plain string
+    pub message: serde_json::Value,
+}
+
+/// 1.20.5+ players are sent back to the proxy with a transfer packet, older
+/// ones are disconnected with a message asking them to reconnect. Their next
+/// login within a minute is sent to the backend whatever host they connect
+/// with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransferPlayerRequest {
+    pub username: String,
+    /// The address of the backend, as configured in the proxy
+    pub backend: String,
+}
+
+/// Frozen players stay connected, but the movement and interaction packets
+/// they send in the play state are dropped, until they are unfrozen or
+/// disconnect. Chat, acknowledgements and keep alives still reach the backend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FreezePlayerRequest {
+    pub username: String,
+    pub frozen: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogLevelMessage {
+    /// Filter directives in the `RUST_LOG` syntax, like `info,mc_proxy=debug`
+    pub level: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandResponseMessage {
+    pub id: RequestId,
+    pub result: CommandResult<CommandResponse>,
+    /// Time spent by the proxy handling the command, in microseconds. Sent
+    /// since protocol version 4
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub took_micros: Option<u64>,
+    /// Unix timestamp in milliseconds of when the proxy finished handling the
+    /// command. Sent since protocol version 4
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handled_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(
+    tag = "type",
+    content = "data",
+    rename_all = "SCREAMING_SNAKE_CASE",
+    deny_unknown_fields
+)]
+pub enum CommandResponse {
+    Hello(HelloResponse),
+    Ping(PingResponse),
+    GetVersion(GetVersionResponse),
+
+    // User bans
+    BanPlayer,
+    BanAndKickPlayer(KickedMessage),
+    UnbanPlayer(ChangedMessage),
+    IsPlayerBanned(IsBannedMessage),
+    GetPlayerBan(Option<PlayerBan>),
+    GetPlayerBans(GetPlayerBansResponse),
+
+    // IP Bans
+    BanIp,
+    UnbanIp(ChangedMessage),
+    IsIpBanned(IsBannedMessage),
+    GetIpBan(Option<IpBan>),
+    GetIpBans(GetIpBansResponse),
+
+    // User IP bans
+    BanUserIp,
+    UnbanUserIp(ChangedMessage),
+    GetUserIpBans(GetUserIpBansResponse),
+
+    // Whitelist
+    SetWhitelistEnabled(ChangedMessage),
+    IsWhitelistEnabled(IsWhitelistEnabledResponse),
+    IsWhitelisted(IsWhitelistedResponse),
+    WhitelistAddPlayer(ChangedMessage),
+    WhitelistRemovePlayer(ChangedMessage),
+    WhitelistGetAll(WhitelistGetAllResponse),
+    WhitelistBypassAdd(ChangedMessage),
+    WhitelistBypassRemove(ChangedMessage),
+    WhitelistAddPattern(ChangedMessage),
+    WhitelistRemovePattern(ChangedMessage),
+    /// `changed` is `false` if the group had no patterns
+    WhitelistRemovePatternGroup(ChangedMessage),
+    WhitelistGetPatterns(WhitelistGetPatternsResponse),
+
+    // Stats
+    GetStats(GetStatsResponse),
+    GetLiveStats(GetLiveStatsResponse),
+    ResetLiveStats,
+
+    // Backends
+    GetBackendHealth(GetBackendHealthResponse),
+
+    // Server list
+    SetDescription,
+    #[cfg(feature = "json")]
+    GetDescription(DescriptionMessage),
+    ResetDescription,
+
+    // Sessions
+    ClearSessionLock(ChangedMessage),
+
+    // Players
+    /// `changed` is `false` if the player is not online
+    TransferPlayer(ChangedMessage),
+    /// `changed` is `false` if the player is not online
+    FreezePlayer(ChangedMessage),
+
+    // Logging
+    SetLogLevel,
+
+    /// The result of every command that ran, in the order they were sent
+    Batch(Vec<CommandResult<CommandResponse>>),
+}
+
+impl CommandResponse {
+    /// Leaves out what peers speaking `version` don't understand, since they
+    /// reject the messages with unknown fields.
+    pub fn downgrade(&mut self, version: u32) {
+        match self {
+            CommandResponse::GetStats(response) if version < CLOSE_REASONS_PROTOCOL_VERSION => {
+                response.connections.clear();
+            }
+            CommandResponse::WhitelistGetAll(response)
+                if version < WHITELIST_ENTRIES_PROTOCOL_VERSION =>
+            {
+                let entries = std::mem::take(&mut response.entries);
+                response.whitelist = Some(entries.into_iter().map(|v| v.username).collect());
+            }
+            CommandResponse::GetBackendHealth(response)
+                if version < CIRCUIT_BREAKER_PROTOCOL_VERSION =>
+            {
+                for backend in &mut response.backends {
+                    backend.tripped_until = None;
+                }
+            }
+            CommandResponse::Batch(results) => {
+                for result in results {
+                    result.downgrade(version);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl CommandResult<CommandResponse> {
+    /// See [`CommandResponse::downgrade`].
+    pub fn downgrade(&mut self, version: u32) {
+        match self {
+            CommandResult::Success(response) => response.downgrade(version),
+            CommandResult::Error(error) => error.downgrade(version),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HelloResponse {
+    /// The version both sides should speak from now on
+    pub version: u32,
+    pub min_version: u32,
+    pub max_version: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PingResponse {
+    pub payload: String,
+    /// Unix timestamp in milliseconds of when the proxy received the ping
+    pub received_at: i64,
+    /// Time from when the proxy received the ping until it answered, in
+    /// microseconds
+    pub processing_time: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GetVersionResponse {
+    /// The version of the proxy itself
+    pub proxy_version: String,
+    pub protocol_version: u32,
+    pub min_protocol_version: u32,
+    /// Names of the optional features enabled on the proxy, like `online_mode`.
+    /// Plugins should ignore the names they don't know about
+    pub features: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChangedMessage {
+    pub changed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KickedMessage {
+    /// Whether the player was online and got disconnected
+    pub kicked: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IsBannedMessage {
+    pub banned: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GetPlayerBansResponse {
+    pub bans: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GetIpBansResponse {
+    pub bans: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GetUserIpBansResponse {
+    pub bans: Vec<UserIpBan>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlayerBan {
+    pub username: String,
+    /// Unix timestamp in milliseconds
+    pub created_at: i64,
+    /// Unix timestamp in milliseconds, the ban is permanent when unset
+    pub expiration: Option<i64>,
+    /// Milliseconds until the ban expires
+    pub remaining: Option<u64>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IpBan {
+    pub ip: IpAddr,
+    /// Unix timestamp in milliseconds
+    pub created_at: i64,
+    /// Unix timestamp in milliseconds, the ban is permanent when unset
+    pub expiration: Option<i64>,
+    /// Milliseconds until the ban expires
+    pub remaining: Option<u64>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserIpBan {
+    pub username: String,
+    /// The banned network in CIDR notation
+    pub network: String,
+    /// Unix timestamp in milliseconds
+    pub created_at: i64,
+    /// Unix timestamp in milliseconds
+    pub expiration: Option<i64>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IsWhitelistEnabledResponse {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IsWhitelistedResponse {
+    pub whitelisted: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WhitelistGetAllResponse {
+    /// The usernames, only sent to peers older than protocol version 5
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub whitelist: Option<Vec<String>>,
+    /// Sent since protocol version 5
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entries: Vec<WhitelistEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WhitelistEntry {
+    pub username: String,
+    /// Unix timestamp in milliseconds
+    pub created_at: i64,
+    /// Unix timestamp in milliseconds
+    pub expiration: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WhitelistGetPatternsResponse {
+    /// Sorted by pattern, in lowercase
+    pub patterns: Vec<WhitelistPatternEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WhitelistPatternEntry {
+    pub pattern: String,
+    /// The named group of the pattern, `null` if it has none
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GetStatsResponse {
+    /// Unique players ever seen
+    pub unique_players: u64,
+    pub peak_players: u64,
+    /// Unix timestamp in milliseconds
+    pub peak_at: Option<i64>,
+    /// The most recent days, newest first
+    pub days: Vec<DailyStats>,
+    /// Connections handled since the proxy started, by close reason. Sent
+    /// since protocol version 5
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub connections: HashMap<String, u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GetLiveStatsResponse {
+    pub online_players: u64,
+    /// Connections that were closed, whatever the reason
+    pub connections: u64,
+    /// Server list pings served
+    pub status_pings: u64,
+    /// Bytes sent to both the players and the backends while relaying
+    pub bytes_proxied: u64,
+    pub uptime_secs: u64,
+    /// Unix timestamp in milliseconds of when the counters started, either
+    /// when the proxy started or when they were last reset
+    pub since: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DailyStats {
+    /// Formatted as `YYYY-MM-DD`, in UTC
+    pub day: String,
+    pub logins: u64,
+    pub unique_players: u64,
+    pub peak_players: u64,
+    /// Unix timestamp in milliseconds
+    pub peak_at: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GetBackendHealthResponse {
+    pub backends: Vec<BackendHealth>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackendHealth {
+    pub address: String,
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    /// Unix timestamp in milliseconds
+    pub last_check: Option<i64>,
+    /// Latency of the last successful ping in milliseconds
+    pub latency: Option<u64>,
+    pub last_error: Option<String>,
+    /// Unix timestamp in milliseconds until which new players skip the
+    /// backend, set after too many failed connections. Sent since protocol
+    /// version 5
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tripped_until: Option<i64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CommandRequest, CommandRequestMessage, CommandResponse, GetStatsResponse, WhitelistEntry,
+        WhitelistGetAllResponse,
+    };
+    use crate::{auth::Permission, CommandResult};
+
+    #[test]
+    fn test_request_without_version_decodes() {
+        let json = r#"{
+            "id": "c676f0db-9695-4fcd-a3cc-d75f129fde7f",
+            "command": { "type": "IS_PLAYER_BANNED", "data": { "username": "Username" } }
+        }"#;
+
+        let message: CommandRequestMessage = serde_json::from_str(json).unwrap();
+
+        assert_eq!(message.version, None);
+        assert!(matches!(message.command, CommandRequest::IsPlayerBanned(_)));
+    }
+
+    #[test]
+    fn test_hello_request_decodes() {
+        let json = r#"{
+            "id": "c676f0db-9695-4fcd-a3cc-d75f129fde7f",
+            "version": 1,
+            "command": { "type": "HELLO", "data": { "version": 1 } }
+        }"#;
+
+        let message: CommandRequestMessage = serde_json::from_str(json).unwrap();
+
+        assert_eq!(message.version, Some(1));
+        assert!(matches!(
+            message.command,
+            CommandRequest::Hello(hello) if hello.version == 1
+        ));
+    }
+
+    #[test]
+    fn test_ping_and_get_version_decode() {
+        let ping: CommandRequest =
+            serde_json::from_str(r#"{ "type": "PING", "data": { "payload": "probe" } }"#).unwrap();
+        assert!(matches!(ping, CommandRequest::Ping(ping) if ping.payload == "probe"));
+
+        let version: CommandRequest = serde_json::from_str(r#"{ "type": "GET_VERSION" }"#).unwrap();
+        assert!(matches!(version, CommandRequest::GetVersion));
+    }
+
+    #[test]
+    fn test_batch_permission() {
+        let batch: CommandRequest = serde_json::from_str(
+            r#"{ "type": "BATCH", "data": [
+                { "type": "IS_WHITELIST_ENABLED" },
+                { "type": "GET_PLAYER_BANS" }
+            ] }"#,
+        )
+        .unwrap();
+        assert_eq!(batch.permission(), Permission::ReadOnly);
+
+        let batch: CommandRequest = serde_json::from_str(
+            r#"{ "type": "BATCH", "data": [
+                { "type": "GET_PLAYER_BANS" },
+                { "type": "BAN_PLAYER", "data": { "username": "Notch" } }
+            ] }"#,
+        )
+        .unwrap();
+        assert_eq!(batch.permission(), Permission::Full);
+    }
+
+    #[test]
+    fn test_stats_downgrade() {
+        let stats = CommandResponse::GetStats(GetStatsResponse {
+            unique_players: 1,
+            peak_players: 1,
+            peak_at: None,
+            days: Vec::new(),
+            connections: [("backend_eof".to_owned(), 1)].into(),
+        });
+
+        for (version, sent) in [(4, false), (5, true)] {
+            let mut result =
+                CommandResult::Success(CommandResponse::Batch(vec![CommandResult::Success(
+                    stats.clone(),
+                )]));
+            result.downgrade(version);
+
+            let json = serde_json::to_string(&result).unwrap();
+            assert_eq!(json.contains("connections"), sent, "{version}");
+        }
+    }
+
+    #[test]
+    fn test_whitelist_downgrade() {
+        let whitelist = CommandResponse::WhitelistGetAll(WhitelistGetAllResponse {
+            whitelist: None,
+            entries: vec![WhitelistEntry {
+                username: "Notch".into(),
+                created_at: 0,
+                expiration: None,
+            }],
+        });
+
+        let mut old = CommandResult::Success(whitelist.clone());
+        old.downgrade(4);
+        assert_eq!(
+            serde_json::to_string(&old).unwrap(),
+            r#"{"type":"SUCCESS","data":{"type":"WHITELIST_GET_ALL","data":{"whitelist":["Notch"]}}}"#
+        );
+
+        let mut new = CommandResult::Success(whitelist);
+        new.downgrade(5);
+        let json = serde_json::to_string(&new).unwrap();
+        assert!(!json.contains("whitelist\""));
+        assert!(json.contains("entries"));
+    }
+}
@@ -0,0 +1,4 @@
+-- Add down migration script here
+
+DROP TABLE IF EXISTS daily_stats;
+DROP TABLE IF EXISTS seen_players;
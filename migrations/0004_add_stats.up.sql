@@ -0,0 +1,15 @@
+-- Add up migration script here
+
+CREATE TABLE seen_players (
+    uuid text PRIMARY KEY,
+    username text NOT NULL,
+    first_seen integer NOT NULL
+) STRICT;
+
+CREATE TABLE daily_stats (
+    day text PRIMARY KEY,
+    logins integer NOT NULL,
+    unique_players integer NOT NULL,
+    peak_players integer NOT NULL,
+    peak_at integer
+) STRICT;
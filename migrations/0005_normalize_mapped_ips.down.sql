@@ -0,0 +1,3 @@
+-- Add down migration script here
+
+-- Nothing to undo, plain IPv4 bans are valid in the previous version
@@ -0,0 +1,13 @@
+-- Add up migration script here
+
+-- IPv4-mapped IPv6 bans (::ffff:a.b.c.d) are now stored as plain IPv4, the
+-- mapped row is dropped if the plain address is already banned
+DELETE FROM ip_bans
+WHERE length(ip) = 17
+    AND substr(ip, 1, 13) = X'0600000000000000000000FFFF'
+    AND unhex('04' || hex(substr(ip, 14, 4))) IN (SELECT ip FROM ip_bans);
+
+UPDATE ip_bans
+SET ip = unhex('04' || hex(substr(ip, 14, 4)))
+WHERE length(ip) = 17
+    AND substr(ip, 1, 13) = X'0600000000000000000000FFFF';
@@ -0,0 +1,13 @@
+-- Add down migration script here
+
+CREATE TABLE whitelist_username (
+    username text PRIMARY KEY,
+    created_at integer NOT NULL
+) STRICT;
+
+INSERT OR IGNORE INTO whitelist_username (username, created_at)
+SELECT username, created_at FROM whitelist;
+
+DROP TABLE whitelist;
+
+ALTER TABLE whitelist_username RENAME TO whitelist;
@@ -0,0 +1,19 @@
+-- Add up migration script here
+
+-- Entries pinned to an uuid keep their username only as a hint, so the same
+-- username may show up more than once after players rename
+CREATE TABLE whitelist_uuid (
+    username text NOT NULL,
+    uuid text UNIQUE,
+    created_at integer NOT NULL
+) STRICT;
+
+INSERT INTO whitelist_uuid (username, created_at)
+SELECT username, created_at FROM whitelist;
+
+DROP TABLE whitelist;
+
+ALTER TABLE whitelist_uuid RENAME TO whitelist;
+
+CREATE INDEX whitelist_username ON whitelist (username);
+CREATE UNIQUE INDEX whitelist_unpinned_username ON whitelist (username) WHERE uuid IS NULL;
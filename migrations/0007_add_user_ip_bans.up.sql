@@ -0,0 +1,10 @@
+-- Add up migration script here
+
+CREATE TABLE user_ip_bans (
+    username text NOT NULL,
+    network text NOT NULL,
+    created_at text NOT NULL,
+    expiration text,
+    reason text,
+    PRIMARY KEY (username, network)
+) STRICT;
@@ -0,0 +1,8 @@
+-- Add up migration script here
+
+-- Patterns are stored lowercase, `*` matching any characters and `?` a single one
+CREATE TABLE whitelist_patterns (
+    pattern text PRIMARY KEY,
+    tag text,
+    created_at integer NOT NULL
+) STRICT;
@@ -0,0 +1,5 @@
+-- Add down migration script here
+
+DELETE FROM whitelist WHERE expiration IS NOT NULL;
+
+ALTER TABLE whitelist DROP COLUMN expiration;
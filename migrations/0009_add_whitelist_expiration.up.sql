@@ -0,0 +1,4 @@
+-- Add up migration script here
+
+-- Unix timestamp in milliseconds, like created_at
+ALTER TABLE whitelist ADD COLUMN expiration integer;
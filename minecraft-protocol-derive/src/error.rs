@@ -9,6 +9,10 @@ pub(crate) enum DeriveInputParserError {
     UnnamedDataFields,
     /// Possible errors while parsing attributes.
     AttributeError { attribute_error: AttributeError },
+    /// `present_if` fields must be an `Option`.
+    PresentIfNotOption,
+    /// `present_if` must name a field declared before.
+    PresentIfUnknownField,
 }
 
 /// Possible errors while parsing attributes.
@@ -34,9 +34,21 @@ pub(crate) struct FieldData<'a> {
 
 #[derive(Debug, Eq, PartialEq)]
 pub(crate) enum AttributeData {
-    With { module: String },
-    MaxLength { length: usize },
-    Bitfield { idx: u8, position: BitfieldPosition },
+    /// Uses the `decode` and `encode` functions of a module with the same name
+    /// in `decoder` and `encoder`, like `var_int` or `var_long`.
+    With {
+        module: String,
+    },
+    MaxLength {
+        length: usize,
+    },
+    PresentIf {
+        field: String,
+    },
+    Bitfield {
+        idx: u8,
+        position: BitfieldPosition,
+    },
     Empty,
 }
 
@@ -151,6 +163,18 @@ fn parse_fields(named_fields: &FieldsNamed) -> Result<Vec<FieldData>, DeriveInpu
 
         let attribute = parse_attribute(nested_metas, next_nested_metas_opt, current_bitfield_idx)?;
 
+        if let AttributeData::PresentIf { field } = &attribute {
+            if !is_option(ty) {
+                return Err(DeriveInputParserError::PresentIfNotOption);
+            }
+            if !fields[..idx]
+                .iter()
+                .any(|v| v.ident.as_ref().is_some_and(|v| v == field))
+            {
+                return Err(DeriveInputParserError::PresentIfUnknownField);
+            }
+        }
+
         match attribute {
             AttributeData::Bitfield { .. } => current_bitfield_idx += 1,
             _ => current_bitfield_idx = 0,
@@ -166,6 +190,17 @@ fn parse_fields(named_fields: &FieldsNamed) -> Result<Vec<FieldData>, DeriveInpu
     Ok(fields_data)
 }
 
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(type_path) => type_path
+            .path
+            .segments
+            .last()
+            .is_some_and(|v| v.ident == "Option"),
+        _ => false,
+    }
+}
+
 fn parse_attributes_nested_metas(
     attributes: &Vec<Attribute>,
 ) -> Result<Vec<NestedMeta>, DeriveInputParserError> {
@@ -191,8 +226,11 @@ fn parse_attribute(
     next_nested_metas_opt: Option<Vec<NestedMeta>>,
     current_bitfield_idx: u8,
 ) -> Result<AttributeData, DeriveInputParserError> {
-    let simple_attribute_parsers: Vec<fn(&NestedMeta) -> Result<AttributeData, AttributeError>> =
-        vec![get_module_attribute, get_max_length_attribute];
+    let simple_attribute_parsers: Vec<fn(&NestedMeta) -> Result<AttributeData, AttributeError>> = vec![
+        get_module_attribute,
+        get_max_length_attribute,
+        get_present_if_attribute,
+    ];
 
     for nested_meta in nested_metas.iter() {
         let bitfield_attribute =
@@ -244,6 +282,21 @@ fn get_max_length_attribute(nested_meta: &NestedMeta) -> Result<AttributeData, A
     Ok(AttributeData::Empty)
 }
 
+fn get_present_if_attribute(nested_meta: &NestedMeta) -> Result<AttributeData, AttributeError> {
+    if let NestedMeta::Meta(Meta::NameValue(named_meta)) = nested_meta {
+        if matches!(&named_meta.path, path if path.is_ident("present_if")) {
+            return match &named_meta.lit {
+                Lit::Str(lit_str) => Ok(AttributeData::PresentIf {
+                    field: lit_str.value(),
+                }),
+                _ => Err(AttributeError::AttributeWrongValueType),
+            };
+        }
+    }
+
+    Ok(AttributeData::Empty)
+}
+
 fn get_bitfield_attribute(
     current_bitfield_idx: u8,
     nested_meta: &NestedMeta,
@@ -1,3 +1,4 @@
+use super::is_byte_vec;
 use crate::parse::{AttributeData, BitfieldPosition, DiscriminantType, FieldData, VariantData};
 use proc_macro2::TokenStream as TokenStream2;
 use proc_macro2::{Ident, Span};
@@ -139,7 +140,11 @@ fn render_field(field: &FieldData) -> TokenStream2 {
 
     match &field.attribute {
         AttributeData::With { module } => render_with_field(name, module),
+        AttributeData::MaxLength { length } if is_byte_vec(ty) => {
+            render_max_length_byte_array_field(name, *length)
+        }
         AttributeData::MaxLength { length } => render_max_length_field(name, *length as u16),
+        AttributeData::PresentIf { field } => render_present_if_field(name, field),
         AttributeData::Bitfield { idx, position } => render_bitfield(name, *idx, position),
         AttributeData::Empty => render_simple_field(name, ty),
     }
@@ -165,6 +170,20 @@ fn render_max_length_field(name: &Ident, max_length: u16) -> TokenStream2 {
     }
 }
 
+fn render_max_length_byte_array_field(name: &Ident, max_length: usize) -> TokenStream2 {
+    quote! {
+        let #name = crate::decoder::DecoderReadExt::read_byte_array(reader, #max_length)?;
+    }
+}
+
+fn render_present_if_field(name: &Ident, field: &str) -> TokenStream2 {
+    let field_ident = Ident::new(field, Span::call_site());
+
+    quote! {
+        let #name = crate::decoder::present_if::decode(#field_ident, reader)?;
+    }
+}
+
 fn render_bitfield(name: &Ident, idx: u8, position: &BitfieldPosition) -> TokenStream2 {
     let mask = 1u8 << idx;
 
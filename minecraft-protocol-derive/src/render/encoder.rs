@@ -1,3 +1,4 @@
+use super::is_byte_vec;
 use crate::parse::{AttributeData, BitfieldPosition, DiscriminantType, FieldData, VariantData};
 use proc_macro2::TokenStream as TokenStream2;
 use proc_macro2::{Ident, Span};
@@ -127,9 +128,13 @@ fn render_field(field: &FieldData, with_self: bool) -> TokenStream2 {
 
     match &field.attribute {
         AttributeData::With { module } => render_with_field(name, module, with_self),
+        AttributeData::MaxLength { .. } if is_byte_vec(field.ty) => {
+            render_byte_array_field(name, with_self)
+        }
         AttributeData::MaxLength { length } => {
             render_max_length_field(name, *length as u16, with_self)
         }
+        AttributeData::PresentIf { field } => render_present_if_field(name, field, with_self),
         AttributeData::Bitfield { idx, position } => render_bitfield(name, *idx, position),
         AttributeData::Empty => render_simple_field(name, with_self),
     }
@@ -156,6 +161,23 @@ fn render_max_length_field(name: &Ident, max_length: u16, with_self: bool) -> To
     }
 }
 
+fn render_byte_array_field(name: &Ident, with_self: bool) -> TokenStream2 {
+    let final_name = get_field_final_name(name, with_self);
+
+    quote! {
+        crate::encoder::EncoderWriteExt::write_byte_array(writer, #final_name)?;
+    }
+}
+
+fn render_present_if_field(name: &Ident, field: &str, with_self: bool) -> TokenStream2 {
+    let final_name = get_field_final_name(name, with_self);
+    let present = get_field_final_name(&Ident::new(field, Span::call_site()), with_self);
+
+    quote! {
+        crate::encoder::present_if::encode(*#present, #final_name, writer)?;
+    }
+}
+
 fn render_bitfield(name: &Ident, idx: u8, position: &BitfieldPosition) -> TokenStream2 {
     let mask = 1u8 << idx;
 
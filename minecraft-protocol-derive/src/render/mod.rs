@@ -1,2 +1,28 @@
+use syn::{GenericArgument, PathArguments, Type};
+
 pub(crate) mod decoder;
 pub(crate) mod encoder;
+
+/// Whether the type is `Vec<u8>`, encoded as a byte array instead of a string
+/// when it has a `max_length`.
+pub(crate) fn is_byte_vec(ty: &Type) -> bool {
+    let segment = match ty {
+        Type::Path(type_path) => match type_path.path.segments.last() {
+            Some(v) => v,
+            None => return false,
+        },
+        _ => return false,
+    };
+
+    if segment.ident != "Vec" {
+        return false;
+    }
+
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => matches!(
+            args.args.first(),
+            Some(GenericArgument::Type(Type::Path(inner))) if inner.path.is_ident("u8")
+        ),
+        _ => false,
+    }
+}
@@ -0,0 +1,57 @@
+//! Feeds arbitrary bytes to `MinecraftCodec::accept`/`next_packet`, which must
+//! only ever return errors on invalid input.
+//!
+//! `cargo +nightly fuzz run codec` from the `minecraft-protocol` directory.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use minecraft_protocol::{
+    codec::codec::MinecraftCodec,
+    decoder::Decoder,
+    packet::{
+        configuration::{ConfigClientBoundPaket, ConfigServerBoundPacket},
+        game::{GameClientBoundPacket, GameServerBoundPacket},
+        handshake::HandshakeServerBoundPacket,
+        login::{LoginClientBoundPacket, LoginServerBoundPacket},
+        status::{StatusClientBoundPacket, StatusServerBoundPacket},
+    },
+};
+
+fn drain<T: Decoder>(codec: &mut MinecraftCodec) {
+    // Errors don't always consume the input, so the rest is left for the
+    // next chunk instead of decoding the same bytes forever
+    while let Ok(Some(_)) = codec.next_packet::<T>() {}
+}
+
+fuzz_target!(|data: &[u8]| {
+    // The first bytes select the codec settings, the packets to decode and
+    // how the rest is split between the `accept` calls
+    let [flags, chunk_size, data @ ..] = data else {
+        return;
+    };
+
+    let mut codec = MinecraftCodec::new();
+    if flags & 0b0001 != 0 {
+        codec.enable_compression(64);
+    }
+    if flags & 0b0010 != 0 {
+        codec.enable_encryption(*b"0123456789abcdef");
+    }
+
+    for chunk in data.chunks(usize::from(*chunk_size).max(1)) {
+        codec.accept(chunk);
+
+        match flags >> 4 {
+            0 => drain::<HandshakeServerBoundPacket>(&mut codec),
+            1 => drain::<StatusServerBoundPacket>(&mut codec),
+            2 => drain::<StatusClientBoundPacket>(&mut codec),
+            3 => drain::<LoginServerBoundPacket>(&mut codec),
+            4 => drain::<LoginClientBoundPacket>(&mut codec),
+            5 => drain::<ConfigServerBoundPacket>(&mut codec),
+            6 => drain::<ConfigClientBoundPaket>(&mut codec),
+            7 => drain::<GameServerBoundPacket>(&mut codec),
+            _ => drain::<GameClientBoundPacket>(&mut codec),
+        }
+    }
+});
@@ -0,0 +1,227 @@
+//! High level helpers for talking to a server as a client.
+
+use crate::{
+    codec::{
+        client::{ClientPacket, ClientPacketCodec},
+        server::{ServerPacket, ServerPacketCodec},
+        ProtocolState,
+    },
+    data::server_status::ServerStatus,
+    error::{DecodeError, EncodeError},
+    packet::{
+        handshake::{Handshake, HandshakeServerBoundPacket, NextState},
+        status::{PingRequest, StatusClientBoundPacket, StatusServerBoundPacket},
+    },
+};
+use futures_util::{SinkExt, StreamExt};
+use std::{
+    io,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+const DEFAULT_PORT: u16 = 25565;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("Io error: {0}")]
+    IoError(#[from] io::Error),
+    #[error("Failed to decode packet: {0}")]
+    DecodeError(#[from] DecodeError),
+    #[error("Failed to encode packet: {0}")]
+    EncodeError(#[from] EncodeError),
+    #[error("Invalid server address `{0}`")]
+    InvalidAddress(String),
+    #[error("Server sent an unexpected packet: {0:?}")]
+    UnexpectedPacket(Box<ServerPacket>),
+    #[error("Server closed the connection")]
+    ConnectionClosed,
+    #[error("Server answered the ping with a different payload")]
+    PingMismatch,
+}
+
+#[derive(Debug, Clone)]
+pub struct Ping {
+    pub status: ServerStatus,
+    /// The time it took for the ping request to be answered.
+    pub latency: Duration,
+}
+
+/// Connects to `addr` (`host` or `host:port`) and requests its status, followed
+/// by a ping to measure the latency.
+pub async fn ping(addr: &str, protocol_version: i32) -> Result<Ping, ClientError> {
+    let (host, port) = split_host_port(addr)?;
+    let stream = TcpStream::connect((host, port)).await?;
+
+    ping_stream(stream, host, port, protocol_version).await
+}
+
+/// Same as [`ping`], but over an already connected stream. `host` and `port` are
+/// only sent in the handshake.
+pub async fn ping_stream<S>(
+    stream: S,
+    host: &str,
+    port: u16,
+    protocol_version: i32,
+) -> Result<Ping, ClientError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (read, write) = tokio::io::split(stream);
+
+    let mut sink = FramedWrite::new(write, ClientPacketCodec::new());
+    let mut stream = FramedRead::new(read, ServerPacketCodec::new());
+    stream.decoder_mut().set_state(ProtocolState::Status);
+
+    let handshake = HandshakeServerBoundPacket::Handshake(Handshake {
+        protocol_version,
+        server_addr: host.to_owned(),
+        server_port: port,
+        next_state: NextState::Status,
+    });
+    sink.send(handshake.into()).await?;
+    sink.send(ClientPacket::from(StatusServerBoundPacket::StatusRequest))
+        .await?;
+
+    let status = match stream.next().await.ok_or(ClientError::ConnectionClosed)?? {
+        ServerPacket::Status(StatusClientBoundPacket::StatusResponse(response)) => {
+            response.server_status
+        }
+        packet => return Err(ClientError::UnexpectedPacket(Box::new(packet))),
+    };
+
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+
+    let start = Instant::now();
+    sink.send(StatusServerBoundPacket::PingRequest(PingRequest { time }).into())
+        .await?;
+
+    match stream.next().await.ok_or(ClientError::ConnectionClosed)?? {
+        ServerPacket::Status(StatusClientBoundPacket::PingResponse(response)) => {
+            if response.time != time {
+                return Err(ClientError::PingMismatch);
+            }
+        }
+        packet => return Err(ClientError::UnexpectedPacket(Box::new(packet))),
+    }
+
+    Ok(Ping {
+        status,
+        latency: start.elapsed(),
+    })
+}
+
+fn split_host_port(addr: &str) -> Result<(&str, u16), ClientError> {
+    match addr.rsplit_once(':') {
+        // Bare ipv6 addresses contain colons but no port
+        Some((host, _)) if host.contains(':') && !host.ends_with(']') => Ok((addr, DEFAULT_PORT)),
+        Some((host, port)) => {
+            let port = port
+                .parse()
+                .map_err(|_| ClientError::InvalidAddress(addr.to_owned()))?;
+
+            Ok((host.trim_start_matches('[').trim_end_matches(']'), port))
+        }
+        None => Ok((addr, DEFAULT_PORT)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ping_stream, split_host_port};
+    use crate::{
+        codec::{
+            client::{ClientPacket, ClientPacketCodec},
+            server::{ServerPacket, ServerPacketCodec},
+            ProtocolState,
+        },
+        data::{
+            chat::{Message, Payload},
+            server_status::{OnlinePlayers, ServerStatus, ServerVersion},
+        },
+        packet::{
+            handshake::HandshakeServerBoundPacket,
+            status::{PingResponse, StatusResponse, StatusServerBoundPacket},
+        },
+    };
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(split_host_port("localhost").unwrap(), ("localhost", 25565));
+        assert_eq!(
+            split_host_port("localhost:1234").unwrap(),
+            ("localhost", 1234)
+        );
+        assert_eq!(split_host_port("::1").unwrap(), ("::1", 25565));
+        assert_eq!(split_host_port("[::1]:1234").unwrap(), ("::1", 1234));
+        assert!(split_host_port("localhost:port").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ping() {
+        let (client, server) = tokio::io::duplex(4096);
+
+        let server = tokio::spawn(async move {
+            let (read, write) = tokio::io::split(server);
+            let mut stream = FramedRead::new(read, ClientPacketCodec::new());
+            let mut sink = FramedWrite::new(write, ServerPacketCodec::new());
+
+            match stream.next().await.unwrap().unwrap() {
+                ClientPacket::Handshake(HandshakeServerBoundPacket::Handshake(handshake)) => {
+                    assert_eq!(handshake.server_addr, "localhost");
+                    assert_eq!(handshake.server_port, 25565);
+                    assert_eq!(handshake.protocol_version, 765);
+                }
+                packet => panic!("unexpected packet {packet:?}"),
+            }
+            stream.decoder_mut().set_state(ProtocolState::Status);
+            sink.encoder_mut().set_state(ProtocolState::Status);
+
+            loop {
+                let packet = match stream.next().await {
+                    Some(packet) => packet.unwrap(),
+                    None => break,
+                };
+
+                let response = match packet {
+                    ClientPacket::Status(StatusServerBoundPacket::StatusRequest) => {
+                        StatusResponse::new(ServerStatus {
+                            version: ServerVersion {
+                                name: "1.20.4".into(),
+                                protocol: 765,
+                            },
+                            players: OnlinePlayers {
+                                max: 20,
+                                online: 1,
+                                sample: Vec::new(),
+                            },
+                            description: Message::new(Payload::text("A Minecraft Server")),
+                            enforces_secure_chat: None,
+                            previews_chat: None,
+                        })
+                    }
+                    ClientPacket::Status(StatusServerBoundPacket::PingRequest(ping)) => {
+                        PingResponse::new(ping.time)
+                    }
+                    packet => panic!("unexpected packet {packet:?}"),
+                };
+                sink.send(ServerPacket::from(response)).await.unwrap();
+            }
+        });
+
+        let ping = ping_stream(client, "localhost", 25565, 765).await.unwrap();
+        assert_eq!(ping.status.version.protocol, 765);
+        assert_eq!(ping.status.players.online, 1);
+
+        server.await.unwrap();
+    }
+}
@@ -1,17 +1,26 @@
-use super::{codec::MinecraftCodec, ProtocolState};
+use super::{
+    codec::MinecraftCodec, decode_registered, ProtocolState, Versioned, STATE_PROTOCOL_VERSION,
+};
 use crate::{
+    decoder::EnumDecoder,
     encoder::EnumEncoder,
     error::DecodeError,
     packet::{
-        configuration::ConfigServerBoundPacket, game::GameServerBoundPacket,
-        handshake::HandshakeServerBoundPacket, login::LoginServerBoundPacket,
+        configuration::ConfigServerBoundPacket,
+        game::GameServerBoundPacket,
+        handshake::HandshakeServerBoundPacket,
+        login::LoginServerBoundPacket,
+        registry::{Direction, PacketRegistry, RegisteredPacket},
         status::StatusServerBoundPacket,
     },
 };
+use std::sync::Arc;
 
 pub struct ClientPacketCodec {
     state: ProtocolState,
+    protocol_version: i32,
     codec: MinecraftCodec,
+    registry: Option<Arc<PacketRegistry>>,
 }
 
 impl Default for ClientPacketCodec {
@@ -26,7 +35,9 @@ impl ClientPacketCodec {
     pub fn new() -> Self {
         Self {
             state: ProtocolState::Handshake,
+            protocol_version: STATE_PROTOCOL_VERSION,
             codec: MinecraftCodec::new(),
+            registry: None,
         }
     }
 
@@ -40,45 +51,102 @@ impl ClientPacketCodec {
         self.state = state
     }
 
+    /// The configuration and play packets are decoded and encoded with the
+    /// type ids of this version, 1.20.4 until it is set.
+    #[inline]
+    pub fn set_protocol_version(&mut self, protocol_version: i32) {
+        self.protocol_version = protocol_version
+    }
+
+    /// The packets with a decoder in `registry` are decoded by it, as
+    /// [`ClientPacket::Registered`], instead of by the packet enums. Their type
+    /// ids are the ones sent in the session.
     #[inline]
-    pub fn set_compression(&mut self, threshold: usize) {
-        self.codec.enable_compression(threshold)
+    pub fn set_registry(&mut self, registry: Arc<PacketRegistry>) {
+        self.registry = Some(registry)
+    }
+
+    fn next_packet<T>(&mut self) -> Result<Option<ClientPacket>, DecodeError>
+    where
+        T: EnumDecoder,
+        ClientPacket: From<T::Output>,
+    {
+        let (state, protocol_version) = (self.state, self.protocol_version);
+        let registry = self.registry.as_deref();
+        self.codec.next_packet_with(|reader| {
+            decode_registered::<T, _>(
+                registry,
+                protocol_version,
+                state,
+                Direction::ServerBound,
+                reader,
+            )
+        })
+    }
+
+    /// Negative thresholds disable compression, as in `SetCompression` packets.
+    #[inline]
+    pub fn set_compression(&mut self, threshold: i32) {
+        self.codec.set_compression_threshold(threshold)
+    }
+
+    #[cfg(feature = "tokio")]
+    #[inline]
+    pub(crate) fn inner_mut(&mut self) -> &mut MinecraftCodec {
+        &mut self.codec
     }
 
     pub fn decode(&mut self, data: &[u8]) -> Result<Option<ClientPacket>, DecodeError> {
         self.codec.accept(data);
         match self.state {
-            ProtocolState::Handshake => self
-                .codec
-                .next_packet::<HandshakeServerBoundPacket>()
-                .map(|opt| opt.map(ClientPacket::from)),
-            ProtocolState::Status => self
-                .codec
-                .next_packet::<StatusServerBoundPacket>()
-                .map(|opt| opt.map(ClientPacket::from)),
-            ProtocolState::Login => self
-                .codec
-                .next_packet::<LoginServerBoundPacket>()
-                .map(|opt| opt.map(ClientPacket::from)),
-            ProtocolState::Configuration => self
-                .codec
-                .next_packet::<ConfigServerBoundPacket>()
-                .map(|opt| opt.map(ClientPacket::from)),
-            ProtocolState::Play => self
-                .codec
-                .next_packet::<GameServerBoundPacket>()
-                .map(|opt| opt.map(ClientPacket::from)),
+            ProtocolState::Handshake => self.next_packet::<HandshakeServerBoundPacket>(),
+            ProtocolState::Status => self.next_packet::<StatusServerBoundPacket>(),
+            ProtocolState::Login => self.next_packet::<LoginServerBoundPacket>(),
+            ProtocolState::Configuration => self.next_packet::<ConfigServerBoundPacket>(),
+            ProtocolState::Play => self.next_packet::<GameServerBoundPacket>(),
+        }
+    }
+
+    fn encode_versioned<T: EnumEncoder>(
+        &mut self,
+        state: ProtocolState,
+        packet: &T,
+        buffer: &mut Vec<u8>,
+    ) {
+        let packet = Versioned::new(self.protocol_version, state, Direction::ServerBound, packet);
+        self.codec.encode(&packet, buffer).unwrap()
+    }
+
+    pub fn encode(&mut self, packet: &ClientPacket, buffer: &mut Vec<u8>) {
+        match packet {
+            ClientPacket::Handshake(packet) => self.codec.encode(packet, buffer).unwrap(),
+            ClientPacket::Status(packet) => self.codec.encode(packet, buffer).unwrap(),
+            ClientPacket::Login(packet) => self.codec.encode(packet, buffer).unwrap(),
+            // Other and registered packets are sent with the type id they were
+            // received with
+            ClientPacket::Registered(packet) => self.codec.encode(packet, buffer).unwrap(),
+            ClientPacket::Game(packet @ GameServerBoundPacket::Other { .. }) => {
+                self.codec.encode(packet, buffer).unwrap()
+            }
+            ClientPacket::Configuration(packet) => {
+                self.encode_versioned(ProtocolState::Configuration, packet, buffer)
+            }
+            ClientPacket::Game(packet) => {
+                self.encode_versioned(ProtocolState::Play, packet, buffer)
+            }
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub enum ClientPacket {
     Handshake(HandshakeServerBoundPacket),
     Status(StatusServerBoundPacket),
     Login(LoginServerBoundPacket),
     Configuration(ConfigServerBoundPacket),
     Game(GameServerBoundPacket),
+    /// Decoded by the registry of the codec, see [`ClientPacketCodec::set_registry`]
+    Registered(RegisteredPacket),
 }
 
 impl ClientPacket {
@@ -90,6 +158,7 @@ impl ClientPacket {
             ClientPacket::Login(packet) => packet.get_type_id(),
             ClientPacket::Configuration(packet) => packet.get_type_id(),
             ClientPacket::Game(packet) => packet.get_type_id(),
+            ClientPacket::Registered(packet) => packet.type_id(),
         }
     }
 }
@@ -128,3 +197,10 @@ impl From<GameServerBoundPacket> for ClientPacket {
         ClientPacket::Game(packet)
     }
 }
+
+impl From<RegisteredPacket> for ClientPacket {
+    #[inline]
+    fn from(packet: RegisteredPacket) -> Self {
+        ClientPacket::Registered(packet)
+    }
+}
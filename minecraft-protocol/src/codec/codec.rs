@@ -3,9 +3,12 @@ use crate::{
     encoder::{var_int as var_int_encoder, Encoder},
     error::{DecodeError, EncodeError},
 };
-use aes::{cipher::KeyIvInit, Aes128};
+use aes::{
+    cipher::{generic_array::GenericArray, BlockDecryptMut, BlockEncryptMut, KeyIvInit},
+    Aes128,
+};
 use bytes::BytesMut;
-use cfb8::{cipher::AsyncStreamCipher, Decryptor, Encryptor};
+use cfb8::{Decryptor, Encryptor};
 use flate2::{
     read::{ZlibDecoder, ZlibEncoder},
     Compression,
@@ -14,9 +17,40 @@ use std::io::{Cursor, Read};
 
 pub type CryptKey = [u8; 16];
 
+/// The AES/CFB8 stream of each direction, kept across packets as the
+/// protocol encrypts the connection as a single stream.
+#[derive(Clone)]
+struct Cipher {
+    encryptor: Encryptor<Aes128>,
+    decryptor: Decryptor<Aes128>,
+}
+
+impl Cipher {
+    fn new(key: &CryptKey) -> Self {
+        Self {
+            encryptor: Encryptor::new(key.into(), key.into()),
+            decryptor: Decryptor::new(key.into(), key.into()),
+        }
+    }
+
+    fn encrypt(&mut self, data: &mut [u8]) {
+        for byte in data {
+            self.encryptor
+                .encrypt_block_mut(GenericArray::from_mut_slice(std::slice::from_mut(byte)));
+        }
+    }
+
+    fn decrypt(&mut self, data: &mut [u8]) {
+        for byte in data {
+            self.decryptor
+                .decrypt_block_mut(GenericArray::from_mut_slice(std::slice::from_mut(byte)));
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct MinecraftCodec {
-    crypt_key: Option<CryptKey>,
+    cipher: Option<Cipher>,
 
     compression: Option<usize>,
 
@@ -34,7 +68,14 @@ impl MinecraftCodec {
 
     #[inline]
     pub fn enable_encryption(&mut self, key: CryptKey) {
-        self.crypt_key = Some(key);
+        self.cipher = Some(Cipher::new(&key));
+    }
+
+    /// Bytes already accepted stay decrypted, only the following ones are
+    /// read as plain text.
+    #[inline]
+    pub fn disable_encryption(&mut self) {
+        self.cipher = None;
     }
 
     #[inline]
@@ -42,10 +83,25 @@ impl MinecraftCodec {
         self.compression = Some(threshold);
     }
 
+    #[inline]
+    pub fn disable_compression(&mut self) {
+        self.compression = None;
+    }
+
+    /// Applies the threshold of a `SetCompression` packet, negative thresholds
+    /// disable compression.
+    #[inline]
+    pub fn set_compression_threshold(&mut self, threshold: i32) {
+        match usize::try_from(threshold) {
+            Ok(threshold) => self.enable_compression(threshold),
+            Err(_) => self.disable_compression(),
+        }
+    }
+
     #[inline]
     pub fn clone_with_settings(&self) -> Self {
         Self {
-            crypt_key: self.crypt_key,
+            cipher: self.cipher.clone(),
             compression: self.compression,
             received_buf: BytesMut::new(),
             staging_buf: Vec::new(),
@@ -59,6 +115,7 @@ impl MinecraftCodec {
         output: &mut Vec<u8>,
     ) -> Result<(), EncodeError> {
         packet.encode(&mut self.staging_buf)?;
+        let start_index = output.len();
 
         if let Some(threshold) = self.compression {
             self.encode_compressed(output, threshold)?;
@@ -66,10 +123,8 @@ impl MinecraftCodec {
             self.encode_uncompressed(output)?;
         }
 
-        if let Some(key) = &self.crypt_key {
-            Encryptor::<Aes128>::new_from_slices(key, key)
-                .expect("key size is invalid")
-                .encrypt(output)
+        if let Some(cipher) = &mut self.cipher {
+            cipher.encrypt(&mut output[start_index..]);
         }
 
         self.staging_buf.clear();
@@ -90,13 +145,15 @@ impl MinecraftCodec {
 
         const MAX_VAR_INT_LENGTH: usize = 5;
         let mut buf = [0u8; MAX_VAR_INT_LENGTH];
-        let data_length_bytes = Cursor::new(&mut buf[..]);
-        var_int_encoder::encode(&(data_length as i32), output)?;
+        let mut data_length_bytes = Cursor::new(&mut buf[..]);
+        var_int_encoder::encode(&(data_length as i32), &mut data_length_bytes)?;
+        let data_length_len = data_length_bytes.position() as usize;
 
-        let packet_length = data_length_bytes.position() as usize + data.len();
+        // [packet length][data length][data], the packet length counting both
+        // the data length and the data
+        let packet_length = data_length_len + data.len();
         var_int_encoder::encode(&(packet_length as i32), output)?;
-        var_int_encoder::encode(&(data_length as i32), output)?;
-
+        output.extend_from_slice(&buf[..data_length_len]);
         output.extend_from_slice(data);
 
         self.compression_target.clear();
@@ -130,50 +187,227 @@ impl MinecraftCodec {
         let start_index = self.received_buf.len();
         self.received_buf.extend(bytes);
 
-        if let Some(key) = &self.crypt_key {
-            Decryptor::<Aes128>::new_from_slices(key, key)
-                .expect("key size is invalid")
-                .decrypt(&mut self.received_buf[start_index..]);
+        if let Some(cipher) = &mut self.cipher {
+            cipher.decrypt(&mut self.received_buf[start_index..]);
         }
     }
 
+    /// Decodes the next buffered packet. Returns `Ok(None)` only when the packet
+    /// was not fully received yet, a packet that can't be decoded is skipped and
+    /// returned as an error, so that the following ones can still be decoded.
     pub fn next_packet<T>(&mut self) -> Result<Option<T::Output>, DecodeError>
     where
         T: Decoder,
+    {
+        self.next_packet_with(|reader| T::decode(reader))
+    }
+
+    /// Same as [`MinecraftCodec::next_packet`], with the packet decoded by
+    /// `decode` instead, like the decoders of a
+    /// [`PacketRegistry`](crate::packet::registry::PacketRegistry).
+    pub fn next_packet_with<T, F>(&mut self, decode: F) -> Result<Option<T>, DecodeError>
+    where
+        F: FnOnce(&mut Cursor<&[u8]>) -> Result<T, DecodeError>,
     {
         let mut cursor = Cursor::new(&self.received_buf[..]);
-        let packet = if let Ok(length) = var_int_decoder::decode(&mut cursor) {
-            let length_field_length = cursor.position() as usize;
+        let length = match var_int_decoder::decode(&mut cursor) {
+            Ok(v) => usize::try_from(v).map_err(|_| DecodeError::InvalidPacketLength)?,
+            // The length itself was not fully received
+            Err(error) if error.is_eof_error() => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let length_field_length = cursor.position() as usize;
 
-            if self.received_buf.len() - length_field_length >= length as usize {
-                cursor = Cursor::new(
-                    &self.received_buf[length_field_length..length_field_length + length as usize],
+        if self.received_buf.len() - length_field_length < length {
+            return Ok(None);
+        }
+
+        let frame = self.received_buf.split_to(length_field_length + length);
+        let mut cursor = Cursor::new(&frame[length_field_length..]);
+
+        self.compression_target.clear();
+        if self.compression.is_some() {
+            let data_length = var_int_decoder::decode(&mut cursor)?;
+            if data_length != 0 {
+                let mut decoder = ZlibDecoder::new(&cursor.get_ref()[cursor.position() as usize..]);
+                decoder.read_to_end(&mut self.compression_target)?;
+
+                return decode(&mut Cursor::new(&self.compression_target[..])).map(Some);
+            }
+        }
+
+        decode(&mut cursor).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CryptKey, MinecraftCodec};
+    use crate::data::identifier::Identifier;
+    use crate::packet::configuration::{ClientBoundPluginMessage, ConfigClientBoundPaket, Ping};
+
+    const KEY: CryptKey = *b"0123456789abcdef";
+
+    fn packets() -> Vec<ConfigClientBoundPaket> {
+        vec![
+            ConfigClientBoundPaket::FinishConfiguration,
+            ConfigClientBoundPaket::ClientBoundPluginMessage(ClientBoundPluginMessage {
+                channel: Identifier::from_static("minecraft:brand"),
+                data: (0..512).map(|i| (i % 7) as u8).collect(),
+            }),
+            ConfigClientBoundPaket::Ping(Ping { id: 42 }),
+        ]
+    }
+
+    /// A codec for each side of the connection, with the same settings.
+    fn codecs(compression: Option<usize>, encryption: bool) -> (MinecraftCodec, MinecraftCodec) {
+        let mut codec = MinecraftCodec::new();
+        if let Some(threshold) = compression {
+            codec.enable_compression(threshold);
+        }
+        if encryption {
+            codec.enable_encryption(KEY);
+        }
+
+        let peer = codec.clone_with_settings();
+        (codec, peer)
+    }
+
+    fn settings() -> Vec<(Option<usize>, bool)> {
+        vec![
+            (None, false),
+            (Some(64), false),
+            (None, true),
+            (Some(64), true),
+        ]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for (compression, encryption) in settings() {
+            let (mut encoder, mut decoder) = codecs(compression, encryption);
+
+            for packet in packets() {
+                let mut encoded = Vec::new();
+                encoder.encode(&packet, &mut encoded).unwrap();
+                decoder.accept(&encoded);
+
+                let decoded = decoder.next_packet::<ConfigClientBoundPaket>().unwrap();
+                assert_eq!(
+                    format!("{decoded:?}"),
+                    format!("{:?}", Some(packet)),
+                    "compression: {compression:?}, encryption: {encryption}"
                 );
+            }
+            assert!(decoder
+                .next_packet::<ConfigClientBoundPaket>()
+                .unwrap()
+                .is_none());
+        }
+    }
 
-                if self.compression.is_some() {
-                    let data_length = var_int_decoder::decode(&mut cursor)?;
-                    if data_length != 0 {
-                        let mut decoder =
-                            ZlibDecoder::new(&cursor.get_ref()[cursor.position() as usize..]);
-                        decoder.read_to_end(&mut self.compression_target)?;
-                        cursor = Cursor::new(&self.compression_target);
-                    }
+    #[test]
+    fn test_round_trip_byte_by_byte() {
+        for (compression, encryption) in settings() {
+            let (mut encoder, mut decoder) = codecs(compression, encryption);
+
+            // Encoded in separate calls to check the cipher stream carries over
+            let mut encoded = Vec::new();
+            for packet in packets() {
+                encoder.encode(&packet, &mut encoded).unwrap();
+            }
+
+            let mut decoded = Vec::new();
+            for byte in encoded {
+                decoder.accept(&[byte]);
+                if let Some(packet) = decoder.next_packet::<ConfigClientBoundPaket>().unwrap() {
+                    decoded.push(packet);
                 }
+            }
 
-                let packet = T::decode(&mut cursor)?;
+            assert_eq!(
+                format!("{decoded:?}"),
+                format!("{:?}", packets()),
+                "compression: {compression:?}, encryption: {encryption}"
+            );
+        }
+    }
 
-                let bytes_read = length as usize + length_field_length;
-                self.received_buf = self.received_buf.split_off(bytes_read);
+    #[test]
+    fn test_toggle_compression_mid_stream() {
+        let (mut encoder, mut decoder) = codecs(None, false);
+        let thresholds = [Some(0), None, Some(64), Some(1024), None];
+
+        for threshold in thresholds {
+            // Both sides switch between packets, like after a `SetCompression`
+            for codec in [&mut encoder, &mut decoder] {
+                match threshold {
+                    Some(threshold) => codec.enable_compression(threshold),
+                    None => codec.disable_compression(),
+                }
+            }
+
+            for packet in packets() {
+                let mut encoded = Vec::new();
+                encoder.encode(&packet, &mut encoded).unwrap();
+                decoder.accept(&encoded);
 
-                self.compression_target.clear();
-                Some(packet)
-            } else {
-                None
+                let decoded = decoder.next_packet::<ConfigClientBoundPaket>().unwrap();
+                assert_eq!(
+                    format!("{decoded:?}"),
+                    format!("{:?}", Some(packet)),
+                    "threshold: {threshold:?}"
+                );
             }
-        } else {
-            None
+        }
+    }
+
+    #[test]
+    fn test_disable_encryption() {
+        let (mut encoder, mut decoder) = codecs(Some(64), true);
+
+        for (i, packet) in packets().into_iter().enumerate() {
+            // Both sides switch after the first packet
+            if i == 1 {
+                encoder.disable_encryption();
+                decoder.disable_encryption();
+            }
+
+            let mut encoded = Vec::new();
+            encoder.encode(&packet, &mut encoded).unwrap();
+            decoder.accept(&encoded);
+
+            let decoded = decoder.next_packet::<ConfigClientBoundPaket>().unwrap();
+            assert_eq!(format!("{decoded:?}"), format!("{:?}", Some(packet)));
+        }
+    }
+
+    /// Random input must only ever produce errors, see the fuzz target for a
+    /// more thorough version.
+    #[test]
+    fn test_random_input_does_not_panic() {
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
         };
 
-        Ok(packet)
+        for (compression, encryption) in settings() {
+            for _ in 0..200 {
+                let (mut codec, _) = codecs(compression, encryption);
+                let len = (next() % 256) as usize;
+                let bytes: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+
+                codec.accept(&bytes);
+                for _ in 0..len {
+                    match codec.next_packet::<ConfigClientBoundPaket>() {
+                        Ok(Some(_)) | Err(_) => {}
+                        Ok(None) => break,
+                    }
+                }
+            }
+        }
     }
 }
@@ -0,0 +1,160 @@
+//! [`tokio_util::codec`] implementations of the packet codecs, allowing them to
+//! be used with [`Framed`](tokio_util::codec::Framed),
+//! [`FramedRead`](tokio_util::codec::FramedRead) and
+//! [`FramedWrite`](tokio_util::codec::FramedWrite).
+//!
+//! The protocol state is not tracked automatically, `set_state` must be called on
+//! the codec (e.g. through `Framed::codec_mut`) whenever a packet that switches
+//! it is handled. Compression is, on the decoding side of the server codec.
+
+use super::{
+    client::{ClientPacket, ClientPacketCodec},
+    codec::MinecraftCodec,
+    server::{ServerPacket, ServerPacketCodec},
+};
+use crate::{
+    encoder::Encoder,
+    error::{DecodeError, EncodeError},
+};
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder as FrameDecoder, Encoder as FrameEncoder};
+
+fn encode_into(
+    codec: &mut MinecraftCodec,
+    packet: &impl Encoder,
+    dst: &mut BytesMut,
+) -> Result<(), EncodeError> {
+    let mut buf = Vec::new();
+    codec.encode(packet, &mut buf)?;
+    dst.extend_from_slice(&buf);
+
+    Ok(())
+}
+
+impl FrameDecoder for ClientPacketCodec {
+    type Item = ClientPacket;
+    type Error = DecodeError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let data = src.split();
+        ClientPacketCodec::decode(self, &data)
+    }
+}
+
+impl FrameEncoder<ClientPacket> for ClientPacketCodec {
+    type Error = EncodeError;
+
+    fn encode(&mut self, packet: ClientPacket, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let codec = self.inner_mut();
+        match &packet {
+            ClientPacket::Handshake(packet) => encode_into(codec, packet, dst),
+            ClientPacket::Status(packet) => encode_into(codec, packet, dst),
+            ClientPacket::Login(packet) => encode_into(codec, packet, dst),
+            ClientPacket::Configuration(packet) => encode_into(codec, packet, dst),
+            ClientPacket::Game(packet) => encode_into(codec, packet, dst),
+            ClientPacket::Registered(packet) => encode_into(codec, packet, dst),
+        }
+    }
+}
+
+impl FrameDecoder for ServerPacketCodec {
+    type Item = ServerPacket;
+    type Error = DecodeError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let data = src.split();
+        ServerPacketCodec::decode(self, &data)
+    }
+}
+
+impl FrameEncoder<ServerPacket> for ServerPacketCodec {
+    type Error = EncodeError;
+
+    fn encode(&mut self, packet: ServerPacket, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let codec = self.inner_mut();
+        match &packet {
+            ServerPacket::Status(packet) => encode_into(codec, packet, dst),
+            ServerPacket::Login(packet) => encode_into(codec, packet, dst),
+            ServerPacket::Configuration(packet) => encode_into(codec, packet, dst),
+            ServerPacket::Play(packet) => encode_into(codec, packet, dst),
+            ServerPacket::Registered(packet) => encode_into(codec, packet, dst),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::{
+        client::{ClientPacket, ClientPacketCodec},
+        server::{ServerPacket, ServerPacketCodec},
+        ProtocolState,
+    };
+    use crate::packet::{
+        handshake::{Handshake, HandshakeServerBoundPacket, NextState},
+        status::{PingRequest, PingResponse, StatusClientBoundPacket, StatusServerBoundPacket},
+    };
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    #[tokio::test]
+    async fn test_framed_client_packets() {
+        let (client, server) = tokio::io::duplex(1024);
+        let mut sink = FramedWrite::new(client, ClientPacketCodec::new());
+        let mut stream = FramedRead::new(server, ClientPacketCodec::new());
+
+        let handshake = HandshakeServerBoundPacket::Handshake(Handshake {
+            protocol_version: 765,
+            server_addr: "localhost".repeat(20),
+            server_port: 25565,
+            next_state: NextState::Status,
+        });
+        sink.send(handshake.into()).await.unwrap();
+        sink.encoder_mut().set_state(ProtocolState::Status);
+
+        let ping = StatusServerBoundPacket::PingRequest(PingRequest { time: 42 });
+        sink.send(ping.clone().into()).await.unwrap();
+        sink.send(ping.into()).await.unwrap();
+
+        match stream.next().await.unwrap().unwrap() {
+            ClientPacket::Handshake(HandshakeServerBoundPacket::Handshake(packet)) => {
+                assert_eq!(packet.protocol_version, 765);
+                assert_eq!(packet.server_addr, "localhost".repeat(20));
+                assert_eq!(packet.server_port, 25565);
+            }
+            packet => panic!("unexpected packet {packet:?}"),
+        }
+        stream.decoder_mut().set_state(ProtocolState::Status);
+
+        for _ in 0..2 {
+            match stream.next().await.unwrap().unwrap() {
+                ClientPacket::Status(StatusServerBoundPacket::PingRequest(packet)) => {
+                    assert_eq!(packet.time, 42);
+                }
+                packet => panic!("unexpected packet {packet:?}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_framed_server_packets() {
+        let (client, server) = tokio::io::duplex(1024);
+
+        let mut sink = FramedWrite::new(server, ServerPacketCodec::new());
+        sink.encoder_mut().set_state(ProtocolState::Status);
+
+        let mut stream = FramedRead::new(client, ServerPacketCodec::new());
+        stream.decoder_mut().set_state(ProtocolState::Status);
+
+        let packet = PingResponse::new(1577735845610);
+        sink.send(ServerPacket::from(packet)).await.unwrap();
+        drop(sink);
+
+        match stream.next().await.unwrap().unwrap() {
+            ServerPacket::Status(StatusClientBoundPacket::PingResponse(packet)) => {
+                assert_eq!(packet.time, 1577735845610);
+            }
+            packet => panic!("unexpected packet {packet:?}"),
+        }
+        assert!(stream.next().await.is_none());
+    }
+}
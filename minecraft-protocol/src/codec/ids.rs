@@ -0,0 +1,182 @@
+//! The packet enums are modeled after the type ids of 1.20.4, 1.20.5 moved
+//! most of the configuration and play ones. The codecs translate the ids of
+//! the packets the enums model from and to the ones of the session.
+
+use super::{ProtocolState, TRANSFER_PROTOCOL_VERSION};
+use crate::packet::registry::Direction;
+
+/// The 1.20.4 and 1.20.5 ids of the modeled packets, only those that kept
+/// their format.
+const MOVED: &[(ProtocolState, Direction, u8, u8)] = &[
+    // Plugin message, disconnect, finish configuration, keep alive, ping
+    (
+        ProtocolState::Configuration,
+        Direction::ClientBound,
+        0x00,
+        0x01,
+    ),
+    (
+        ProtocolState::Configuration,
+        Direction::ClientBound,
+        0x01,
+        0x02,
+    ),
+    (
+        ProtocolState::Configuration,
+        Direction::ClientBound,
+        0x02,
+        0x03,
+    ),
+    (
+        ProtocolState::Configuration,
+        Direction::ClientBound,
+        0x03,
+        0x04,
+    ),
+    (
+        ProtocolState::Configuration,
+        Direction::ClientBound,
+        0x04,
+        0x05,
+    ),
+    // Plugin message, acknowledge finish configuration, keep alive, pong
+    (
+        ProtocolState::Configuration,
+        Direction::ServerBound,
+        0x01,
+        0x02,
+    ),
+    (
+        ProtocolState::Configuration,
+        Direction::ServerBound,
+        0x02,
+        0x03,
+    ),
+    (
+        ProtocolState::Configuration,
+        Direction::ServerBound,
+        0x03,
+        0x04,
+    ),
+    (
+        ProtocolState::Configuration,
+        Direction::ServerBound,
+        0x04,
+        0x05,
+    ),
+    // Plugin message, disconnect
+    (ProtocolState::Play, Direction::ClientBound, 0x18, 0x19),
+    (ProtocolState::Play, Direction::ClientBound, 0x1B, 0x1D),
+    // Plugin message, keep alive
+    (ProtocolState::Play, Direction::ServerBound, 0x10, 0x12),
+    (ProtocolState::Play, Direction::ServerBound, 0x15, 0x18),
+];
+
+/// The transfer packets, which only exist since 1.20.5. The play one takes
+/// the id of Update Recipes in 1.20.4.
+const ADDED: &[(ProtocolState, Direction, u8)] = &[
+    (ProtocolState::Configuration, Direction::ClientBound, 0x0B),
+    (ProtocolState::Play, Direction::ClientBound, 0x73),
+];
+
+#[inline]
+fn is_translated(state: ProtocolState) -> bool {
+    matches!(state, ProtocolState::Configuration | ProtocolState::Play)
+}
+
+/// The id the enums model the packet sent with `type_id` at, `None` if they
+/// don't model it in `protocol_version`.
+pub(crate) fn to_modeled(
+    protocol_version: i32,
+    state: ProtocolState,
+    direction: Direction,
+    type_id: u8,
+) -> Option<u8> {
+    if !is_translated(state) {
+        return Some(type_id);
+    }
+    let added = ADDED
+        .iter()
+        .any(|&(s, d, id)| (s, d, id) == (state, direction, type_id));
+
+    if protocol_version < TRANSFER_PROTOCOL_VERSION {
+        return (!added).then_some(type_id);
+    }
+    if added {
+        return Some(type_id);
+    }
+    MOVED
+        .iter()
+        .find(|&&(s, d, _, id)| (s, d, id) == (state, direction, type_id))
+        .map(|&(_, _, id, _)| id)
+}
+
+/// The id the packet modeled at `type_id` is sent with in `protocol_version`.
+/// Packets without a known id in the version keep the modeled one.
+pub(crate) fn from_modeled(
+    protocol_version: i32,
+    state: ProtocolState,
+    direction: Direction,
+    type_id: u8,
+) -> u8 {
+    if !is_translated(state) || protocol_version < TRANSFER_PROTOCOL_VERSION {
+        return type_id;
+    }
+    MOVED
+        .iter()
+        .find(|&&(s, d, id, _)| (s, d, id) == (state, direction, type_id))
+        .map_or(type_id, |&(_, _, _, id)| id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_modeled, to_modeled};
+    use crate::{
+        codec::{ProtocolState, STATE_PROTOCOL_VERSION, TRANSFER_PROTOCOL_VERSION},
+        packet::registry::Direction,
+    };
+
+    #[test]
+    fn test_transfer_ids() {
+        let play = (ProtocolState::Play, Direction::ClientBound);
+
+        // Update Recipes before 1.20.5
+        assert_eq!(
+            to_modeled(STATE_PROTOCOL_VERSION, play.0, play.1, 0x73),
+            None
+        );
+        assert_eq!(
+            to_modeled(TRANSFER_PROTOCOL_VERSION, play.0, play.1, 0x73),
+            Some(0x73)
+        );
+        assert_eq!(
+            from_modeled(TRANSFER_PROTOCOL_VERSION, play.0, play.1, 0x73),
+            0x73
+        );
+    }
+
+    #[test]
+    fn test_moved_ids() {
+        let config = (ProtocolState::Configuration, Direction::ClientBound);
+        for version in [764, STATE_PROTOCOL_VERSION] {
+            assert_eq!(to_modeled(version, config.0, config.1, 0x02), Some(0x02));
+            assert_eq!(from_modeled(version, config.0, config.1, 0x02), 0x02);
+        }
+
+        // Finish configuration, then the 1.20.5 disconnect
+        let version = TRANSFER_PROTOCOL_VERSION;
+        assert_eq!(from_modeled(version, config.0, config.1, 0x02), 0x03);
+        assert_eq!(to_modeled(version, config.0, config.1, 0x03), Some(0x02));
+        assert_eq!(to_modeled(version, config.0, config.1, 0x02), Some(0x01));
+        // Registry data changed its format
+        assert_eq!(to_modeled(version, config.0, config.1, 0x07), None);
+
+        let play = (ProtocolState::Play, Direction::ServerBound);
+        assert_eq!(to_modeled(version, play.0, play.1, 0x18), Some(0x15));
+        assert_eq!(to_modeled(version, play.0, play.1, 0x15), None);
+
+        // Login didn't change
+        let login = (ProtocolState::Login, Direction::ClientBound);
+        assert_eq!(to_modeled(version, login.0, login.1, 0x02), Some(0x02));
+    }
+}
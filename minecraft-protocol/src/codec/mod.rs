@@ -1,7 +1,27 @@
+use crate::{
+    decoder::{read_type_id, EnumDecoder},
+    encoder::{var_int, Encoder, EnumEncoder},
+    error::{DecodeError, EncodeError},
+    packet::registry::{Direction, PacketRegistry, RegisteredPacket},
+};
+use std::io::{Cursor, Read, Write};
+
 pub mod client;
 pub mod codec;
+#[cfg(feature = "tokio")]
+pub mod framed;
+mod ids;
 pub mod server;
 
+/// The protocol version (1.20.4) the states and their packet ids are modeled
+/// after, other versions may send packets [`ProtocolState::max_type_id`]
+/// doesn't expect.
+pub const STATE_PROTOCOL_VERSION: i32 = 765;
+
+/// Clients understand the transfer packet since 1.20.5, which also moved the
+/// ids of most configuration and play packets. The codecs translate them.
+pub const TRANSFER_PROTOCOL_VERSION: i32 = 766;
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum ProtocolState {
     Handshake,
@@ -10,3 +30,91 @@ pub enum ProtocolState {
     Configuration,
     Play,
 }
+
+impl ProtocolState {
+    /// The highest type id of the packets sent to `direction` in the state,
+    /// with room for the ones newer versions may add. Play packets sent to
+    /// the client use the whole range.
+    pub fn max_type_id(self, direction: Direction) -> u8 {
+        match (self, direction) {
+            (ProtocolState::Handshake, _) => 0x00,
+            (ProtocolState::Status, _) => 0x01,
+            (ProtocolState::Login, Direction::ServerBound) => 0x04,
+            (ProtocolState::Login, Direction::ClientBound) => 0x05,
+            (ProtocolState::Configuration, Direction::ServerBound) => 0x0F,
+            (ProtocolState::Configuration, Direction::ClientBound) => 0x1F,
+            (ProtocolState::Play, Direction::ServerBound) => 0x4F,
+            (ProtocolState::Play, Direction::ClientBound) => u8::MAX,
+        }
+    }
+}
+
+/// Decodes a packet like `Decoder::decode`, rejecting the type ids that can't
+/// be sent in `state` with [`DecodeError::UnexpectedPacket`]. The type id is
+/// translated from the one of `protocol_version`.
+fn decode_in_state<T: EnumDecoder, R: Read>(
+    protocol_version: i32,
+    state: ProtocolState,
+    direction: Direction,
+    reader: &mut R,
+) -> Result<T::Output, DecodeError> {
+    let type_id = read_type_id(reader)?;
+    if type_id > state.max_type_id(direction) {
+        return Err(DecodeError::UnexpectedPacket { state, type_id });
+    }
+
+    match ids::to_modeled(protocol_version, state, direction, type_id) {
+        Some(modeled) => T::decode(modeled, reader),
+        None => T::decode_unmodeled(type_id),
+    }
+}
+
+/// Decodes a packet with `registry` if it has a decoder for its type id, as
+/// sent in the session, or like [`decode_in_state`] otherwise.
+fn decode_registered<T, P>(
+    registry: Option<&PacketRegistry>,
+    protocol_version: i32,
+    state: ProtocolState,
+    direction: Direction,
+    reader: &mut Cursor<&[u8]>,
+) -> Result<P, DecodeError>
+where
+    T: EnumDecoder,
+    P: From<T::Output> + From<RegisteredPacket>,
+{
+    if let Some(registry) = registry {
+        let start = reader.position();
+        if let Some(packet) = registry.decode(state, direction, reader)? {
+            return Ok(packet.into());
+        }
+        reader.set_position(start);
+    }
+
+    decode_in_state::<T, _>(protocol_version, state, direction, reader).map(P::from)
+}
+
+/// Encodes a packet with the type id of `protocol_version`.
+struct Versioned<'a, T> {
+    type_id: u8,
+    packet: &'a T,
+}
+
+impl<'a, T: EnumEncoder> Versioned<'a, T> {
+    fn new(
+        protocol_version: i32,
+        state: ProtocolState,
+        direction: Direction,
+        packet: &'a T,
+    ) -> Self {
+        let type_id = ids::from_modeled(protocol_version, state, direction, packet.get_type_id());
+        Self { type_id, packet }
+    }
+}
+
+impl<T: EnumEncoder> Encoder for Versioned<'_, T> {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<(), EncodeError> {
+        var_int::encode(&(self.type_id as i32), writer)?;
+
+        EnumEncoder::encode(self.packet, writer)
+    }
+}
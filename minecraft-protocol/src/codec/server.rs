@@ -1,16 +1,25 @@
-use super::{codec::MinecraftCodec, ProtocolState};
+use super::{
+    codec::MinecraftCodec, decode_registered, ProtocolState, Versioned, STATE_PROTOCOL_VERSION,
+};
 use crate::{
+    decoder::EnumDecoder,
     encoder::EnumEncoder,
     error::DecodeError,
     packet::{
-        configuration::ConfigClientBoundPaket, game::GameClientBoundPacket,
-        login::LoginClientBoundPacket, status::StatusClientBoundPacket,
+        configuration::ConfigClientBoundPaket,
+        game::GameClientBoundPacket,
+        login::LoginClientBoundPacket,
+        registry::{Direction, PacketRegistry, RegisteredPacket},
+        status::StatusClientBoundPacket,
     },
 };
+use std::sync::Arc;
 
 pub struct ServerPacketCodec {
     state: ProtocolState,
+    protocol_version: i32,
     codec: MinecraftCodec,
+    registry: Option<Arc<PacketRegistry>>,
 }
 
 impl Default for ServerPacketCodec {
@@ -25,7 +34,9 @@ impl ServerPacketCodec {
     pub fn new() -> Self {
         Self {
             state: ProtocolState::Handshake,
+            protocol_version: STATE_PROTOCOL_VERSION,
             codec: MinecraftCodec::new(),
+            registry: None,
         }
     }
 
@@ -39,50 +50,114 @@ impl ServerPacketCodec {
         self.state = state
     }
 
+    /// The configuration and play packets are decoded and encoded with the
+    /// type ids of this version, 1.20.4 until it is set.
+    #[inline]
+    pub fn set_protocol_version(&mut self, protocol_version: i32) {
+        self.protocol_version = protocol_version
+    }
+
+    /// The packets with a decoder in `registry` are decoded by it, as
+    /// [`ServerPacket::Registered`], instead of by the packet enums. Their type
+    /// ids are the ones sent in the session.
+    #[inline]
+    pub fn set_registry(&mut self, registry: Arc<PacketRegistry>) {
+        self.registry = Some(registry)
+    }
+
+    fn next_packet<T>(&mut self) -> Result<Option<ServerPacket>, DecodeError>
+    where
+        T: EnumDecoder,
+        ServerPacket: From<T::Output>,
+    {
+        let (state, protocol_version) = (self.state, self.protocol_version);
+        let registry = self.registry.as_deref();
+        self.codec.next_packet_with(|reader| {
+            decode_registered::<T, _>(
+                registry,
+                protocol_version,
+                state,
+                Direction::ClientBound,
+                reader,
+            )
+        })
+    }
+
+    /// Negative thresholds disable compression, as in `SetCompression` packets.
     #[inline]
-    pub fn set_compression(&mut self, threshold: usize) {
-        self.codec.enable_compression(threshold)
+    pub fn set_compression(&mut self, threshold: i32) {
+        self.codec.set_compression_threshold(threshold)
     }
 
+    #[cfg(feature = "tokio")]
+    #[inline]
+    pub(crate) fn inner_mut(&mut self) -> &mut MinecraftCodec {
+        &mut self.codec
+    }
+
+    /// Compression is enabled or disabled as soon as a `SetCompression` packet
+    /// is decoded, the caller doesn't need to call [`set_compression`](Self::set_compression).
     pub fn decode(&mut self, data: &[u8]) -> Result<Option<ServerPacket>, DecodeError> {
         self.codec.accept(data);
         match self.state {
             ProtocolState::Handshake => Err(DecodeError::DataSentDuringHandshake),
-            ProtocolState::Status => self
-                .codec
-                .next_packet::<StatusClientBoundPacket>()
-                .map(|opt| opt.map(ServerPacket::from)),
-            ProtocolState::Login => self
-                .codec
-                .next_packet::<LoginClientBoundPacket>()
-                .map(|opt| opt.map(ServerPacket::from)),
-            ProtocolState::Configuration => self
-                .codec
-                .next_packet::<ConfigClientBoundPaket>()
-                .map(|opt| opt.map(ServerPacket::from)),
-            ProtocolState::Play => self
-                .codec
-                .next_packet::<GameClientBoundPacket>()
-                .map(|opt| opt.map(ServerPacket::from)),
+            ProtocolState::Status => self.next_packet::<StatusClientBoundPacket>(),
+            ProtocolState::Login => {
+                let packet = self.next_packet::<LoginClientBoundPacket>()?;
+
+                // Every frame after this one is sent with the new settings,
+                // including the ones already buffered
+                if let Some(ServerPacket::Login(LoginClientBoundPacket::SetCompression(packet))) =
+                    &packet
+                {
+                    self.codec.set_compression_threshold(packet.threshold);
+                }
+
+                Ok(packet)
+            }
+            ProtocolState::Configuration => self.next_packet::<ConfigClientBoundPaket>(),
+            ProtocolState::Play => self.next_packet::<GameClientBoundPacket>(),
         }
     }
 
+    fn encode_versioned<T: EnumEncoder>(
+        &mut self,
+        state: ProtocolState,
+        packet: &T,
+        buffer: &mut Vec<u8>,
+    ) {
+        let packet = Versioned::new(self.protocol_version, state, Direction::ClientBound, packet);
+        self.codec.encode(&packet, buffer).unwrap()
+    }
+
     pub fn encode(&mut self, packet: &ServerPacket, buffer: &mut Vec<u8>) {
         match packet {
             ServerPacket::Status(packet) => self.codec.encode(packet, buffer).unwrap(),
             ServerPacket::Login(packet) => self.codec.encode(packet, buffer).unwrap(),
-            ServerPacket::Configuration(packet) => self.codec.encode(packet, buffer).unwrap(),
-            ServerPacket::Play(packet) => self.codec.encode(packet, buffer).unwrap(),
+            // Other and registered packets are sent with the type id they were
+            // received with
+            ServerPacket::Registered(packet) => self.codec.encode(packet, buffer).unwrap(),
+            ServerPacket::Play(packet @ GameClientBoundPacket::Other { .. }) => {
+                self.codec.encode(packet, buffer).unwrap()
+            }
+            ServerPacket::Configuration(packet) => {
+                self.encode_versioned(ProtocolState::Configuration, packet, buffer)
+            }
+            ServerPacket::Play(packet) => {
+                self.encode_versioned(ProtocolState::Play, packet, buffer)
+            }
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub enum ServerPacket {
     Status(StatusClientBoundPacket),
     Login(LoginClientBoundPacket),
     Configuration(ConfigClientBoundPaket),
     Play(GameClientBoundPacket),
+    /// Decoded by the registry of the codec, see [`ServerPacketCodec::set_registry`]
+    Registered(RegisteredPacket),
 }
 
 impl ServerPacket {
@@ -93,6 +168,7 @@ impl ServerPacket {
             ServerPacket::Login(packet) => packet.get_type_id(),
             ServerPacket::Configuration(packet) => packet.get_type_id(),
             ServerPacket::Play(packet) => packet.get_type_id(),
+            ServerPacket::Registered(packet) => packet.type_id(),
         }
     }
 }
@@ -124,3 +200,266 @@ impl From<GameClientBoundPacket> for ServerPacket {
         ServerPacket::Play(packet)
     }
 }
+
+impl From<RegisteredPacket> for ServerPacket {
+    #[inline]
+    fn from(packet: RegisteredPacket) -> Self {
+        ServerPacket::Registered(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ServerPacket, ServerPacketCodec};
+    use crate::{
+        codec::ProtocolState,
+        encoder::{var_int, Encoder},
+        error::DecodeError,
+        packet::{
+            configuration::{ConfigClientBoundPaket, Transfer},
+            game::GameClientBoundPacket,
+            login::{LoginClientBoundPacket, LoginSuccess, SetCompression},
+        },
+    };
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+    use uuid::Uuid;
+
+    fn frame(data: &[u8]) -> Vec<u8> {
+        let mut vec = Vec::new();
+        var_int::encode(&(data.len() as i32), &mut vec).unwrap();
+        vec.extend_from_slice(data);
+        vec
+    }
+
+    fn packet_data(packet: LoginClientBoundPacket) -> Vec<u8> {
+        let mut data = Vec::new();
+        packet.encode(&mut data).unwrap();
+        data
+    }
+
+    fn compressed_frame(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+
+        let mut inner = Vec::new();
+        var_int::encode(&(data.len() as i32), &mut inner).unwrap();
+        inner.extend(encoder.finish().unwrap());
+        frame(&inner)
+    }
+
+    fn login_success(uuid: Uuid) -> Vec<u8> {
+        packet_data(LoginClientBoundPacket::LoginSuccess(LoginSuccess {
+            uuid,
+            username: "Notch".into(),
+        }))
+    }
+
+    fn set_compression(threshold: i32) -> Vec<u8> {
+        frame(&packet_data(LoginClientBoundPacket::SetCompression(
+            SetCompression { threshold },
+        )))
+    }
+
+    fn login_codec() -> ServerPacketCodec {
+        let mut codec = ServerPacketCodec::new();
+        codec.set_state(ProtocolState::Login);
+        codec
+    }
+
+    #[test]
+    fn test_set_compression_and_compressed_packet_in_one_read() {
+        let uuid = Uuid::new_v4();
+        let mut segment = set_compression(0);
+        segment.extend(compressed_frame(&login_success(uuid)));
+
+        let mut codec = login_codec();
+        match codec.decode(&segment).unwrap() {
+            Some(ServerPacket::Login(LoginClientBoundPacket::SetCompression(packet))) => {
+                assert_eq!(packet.threshold, 0);
+            }
+            packet => panic!("unexpected packet {packet:?}"),
+        }
+        match codec.decode(&[]).unwrap() {
+            Some(ServerPacket::Login(LoginClientBoundPacket::LoginSuccess(packet))) => {
+                assert_eq!(packet.uuid, uuid);
+                assert_eq!(packet.username, "Notch");
+            }
+            packet => panic!("unexpected packet {packet:?}"),
+        }
+    }
+
+    #[test]
+    fn test_negative_threshold_disables_compression() {
+        let uuid = Uuid::new_v4();
+
+        // Sent while compression is enabled, so with a data length of 0, with
+        // a threshold of -1
+        let mut segment = frame(&[0x00, 0x03, 0xff, 0xff, 0xff, 0xff, 0x0f]);
+        segment.extend(frame(&login_success(uuid)));
+
+        let mut codec = login_codec();
+        codec.set_compression(256);
+
+        assert!(matches!(
+            codec.decode(&segment).unwrap(),
+            Some(ServerPacket::Login(LoginClientBoundPacket::SetCompression(
+                _
+            )))
+        ));
+        match codec.decode(&[]).unwrap() {
+            Some(ServerPacket::Login(LoginClientBoundPacket::LoginSuccess(packet))) => {
+                assert_eq!(packet.uuid, uuid);
+            }
+            packet => panic!("unexpected packet {packet:?}"),
+        }
+    }
+
+    #[test]
+    fn test_packet_of_another_state() {
+        let uuid = Uuid::new_v4();
+        // A play packet, then a login one
+        let mut segment = frame(&[0x29, 0, 0, 0, 1]);
+        segment.extend(frame(&login_success(uuid)));
+
+        let mut codec = login_codec();
+        assert!(matches!(
+            codec.decode(&segment),
+            Err(DecodeError::UnexpectedPacket {
+                state: ProtocolState::Login,
+                type_id: 0x29
+            })
+        ));
+        // The frame was skipped
+        assert!(matches!(
+            codec.decode(&[]).unwrap(),
+            Some(ServerPacket::Login(LoginClientBoundPacket::LoginSuccess(_)))
+        ));
+
+        // Every id is taken during play
+        codec.set_state(ProtocolState::Play);
+        codec.decode(&frame(&[0xFF, 0x01])).unwrap();
+    }
+
+    #[test]
+    fn test_incomplete_packet() {
+        let uuid = Uuid::new_v4();
+        let segment = frame(&login_success(uuid));
+        let mut codec = login_codec();
+
+        // Part of the length, then part of the packet
+        assert!(codec.decode(&[]).unwrap().is_none());
+        assert!(codec.decode(&segment[..1]).unwrap().is_none());
+        assert!(codec.decode(&segment[1..8]).unwrap().is_none());
+        assert!(matches!(
+            codec.decode(&segment[8..]).unwrap(),
+            Some(ServerPacket::Login(LoginClientBoundPacket::LoginSuccess(_)))
+        ));
+    }
+
+    #[test]
+    fn test_malformed_packet_is_skipped() {
+        let uuid = Uuid::new_v4();
+        let mut segment = frame(&[0x7f, 0x01, 0x02]);
+        segment.extend(frame(&login_success(uuid)));
+
+        let mut codec = login_codec();
+        assert!(codec.decode(&segment).is_err());
+        assert!(matches!(
+            codec.decode(&[]).unwrap(),
+            Some(ServerPacket::Login(LoginClientBoundPacket::LoginSuccess(_)))
+        ));
+
+        // A length that can never be valid is an error, not an incomplete packet
+        let mut codec = login_codec();
+        assert!(codec.decode(&[0xff, 0xff, 0xff, 0xff, 0xff, 0x01]).is_err());
+        let mut codec = login_codec();
+        assert!(codec.decode(&[0xff, 0xff, 0xff, 0xff, 0x0f]).is_err());
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let uuid = Uuid::new_v4();
+        let packet: ServerPacket = LoginClientBoundPacket::LoginSuccess(LoginSuccess {
+            uuid,
+            username: "Notch".into(),
+        })
+        .into();
+        let data = login_success(uuid);
+
+        // Above and below the threshold
+        for threshold in [0, data.len() + 1] {
+            let mut encoder = login_codec();
+            encoder.set_compression(threshold as i32);
+
+            let mut encoded = Vec::new();
+            encoder.encode(&packet, &mut encoded);
+            if threshold == 0 {
+                assert_eq!(encoded, compressed_frame(&data));
+            } else {
+                let mut inner = vec![0x00];
+                inner.extend(&data);
+                assert_eq!(encoded, frame(&inner));
+            }
+
+            let mut decoder = login_codec();
+            decoder.set_compression(threshold as i32);
+            match decoder.decode(&encoded).unwrap() {
+                Some(ServerPacket::Login(LoginClientBoundPacket::LoginSuccess(packet))) => {
+                    assert_eq!(packet.uuid, uuid);
+                    assert_eq!(packet.username, "Notch");
+                }
+                packet => panic!("unexpected packet {packet:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_ids_of_the_protocol_version() {
+        let transfer: ServerPacket = GameClientBoundPacket::Transfer(Transfer {
+            host: "lobby.example.com".into(),
+            port: 25565,
+        })
+        .into();
+
+        let mut codec = ServerPacketCodec::new();
+        codec.set_state(ProtocolState::Play);
+        let mut encoded = Vec::new();
+        codec.encode(&transfer, &mut encoded);
+
+        // Update Recipes in 1.20.4
+        assert!(matches!(
+            codec.decode(&encoded).unwrap(),
+            Some(ServerPacket::Play(GameClientBoundPacket::Other {
+                type_id: 0x73
+            }))
+        ));
+
+        codec.set_protocol_version(766);
+        assert!(matches!(
+            codec.decode(&encoded).unwrap(),
+            Some(ServerPacket::Play(GameClientBoundPacket::Transfer(_)))
+        ));
+
+        // Moved from 0x02
+        codec.set_state(ProtocolState::Configuration);
+        let mut encoded = Vec::new();
+        codec.encode(
+            &ConfigClientBoundPaket::FinishConfiguration.into(),
+            &mut encoded,
+        );
+        assert_eq!(encoded, frame(&[0x03]));
+        assert!(matches!(
+            codec.decode(&encoded).unwrap(),
+            Some(ServerPacket::Configuration(
+                ConfigClientBoundPaket::FinishConfiguration
+            ))
+        ));
+
+        // Registry data, whose format changed
+        assert!(matches!(
+            codec.decode(&frame(&[0x07])),
+            Err(DecodeError::UnknownPacketType { type_id: 0x07 })
+        ));
+    }
+}
@@ -61,12 +61,15 @@
 //! assert_eq!(expected_message, Message::from_json(json).unwrap());
 //! ```
 
-use crate::impl_json_encoder_decoder;
+use crate::{
+    impl_json_encoder_decoder,
+    nbt::{CompoundTag, Tag},
+};
 use serde::{
     de::{self, Visitor},
     Deserialize, Serialize,
 };
-use serde_json::Error;
+use serde_json::{Error, Number, Value};
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Color {
@@ -219,6 +222,7 @@ pub enum Payload {
     },
     Translation {
         translate: String,
+        #[serde(default)]
         with: Vec<FormatedMessage>,
     },
     Keybind {
@@ -296,10 +300,104 @@ impl Message {
     pub fn to_json(&self) -> Result<String, Error> {
         serde_json::to_string(&self)
     }
+
+    /// Converts the message to the nbt form used by packets since 1.20.3.
+    pub fn to_nbt(&self) -> Result<Tag, Error> {
+        Ok(json_to_tag(serde_json::to_value(self)?))
+    }
+
+    pub fn from_nbt(tag: Tag) -> Result<Self, Error> {
+        serde_json::from_value(tag_to_json(tag))
+    }
+
+    /// Renders the text a player would read, without colors nor formatting
+    /// codes, to be logged.
+    pub fn to_plain_text(&self) -> String {
+        match self {
+            Message::Plain(text) => {
+                let mut out = String::new();
+                push_unformatted(&mut out, text);
+                out
+            }
+            Message::Formated(message) => message.to_plain_text(),
+        }
+    }
 }
 
 impl_json_encoder_decoder!(Message);
 
+fn json_to_tag(value: Value) -> Tag {
+    match value {
+        Value::Null => Tag::String(String::new()),
+        Value::Bool(v) => Tag::Byte(v as i8),
+        Value::Number(v) => match (v.as_i64(), v.as_f64()) {
+            (Some(v), _) => match i32::try_from(v) {
+                Ok(v) => Tag::Int(v),
+                Err(_) => Tag::Long(v),
+            },
+            (None, Some(v)) => Tag::Double(v),
+            (None, None) => Tag::String(v.to_string()),
+        },
+        Value::String(v) => Tag::String(v),
+        Value::Array(values) => {
+            let tags: Vec<_> = values.into_iter().map(json_to_tag).collect();
+
+            // Nbt lists can't mix tag types, so mixed lists are made of
+            // compounds wrapping the other values in an empty key
+            let mixed = tags
+                .windows(2)
+                .any(|v| std::mem::discriminant(&v[0]) != std::mem::discriminant(&v[1]));
+            if mixed {
+                Tag::List(
+                    tags.into_iter()
+                        .map(|tag| match tag {
+                            Tag::Compound(v) => Tag::Compound(v),
+                            tag => Tag::Compound(CompoundTag::from_iter([("", tag)])),
+                        })
+                        .collect(),
+                )
+            } else {
+                Tag::List(tags)
+            }
+        }
+        Value::Object(map) => Tag::Compound(
+            map.into_iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k, json_to_tag(v)))
+                .collect(),
+        ),
+    }
+}
+
+fn tag_to_json(tag: Tag) -> Value {
+    match tag {
+        // Text components only use bytes as booleans
+        Tag::Byte(v) => Value::Bool(v != 0),
+        Tag::Short(v) => Value::from(v),
+        Tag::Int(v) => Value::from(v),
+        Tag::Long(v) => Value::from(v),
+        Tag::Float(v) => Number::from_f64(v as f64).map_or(Value::Null, Value::Number),
+        Tag::Double(v) => Number::from_f64(v).map_or(Value::Null, Value::Number),
+        Tag::String(v) => Value::String(v),
+        Tag::List(tags) => Value::Array(
+            tags.into_iter()
+                .map(|tag| match tag {
+                    Tag::Compound(v) if v.iter().count() == 1 && v.contains_key("") => {
+                        tag_to_json(v.into_iter().next().unwrap().1)
+                    }
+                    tag => tag_to_json(tag),
+                })
+                .collect(),
+        ),
+        Tag::Compound(v) => {
+            Value::Object(v.into_iter().map(|(k, v)| (k, tag_to_json(v))).collect())
+        }
+        Tag::ByteArray(v) => Value::from(v),
+        Tag::IntArray(v) => Value::from(v),
+        Tag::LongArray(v) => Value::from(v),
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct FormatedMessage {
@@ -359,6 +457,178 @@ impl FormatedMessage {
     pub fn to_json(&self) -> Result<String, Error> {
         serde_json::to_string(&self)
     }
+
+    /// See [`Message::to_plain_text`].
+    pub fn to_plain_text(&self) -> String {
+        let mut out = String::new();
+        self.write_plain_text(&mut out);
+        out
+    }
+
+    fn write_plain_text(&self, out: &mut String) {
+        match &self.payload {
+            Payload::Text { text } => push_unformatted(out, text),
+            Payload::Translation { translate, with } => write_translation(out, translate, with),
+            Payload::Keybind { keybind } => out.push_str(keybind),
+            Payload::Score { value, .. } => out.push_str(value),
+            Payload::Selector { selector } => out.push_str(selector),
+        }
+
+        for extra in &self.extra {
+            extra.write_plain_text(out);
+        }
+    }
+}
+
+/// Parses text formatted with the legacy `§` codes, or with `&` as written in
+/// Bukkit configs, into a component per run of text sharing a style. Like in
+/// the client, a color code resets the formatting codes before it.
+///
+/// A `&` is kept when not followed by a known code, while unknown `§` codes
+/// are dropped.
+pub fn legacy_to_message(text: &str) -> Message {
+    let mut parts = Vec::new();
+    let mut current = FormatedMessage::from_str("");
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        let code = match c {
+            '§' => chars.next(),
+            '&' => match chars.clone().next() {
+                Some(next) if is_legacy_code(next) => chars.next(),
+                _ => None,
+            },
+            _ => None,
+        };
+        let code = match code {
+            Some(code) => code.to_ascii_lowercase(),
+            None if c == '§' => continue,
+            None => {
+                if let Payload::Text { text } = &mut current.payload {
+                    text.push(c);
+                }
+                continue;
+            }
+        };
+        if !is_legacy_code(code) {
+            continue;
+        }
+
+        let mut next = match code {
+            'k' | 'l' | 'm' | 'n' | 'o' => FormatedMessage {
+                payload: Payload::text(""),
+                extra: Vec::new(),
+                ..current.clone()
+            },
+            _ => FormatedMessage::from_str(""),
+        };
+        match code {
+            'k' => next.obfuscated = Some(true),
+            'l' => next.bold = Some(true),
+            'm' => next.strikethrough = Some(true),
+            'n' => next.underlined = Some(true),
+            'o' => next.italic = Some(true),
+            code => next.color = legacy_color(code),
+        }
+
+        parts.push(std::mem::replace(&mut current, next));
+    }
+    parts.push(current);
+    parts.retain(|part| !matches!(&part.payload, Payload::Text { text } if text.is_empty()));
+
+    match parts.len() {
+        0 => Message::from_str(""),
+        1 => Message::Formated(parts.remove(0)),
+        _ => {
+            let mut root = FormatedMessage::from_str("");
+            root.extra = parts;
+            Message::Formated(root)
+        }
+    }
+}
+
+fn is_legacy_code(code: char) -> bool {
+    let code = code.to_ascii_lowercase();
+    matches!(code, 'k'..='o' | 'r') || legacy_color(code).is_some()
+}
+
+fn legacy_color(code: char) -> Option<Color> {
+    let color = match code {
+        '0' => Color::Black,
+        '1' => Color::DarkBlue,
+        '2' => Color::DarkGreen,
+        '3' => Color::DarkAqua,
+        '4' => Color::DarkRed,
+        '5' => Color::DarkPurple,
+        '6' => Color::Gold,
+        '7' => Color::Gray,
+        '8' => Color::DarkGray,
+        '9' => Color::Blue,
+        'a' => Color::Green,
+        'b' => Color::Aqua,
+        'c' => Color::Red,
+        'd' => Color::LightPurple,
+        'e' => Color::Yellow,
+        'f' => Color::White,
+        _ => return None,
+    };
+
+    Some(color)
+}
+
+/// The proxy has no translation table, so the key is rendered as the format
+/// string, with both `%s` and `%1$s` arguments filled in. Missing arguments
+/// are left out.
+fn write_translation(out: &mut String, translate: &str, with: &[FormatedMessage]) {
+    let mut args = with.iter();
+    let mut rest = translate;
+
+    while let Some(i) = rest.find('%') {
+        push_unformatted(out, &rest[..i]);
+        rest = &rest[i + 1..];
+
+        if let Some(next) = rest.strip_prefix('%') {
+            out.push('%');
+            rest = next;
+        } else if let Some(next) = rest.strip_prefix('s') {
+            if let Some(arg) = args.next() {
+                arg.write_plain_text(out);
+            }
+            rest = next;
+        } else {
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+
+            match rest[digits..].strip_prefix("$s") {
+                Some(next) if digits > 0 => {
+                    let arg = rest[..digits]
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|n| n.checked_sub(1))
+                        .and_then(|n| with.get(n));
+                    if let Some(arg) = arg {
+                        arg.write_plain_text(out);
+                    }
+                    rest = next;
+                }
+                _ => out.push('%'),
+            }
+        }
+    }
+    push_unformatted(out, rest);
+}
+
+/// Pushes `text` without the legacy `§` formatting codes.
+fn push_unformatted(out: &mut String, text: &str) {
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            out.push(c);
+        }
+    }
 }
 
 pub struct MessageBuilder {
@@ -771,3 +1041,111 @@ fn test_deserialize_hex_color() {
         expected_message
     );
 }
+
+#[test]
+fn test_nbt_round_trip() {
+    let message = MessageBuilder::builder(Payload::text("Hello"))
+        .color(Color::Yellow)
+        .bold(true)
+        .then(Payload::text("world"))
+        .italic(true)
+        .build();
+
+    let tag = message.to_nbt().unwrap();
+    assert!(matches!(&tag, Tag::Compound(v) if v.get_str("text").unwrap() == "Hello"));
+    assert_eq!(Message::from_nbt(tag).unwrap(), message);
+
+    let plain = Message::Plain("Server restarting".into());
+    assert!(matches!(plain.to_nbt().unwrap(), Tag::String(_)));
+    assert_eq!(Message::from_nbt(plain.to_nbt().unwrap()).unwrap(), plain);
+}
+
+#[test]
+fn test_to_plain_text() {
+    let message = MessageBuilder::builder(Payload::text("Hello "))
+        .color(Color::Yellow)
+        .bold(true)
+        .then(Payload::text("§cworld"))
+        .italic(true)
+        .then(Payload::keybind("key.jump"))
+        .build();
+    assert_eq!(message.to_plain_text(), "Hello worldkey.jump");
+
+    // Nested extras are rendered depth first
+    let mut nested = FormatedMessage::from_str("a");
+    let mut inner = FormatedMessage::from_str("b");
+    inner.extra.push(FormatedMessage::from_str("c"));
+    nested.extra.extend([inner, FormatedMessage::from_str("d")]);
+    assert_eq!(Message::Formated(nested).to_plain_text(), "abcd");
+
+    let plain = Message::Plain("§l§6Server §rrestarting§".into());
+    assert_eq!(plain.to_plain_text(), "Server restarting");
+}
+
+#[test]
+fn test_translation_to_plain_text() {
+    let with = vec![
+        FormatedMessage::from_str("Steve"),
+        FormatedMessage::from_str("Alex"),
+    ];
+    let message = FormatedMessage::new(Payload::translation("%s gave %s 100%%", with.clone()));
+    assert_eq!(message.to_plain_text(), "Steve gave Alex 100%");
+
+    let message = FormatedMessage::new(Payload::translation("%2$s by %1$s, %3$s%", with));
+    assert_eq!(message.to_plain_text(), "Alex by Steve, %");
+
+    // The arguments can be omitted
+    let message = Message::from_json(r#"{"translate":"multiplayer.disconnect.kicked"}"#).unwrap();
+    assert_eq!(message.to_plain_text(), "multiplayer.disconnect.kicked");
+}
+
+#[test]
+fn test_legacy_to_message() {
+    let message = legacy_to_message("&6Welcome to &lMy Server\n§7Have §lfun§r!");
+    let expected = MessageBuilder::builder(Payload::text(""))
+        .then(Payload::text("Welcome to "))
+        .color(Color::Gold)
+        .then(Payload::text("My Server\n"))
+        .color(Color::Gold)
+        .bold(true)
+        .then(Payload::text("Have "))
+        .color(Color::Gray)
+        .then(Payload::text("fun"))
+        .color(Color::Gray)
+        .bold(true)
+        .then(Payload::text("!"))
+        .build();
+    assert_eq!(message, expected);
+    assert_eq!(message.to_plain_text(), "Welcome to My Server\nHave fun!");
+
+    // Formatting codes add up until a color resets them
+    let message = legacy_to_message("§o§Kx§ny&Cz");
+    let expected = MessageBuilder::builder(Payload::text(""))
+        .then(Payload::text("x"))
+        .italic(true)
+        .obfuscated(true)
+        .then(Payload::text("y"))
+        .italic(true)
+        .obfuscated(true)
+        .underlined(true)
+        .then(Payload::text("z"))
+        .color(Color::Red)
+        .build();
+    assert_eq!(message, expected);
+}
+
+#[test]
+fn test_legacy_to_message_unknown_codes() {
+    assert_eq!(
+        legacy_to_message("Tom & Jerry &z"),
+        Message::from_str("Tom & Jerry &z")
+    );
+    assert_eq!(legacy_to_message("100§z%§"), Message::from_str("100%"));
+    assert_eq!(legacy_to_message("§a"), Message::from_str(""));
+
+    // Only the last of consecutive colors is kept
+    let expected = MessageBuilder::builder(Payload::text("Hello&"))
+        .color(Color::Green)
+        .build();
+    assert_eq!(legacy_to_message("&a&r&aHello&"), expected);
+}
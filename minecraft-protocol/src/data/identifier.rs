@@ -0,0 +1,191 @@
+use crate::decoder::{Decoder, DecoderReadExt};
+use crate::encoder::{Encoder, EncoderWriteExt};
+use crate::error::{DecodeError, EncodeError};
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+const MAX_LENGTH: u16 = 32767;
+const DEFAULT_NAMESPACE: &str = "minecraft";
+
+/// A namespaced key, like `minecraft:brand`.
+///
+/// Kept as it was received, so the namespace may be omitted, in which case
+/// it's `minecraft`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Identifier(String);
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid identifier: {identifier:?}")]
+pub struct InvalidIdentifier {
+    pub identifier: String,
+}
+
+impl Identifier {
+    pub fn new(identifier: impl Into<String>) -> Result<Self, InvalidIdentifier> {
+        let identifier = identifier.into();
+
+        let valid = identifier.len() <= MAX_LENGTH as usize
+            && match identifier.split_once(':') {
+                Some((namespace, path)) => {
+                    namespace.bytes().all(is_namespace_char) && path.bytes().all(is_path_char)
+                }
+                None => identifier.bytes().all(is_path_char),
+            };
+
+        if valid {
+            Ok(Identifier(identifier))
+        } else {
+            Err(InvalidIdentifier { identifier })
+        }
+    }
+
+    /// Creates an identifier from a constant, panicking if it's invalid.
+    pub fn from_static(identifier: &'static str) -> Self {
+        match Identifier::new(identifier) {
+            Ok(v) => v,
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    pub fn namespace(&self) -> &str {
+        match self.0.split_once(':') {
+            Some(("", _)) | None => DEFAULT_NAMESPACE,
+            Some((namespace, _)) => namespace,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        match self.0.split_once(':') {
+            Some((_, path)) => path,
+            None => &self.0,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+fn is_namespace_char(c: u8) -> bool {
+    matches!(c, b'a'..=b'z' | b'0'..=b'9' | b'.' | b'-' | b'_')
+}
+
+fn is_path_char(c: u8) -> bool {
+    is_namespace_char(c) || c == b'/'
+}
+
+impl FromStr for Identifier {
+    type Err = InvalidIdentifier;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Identifier::new(s)
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for Identifier {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for Identifier {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Identifier {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl Encoder for Identifier {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<(), EncodeError> {
+        writer.write_string(&self.0, MAX_LENGTH)
+    }
+}
+
+impl Decoder for Identifier {
+    type Output = Self;
+
+    fn decode<R: Read>(reader: &mut R) -> Result<Self::Output, DecodeError> {
+        Ok(Identifier::new(reader.read_string(MAX_LENGTH)?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::identifier::Identifier;
+    use crate::decoder::Decoder;
+    use crate::encoder::{Encoder, EncoderWriteExt};
+    use crate::error::DecodeError;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_valid_identifiers() {
+        let cases = [
+            ("minecraft:brand", "minecraft", "brand"),
+            ("basileia:proxy", "basileia", "proxy"),
+            ("brand", "minecraft", "brand"),
+            (":brand", "minecraft", "brand"),
+            ("fml:loginwrapper", "fml", "loginwrapper"),
+            (
+                "my-mod_1.0:textures/block/stone.png",
+                "my-mod_1.0",
+                "textures/block/stone.png",
+            ),
+        ];
+
+        for (value, namespace, path) in cases {
+            let identifier: Identifier = value.parse().unwrap();
+            assert_eq!(identifier.namespace(), namespace, "{value}");
+            assert_eq!(identifier.path(), path, "{value}");
+            assert_eq!(identifier, value);
+        }
+    }
+
+    #[test]
+    fn test_invalid_identifiers() {
+        let cases = [
+            "BungeeCord",
+            "minecraft:Brand",
+            "name/space:path",
+            "minecraft:brand:extra",
+            "minecraft:br and",
+            "minecraft:é",
+        ];
+
+        for value in cases {
+            assert!(value.parse::<Identifier>().is_err(), "{value}");
+        }
+
+        assert!(Identifier::new("a".repeat(32768)).is_err());
+    }
+
+    #[test]
+    fn test_identifier_round_trip() {
+        let identifier: Identifier = "minecraft:brand".parse().unwrap();
+
+        let mut vec = Vec::new();
+        identifier.encode(&mut vec).unwrap();
+        assert_eq!(vec[0] as usize, "minecraft:brand".len());
+
+        let decoded = Identifier::decode(&mut Cursor::new(vec)).unwrap();
+        assert_eq!(decoded, identifier);
+
+        let mut vec = Vec::new();
+        vec.write_string("minecraft:Brand", 32767).unwrap();
+        assert!(matches!(
+            Identifier::decode(&mut Cursor::new(vec)),
+            Err(DecodeError::InvalidIdentifier { .. })
+        ));
+    }
+}
@@ -1,2 +1,4 @@
 pub mod chat;
+pub mod identifier;
+pub mod position;
 pub mod server_status;
@@ -0,0 +1,126 @@
+use crate::decoder::Decoder;
+use crate::encoder::Encoder;
+use crate::error::{DecodeError, EncodeError};
+use std::io::{Read, Write};
+
+/// A block position, packed in 64 bits as 26 bits of `x`, 26 bits of `z`
+/// and 12 bits of `y`, in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Position {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+const XZ_BITS: u32 = 26;
+const Y_BITS: u32 = 12;
+
+impl Position {
+    pub const fn new(x: i32, y: i32, z: i32) -> Self {
+        Position { x, y, z }
+    }
+
+    /// Packs the position, failing if a coordinate doesn't fit in its bits.
+    pub fn to_packed(&self) -> Result<i64, EncodeError> {
+        if !fits(self.x, XZ_BITS) || !fits(self.y, Y_BITS) || !fits(self.z, XZ_BITS) {
+            return Err(EncodeError::PositionOutOfRange {
+                x: self.x,
+                y: self.y,
+                z: self.z,
+            });
+        }
+
+        let x = self.x as i64 & mask(XZ_BITS);
+        let z = self.z as i64 & mask(XZ_BITS);
+        let y = self.y as i64 & mask(Y_BITS);
+
+        Ok(x << (XZ_BITS + Y_BITS) | z << Y_BITS | y)
+    }
+
+    pub fn from_packed(value: i64) -> Self {
+        // Arithmetic shifts, so that the coordinates are sign extended
+        Position {
+            x: (value >> (XZ_BITS + Y_BITS)) as i32,
+            y: (value << (64 - Y_BITS) >> (64 - Y_BITS)) as i32,
+            z: (value << XZ_BITS >> (XZ_BITS + Y_BITS)) as i32,
+        }
+    }
+}
+
+const fn mask(bits: u32) -> i64 {
+    (1 << bits) - 1
+}
+
+const fn fits(value: i32, bits: u32) -> bool {
+    let min = -(1 << (bits - 1));
+    let max = (1 << (bits - 1)) - 1;
+
+    min <= value && value <= max
+}
+
+impl Encoder for Position {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<(), EncodeError> {
+        self.to_packed()?.encode(writer)
+    }
+}
+
+impl Decoder for Position {
+    type Output = Self;
+
+    fn decode<R: Read>(reader: &mut R) -> Result<Self::Output, DecodeError> {
+        Ok(Position::from_packed(i64::decode(reader)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::position::Position;
+    use crate::decoder::Decoder;
+    use crate::encoder::Encoder;
+    use crate::error::EncodeError;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_position_known_values() {
+        let cases = [
+            // The example of the protocol documentation
+            (
+                Position::new(18357644, 831, -20882616),
+                0x4607632c15b4833f_u64,
+            ),
+            (Position::new(0, 0, 0), 0),
+            (Position::new(-1, -1, -1), u64::MAX),
+            (Position::new(1, 2, 3), 0x0000004000003002),
+            (
+                Position::new(-33554432, -2048, -33554432),
+                0x8000002000000800,
+            ),
+            (Position::new(33554431, 2047, 33554431), 0x7fffffdffffff7ff),
+        ];
+
+        for (position, packed) in cases {
+            let mut vec = Vec::new();
+            position.encode(&mut vec).unwrap();
+            assert_eq!(vec, packed.to_be_bytes(), "{position:?}");
+
+            let decoded = Position::decode(&mut Cursor::new(vec)).unwrap();
+            assert_eq!(decoded, position);
+        }
+    }
+
+    #[test]
+    fn test_position_out_of_range() {
+        let positions = [
+            Position::new(33554432, 0, 0),
+            Position::new(0, -2049, 0),
+            Position::new(0, 0, i32::MIN),
+        ];
+
+        for position in positions {
+            assert!(matches!(
+                position.encode(&mut Vec::new()),
+                Err(EncodeError::PositionOutOfRange { .. })
+            ));
+        }
+    }
+}
@@ -8,6 +8,21 @@ pub struct ServerStatus {
     pub version: ServerVersion,
     pub players: OnlinePlayers,
     pub description: Message,
+    /// Whether the server requires signed chat messages, left out of the json
+    /// when `None`
+    #[serde(
+        rename = "enforcesSecureChat",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub enforces_secure_chat: Option<bool>,
+    /// Whether chat previews are enabled, only read by 1.19 to 1.19.2 clients
+    #[serde(
+        rename = "previewsChat",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub previews_chat: Option<bool>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -31,3 +46,45 @@ pub struct OnlinePlayer {
 }
 
 impl_json_encoder_decoder!(ServerStatus);
+
+#[cfg(test)]
+mod tests {
+    use super::{OnlinePlayers, ServerStatus, ServerVersion};
+    use crate::data::chat::{Message, Payload};
+    use serde_json::json;
+
+    fn server_status() -> ServerStatus {
+        ServerStatus {
+            version: ServerVersion {
+                name: "1.20.4".into(),
+                protocol: 765,
+            },
+            players: OnlinePlayers {
+                max: 20,
+                online: 0,
+                sample: Vec::new(),
+            },
+            description: Message::new(Payload::text("A Minecraft Server")),
+            enforces_secure_chat: None,
+            previews_chat: None,
+        }
+    }
+
+    #[test]
+    fn test_optional_fields() {
+        let mut status = server_status();
+        let value = serde_json::to_value(&status).unwrap();
+        assert!(value.get("enforcesSecureChat").is_none());
+        assert!(value.get("previewsChat").is_none());
+
+        status.enforces_secure_chat = Some(true);
+        status.previews_chat = Some(false);
+        let value = serde_json::to_value(&status).unwrap();
+        assert_eq!(value["enforcesSecureChat"], json!(true));
+        assert_eq!(value["previewsChat"], json!(false));
+
+        let status: ServerStatus = serde_json::from_value(value).unwrap();
+        assert_eq!(status.enforces_secure_chat, Some(true));
+        assert_eq!(status.previews_chat, Some(false));
+    }
+}
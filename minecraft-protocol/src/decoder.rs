@@ -14,6 +14,12 @@ pub trait EnumDecoder {
     type Output;
 
     fn decode<R: Read>(type_id: u8, reader: &mut R) -> Result<Self::Output, DecodeError>;
+
+    /// Decodes a packet the enum has no variant for in the protocol version
+    /// of the session, which may share its id with one of another version.
+    fn decode_unmodeled(type_id: u8) -> Result<Self::Output, DecodeError> {
+        Err(DecodeError::UnknownPacketType { type_id })
+    }
 }
 
 impl<T: EnumDecoder> Decoder for T {
@@ -21,21 +27,28 @@ impl<T: EnumDecoder> Decoder for T {
 
     #[inline]
     fn decode<R: Read>(reader: &mut R) -> Result<Self::Output, DecodeError> {
-        let type_id = var_int::decode(reader)?
-            .try_into()
-            .map_err(|_| DecodeError::VarIntTooLong { max_bytes: 1 })?;
+        let type_id = read_type_id(reader)?;
 
         <T as EnumDecoder>::decode(type_id, reader)
     }
 }
 
+/// Reads the var int type id that starts every packet.
+pub fn read_type_id<R: Read>(reader: &mut R) -> Result<u8, DecodeError> {
+    let type_id = var_int::decode(reader)?;
+
+    u8::try_from(type_id).map_err(|_| DecodeError::UnknownEnumType {
+        type_id: type_id as u32 as usize,
+    })
+}
+
 /// Trait adds additional helper methods for `Read` to read protocol data.
 pub trait DecoderReadExt {
     fn read_bool(&mut self) -> Result<bool, DecodeError>;
 
     fn read_string(&mut self, max_length: u16) -> Result<String, DecodeError>;
 
-    fn read_byte_array(&mut self) -> Result<Vec<u8>, DecodeError>;
+    fn read_byte_array(&mut self, max_length: usize) -> Result<Vec<u8>, DecodeError>;
 
     fn read_compound_tag(&mut self) -> Result<CompoundTag, DecodeError>;
 
@@ -91,8 +104,14 @@ impl<R: Read> DecoderReadExt for R {
         Ok(String::from_utf8(buf)?)
     }
 
-    fn read_byte_array(&mut self) -> Result<Vec<u8>, DecodeError> {
-        let length = self.read_var_i32()? as usize;
+    fn read_byte_array(&mut self, max_length: usize) -> Result<Vec<u8>, DecodeError> {
+        let length = self.read_var_i32()?;
+        // Negative lengths are never valid either
+        let length = usize::try_from(length).unwrap_or(usize::MAX);
+
+        if length > max_length {
+            return Err(DecodeError::ByteArrayTooLong { length, max_length });
+        }
 
         let mut buf = vec![0; length];
         self.read_exact(&mut buf)?;
@@ -200,7 +219,7 @@ impl Decoder for Vec<u8> {
     type Output = Self;
 
     fn decode<R: Read>(reader: &mut R) -> Result<Self::Output, DecodeError> {
-        Ok(reader.read_byte_array()?)
+        Ok(reader.read_byte_array(crate::BYTE_ARRAY_MAX_LENGTH)?)
     }
 }
 
@@ -305,11 +324,47 @@ pub mod bool_option {
     }
 }
 
+/// Fields sent only when a flag read before is set.
+pub mod present_if {
+    use crate::decoder::Decoder;
+    use crate::error::DecodeError;
+    use std::io::Read;
+
+    pub fn decode<R: Read, T: Decoder<Output = T>>(
+        present: bool,
+        reader: &mut R,
+    ) -> Result<Option<T>, DecodeError> {
+        match present {
+            true => Ok(Some(T::decode(reader)?)),
+            false => Ok(None),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::decoder::DecoderReadExt;
+    use crate::decoder::{Decoder, DecoderReadExt};
+    use crate::encoder::Encoder;
+    use crate::error::{DecodeError, EncodeError};
+    use crate::packet::status::StatusServerBoundPacket;
+    use minecraft_protocol_derive::{Decoder, Encoder};
     use std::io::Cursor;
 
+    #[derive(Encoder, Decoder, Debug, Clone, PartialEq)]
+    struct ConditionalField {
+        has_value: bool,
+        #[data_type(present_if = "has_value")]
+        value: Option<String>,
+        trailing: u8,
+    }
+
+    #[derive(Encoder, Decoder, Debug, Clone, PartialEq)]
+    struct VarLongField {
+        #[data_type(with = "var_long")]
+        value: i64,
+        trailing: u8,
+    }
+
     #[test]
     fn test_read_variable_i32_2_bytes_value() {
         let mut cursor = Cursor::new(vec![0b10101100, 0b00000010]);
@@ -325,4 +380,103 @@ mod tests {
 
         assert_eq!(value, 2147483647);
     }
+
+    #[test]
+    fn test_var_long_field_round_trip() {
+        let cases: [(i64, &[u8]); 4] = [
+            (0, &[0x00]),
+            (300, &[0xac, 0x02]),
+            (
+                i64::MAX,
+                &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f],
+            ),
+            (
+                -1,
+                &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01],
+            ),
+        ];
+
+        for (value, bytes) in cases {
+            let field = VarLongField {
+                value,
+                trailing: 42,
+            };
+
+            let mut vec = Vec::new();
+            field.encode(&mut vec).unwrap();
+            assert_eq!(&vec[..vec.len() - 1], bytes, "{value}");
+
+            let decoded = VarLongField::decode(&mut Cursor::new(vec)).unwrap();
+            assert_eq!(decoded, field);
+        }
+    }
+
+    #[test]
+    fn test_present_if_field_present() {
+        let packet = ConditionalField {
+            has_value: true,
+            value: Some(String::from("Value")),
+            trailing: 7,
+        };
+
+        let mut vec = Vec::new();
+        packet.encode(&mut vec).unwrap();
+
+        assert_eq!(vec, vec![1, 5, b'V', b'a', b'l', b'u', b'e', 7]);
+
+        let mut cursor = Cursor::new(vec);
+        let decoded = ConditionalField::decode(&mut cursor).unwrap();
+
+        assert_eq!(decoded, packet);
+    }
+
+    #[test]
+    fn test_present_if_field_follows_flag() {
+        // Left out with its flag
+        let packet = ConditionalField {
+            has_value: false,
+            value: Some(String::from("Value")),
+            trailing: 7,
+        };
+        let mut vec = Vec::new();
+        packet.encode(&mut vec).unwrap();
+        assert_eq!(vec, vec![0, 7]);
+
+        let packet = ConditionalField {
+            has_value: true,
+            value: None,
+            trailing: 7,
+        };
+        assert!(matches!(
+            packet.encode(&mut Vec::new()),
+            Err(EncodeError::MissingPresentField)
+        ));
+    }
+
+    #[test]
+    fn test_present_if_field_absent() {
+        let mut cursor = Cursor::new(vec![0, 7]);
+        let decoded = ConditionalField::decode(&mut cursor).unwrap();
+
+        assert_eq!(
+            decoded,
+            ConditionalField {
+                has_value: false,
+                value: None,
+                trailing: 7,
+            }
+        );
+    }
+
+    #[test]
+    fn test_enum_type_id_out_of_range() {
+        // 300 encoded as a var-int, a valid var-int that doesn't fit in a packet id
+        let mut cursor = Cursor::new(vec![0b10101100, 0b00000010]);
+        let result = StatusServerBoundPacket::decode(&mut cursor);
+
+        assert!(matches!(
+            result,
+            Err(DecodeError::UnknownEnumType { type_id: 300 })
+        ));
+    }
 }
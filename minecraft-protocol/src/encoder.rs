@@ -38,8 +38,11 @@ pub trait EncoderWriteExt {
 }
 
 macro_rules! write_signed_var_int (
-    ($type: ident, $name: ident) => (
-        fn $name(&mut self, mut value: $type) -> Result<(), EncodeError> {
+    ($type: ident, $unsigned: ident, $name: ident) => (
+        fn $name(&mut self, value: $type) -> Result<(), EncodeError> {
+            // Shifted as unsigned, so negative values end after the last byte
+            let mut value = value as $unsigned;
+
             loop {
                 let mut byte = (value & 0b01111111) as u8;
                 value = value >> 7;
@@ -96,8 +99,8 @@ impl<W: Write> EncoderWriteExt for W {
         Ok(())
     }
 
-    write_signed_var_int!(i32, write_var_i32);
-    write_signed_var_int!(i64, write_var_i64);
+    write_signed_var_int!(i32, u32, write_var_i32);
+    write_signed_var_int!(i64, u64, write_var_i64);
 }
 
 impl Encoder for u8 {
@@ -262,6 +265,25 @@ pub mod bool_option {
     }
 }
 
+/// Fields written only when their flag is set, which then requires a value.
+pub mod present_if {
+    use crate::encoder::Encoder;
+    use crate::error::EncodeError;
+    use std::io::Write;
+
+    pub fn encode<W: Write, T: Encoder>(
+        present: bool,
+        value: &Option<T>,
+        writer: &mut W,
+    ) -> Result<(), EncodeError> {
+        match (present, value) {
+            (true, Some(v)) => v.encode(writer),
+            (true, None) => Err(EncodeError::MissingPresentField),
+            (false, _) => Ok(()),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::encoder::EncoderWriteExt;
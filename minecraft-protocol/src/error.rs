@@ -1,3 +1,5 @@
+use crate::codec::ProtocolState;
+use crate::data::identifier::InvalidIdentifier;
 use crate::nbt::decode::TagDecodeError;
 use serde_json::error::Error as JsonError;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
@@ -19,6 +21,12 @@ pub enum EncodeError {
     IOError { io_error: IoError },
     #[error("Failed to encode json data: {json_error}")]
     JsonError { json_error: JsonError },
+    /// Position coordinates don't fit in their packed bits.
+    #[error("Position out of range: {x}, {y}, {z}")]
+    PositionOutOfRange { x: i32, y: i32, z: i32 },
+    /// A field is flagged as present but has no value.
+    #[error("Field flagged as present has no value")]
+    MissingPresentField,
 }
 
 impl From<IoError> for EncodeError {
@@ -49,6 +57,14 @@ pub enum DecodeError {
         /// Max string length.
         max_length: u16,
     },
+    /// Byte array length can't be more than provided value.
+    #[error("Byte array too long: got {length} while max length is {max_length}")]
+    ByteArrayTooLong {
+        /// Byte array length.
+        length: usize,
+        /// Max byte array length.
+        max_length: usize,
+    },
     #[error("Io error: {io_error}")]
     IOError {
         #[from]
@@ -68,6 +84,11 @@ pub enum DecodeError {
     /// Boolean are parsed from byte. Valid byte value are 0 or 1.
     #[error("The value is not a valid boolean")]
     NonBoolValue,
+    #[error("{invalid_identifier}")]
+    InvalidIdentifier {
+        #[from]
+        invalid_identifier: InvalidIdentifier,
+    },
     #[error("Invalid uuid: {uuid_parse_error}")]
     UuidParseError {
         #[from]
@@ -87,6 +108,15 @@ pub enum DecodeError {
     DataSentDuringHandshake,
     #[error("The provided packet length is invalid")]
     InvalidPacketLength,
+    #[error("Port out of range: {port}")]
+    InvalidPort { port: i32 },
+    /// More packets were sent than allowed in the current state.
+    #[error("More than {max_packets} packets were sent")]
+    TooManyPackets { max_packets: u32 },
+    /// The type id is higher than any packet of the state, as when a play
+    /// packet is sent during the login.
+    #[error("Packet with type id {type_id:#04x} can't be sent in the {state:?} state")]
+    UnexpectedPacket { state: ProtocolState, type_id: u8 },
 }
 
 impl DecodeError {
@@ -1,3 +1,5 @@
+#[cfg(feature = "tokio")]
+pub mod client;
 pub mod codec;
 pub mod data;
 pub mod decoder;
@@ -11,6 +13,10 @@ pub mod tokio;
 
 const STRING_MAX_LENGTH: u16 = 32_768;
 
+/// The max length of byte arrays without a `max_length`, the largest packet
+/// length the protocol allows.
+pub const BYTE_ARRAY_MAX_LENGTH: usize = (1 << 21) - 1;
+
 #[macro_export]
 macro_rules! impl_json_encoder_decoder (
     ($ty: ident) => (
@@ -94,6 +94,12 @@ pub fn read_compound_tag<R: Read>(reader: &mut R) -> Result<CompoundTag, TagDeco
     }
 }
 
+/// Read a tag without a name, as the network protocol sends them since 1.20.2.
+pub fn read_network_tag<R: Read>(reader: &mut R) -> Result<Tag, TagDecodeError> {
+    let tag_id = reader.read_u8()?;
+    read_tag(tag_id, None, reader)
+}
+
 fn read_tag<R: Read>(
     tag_id: u8,
     name: Option<&str>,
@@ -59,6 +59,12 @@ pub fn write_compound_tag<W: Write>(
     write_inner_compound_tag(writer, compound_tag)
 }
 
+/// Write a tag without a name, as the network protocol does since 1.20.2.
+pub fn write_network_tag<W: Write>(writer: &mut W, tag: &Tag) -> Result<(), Error> {
+    writer.write_u8(tag.type_id())?;
+    write_tag(writer, tag)
+}
+
 pub fn write_inner_compound_tag<W: Write>(
     writer: &mut W,
     compound_tag: &CompoundTag,
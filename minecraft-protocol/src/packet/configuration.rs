@@ -1,7 +1,7 @@
 use crate::{
-    data::chat::Message,
-    decoder::{Decoder, EnumDecoder},
-    encoder::{Encoder, EnumEncoder},
+    data::{chat::Message, identifier::Identifier},
+    decoder::{Decoder, DecoderReadExt, EnumDecoder},
+    encoder::{Encoder, EncoderWriteExt, EnumEncoder},
     error::{DecodeError, EncodeError},
     nbt::CompoundTag,
 };
@@ -31,6 +31,8 @@ pub enum ConfigClientBoundPaket {
     AddResourcePack(AddResourcePack),
     FeatureFlags(FeatureFlags),
     UpdateTags(UpdateTags),
+    /// Only understood by 1.20.5+ clients
+    Transfer(Transfer),
 }
 
 impl EnumEncoder for ConfigServerBoundPacket {
@@ -112,6 +114,7 @@ impl EnumEncoder for ConfigClientBoundPaket {
             ConfigClientBoundPaket::AddResourcePack(_) => 0x07,
             ConfigClientBoundPaket::FeatureFlags(_) => 0x08,
             ConfigClientBoundPaket::UpdateTags(_) => 0x09,
+            ConfigClientBoundPaket::Transfer(_) => 0x0B,
         }
     }
 
@@ -127,6 +130,7 @@ impl EnumEncoder for ConfigClientBoundPaket {
             ConfigClientBoundPaket::AddResourcePack(packet) => packet.encode(writer),
             ConfigClientBoundPaket::FeatureFlags(packet) => packet.encode(writer),
             ConfigClientBoundPaket::UpdateTags(packet) => packet.encode(writer),
+            ConfigClientBoundPaket::Transfer(packet) => packet.encode(writer),
         }
     }
 }
@@ -186,6 +190,11 @@ impl EnumDecoder for ConfigClientBoundPaket {
 
                 Ok(ConfigClientBoundPaket::UpdateTags(update_tags))
             }
+            0x0B => {
+                let transfer = Transfer::decode(reader)?;
+
+                Ok(ConfigClientBoundPaket::Transfer(transfer))
+            }
             _ => Err(DecodeError::UnknownPacketType { type_id }),
         }
     }
@@ -215,7 +224,7 @@ pub enum ChatMode {
 
 #[derive(Encoder, Decoder, Debug, Clone)]
 pub struct ServerBoundPluginMessage {
-    pub channel: String,
+    pub channel: Identifier,
     #[data_type(with = "rest")]
     pub data: Vec<u8>,
 }
@@ -251,7 +260,7 @@ pub enum ResourcePackResult {
 
 #[derive(Encoder, Decoder, Debug, Clone)]
 pub struct ClientBoundPluginMessage {
-    pub channel: String,
+    pub channel: Identifier,
     #[data_type(with = "rest")]
     pub data: Vec<u8>,
 }
@@ -311,3 +320,32 @@ pub struct UpdateTags {
     #[data_type(with = "rest")]
     pub tags: Vec<u8>,
 }
+
+/// Tells the client to connect to another server, sending a handshake with
+/// the transfer intent. Sent in the configuration and play states.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transfer {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Encoder for Transfer {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<(), EncodeError> {
+        self.host.encode(writer)?;
+        writer.write_var_i32(self.port.into())
+    }
+}
+
+impl Decoder for Transfer {
+    type Output = Self;
+
+    fn decode<R: Read>(reader: &mut R) -> Result<Self::Output, DecodeError> {
+        let host = String::decode(reader)?;
+        let port = reader.read_var_i32()?;
+
+        Ok(Transfer {
+            host,
+            port: u16::try_from(port).map_err(|_| DecodeError::InvalidPort { port })?,
+        })
+    }
+}
@@ -1,7 +1,10 @@
 use crate::{
+    data::{chat::Message, identifier::Identifier},
     decoder::{Decoder, EnumDecoder},
     encoder::{Encoder, EnumEncoder},
     error::{DecodeError, EncodeError},
+    nbt::{decode::read_network_tag, encode::write_network_tag},
+    packet::configuration::Transfer,
 };
 use minecraft_protocol_derive::{Decoder, Encoder};
 use std::io::{Read, Write};
@@ -9,18 +12,26 @@ use std::io::{Read, Write};
 #[derive(Debug, Clone)]
 pub enum GameServerBoundPacket {
     Other { type_id: u8 },
+    KeepAlive(PlayKeepAlive),
     ServerBoundPluginMessage(PlayPluginMessage),
 }
 
 #[derive(Debug, Clone)]
 pub enum GameClientBoundPacket {
-    Other { type_id: u8 },
+    Other {
+        type_id: u8,
+    },
     ClientBoundPluginMessage(PlayPluginMessage),
+    Disconnect(PlayDisconnect),
+    /// Only understood by 1.20.5+ clients, its id is the one of Update Recipes
+    /// before
+    Transfer(Transfer),
 }
 
 impl EnumEncoder for GameServerBoundPacket {
     fn get_type_id(&self) -> u8 {
         match self {
+            GameServerBoundPacket::KeepAlive(_) => 0x15,
             GameServerBoundPacket::ServerBoundPluginMessage(_) => 0x10,
             GameServerBoundPacket::Other { type_id } => *type_id,
         }
@@ -29,6 +40,7 @@ impl EnumEncoder for GameServerBoundPacket {
     fn encode<W: Write>(&self, writer: &mut W) -> Result<(), EncodeError> {
         match self {
             GameServerBoundPacket::Other { type_id: _ } => Ok(()),
+            GameServerBoundPacket::KeepAlive(packet) => packet.encode(writer),
             GameServerBoundPacket::ServerBoundPluginMessage(packet) => packet.encode(writer),
         }
     }
@@ -46,9 +58,19 @@ impl EnumDecoder for GameServerBoundPacket {
                     plugin_message,
                 ))
             }
+            0x15 => {
+                let keep_alive = PlayKeepAlive::decode(reader)?;
+
+                Ok(GameServerBoundPacket::KeepAlive(keep_alive))
+            }
             type_id => Ok(GameServerBoundPacket::Other { type_id }),
         }
     }
+
+    #[inline]
+    fn decode_unmodeled(type_id: u8) -> Result<Self::Output, DecodeError> {
+        Ok(GameServerBoundPacket::Other { type_id })
+    }
 }
 
 impl EnumEncoder for GameClientBoundPacket {
@@ -56,6 +78,8 @@ impl EnumEncoder for GameClientBoundPacket {
         match self {
             GameClientBoundPacket::Other { type_id } => *type_id,
             GameClientBoundPacket::ClientBoundPluginMessage(_) => 0x18,
+            GameClientBoundPacket::Disconnect(_) => 0x1B,
+            GameClientBoundPacket::Transfer(_) => 0x73,
         }
     }
 
@@ -63,6 +87,8 @@ impl EnumEncoder for GameClientBoundPacket {
         match self {
             GameClientBoundPacket::Other { type_id: _ } => Ok(()),
             GameClientBoundPacket::ClientBoundPluginMessage(packet) => packet.encode(writer),
+            GameClientBoundPacket::Disconnect(packet) => packet.encode(writer),
+            GameClientBoundPacket::Transfer(packet) => packet.encode(writer),
         }
     }
 }
@@ -79,14 +105,143 @@ impl EnumDecoder for GameClientBoundPacket {
                     plugin_message,
                 ))
             }
+            0x1B => {
+                let disconnect = PlayDisconnect::decode(reader)?;
+
+                Ok(GameClientBoundPacket::Disconnect(disconnect))
+            }
+            0x73 => {
+                let transfer = Transfer::decode(reader)?;
+
+                Ok(GameClientBoundPacket::Transfer(transfer))
+            }
             type_id => Ok(GameClientBoundPacket::Other { type_id }),
         }
     }
+
+    #[inline]
+    fn decode_unmodeled(type_id: u8) -> Result<Self::Output, DecodeError> {
+        Ok(GameClientBoundPacket::Other { type_id })
+    }
 }
 
 #[derive(Encoder, Decoder, Debug, Clone)]
 pub struct PlayPluginMessage {
-    pub channel: String,
+    pub channel: Identifier,
     #[data_type(with = "rest")]
     pub data: Vec<u8>,
 }
+
+/// The answer of the client to the keep alive of the server, with its id.
+#[derive(Encoder, Decoder, Debug, Clone)]
+pub struct PlayKeepAlive {
+    pub id: i64,
+}
+
+/// The reason is sent as nbt instead of json since 1.20.3.
+#[derive(Debug, Clone)]
+pub struct PlayDisconnect {
+    pub reason: Message,
+}
+
+impl Encoder for PlayDisconnect {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<(), EncodeError> {
+        write_network_tag(writer, &self.reason.to_nbt()?)?;
+
+        Ok(())
+    }
+}
+
+impl Decoder for PlayDisconnect {
+    type Output = Self;
+
+    fn decode<R: Read>(reader: &mut R) -> Result<Self::Output, DecodeError> {
+        let tag = read_network_tag(reader)?;
+
+        Ok(PlayDisconnect {
+            reason: Message::from_nbt(tag)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GameClientBoundPacket, GameServerBoundPacket, PlayDisconnect, PlayKeepAlive};
+    use crate::{
+        data::chat::Message,
+        decoder::EnumDecoder,
+        encoder::EnumEncoder,
+        error::DecodeError,
+        packet::configuration::{ConfigClientBoundPaket, Transfer},
+    };
+    use std::io::Cursor;
+
+    #[test]
+    fn test_disconnect_round_trip() {
+        let packet = GameClientBoundPacket::Disconnect(PlayDisconnect {
+            reason: Message::from_str("Server restarting"),
+        });
+
+        let mut vec = Vec::new();
+        packet.encode(&mut vec).unwrap();
+        // Compound tag type and no root name
+        assert_eq!(vec[0], 10);
+        assert_eq!(vec[1], 8);
+
+        let decoded =
+            GameClientBoundPacket::decode(packet.get_type_id(), &mut Cursor::new(vec)).unwrap();
+        match decoded {
+            GameClientBoundPacket::Disconnect(v) => {
+                assert_eq!(v.reason, Message::from_str("Server restarting"))
+            }
+            _ => panic!("Invalid packet decoded"),
+        }
+    }
+
+    #[test]
+    fn test_keep_alive_round_trip() {
+        let packet = GameServerBoundPacket::KeepAlive(PlayKeepAlive { id: -42 });
+
+        let mut vec = Vec::new();
+        packet.encode(&mut vec).unwrap();
+        assert_eq!(packet.get_type_id(), 0x15);
+        assert_eq!(vec.len(), 8);
+
+        match GameServerBoundPacket::decode(0x15, &mut Cursor::new(vec)).unwrap() {
+            GameServerBoundPacket::KeepAlive(v) => assert_eq!(v.id, -42),
+            _ => panic!("Invalid packet decoded"),
+        }
+    }
+
+    #[test]
+    fn test_transfer_round_trip() {
+        let transfer = Transfer {
+            host: "lobby.example.com".into(),
+            port: 25565,
+        };
+
+        let packet = GameClientBoundPacket::Transfer(transfer.clone());
+        let mut vec = Vec::new();
+        packet.encode(&mut vec).unwrap();
+        assert_eq!(packet.get_type_id(), 0x73);
+        // The port is a var int
+        assert_eq!(&vec[vec.len() - 3..], &[0xDD, 0xC7, 0x01]);
+
+        match GameClientBoundPacket::decode(0x73, &mut Cursor::new(&vec)).unwrap() {
+            GameClientBoundPacket::Transfer(v) => assert_eq!(v, transfer),
+            _ => panic!("Invalid packet decoded"),
+        }
+        match ConfigClientBoundPaket::decode(0x0B, &mut Cursor::new(&vec)).unwrap() {
+            ConfigClientBoundPaket::Transfer(v) => assert_eq!(v, transfer),
+            _ => panic!("Invalid packet decoded"),
+        }
+
+        // Out of the u16 range
+        let mut vec = vec[..vec.len() - 3].to_vec();
+        vec.extend([0x80, 0x80, 0x04]);
+        assert!(matches!(
+            ConfigClientBoundPaket::decode(0x0B, &mut Cursor::new(vec)),
+            Err(DecodeError::InvalidPort { port: 65536 })
+        ));
+    }
+}
@@ -52,4 +52,6 @@ pub struct Handshake {
 pub enum NextState {
     Status = 1,
     Login = 2,
+    /// A login after being transferred from another server, since 1.20.5
+    Transfer = 3,
 }
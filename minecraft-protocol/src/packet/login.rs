@@ -1,6 +1,7 @@
+use crate::data::chat::Message;
 use crate::decoder::Decoder;
-use crate::decoder::EnumDecoder;
-use crate::encoder::{Encoder, EnumEncoder};
+use crate::decoder::{var_int, DecoderReadExt, EnumDecoder};
+use crate::encoder::{Encoder, EncoderWriteExt, EnumEncoder};
 use crate::error::{DecodeError, EncodeError};
 use minecraft_protocol_derive::{Decoder, Encoder};
 use std::io::{Read, Write};
@@ -43,6 +44,49 @@ impl EnumEncoder for LoginServerBoundPacket {
     }
 }
 
+impl LoginServerBoundPacket {
+    /// Decodes a packet like `Decoder::decode`, but using the `LoginStart`
+    /// shape of `protocol_version`.
+    pub fn decode_versioned<R: Read>(
+        reader: &mut R,
+        protocol_version: i32,
+    ) -> Result<Self, DecodeError> {
+        let type_id = var_int::decode(reader)?;
+
+        match type_id {
+            0x00 => {
+                let login_start = LoginStart::decode_versioned(reader, protocol_version)?;
+
+                Ok(LoginServerBoundPacket::LoginStart(login_start))
+            }
+            type_id => {
+                let type_id = u8::try_from(type_id).map_err(|_| DecodeError::UnknownEnumType {
+                    type_id: type_id as u32 as usize,
+                })?;
+
+                <Self as EnumDecoder>::decode(type_id, reader)
+            }
+        }
+    }
+}
+
+/// Encodes a `LoginStart` packet with the shape of `protocol_version`.
+pub struct VersionedLoginStart<'a> {
+    pub login_start: &'a LoginStart,
+    pub protocol_version: i32,
+}
+
+impl EnumEncoder for VersionedLoginStart<'_> {
+    fn get_type_id(&self) -> u8 {
+        0x00
+    }
+
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<(), EncodeError> {
+        self.login_start
+            .encode_versioned(writer, self.protocol_version)
+    }
+}
+
 impl EnumDecoder for LoginServerBoundPacket {
     type Output = Self;
 
@@ -134,15 +178,90 @@ impl EnumDecoder for LoginClientBoundPacket {
     }
 }
 
+/// The `Encoder` and `Decoder` implementations use the 1.20.2+ shape, use
+/// [`LoginStart::decode_versioned`] and [`LoginStart::encode_versioned`] for
+/// older clients.
 #[derive(Encoder, Decoder, Debug, Clone)]
 pub struct LoginStart {
     pub name: String,
+    /// Nil when the client didn't send it, as it's only required since 1.20.2
     pub uuid: Uuid,
 }
 
+/// 1.19, the first version sending the player's signature data
+const SIGNATURE_DATA_VERSION: i32 = 759;
+/// 1.19.1, the first version sending the player's uuid
+const OPTIONAL_UUID_VERSION: i32 = 760;
+/// 1.19.3, which dropped the signature data
+const NO_SIGNATURE_DATA_VERSION: i32 = 761;
+/// 1.20.2, which made the uuid mandatory
+const REQUIRED_UUID_VERSION: i32 = 764;
+
+impl LoginStart {
+    /// Decodes the shape of the packet sent by clients of `protocol_version`.
+    ///
+    /// The signature data sent by 1.19 and 1.19.1 clients is discarded.
+    pub fn decode_versioned<R: Read>(
+        reader: &mut R,
+        protocol_version: i32,
+    ) -> Result<Self, DecodeError> {
+        if protocol_version >= REQUIRED_UUID_VERSION {
+            return Self::decode(reader);
+        }
+
+        let name = String::decode(reader)?;
+
+        if (SIGNATURE_DATA_VERSION..NO_SIGNATURE_DATA_VERSION).contains(&protocol_version)
+            && reader.read_bool()?
+        {
+            let _timestamp = i64::decode(reader)?;
+            let _public_key = reader.read_byte_array(512)?;
+            let _signature = reader.read_byte_array(4096)?;
+        }
+
+        let uuid = if protocol_version >= OPTIONAL_UUID_VERSION && reader.read_bool()? {
+            Uuid::decode(reader)?
+        } else {
+            Uuid::nil()
+        };
+
+        Ok(LoginStart { name, uuid })
+    }
+
+    /// Encodes the shape of the packet expected by servers of
+    /// `protocol_version`, the opposite of [`LoginStart::decode_versioned`].
+    pub fn encode_versioned<W: Write>(
+        &self,
+        writer: &mut W,
+        protocol_version: i32,
+    ) -> Result<(), EncodeError> {
+        if protocol_version >= REQUIRED_UUID_VERSION {
+            return self.encode(writer);
+        }
+
+        self.name.encode(writer)?;
+
+        if (SIGNATURE_DATA_VERSION..NO_SIGNATURE_DATA_VERSION).contains(&protocol_version) {
+            writer.write_bool(false)?;
+        }
+
+        if protocol_version >= OPTIONAL_UUID_VERSION {
+            writer.write_bool(!self.uuid.is_nil())?;
+            if !self.uuid.is_nil() {
+                self.uuid.encode(writer)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Encoder, Decoder, Debug, Clone)]
 pub struct EncryptionResponse {
+    /// Encrypted with the 1024 bit RSA key of the server, so 128 bytes long
+    #[data_type(max_length = 256)]
     pub shared_secret: Vec<u8>,
+    #[data_type(max_length = 256)]
     pub verify_token: Vec<u8>,
 }
 
@@ -155,16 +274,37 @@ pub struct LoginPluginResponse {
     pub data: Vec<u8>,
 }
 
-#[derive(Encoder, Decoder, Debug, Clone)]
+/// The reason is sent as a json string, reasons that are not valid chat
+/// components are decoded as plain text.
+#[derive(Debug, Clone)]
 pub struct LoginDisconnect {
-    pub reason: String,
+    pub reason: Message,
+}
+
+impl Encoder for LoginDisconnect {
+    fn encode<W: Write>(&self, writer: &mut W) -> Result<(), EncodeError> {
+        writer.write_string(&self.reason.to_json()?, crate::STRING_MAX_LENGTH)
+    }
+}
+
+impl Decoder for LoginDisconnect {
+    type Output = Self;
+
+    fn decode<R: Read>(reader: &mut R) -> Result<Self::Output, DecodeError> {
+        let json = reader.read_string(crate::STRING_MAX_LENGTH)?;
+        let reason = Message::from_json(&json).unwrap_or(Message::Plain(json));
+
+        Ok(LoginDisconnect { reason })
+    }
 }
 
 #[derive(Encoder, Decoder, Debug, Clone)]
 pub struct EncryptionRequest {
     #[data_type(max_length = 20)]
     pub server_id: String,
+    #[data_type(max_length = 512)]
     pub public_key: Vec<u8>,
+    #[data_type(max_length = 256)]
     pub verify_token: Vec<u8>,
 }
 
@@ -192,8 +332,10 @@ pub struct LoginPluginRequest {
 
 #[cfg(test)]
 mod tests {
+    use crate::data::chat::Message;
     use crate::decoder::Decoder;
     use crate::encoder::Encoder;
+    use crate::error::DecodeError;
     use crate::packet::login::*;
     use std::io::Cursor;
     use std::str::FromStr;
@@ -228,6 +370,67 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_login_start_versioned() {
+        let uuid = Uuid::from_str("c676f0db-9695-4fcd-a3cc-d75f129fde7f").unwrap();
+        let mut name = Vec::new();
+        String::from("Username").encode(&mut name).unwrap();
+
+        let shapes: [(i32, Vec<u8>, Uuid); 6] = [
+            // 1.18.2, name only
+            (758, vec![], Uuid::nil()),
+            // 1.19, signature data
+            (759, vec![0], Uuid::nil()),
+            // 1.19.2, signature data and optional uuid
+            (760, [&[0, 1][..], uuid.as_bytes()].concat(), uuid),
+            // 1.20.1, optional uuid
+            (763, [&[1][..], uuid.as_bytes()].concat(), uuid),
+            (763, vec![0], Uuid::nil()),
+            // 1.20.2, mandatory uuid
+            (764, uuid.as_bytes().to_vec(), uuid),
+        ];
+
+        for (protocol_version, rest, expected_uuid) in shapes {
+            let bytes = [name.as_slice(), &rest].concat();
+
+            let mut cursor = Cursor::new(bytes.clone());
+            let login_start = LoginStart::decode_versioned(&mut cursor, protocol_version).unwrap();
+            assert_eq!(login_start.name, "Username");
+            assert_eq!(login_start.uuid, expected_uuid);
+            assert_eq!(
+                cursor.position() as usize,
+                bytes.len(),
+                "{protocol_version}"
+            );
+
+            let mut vec = Vec::new();
+            login_start
+                .encode_versioned(&mut vec, protocol_version)
+                .unwrap();
+            assert_eq!(vec, bytes, "{protocol_version}");
+        }
+    }
+
+    #[test]
+    fn test_login_start_signature_data_is_skipped() {
+        let mut vec = vec![0x00];
+        String::from("Username").encode(&mut vec).unwrap();
+        vec.push(1);
+        1_700_000_000_000i64.encode(&mut vec).unwrap();
+        vec![1u8; 162].encode(&mut vec).unwrap();
+        vec![2u8; 512].encode(&mut vec).unwrap();
+        vec.push(0);
+
+        let mut cursor = Cursor::new(vec);
+        let packet = LoginServerBoundPacket::decode_versioned(&mut cursor, 760).unwrap();
+
+        let LoginServerBoundPacket::LoginStart(login_start) = packet else {
+            panic!("Expected a LoginStart packet");
+        };
+        assert_eq!(login_start.name, "Username");
+        assert!(login_start.uuid.is_nil());
+    }
+
     #[test]
     fn test_encryption_response_encode() {
         let encryption_response = EncryptionResponse {
@@ -244,6 +447,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_encryption_response_max_length() {
+        // A shared secret announcing 2 GiB
+        let mut cursor = Cursor::new(vec![0xff, 0xff, 0xff, 0xff, 0x07, 1, 2, 3]);
+        assert!(matches!(
+            EncryptionResponse::decode(&mut cursor),
+            Err(DecodeError::ByteArrayTooLong {
+                length: 2147483647,
+                max_length: 256
+            })
+        ));
+
+        let mut vec = Vec::new();
+        let response = EncryptionResponse {
+            shared_secret: vec![0; 257],
+            verify_token: vec![1, 2, 3, 4],
+        };
+        response.encode(&mut vec).unwrap();
+        assert!(matches!(
+            EncryptionResponse::decode(&mut Cursor::new(vec)),
+            Err(DecodeError::ByteArrayTooLong { length: 257, .. })
+        ));
+
+        // Negative lengths can't be valid either
+        let mut cursor = Cursor::new(vec![0xff, 0xff, 0xff, 0xff, 0x0f]);
+        assert!(matches!(
+            Vec::<u8>::decode(&mut cursor),
+            Err(DecodeError::ByteArrayTooLong { .. })
+        ));
+    }
+
     #[test]
     fn test_encryption_response_decode() {
         let mut cursor =
@@ -292,7 +526,7 @@ mod tests {
     #[test]
     fn test_login_disconnect_encode() {
         let login_disconnect = LoginDisconnect {
-            reason: r#"{"text":"Message"}"#.into(),
+            reason: Message::from_json(r#"{"text":"Message"}"#).unwrap(),
         };
 
         let mut vec = Vec::new();
@@ -312,10 +546,19 @@ mod tests {
 
         assert_eq!(
             login_disconnect.reason,
-            String::from(r#"{"text":"Message"}"#)
+            Message::from_json(r#"{"text":"Message"}"#).unwrap()
         );
     }
 
+    #[test]
+    fn test_login_disconnect_decode_plain() {
+        let mut vec = Vec::new();
+        String::from("Not json {").encode(&mut vec).unwrap();
+
+        let login_disconnect = LoginDisconnect::decode(&mut Cursor::new(vec)).unwrap();
+        assert_eq!(login_disconnect.reason, Message::Plain("Not json {".into()));
+    }
+
     #[test]
     fn test_encryption_request_encode() {
         let encryption_request = EncryptionRequest {
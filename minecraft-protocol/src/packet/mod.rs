@@ -1,6 +1,7 @@
 pub mod configuration;
 pub mod handshake;
 pub mod login;
+pub mod registry;
 pub mod status;
 
 pub mod game;
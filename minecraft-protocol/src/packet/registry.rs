@@ -0,0 +1,305 @@
+use crate::{
+    codec::ProtocolState,
+    decoder::{read_type_id, Decoder},
+    encoder::EnumEncoder,
+    error::{DecodeError, EncodeError},
+};
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt,
+    io::{Read, Write},
+};
+
+/// The side a packet is sent to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    ServerBound,
+    ClientBound,
+}
+
+type DecodeFn = Box<dyn Fn(&mut dyn Read) -> Result<RegisteredPacket, DecodeError> + Send + Sync>;
+
+/// Decoders of packets that the packet enums leave as `Other`, keyed by the
+/// state, direction and type id of the packet. Codecs given a registry with
+/// `set_registry` decode the packets it knows with it, before the enums.
+///
+/// ```
+/// use minecraft_protocol::{
+///     codec::ProtocolState,
+///     packet::{
+///         game::PlayKeepAlive,
+///         registry::{Direction, PacketRegistry},
+///     },
+/// };
+///
+/// let mut registry = PacketRegistry::new();
+/// registry.register::<PlayKeepAlive>(ProtocolState::Play, Direction::ServerBound, 0x15);
+///
+/// // Type id followed by the keep alive id
+/// let data = [0x15, 0, 0, 0, 0, 0, 0, 0, 42];
+/// let packet = registry
+///     .decode(ProtocolState::Play, Direction::ServerBound, &mut &data[..])
+///     .unwrap()
+///     .unwrap();
+/// assert_eq!(packet.downcast_ref::<PlayKeepAlive>().unwrap().id, 42);
+/// ```
+#[derive(Default)]
+pub struct PacketRegistry {
+    decoders: HashMap<(ProtocolState, Direction, u8), DecodeFn>,
+}
+
+impl PacketRegistry {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes the packets with `type_id` as `P`, replacing the decoder
+    /// registered before for it.
+    pub fn register<P>(
+        &mut self,
+        state: ProtocolState,
+        direction: Direction,
+        type_id: u8,
+    ) -> &mut Self
+    where
+        P: Decoder<Output = P> + Send + 'static,
+    {
+        self.register_with(state, direction, type_id, |mut reader| {
+            P::decode(&mut reader)
+        })
+    }
+
+    /// Decodes the packets with `type_id` with `decode`, which is given the
+    /// packet without its type id.
+    pub fn register_with<T, F>(
+        &mut self,
+        state: ProtocolState,
+        direction: Direction,
+        type_id: u8,
+        decode: F,
+    ) -> &mut Self
+    where
+        T: Send + 'static,
+        F: Fn(&mut dyn Read) -> Result<T, DecodeError> + Send + Sync + 'static,
+    {
+        let decode: DecodeFn = Box::new(move |reader| {
+            decode(reader).map(|packet| RegisteredPacket {
+                type_id,
+                inner: Box::new(packet),
+            })
+        });
+        self.decoders.insert((state, direction, type_id), decode);
+
+        self
+    }
+
+    #[inline]
+    pub fn contains(&self, state: ProtocolState, direction: Direction, type_id: u8) -> bool {
+        self.decoders.contains_key(&(state, direction, type_id))
+    }
+
+    /// Decodes a packet starting with its type id, as given by
+    /// [`MinecraftCodec::next_packet_with`](crate::codec::codec::MinecraftCodec::next_packet_with).
+    /// Returns `Ok(None)` when no decoder is registered for the type id, with
+    /// the rest of the packet left unread.
+    pub fn decode<R: Read>(
+        &self,
+        state: ProtocolState,
+        direction: Direction,
+        mut reader: &mut R,
+    ) -> Result<Option<RegisteredPacket>, DecodeError> {
+        let type_id = read_type_id(reader)?;
+
+        match self.decoders.get(&(state, direction, type_id)) {
+            Some(decode) => decode(&mut reader).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl fmt::Debug for PacketRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.decoders.keys()).finish()
+    }
+}
+
+/// A packet decoded by a [`PacketRegistry`], to be downcast to the type it
+/// was registered with. Like the `Other` packets, only its type id is encoded
+/// back.
+pub struct RegisteredPacket {
+    type_id: u8,
+    inner: Box<dyn Any + Send>,
+}
+
+impl RegisteredPacket {
+    #[inline]
+    pub fn type_id(&self) -> u8 {
+        self.type_id
+    }
+
+    #[inline]
+    pub fn is<P: Any>(&self) -> bool {
+        self.inner.is::<P>()
+    }
+
+    #[inline]
+    pub fn downcast_ref<P: Any>(&self) -> Option<&P> {
+        self.inner.downcast_ref()
+    }
+
+    /// Takes the packet out, or gives it back if it isn't a `P`.
+    pub fn downcast<P: Any>(self) -> Result<P, Self> {
+        match self.inner.downcast() {
+            Ok(packet) => Ok(*packet),
+            Err(inner) => Err(Self {
+                type_id: self.type_id,
+                inner,
+            }),
+        }
+    }
+}
+
+impl EnumEncoder for RegisteredPacket {
+    #[inline]
+    fn get_type_id(&self) -> u8 {
+        self.type_id
+    }
+
+    #[inline]
+    fn encode<W: Write>(&self, _writer: &mut W) -> Result<(), EncodeError> {
+        Ok(())
+    }
+}
+
+impl fmt::Debug for RegisteredPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegisteredPacket")
+            .field("type_id", &self.type_id)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Direction, PacketRegistry};
+    use crate::{
+        codec::{
+            client::{ClientPacket, ClientPacketCodec},
+            codec::MinecraftCodec,
+            ProtocolState,
+        },
+        decoder::DecoderReadExt,
+        encoder::Encoder,
+        packet::game::{GameServerBoundPacket, PlayKeepAlive},
+    };
+    use std::{io::Cursor, sync::Arc};
+
+    /// A packet unknown to the crate.
+    #[derive(Debug, PartialEq)]
+    struct ChatCommand {
+        command: String,
+    }
+
+    fn registry() -> PacketRegistry {
+        let mut registry = PacketRegistry::new();
+        registry
+            .register::<PlayKeepAlive>(ProtocolState::Play, Direction::ServerBound, 0x15)
+            .register_with(
+                ProtocolState::Play,
+                Direction::ServerBound,
+                0x04,
+                |mut reader| {
+                    Ok(ChatCommand {
+                        command: reader.read_string(256)?,
+                    })
+                },
+            );
+        registry
+    }
+
+    #[test]
+    fn test_decode_registered() {
+        let registry = registry();
+
+        let mut vec = vec![0x15];
+        PlayKeepAlive { id: 7 }.encode(&mut vec).unwrap();
+
+        let decoded = registry
+            .decode(
+                ProtocolState::Play,
+                Direction::ServerBound,
+                &mut Cursor::new(&vec),
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded.type_id(), 0x15);
+        assert!(!decoded.is::<ChatCommand>());
+        let decoded = decoded.downcast::<ChatCommand>().unwrap_err();
+        assert_eq!(decoded.downcast::<PlayKeepAlive>().unwrap().id, 7);
+
+        // Same id in another state or direction
+        for (state, direction) in [
+            (ProtocolState::Configuration, Direction::ServerBound),
+            (ProtocolState::Play, Direction::ClientBound),
+        ] {
+            let decoded = registry.decode(state, direction, &mut Cursor::new(&vec));
+            assert!(decoded.unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_decode_with_codec() {
+        let registry = registry();
+        let mut codec = MinecraftCodec::new();
+
+        // Length, type id and the command as a string
+        codec.accept(&[6, 0x04, 4, b'h', b'e', b'l', b'p']);
+
+        let decoded = codec
+            .next_packet_with(|reader| {
+                registry.decode(ProtocolState::Play, Direction::ServerBound, reader)
+            })
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            decoded.downcast_ref::<ChatCommand>().unwrap(),
+            &ChatCommand {
+                command: "help".into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_with_packet_codec() {
+        let mut codec = ClientPacketCodec::new();
+        codec.set_state(ProtocolState::Play);
+        codec.set_registry(Arc::new(registry()));
+
+        // The chat command, then a packet left to the enums
+        let packet = codec
+            .decode(&[6, 0x04, 4, b'h', b'e', b'l', b'p'])
+            .unwrap()
+            .unwrap();
+        let ClientPacket::Registered(command) = packet else {
+            panic!("{packet:?} was not decoded by the registry");
+        };
+        assert_eq!(
+            command.downcast_ref::<ChatCommand>().unwrap().command,
+            "help"
+        );
+
+        let packet = codec.decode(&[1, 0x05]).unwrap().unwrap();
+        assert!(matches!(
+            packet,
+            ClientPacket::Game(GameServerBoundPacket::Other { type_id: 0x05 })
+        ));
+
+        // Sent back with its type id only, like the packets left as `Other`
+        let mut buffer = Vec::new();
+        codec.encode(&ClientPacket::Registered(command), &mut buffer);
+        assert_eq!(buffer, [1, 0x04]);
+    }
+}
@@ -13,7 +13,8 @@ pub enum StatusServerBoundPacket {
 
 #[derive(Debug, Clone)]
 pub enum StatusClientBoundPacket {
-    StatusResponse(StatusResponse),
+    /// Boxed as the status is far larger than the ping
+    StatusResponse(Box<StatusResponse>),
     PingResponse(PingResponse),
 }
 
@@ -73,7 +74,9 @@ impl EnumDecoder for StatusClientBoundPacket {
             0x00 => {
                 let status_response = StatusResponse::decode(reader)?;
 
-                Ok(StatusClientBoundPacket::StatusResponse(status_response))
+                Ok(StatusClientBoundPacket::StatusResponse(Box::new(
+                    status_response,
+                )))
             }
             0x01 => {
                 let ping_reponse = PingResponse::decode(reader)?;
@@ -120,7 +123,7 @@ impl StatusResponse {
     pub fn new(server_status: ServerStatus) -> StatusClientBoundPacket {
         let status_response = StatusResponse { server_status };
 
-        StatusClientBoundPacket::StatusResponse(status_response)
+        StatusClientBoundPacket::StatusResponse(Box::new(status_response))
     }
 }
 
@@ -203,6 +206,8 @@ mod tests {
             version,
             description: Message::new(Payload::text("Description")),
             players,
+            enforces_secure_chat: None,
+            previews_chat: None,
         };
 
         let status_response = StatusResponse { server_status };
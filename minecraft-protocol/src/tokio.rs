@@ -1,6 +1,9 @@
-use crate::error::DecodeError;
-use std::future::Future;
-use tokio::io::{AsyncRead, AsyncReadExt};
+use crate::{
+    encoder::Encoder,
+    error::{DecodeError, EncodeError},
+};
+use std::{cell::Cell, future::Future, io::IoSlice};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 pub trait AsyncDecoder {
     type Output;
@@ -20,6 +23,7 @@ pub trait AsyncDecoderReadExt: Send {
 
     fn read_byte_array_async(
         &mut self,
+        max_length: usize,
     ) -> impl Future<Output = Result<Vec<u8>, DecodeError>> + Send;
 
     fn read_var_i32_async(&mut self) -> impl Future<Output = Result<i32, DecodeError>> + Send;
@@ -40,7 +44,7 @@ macro_rules! read_signed_var_int (
 
                 num_read += 1;
 
-                if num_read > 5 {
+                if num_read > $max_bytes {
                     return Err(DecodeError::VarIntTooLong { max_bytes: $max_bytes });
                 }
                 if read & 0b1000_0000 == 0 {
@@ -64,7 +68,7 @@ impl<R: AsyncRead + Unpin + Send> AsyncDecoderReadExt for R {
     async fn read_string_async(&mut self, max_length: u16) -> Result<String, DecodeError> {
         let length = self.read_var_i32_async().await? as usize;
 
-        if length as u16 > max_length {
+        if length > max_length as usize {
             return Err(DecodeError::StringTooLong { length, max_length });
         }
 
@@ -74,10 +78,15 @@ impl<R: AsyncRead + Unpin + Send> AsyncDecoderReadExt for R {
         Ok(String::from_utf8(buf)?)
     }
 
-    async fn read_byte_array_async(&mut self) -> Result<Vec<u8>, DecodeError> {
+    async fn read_byte_array_async(&mut self, max_length: usize) -> Result<Vec<u8>, DecodeError> {
         let length = self.read_var_i32_async().await?;
+        let length = usize::try_from(length).unwrap_or(usize::MAX);
 
-        let mut buf = vec![0; length as usize];
+        if length > max_length {
+            return Err(DecodeError::ByteArrayTooLong { length, max_length });
+        }
+
+        let mut buf = vec![0; length];
         self.read_exact(&mut buf).await?;
 
         Ok(buf)
@@ -86,3 +95,269 @@ impl<R: AsyncRead + Unpin + Send> AsyncDecoderReadExt for R {
     read_signed_var_int!(i32, read_var_i32_async, 5);
     read_signed_var_int!(i64, read_var_i64_async, 10);
 }
+
+pub trait AsyncEncoderWriteExt: Send {
+    /// Writes the packet prefixed by its length.
+    ///
+    /// The packet is encoded into a buffer reused by the calls made on the same
+    /// thread, and written together with the length using vectored IO.
+    fn write_packet_async<T: Encoder + Sync>(
+        &mut self,
+        packet: &T,
+    ) -> impl Future<Output = Result<(), EncodeError>> + Send;
+
+    fn write_bool_async(
+        &mut self,
+        value: bool,
+    ) -> impl Future<Output = Result<(), EncodeError>> + Send;
+
+    fn write_string_async(
+        &mut self,
+        value: &str,
+        max_length: u16,
+    ) -> impl Future<Output = Result<(), EncodeError>> + Send;
+
+    fn write_byte_array_async(
+        &mut self,
+        value: &[u8],
+    ) -> impl Future<Output = Result<(), EncodeError>> + Send;
+
+    fn write_var_i32_async(
+        &mut self,
+        value: i32,
+    ) -> impl Future<Output = Result<(), EncodeError>> + Send;
+
+    fn write_var_i64_async(
+        &mut self,
+        value: i64,
+    ) -> impl Future<Output = Result<(), EncodeError>> + Send;
+}
+
+/// Buffers larger than this are not kept for reuse.
+const MAX_REUSED_BUFFER: usize = 64 * 1024;
+
+thread_local! {
+    static PACKET_BUFFER: Cell<Vec<u8>> = const { Cell::new(Vec::new()) };
+}
+
+/// Encodes a signed VarInt, returning the buffer and how many of its bytes
+/// were used.
+macro_rules! encode_signed_var_int (
+    ($type: ident, $unsigned: ident, $name: ident, $max_bytes: expr) => (
+        fn $name(value: $type) -> ([u8; $max_bytes], usize) {
+            let mut buf = [0; $max_bytes];
+            let mut value = value as $unsigned;
+            let mut len = 0;
+
+            loop {
+                let byte = (value & 0b0111_1111) as u8;
+                value >>= 7;
+
+                if value == 0 {
+                    buf[len] = byte;
+                    return (buf, len + 1);
+                }
+
+                buf[len] = byte | 0b1000_0000;
+                len += 1;
+            }
+        }
+   );
+);
+
+encode_signed_var_int!(i32, u32, encode_var_i32, 5);
+encode_signed_var_int!(i64, u64, encode_var_i64, 10);
+
+async fn write_all_vectored<W: AsyncWrite + Unpin + Send>(
+    writer: &mut W,
+    mut slices: &mut [IoSlice<'_>],
+) -> Result<(), EncodeError> {
+    while !slices.is_empty() {
+        let written = writer.write_vectored(slices).await?;
+        if written == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::WriteZero).into());
+        }
+
+        IoSlice::advance_slices(&mut slices, written);
+    }
+
+    Ok(())
+}
+
+impl<W: AsyncWrite + Unpin + Send> AsyncEncoderWriteExt for W {
+    async fn write_packet_async<T: Encoder + Sync>(
+        &mut self,
+        packet: &T,
+    ) -> Result<(), EncodeError> {
+        let mut buf = PACKET_BUFFER.take();
+        buf.clear();
+
+        let result = match packet.encode(&mut buf) {
+            Ok(()) => {
+                let (length, length_len) = encode_var_i32(buf.len() as i32);
+                let mut slices = [IoSlice::new(&length[..length_len]), IoSlice::new(&buf)];
+
+                write_all_vectored(self, &mut slices).await
+            }
+            Err(error) => Err(error),
+        };
+
+        if buf.capacity() <= MAX_REUSED_BUFFER {
+            PACKET_BUFFER.set(buf);
+        }
+
+        result
+    }
+
+    async fn write_bool_async(&mut self, value: bool) -> Result<(), EncodeError> {
+        self.write_u8(value as u8).await?;
+        Ok(())
+    }
+
+    async fn write_string_async(
+        &mut self,
+        value: &str,
+        max_length: u16,
+    ) -> Result<(), EncodeError> {
+        let length = value.len();
+
+        if length > max_length as usize {
+            return Err(EncodeError::StringTooLong { length, max_length });
+        }
+
+        self.write_byte_array_async(value.as_bytes()).await
+    }
+
+    async fn write_byte_array_async(&mut self, value: &[u8]) -> Result<(), EncodeError> {
+        let (length, length_len) = encode_var_i32(value.len() as i32);
+        let mut slices = [IoSlice::new(&length[..length_len]), IoSlice::new(value)];
+
+        write_all_vectored(self, &mut slices).await
+    }
+
+    async fn write_var_i32_async(&mut self, value: i32) -> Result<(), EncodeError> {
+        let (buf, len) = encode_var_i32(value);
+        self.write_all(&buf[..len]).await?;
+        Ok(())
+    }
+
+    async fn write_var_i64_async(&mut self, value: i64) -> Result<(), EncodeError> {
+        let (buf, len) = encode_var_i64(value);
+        self.write_all(&buf[..len]).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AsyncDecoderReadExt, AsyncEncoderWriteExt};
+    use crate::{
+        encoder::{Encoder, EncoderWriteExt},
+        packet::login::{EncryptionResponse, LoginServerBoundPacket},
+    };
+    use tokio::io::duplex;
+
+    /// Values at every VarInt byte-length boundary
+    const VAR_I32_BOUNDARIES: [(i32, usize); 10] = [
+        (0, 1),
+        (127, 1),
+        (128, 2),
+        (16_383, 2),
+        (16_384, 3),
+        (2_097_151, 3),
+        (2_097_152, 4),
+        (268_435_455, 4),
+        (268_435_456, 5),
+        (i32::MAX, 5),
+    ];
+
+    #[tokio::test]
+    async fn test_var_i32_round_trip() {
+        let (mut writer, mut reader) = duplex(4096);
+
+        for (value, length) in VAR_I32_BOUNDARIES {
+            let mut expected = Vec::new();
+            expected.write_var_i32(value).unwrap();
+            assert_eq!(expected.len(), length);
+
+            writer.write_var_i32_async(value).await.unwrap();
+
+            let mut written = vec![0; length];
+            tokio::io::AsyncReadExt::read_exact(&mut reader, &mut written)
+                .await
+                .unwrap();
+            assert_eq!(written, expected, "{value}");
+        }
+
+        for value in VAR_I32_BOUNDARIES
+            .map(|(v, _)| v)
+            .into_iter()
+            .chain([-1, i32::MIN])
+        {
+            writer.write_var_i32_async(value).await.unwrap();
+            assert_eq!(reader.read_var_i32_async().await.unwrap(), value);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_var_i64_round_trip() {
+        let (mut writer, mut reader) = duplex(4096);
+
+        let values = [
+            0,
+            127,
+            128,
+            1 << 35,
+            1 << 56,
+            1 << 62,
+            i64::MAX,
+            -1,
+            i64::MIN,
+        ];
+        for value in values {
+            writer.write_var_i64_async(value).await.unwrap();
+            assert_eq!(reader.read_var_i64_async().await.unwrap(), value);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_string_round_trip() {
+        let (mut writer, mut reader) = duplex(1 << 16);
+
+        // Lengths of 1, 2 and 3 VarInt bytes
+        for length in [0, 127, 128, 16_383, 16_384] {
+            let value = "a".repeat(length);
+            writer.write_string_async(&value, u16::MAX).await.unwrap();
+            assert_eq!(reader.read_string_async(u16::MAX).await.unwrap(), value);
+        }
+
+        assert!(writer.write_string_async("too long", 4).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_packet_round_trip() {
+        let (mut writer, mut reader) = duplex(1 << 16);
+
+        // Packets whose length needs 1, 2 and 3 VarInt bytes
+        for length in [10, 127, 300, 16_384] {
+            let packet = LoginServerBoundPacket::EncryptionResponse(EncryptionResponse {
+                shared_secret: vec![7; length],
+                verify_token: vec![1, 2, 3, 4],
+            });
+
+            let mut encoded = Vec::new();
+            packet.encode(&mut encoded).unwrap();
+
+            writer.write_packet_async(&packet).await.unwrap();
+
+            let packet_length = reader.read_var_i32_async().await.unwrap();
+            assert_eq!(packet_length as usize, encoded.len());
+
+            let mut payload = vec![0; encoded.len()];
+            tokio::io::AsyncReadExt::read_exact(&mut reader, &mut payload)
+                .await
+                .unwrap();
+            assert_eq!(payload, encoded);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use minecraft_protocol::data::chat::Message;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+
+const ACTION_CHANNEL_SIZE: usize = 4;
+
+/// How long a transfer waits for the player to log in again.
+const TRANSFER_TTL: Duration = Duration::from_secs(60);
+
+/// How long a kick waits for a player that is logging in to be relayed.
+const KICK_TTL: Duration = Duration::from_secs(30);
+
+/// Asks the connection relaying a player to act on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlayerAction {
+    /// Disconnects the player so that it logs in again, through the backend
+    /// recorded with [`PlayerActions::transfer`]
+    Transfer,
+    /// Disconnects the player with the reason
+    Disconnect(Box<Message>),
+    /// Drops, or stops dropping, the packets of the player other than keep
+    /// alives
+    Freeze(bool),
+}
+
+/// Lets the commands act on the players being relayed, which are registered
+/// by username, case insensitively.
+#[derive(Default)]
+pub struct PlayerActions {
+    senders: Mutex<HashMap<String, mpsc::Sender<PlayerAction>>>,
+    /// The backend the next login of each player is sent to
+    transfers: Mutex<HashMap<String, (Instant, String)>>,
+    /// The reasons of the kicks of players that were logging in, sent once
+    /// they are registered
+    kicks: Mutex<HashMap<String, (Instant, Message)>>,
+}
+
+impl PlayerActions {
+    /// Returns the receiver of the actions on the player, until unregistered.
+    pub fn register(&self, username: &str) -> mpsc::Receiver<PlayerAction> {
+        let key = username.to_lowercase();
+        let (sender, receiver) = mpsc::channel(ACTION_CHANNEL_SIZE);
+
+        // Locked until the sender is registered, see `kick`
+        let mut senders = lock(&self.senders);
+        let kick = lock(&self.kicks)
+            .remove(&key)
+            .filter(|(at, _)| at.elapsed() < KICK_TTL);
+        if let Some((_, reason)) = kick {
+            let _ = sender.try_send(PlayerAction::Disconnect(Box::new(reason)));
+        }
+        senders.insert(key, sender);
+
+        receiver
+    }
+
+    pub fn unregister(&self, username: &str) {
+        lock(&self.senders).remove(&username.to_lowercase());
+    }
+
+    /// Returns `false` if the player is not being relayed.
+    pub fn send(&self, username: &str, action: PlayerAction) -> bool {
+        lock(&self.senders)
+            .get(&username.to_lowercase())
+            .is_some_and(|sender| sender.try_send(action).is_ok())
+    }
+
+    /// Disconnects the player so that it logs in again through `backend`.
+    /// Returns `false` if the player is not being relayed.
+    pub fn transfer(&self, username: &str, backend: String) -> bool {
+        let key = username.to_lowercase();
+
+        let mut transfers = lock(&self.transfers);
+        transfers.retain(|_, (at, _)| at.elapsed() < TRANSFER_TTL);
+        transfers.insert(key.clone(), (Instant::now(), backend));
+        drop(transfers);
+
+        if self.send(username, PlayerAction::Transfer) {
+            return true;
+        }
+        lock(&self.transfers).remove(&key);
+        false
+    }
+
+    /// Disconnects the player with `reason`. Returns `false` if the player is
+    /// not being relayed, in which case it's disconnected as soon as it is if
+    /// it was logging in, so that kicks can't be dodged by reconnecting.
+    pub fn kick(&self, username: &str, reason: Message) -> bool {
+        let key = username.to_lowercase();
+
+        let senders = lock(&self.senders);
+        if let Some(sender) = senders.get(&key) {
+            return sender
+                .try_send(PlayerAction::Disconnect(Box::new(reason)))
+                .is_ok();
+        }
+
+        let mut kicks = lock(&self.kicks);
+        kicks.retain(|_, (at, _)| at.elapsed() < KICK_TTL);
+        kicks.insert(key, (Instant::now(), reason));
+        false
+    }
+
+    /// The backend the player was transferred to, if it's logging in again
+    /// after a transfer.
+    pub fn take_transfer(&self, username: &str) -> Option<String> {
+        lock(&self.transfers)
+            .remove(&username.to_lowercase())
+            .filter(|(at, _)| at.elapsed() < TRANSFER_TTL)
+            .map(|(_, backend)| backend)
+    }
+}
+
+#[inline]
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PlayerAction, PlayerActions};
+    use minecraft_protocol::data::chat::Message;
+
+    #[test]
+    fn test_transfer() {
+        let actions = PlayerActions::default();
+        assert!(!actions.transfer("Notch", "lobby:25565".into()));
+        assert_eq!(actions.take_transfer("Notch"), None);
+
+        let mut receiver = actions.register("Notch");
+        assert!(actions.transfer("notch", "lobby:25565".into()));
+        assert_eq!(receiver.try_recv().unwrap(), PlayerAction::Transfer);
+
+        actions.unregister("Notch");
+        assert_eq!(actions.take_transfer("NOTCH").unwrap(), "lobby:25565");
+        assert_eq!(actions.take_transfer("Notch"), None);
+    }
+
+    #[test]
+    fn test_kick() {
+        let actions = PlayerActions::default();
+        let reason = Message::from_str("Banned");
+
+        let mut receiver = actions.register("Notch");
+        assert!(actions.kick("notch", reason.clone()));
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            PlayerAction::Disconnect(Box::new(reason.clone()))
+        );
+        actions.unregister("Notch");
+
+        // Kicked once relayed
+        assert!(!actions.kick("Notch", reason.clone()));
+        let mut receiver = actions.register("Notch");
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            PlayerAction::Disconnect(Box::new(reason))
+        );
+        actions.unregister("Notch");
+
+        let mut receiver = actions.register("Notch");
+        assert!(receiver.try_recv().is_err());
+    }
+}
@@ -0,0 +1,384 @@
+use super::Backend;
+use chrono::{DateTime, Utc};
+use minecraft_protocol::client;
+use std::{collections::HashMap, sync::RwLock, time::Duration};
+use tokio::time::MissedTickBehavior;
+
+#[derive(Debug, Clone, Default)]
+pub struct BackendHealth {
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    pub last_check: Option<DateTime<Utc>>,
+    pub latency: Option<Duration>,
+    pub last_error: Option<String>,
+    /// Player connections that failed in a row
+    pub connect_failures: u32,
+    /// Until when new players skip the backend, see [`CircuitBreaker`]
+    pub tripped_until: Option<DateTime<Utc>>,
+    /// Whether a player connection is probing the backend since the cooldown
+    /// ended
+    probing: bool,
+}
+
+impl BackendHealth {
+    fn is_tripped(&self, now: DateTime<Utc>) -> bool {
+        self.tripped_until.is_some_and(|until| until > now)
+    }
+}
+
+/// Stops sending players to a backend for `cooldown` once `threshold` player
+/// connections to it failed in a row, so that a recovering backend isn't kept
+/// down by every client retrying at once.
+///
+/// Once the cooldown ends a single connection is let through to probe the
+/// backend, and a failure trips the breaker again right away. Successful
+/// connections and health checks close it.
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitBreaker {
+    pub threshold: u32,
+    pub cooldown: Duration,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            threshold: 5,
+            cooldown: Duration::from_secs(10),
+        }
+    }
+}
+
+/// The health of every backend, keyed by address. Backends are assumed to be
+/// healthy until proven otherwise.
+#[derive(Default)]
+pub struct BackendHealthMap {
+    inner: RwLock<HashMap<String, BackendHealth>>,
+    breaker: CircuitBreaker,
+}
+
+impl BackendHealthMap {
+    pub fn new<'a>(addresses: impl IntoIterator<Item = &'a str>) -> Self {
+        let map = addresses
+            .into_iter()
+            .map(|address| {
+                let health = BackendHealth {
+                    healthy: true,
+                    ..Default::default()
+                };
+                (address.to_owned(), health)
+            })
+            .collect();
+
+        Self {
+            inner: RwLock::new(map),
+            breaker: CircuitBreaker::default(),
+        }
+    }
+
+    pub fn with_circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = breaker;
+        self
+    }
+
+    /// Whether the backend is configured.
+    pub fn contains(&self, address: &str) -> bool {
+        self.read().contains_key(address)
+    }
+
+    /// Whether the health checks pass and the circuit breaker isn't tripped.
+    pub fn is_healthy(&self, address: &str) -> bool {
+        let now = Utc::now();
+        self.read()
+            .get(address)
+            .is_none_or(|v| v.healthy && !v.is_tripped(now))
+    }
+
+    /// Whether the circuit breaker is tripped and its cooldown isn't over.
+    pub fn is_tripped(&self, address: &str) -> bool {
+        let now = Utc::now();
+        self.read().get(address).is_some_and(|v| v.is_tripped(now))
+    }
+
+    /// Whether a player connection can be attempted, claiming the probe when
+    /// the cooldown of the circuit breaker is over. Only one connection probes
+    /// the backend at a time, until its outcome is recorded.
+    pub(super) fn try_connect(&self, address: &str) -> bool {
+        let now = Utc::now();
+        let mut lock = self.write();
+        let Some(health) = lock.get_mut(address) else {
+            return true;
+        };
+
+        match health.tripped_until {
+            None => true,
+            Some(until) if until > now => false,
+            Some(_) => !std::mem::replace(&mut health.probing, true),
+        }
+    }
+
+    pub fn get_all(&self) -> HashMap<String, BackendHealth> {
+        self.read().clone()
+    }
+
+    pub(super) fn record_success(&self, address: &str, latency: Duration) {
+        let mut lock = self.write();
+        let health = lock.entry(address.to_owned()).or_default();
+
+        if !health.healthy {
+            tracing::info!(address, "Backend is healthy again");
+        }
+
+        if health.tripped_until.is_some() {
+            tracing::info!(
+                address,
+                "Backend answered the health check, closing circuit breaker"
+            );
+        }
+
+        health.healthy = true;
+        health.consecutive_failures = 0;
+        health.last_check = Some(Utc::now());
+        health.latency = Some(latency);
+        health.last_error = None;
+        health.connect_failures = 0;
+        health.tripped_until = None;
+        health.probing = false;
+    }
+
+    pub(super) fn record_failure(&self, address: &str, error: String, threshold: u32) {
+        let mut lock = self.write();
+        let health = lock
+            .entry(address.to_owned())
+            .or_insert_with(|| BackendHealth {
+                healthy: true,
+                ..Default::default()
+            });
+
+        health.consecutive_failures += 1;
+        health.last_check = Some(Utc::now());
+        health.latency = None;
+
+        if health.healthy && health.consecutive_failures >= threshold {
+            tracing::warn!(address, %error, "Backend marked as unhealthy");
+            health.healthy = false;
+        }
+        health.last_error = Some(error);
+    }
+
+    /// Records a player connection to the backend, closing the circuit
+    /// breaker.
+    pub(super) fn record_connect_success(&self, address: &str) {
+        let mut lock = self.write();
+        let Some(health) = lock.get_mut(address) else {
+            return;
+        };
+
+        health.connect_failures = 0;
+        health.tripped_until = None;
+        health.probing = false;
+    }
+
+    /// Records a failed player connection to the backend, tripping the circuit
+    /// breaker once they reach the threshold.
+    pub(super) fn record_connect_failure(&self, address: &str) {
+        let now = Utc::now();
+        let mut lock = self.write();
+        let health = lock
+            .entry(address.to_owned())
+            .or_insert_with(|| BackendHealth {
+                healthy: true,
+                ..Default::default()
+            });
+
+        health.probing = false;
+        health.connect_failures = health.connect_failures.saturating_add(1);
+        if health.connect_failures < self.breaker.threshold || health.is_tripped(now) {
+            return;
+        }
+
+        tracing::warn!(
+            address,
+            failures = health.connect_failures,
+            cooldown = ?self.breaker.cooldown,
+            "Backend connections keep failing, tripping circuit breaker",
+        );
+        health.tripped_until = Some(now + self.breaker.cooldown);
+    }
+
+    #[inline]
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<String, BackendHealth>> {
+        self.inner.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    #[inline]
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<String, BackendHealth>> {
+        self.inner.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Periodically pings the backends, marking them as unhealthy after
+/// `failure_threshold` consecutive failed pings.
+pub struct HealthChecker {
+    pub interval: Duration,
+    pub timeout: Duration,
+    pub failure_threshold: u32,
+    pub protocol_version: i32,
+}
+
+impl HealthChecker {
+    pub async fn run(&self, backends: &[Backend], health: &BackendHealthMap) {
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            self.check_all(backends, health).await;
+        }
+    }
+
+    pub async fn check_all(&self, backends: &[Backend], health: &BackendHealthMap) {
+        let checks = backends
+            .iter()
+            .map(|backend| async move { (backend.address(), self.check(backend.address()).await) });
+
+        for (address, result) in futures_util::future::join_all(checks).await {
+            match result {
+                Ok(latency) => health.record_success(address, latency),
+                Err(error) => {
+                    tracing::debug!(address, %error, "Backend health check failed");
+                    health.record_failure(address, error, self.failure_threshold);
+                }
+            }
+        }
+    }
+
+    async fn check(&self, address: &str) -> Result<Duration, String> {
+        match tokio::time::timeout(self.timeout, client::ping(address, self.protocol_version)).await
+        {
+            Ok(Ok(ping)) => Ok(ping.latency),
+            Ok(Err(error)) => Err(error.to_string()),
+            Err(_) => Err("Timed out".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BackendHealthMap, CircuitBreaker, HealthChecker};
+    use crate::{
+        backend::{pool::BackendPool, Backend},
+        utils::socket::SocketOptions,
+    };
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    fn backend(address: String) -> Backend {
+        let options = SocketOptions {
+            nodelay: true,
+            keepalive: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        };
+
+        Backend::new(BackendPool::new(
+            address,
+            0,
+            Duration::from_secs(30),
+            options,
+        ))
+    }
+
+    fn checker() -> HealthChecker {
+        HealthChecker {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_millis(500),
+            failure_threshold: 2,
+            protocol_version: 765,
+        }
+    }
+
+    #[test]
+    fn test_failure_threshold() {
+        let health = BackendHealthMap::new(["a:25565"]);
+        assert!(health.is_healthy("a:25565"));
+
+        health.record_failure("a:25565", "error".into(), 2);
+        assert!(health.is_healthy("a:25565"));
+
+        health.record_failure("a:25565", "error".into(), 2);
+        assert!(!health.is_healthy("a:25565"));
+
+        health.record_success("a:25565", Duration::from_millis(5));
+        assert!(health.is_healthy("a:25565"));
+        assert_eq!(health.get_all()["a:25565"].consecutive_failures, 0);
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let health = BackendHealthMap::new(["a:25565"]).with_circuit_breaker(CircuitBreaker {
+            threshold: 2,
+            cooldown: Duration::from_secs(60),
+        });
+
+        health.record_connect_failure("a:25565");
+        assert!(health.is_healthy("a:25565"));
+        health.record_connect_success("a:25565");
+        health.record_connect_failure("a:25565");
+        assert!(health.is_healthy("a:25565"));
+
+        health.record_connect_failure("a:25565");
+        assert!(!health.is_healthy("a:25565"));
+        let all = health.get_all();
+        assert!(all["a:25565"].healthy);
+        assert!(all["a:25565"].tripped_until.is_some());
+
+        // A passing health check closes it before the cooldown ends
+        health.record_success("a:25565", Duration::from_millis(5));
+        assert!(health.is_healthy("a:25565"));
+        assert_eq!(health.get_all()["a:25565"].connect_failures, 0);
+    }
+
+    #[test]
+    fn test_circuit_breaker_cooldown() {
+        let health = BackendHealthMap::new(["a:25565"]).with_circuit_breaker(CircuitBreaker {
+            threshold: 1,
+            cooldown: Duration::ZERO,
+        });
+
+        // Let through once the cooldown ends, until the next failure
+        health.record_connect_failure("a:25565");
+        assert!(health.get_all()["a:25565"].tripped_until.is_some());
+        assert!(health.is_healthy("a:25565"));
+
+        // A single connection probes it
+        assert!(health.try_connect("a:25565"));
+        assert!(!health.try_connect("a:25565"));
+        health.record_connect_failure("a:25565");
+        assert!(health.try_connect("a:25565"));
+        health.record_connect_success("a:25565");
+        assert!(health.try_connect("a:25565"));
+        assert!(health.try_connect("a:25565"));
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_backend_becomes_unhealthy() {
+        // Bind and drop to get a port nothing listens on
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let backends = vec![backend(address.clone())];
+        let health = BackendHealthMap::new([address.as_str()]);
+        let checker = checker();
+
+        checker.check_all(&backends, &health).await;
+        assert!(health.is_healthy(&address));
+
+        checker.check_all(&backends, &health).await;
+        assert!(!health.is_healthy(&address));
+
+        let all = health.get_all();
+        assert_eq!(all[&address].consecutive_failures, 2);
+        assert!(all[&address].last_error.is_some());
+    }
+}
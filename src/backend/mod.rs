@@ -0,0 +1,60 @@
+use self::pool::BackendPool;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub mod health;
+pub mod pool;
+pub mod route;
+
+pub struct Backend {
+    pool: BackendPool,
+    connections: AtomicUsize,
+}
+
+impl Backend {
+    #[inline]
+    pub fn new(pool: BackendPool) -> Self {
+        Self {
+            pool,
+            connections: AtomicUsize::new(0),
+        }
+    }
+
+    #[inline]
+    pub fn address(&self) -> &str {
+        self.pool.address()
+    }
+
+    #[inline]
+    pub fn pool(&self) -> &BackendPool {
+        &self.pool
+    }
+
+    /// The amount of players currently proxied to this backend.
+    #[inline]
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    /// Counts a proxied player until the returned guard is dropped.
+    pub fn track_connection(&self) -> BackendConnection<'_> {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        BackendConnection { backend: self }
+    }
+}
+
+pub struct BackendConnection<'a> {
+    backend: &'a Backend,
+}
+
+impl BackendConnection<'_> {
+    #[inline]
+    pub fn address(&self) -> &str {
+        self.backend.address()
+    }
+}
+
+impl Drop for BackendConnection<'_> {
+    fn drop(&mut self) {
+        self.backend.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::utils::socket::SocketOptions;
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::{
+    net::{lookup_host, TcpStream},
+    sync::Notify,
+};
+
+/// A pool of connections to the backend that completed the TCP connect but
+/// didn't send anything yet, so that logins don't wait for a fresh connection.
+///
+/// Connections idle for longer than `idle_timeout` are closed and replaced. A
+/// pool with size `0` just opens a new connection on every [`BackendPool::get`].
+pub struct BackendPool {
+    address: String,
+    size: usize,
+    idle_timeout: Duration,
+    socket_options: SocketOptions,
+    idle: Mutex<VecDeque<(Instant, TcpStream)>>,
+    refill: Notify,
+}
+
+impl BackendPool {
+    pub fn new(
+        address: String,
+        size: usize,
+        idle_timeout: Duration,
+        socket_options: SocketOptions,
+    ) -> Self {
+        Self {
+            address,
+            size,
+            idle_timeout,
+            socket_options,
+            idle: Mutex::new(VecDeque::with_capacity(size)),
+            refill: Notify::new(),
+        }
+    }
+
+    #[inline]
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Takes an idle connection from the pool, or opens a new one if there is
+    /// none available.
+    pub async fn get(&self) -> io::Result<TcpStream> {
+        if let Some(stream) = self.take_idle() {
+            self.refill.notify_one();
+            tracing::debug!(address = self.address, "Using pooled backend connection");
+            return Ok(stream);
+        }
+
+        self.refill.notify_one();
+        self.connect().await
+    }
+
+    /// Keeps the pool filled, must be running for connections to be pooled.
+    pub async fn run(&self) {
+        if self.size == 0 {
+            return;
+        }
+
+        loop {
+            self.evict();
+
+            while self.idle_len() < self.size {
+                match self.connect().await {
+                    Ok(stream) => self.lock_idle().push_back((Instant::now(), stream)),
+                    Err(_) => break,
+                }
+            }
+
+            let _ = tokio::time::timeout(self.idle_timeout / 2, self.refill.notified()).await;
+        }
+    }
+
+    async fn connect(&self) -> io::Result<TcpStream> {
+        let host = self.resolve_dns().await.map_err(|error| {
+            tracing::error!(%error, "Failed to resolve proxied server address");
+            error
+        })?;
+
+        let stream = TcpStream::connect(host).await.map_err(|error| {
+            tracing::error!(%error, "Failed to connect to proxied server");
+            error
+        })?;
+
+        if let Err(error) = self.socket_options.apply(&stream) {
+            tracing::warn!(%error, "Failed to set proxied server socket options");
+        }
+
+        Ok(stream)
+    }
+
+    async fn resolve_dns(&self) -> io::Result<SocketAddr> {
+        lookup_host(&self.address)
+            .await?
+            .next()
+            .ok_or(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "Failed to resolve proxied server address",
+            ))
+    }
+
+    fn take_idle(&self) -> Option<TcpStream> {
+        let mut idle = self.lock_idle();
+
+        while let Some((created_at, stream)) = idle.pop_front() {
+            if created_at.elapsed() < self.idle_timeout && is_open(&stream) {
+                return Some(stream);
+            }
+        }
+
+        None
+    }
+
+    /// Drops the connections that expired or were closed by the backend.
+    fn evict(&self) {
+        let mut idle = self.lock_idle();
+        let before = idle.len();
+
+        idle.retain(|(created_at, stream)| {
+            created_at.elapsed() < self.idle_timeout && is_open(stream)
+        });
+
+        let evicted = before - idle.len();
+        if evicted > 0 {
+            tracing::debug!(evicted, "Evicted idle backend connections");
+        }
+    }
+
+    #[inline]
+    fn idle_len(&self) -> usize {
+        self.lock_idle().len()
+    }
+
+    #[inline]
+    fn lock_idle(&self) -> std::sync::MutexGuard<'_, VecDeque<(Instant, TcpStream)>> {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Whether the backend didn't close the connection nor send anything on it.
+fn is_open(stream: &TcpStream) -> bool {
+    let mut buf = [0u8; 1];
+    match stream.try_read(&mut buf) {
+        Err(error) => error.kind() == ErrorKind::WouldBlock,
+        Ok(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BackendPool;
+    use crate::utils::socket::SocketOptions;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    const OPTIONS: SocketOptions = SocketOptions {
+        nodelay: true,
+        keepalive: None,
+        send_buffer_size: None,
+        recv_buffer_size: None,
+    };
+
+    async fn listener() -> (TcpListener, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        (listener, address)
+    }
+
+    #[tokio::test]
+    async fn test_unpooled_get() {
+        let (listener, address) = listener().await;
+        let pool = BackendPool::new(address, 0, Duration::from_secs(30), OPTIONS);
+
+        let (stream, accepted) = tokio::join!(pool.get(), listener.accept());
+        assert_eq!(stream.unwrap().local_addr().unwrap(), accepted.unwrap().1);
+    }
+
+    #[tokio::test]
+    async fn test_pool_is_filled() {
+        let (listener, address) = listener().await;
+        let pool = BackendPool::new(address, 2, Duration::from_secs(30), OPTIONS);
+
+        let mut accepted = Vec::new();
+        tokio::select! {
+            _ = pool.run() => unreachable!(),
+            _ = async {
+                for _ in 0..2 {
+                    accepted.push(listener.accept().await.unwrap());
+                }
+                while pool.idle_len() < 2 {
+                    tokio::task::yield_now().await;
+                }
+            } => {}
+        }
+
+        let stream = pool.get().await.unwrap();
+        assert_eq!(pool.idle_len(), 1);
+        assert!(accepted
+            .iter()
+            .any(|(_, addr)| *addr == stream.local_addr().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_closed_connections_are_evicted() {
+        let (listener, address) = listener().await;
+        let pool = BackendPool::new(address, 1, Duration::from_secs(30), OPTIONS);
+
+        let accepted = tokio::select! {
+            _ = pool.run() => unreachable!(),
+            v = async {
+                let accepted = listener.accept().await.unwrap();
+                while pool.idle_len() < 1 {
+                    tokio::task::yield_now().await;
+                }
+                accepted
+            } => v,
+        };
+
+        // The backend closes the idle connection
+        drop(accepted);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert!(pool.take_idle().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_idle_connections_expire() {
+        let (listener, address) = listener().await;
+        let pool = BackendPool::new(address, 1, Duration::from_millis(100), OPTIONS);
+
+        let _accepted = tokio::select! {
+            _ = pool.run() => unreachable!(),
+            v = async {
+                let accepted = listener.accept().await.unwrap();
+                while pool.idle_len() < 1 {
+                    tokio::task::yield_now().await;
+                }
+                accepted
+            } => v,
+        };
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        pool.evict();
+        assert_eq!(pool.idle_len(), 0);
+    }
+}
@@ -0,0 +1,544 @@
+use super::{health::BackendHealthMap, Backend, BackendConnection};
+use crate::handler::handshake::normalize_host;
+use minecraft_protocol::data::chat::Message;
+use serde::Deserialize;
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
+    ops::RangeInclusive,
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use tokio::net::TcpStream;
+
+/// How a route picks one of its backends.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BalanceStrategy {
+    /// The first healthy backend, in the configured order
+    #[default]
+    First,
+    RoundRobin,
+    /// The healthy backend with the least proxied players
+    LeastConnections,
+    /// Always the same backend for the same username, unless it's unhealthy
+    Sticky,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid balance strategy `{0}`")]
+pub struct ParseBalanceStrategyError(String);
+
+impl FromStr for BalanceStrategy {
+    type Err = ParseBalanceStrategyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(BalanceStrategy::First),
+            "round_robin" => Ok(BalanceStrategy::RoundRobin),
+            "least_connections" => Ok(BalanceStrategy::LeastConnections),
+            "sticky" => Ok(BalanceStrategy::Sticky),
+            _ => Err(ParseBalanceStrategyError(s.into())),
+        }
+    }
+}
+
+/// The protocol version of 1.20.4, accepted when nothing else is configured.
+pub const DEFAULT_PROTOCOL_VERSION: i32 = 765;
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid protocol version range `{0}`, expected `<version>` or `<min>-<max>`")]
+pub struct ParseProtocolVersionsError(String);
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum VersionSpec {
+    Version(i32),
+    Range(String),
+}
+
+/// Inclusive ranges of protocol versions, given either as numbers or as
+/// `<min>-<max>` strings.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "Vec<VersionSpec>")]
+pub struct ProtocolVersions(Vec<RangeInclusive<i32>>);
+
+impl ProtocolVersions {
+    #[inline]
+    pub fn contains(&self, version: i32) -> bool {
+        self.0.iter().any(|v| v.contains(&version))
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn parse_range(s: &str) -> Result<RangeInclusive<i32>, ParseProtocolVersionsError> {
+        let error = || ParseProtocolVersionsError(s.into());
+
+        let (min, max) = match s.split_once('-') {
+            Some((min, max)) => (min.trim(), max.trim()),
+            None => (s.trim(), s.trim()),
+        };
+        let min: i32 = min.parse().map_err(|_| error())?;
+        let max: i32 = max.parse().map_err(|_| error())?;
+
+        if min > max {
+            return Err(error());
+        }
+        Ok(min..=max)
+    }
+}
+
+impl Default for ProtocolVersions {
+    fn default() -> Self {
+        Self(vec![DEFAULT_PROTOCOL_VERSION..=DEFAULT_PROTOCOL_VERSION])
+    }
+}
+
+impl TryFrom<Vec<VersionSpec>> for ProtocolVersions {
+    type Error = ParseProtocolVersionsError;
+
+    fn try_from(value: Vec<VersionSpec>) -> Result<Self, Self::Error> {
+        value
+            .into_iter()
+            .map(|v| match v {
+                VersionSpec::Version(v) => Ok(v..=v),
+                VersionSpec::Range(v) => Self::parse_range(&v),
+            })
+            .collect::<Result<_, _>>()
+            .map(ProtocolVersions)
+    }
+}
+
+/// Parses a comma separated list, as used by environment variables.
+impl FromStr for ProtocolVersions {
+    type Err = ParseProtocolVersionsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .filter(|v| !v.trim().is_empty())
+            .map(Self::parse_range)
+            .collect::<Result<_, _>>()
+            .map(ProtocolVersions)
+    }
+}
+
+pub struct Route {
+    /// Hostnames matched against the handshake, matches any if empty
+    hosts: Vec<String>,
+    /// Indexes of the backends in the [`Router`]
+    backends: Vec<usize>,
+    strategy: BalanceStrategy,
+    versions: ProtocolVersions,
+    /// Sent to players with other versions, the global message if unset
+    version_rejected_message: Option<Message>,
+    next: AtomicUsize,
+}
+
+impl Route {
+    pub fn new(hosts: Vec<String>, backends: Vec<usize>, strategy: BalanceStrategy) -> Self {
+        Self {
+            hosts: hosts.into_iter().map(|v| normalize_host(&v)).collect(),
+            backends,
+            strategy,
+            versions: ProtocolVersions::default(),
+            version_rejected_message: None,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Restricts the protocol versions players can log in with.
+    pub fn with_versions(
+        mut self,
+        versions: ProtocolVersions,
+        rejected_message: Option<Message>,
+    ) -> Self {
+        self.versions = versions;
+        self.version_rejected_message = rejected_message;
+        self
+    }
+
+    #[inline]
+    pub fn accepts_version(&self, protocol_version: i32) -> bool {
+        self.versions.contains(protocol_version)
+    }
+
+    #[inline]
+    pub fn version_rejected_message(&self) -> Option<&Message> {
+        self.version_rejected_message.as_ref()
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        self.hosts.iter().any(|v| v == host)
+    }
+
+    /// Orders the backends by preference according to the balance strategy,
+    /// skipping the unhealthy ones unless none of them are healthy. The ones
+    /// with a tripped circuit breaker are always skipped.
+    fn candidates<'a>(
+        &self,
+        backends: &'a [Backend],
+        health: &BackendHealthMap,
+        username: &str,
+    ) -> Vec<&'a Backend> {
+        let all = self.backends.iter().map(|&i| &backends[i]);
+
+        let mut candidates: Vec<_> = all
+            .clone()
+            .filter(|backend| health.is_healthy(backend.address()))
+            .collect();
+
+        if candidates.is_empty() {
+            tracing::warn!("No healthy backend available, trying the ones that aren't tripped");
+            candidates = all
+                .filter(|backend| !health.is_tripped(backend.address()))
+                .collect();
+        }
+
+        match self.strategy {
+            BalanceStrategy::First => {}
+            BalanceStrategy::RoundRobin => {
+                if !candidates.is_empty() {
+                    let start = self.next.fetch_add(1, Ordering::Relaxed) % candidates.len();
+                    candidates.rotate_left(start);
+                }
+            }
+            BalanceStrategy::LeastConnections => {
+                // Stable, so ties keep the configured order
+                candidates.sort_by_key(|backend| backend.connections());
+            }
+            BalanceStrategy::Sticky => {
+                // Rendezvous hashing, so that a backend going down only moves
+                // its own players to other backends
+                candidates.sort_by_cached_key(|backend| {
+                    let mut hasher = DefaultHasher::new();
+                    username.hash(&mut hasher);
+                    backend.address().hash(&mut hasher);
+                    std::cmp::Reverse(hasher.finish())
+                });
+            }
+        }
+
+        candidates
+    }
+}
+
+/// How long to wait before trying the next backend of a route after a
+/// connection failed. The delay doubles on every attempt up to `max`, and half
+/// of it is random so that clients retrying together don't reach the next
+/// backend at the same time.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryBackoff {
+    pub base: Duration,
+    pub max: Duration,
+}
+
+impl Default for RetryBackoff {
+    fn default() -> Self {
+        Self {
+            base: Duration::from_millis(100),
+            max: Duration::from_secs(2),
+        }
+    }
+}
+
+impl RetryBackoff {
+    /// The delay after the `attempt`th failed connection, starting at `0`.
+    fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max);
+
+        let half = delay / 2;
+        half + half.mul_f64(rand::random())
+    }
+}
+
+/// Maps the hostname clients connect with to the backends serving it.
+pub struct Router {
+    backends: Vec<Backend>,
+    routes: Vec<Route>,
+    default: Route,
+    backoff: RetryBackoff,
+}
+
+impl Router {
+    /// `default` is used when no route matches the hostname.
+    pub fn new(backends: Vec<Backend>, routes: Vec<Route>, default: Route) -> Self {
+        Self {
+            backends,
+            routes,
+            default,
+            backoff: RetryBackoff::default(),
+        }
+    }
+
+    pub fn with_retry_backoff(mut self, backoff: RetryBackoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    #[inline]
+    pub fn backends(&self) -> &[Backend] {
+        &self.backends
+    }
+
+    #[inline]
+    pub fn backend(&self, address: &str) -> Option<&Backend> {
+        self.backends.iter().find(|v| v.address() == address)
+    }
+
+    pub fn resolve(&self, host: &str) -> &Route {
+        let host = normalize_host(host);
+
+        self.routes
+            .iter()
+            .find(|route| route.matches(&host))
+            .unwrap_or(&self.default)
+    }
+
+    /// Connects to the backend preferred by the route that accepts the
+    /// connection, trying the others after a [`RetryBackoff`] if it fails.
+    /// The outcome of every attempt feeds the
+    /// [`CircuitBreaker`](super::health::CircuitBreaker) of the backend.
+    ///
+    /// Sticky routes key the backend by the `username` sent in the login
+    /// start. The proxy doesn't take part in authentication and the uuid sent
+    /// along is chosen by the client, or missing for older clients.
+    pub async fn connect(
+        &self,
+        route: &Route,
+        health: &BackendHealthMap,
+        username: &str,
+    ) -> Result<(TcpStream, BackendConnection<'_>), io::Error> {
+        let mut last_error = None;
+        let mut attempt = 0;
+
+        for backend in route.candidates(&self.backends, health, username) {
+            // Another connection is probing it
+            if !health.try_connect(backend.address()) {
+                continue;
+            }
+
+            if attempt > 0 {
+                tokio::time::sleep(self.backoff.delay(attempt - 1)).await;
+            }
+            attempt += 1;
+
+            match backend.pool().get().await {
+                Ok(stream) => {
+                    tracing::debug!(address = backend.address(), "Selected backend");
+                    health.record_connect_success(backend.address());
+                    return Ok((stream, backend.track_connection()));
+                }
+                Err(error) => {
+                    tracing::warn!(address = backend.address(), %error, "Backend connection failed");
+                    health.record_connect_failure(backend.address());
+                    last_error = Some(error);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "No backend server available")
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BalanceStrategy, ProtocolVersions, RetryBackoff, Route, Router};
+    use crate::{
+        backend::{
+            health::{BackendHealthMap, CircuitBreaker},
+            pool::BackendPool,
+            Backend,
+        },
+        utils::socket::SocketOptions,
+    };
+    use std::time::Duration;
+
+    fn router(strategy: BalanceStrategy) -> Router {
+        let options = SocketOptions {
+            nodelay: true,
+            keepalive: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        };
+
+        let backends = ["a:25565", "b:25565", "c:25565"]
+            .into_iter()
+            .map(|v| Backend::new(BackendPool::new(v.into(), 0, Duration::ZERO, options)))
+            .collect();
+
+        let lobby = Route::new(vec!["lobby.example.com".into()], vec![2], strategy);
+        Router::new(
+            backends,
+            vec![lobby],
+            Route::new(vec![], vec![0, 1], strategy),
+        )
+    }
+
+    fn picks(router: &Router, health: &BackendHealthMap, host: &str) -> String {
+        picks_for(router, health, host, "Notch")
+    }
+
+    fn picks_for(router: &Router, health: &BackendHealthMap, host: &str, username: &str) -> String {
+        let route = router.resolve(host);
+        route.candidates(router.backends(), health, username)[0]
+            .address()
+            .to_owned()
+    }
+
+    #[test]
+    fn test_resolve_hostname() {
+        let router = router(BalanceStrategy::First);
+        let health = BackendHealthMap::default();
+
+        assert_eq!(picks(&router, &health, "Lobby.Example.com."), "c:25565");
+        assert_eq!(
+            picks(&router, &health, "lobby.example.com\0FML3\0"),
+            "c:25565"
+        );
+        assert_eq!(picks(&router, &health, "other.example.com"), "a:25565");
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let router = router(BalanceStrategy::RoundRobin);
+        let health = BackendHealthMap::default();
+
+        let picked: Vec<_> = (0..4).map(|_| picks(&router, &health, "")).collect();
+        assert_eq!(picked, ["a:25565", "b:25565", "a:25565", "b:25565"]);
+    }
+
+    #[test]
+    fn test_least_connections() {
+        let router = router(BalanceStrategy::LeastConnections);
+        let health = BackendHealthMap::default();
+
+        let _first = router.backends()[0].track_connection();
+        assert_eq!(picks(&router, &health, ""), "b:25565");
+
+        let _second = router.backends()[1].track_connection();
+        let _third = router.backends()[1].track_connection();
+        assert_eq!(picks(&router, &health, ""), "a:25565");
+    }
+
+    #[test]
+    fn test_sticky() {
+        let router = router(BalanceStrategy::Sticky);
+        let health = BackendHealthMap::new(["a:25565", "b:25565"]);
+
+        let players: Vec<_> = (0..32).map(|i| format!("Player{i}")).collect();
+        let picked: Vec<_> = players
+            .iter()
+            .map(|v| picks_for(&router, &health, "", v))
+            .collect();
+
+        assert!(picked.iter().any(|v| v == "a:25565"));
+        assert!(picked.iter().any(|v| v == "b:25565"));
+        for (player, backend) in players.iter().zip(&picked) {
+            assert_eq!(&picks_for(&router, &health, "", player), backend);
+        }
+
+        for _ in 0..3 {
+            health.record_failure("a:25565", "error".into(), 3);
+        }
+        assert!(players
+            .iter()
+            .all(|v| picks_for(&router, &health, "", v) == "b:25565"));
+    }
+
+    #[test]
+    fn test_skips_unhealthy() {
+        let router = router(BalanceStrategy::RoundRobin);
+        let health = BackendHealthMap::new(["a:25565", "b:25565"]);
+        for _ in 0..3 {
+            health.record_failure("a:25565", "error".into(), 3);
+        }
+
+        assert!((0..4).all(|_| picks(&router, &health, "") == "b:25565"));
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let backoff = RetryBackoff {
+            base: Duration::from_millis(100),
+            max: Duration::from_millis(1000),
+        };
+
+        for _ in 0..16 {
+            let first = backoff.delay(0);
+            assert!((50..=100).contains(&first.as_millis()), "{first:?}");
+            let third = backoff.delay(2);
+            assert!((200..=400).contains(&third.as_millis()), "{third:?}");
+            let capped = backoff.delay(40);
+            assert!((500..=1000).contains(&capped.as_millis()), "{capped:?}");
+        }
+
+        let disabled = RetryBackoff {
+            base: Duration::ZERO,
+            max: Duration::ZERO,
+        };
+        assert_eq!(disabled.delay(3), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_failed_connections_trip_circuit_breaker() {
+        let router = router(BalanceStrategy::First).with_retry_backoff(RetryBackoff {
+            base: Duration::ZERO,
+            max: Duration::ZERO,
+        });
+        let health =
+            BackendHealthMap::new(["a:25565", "b:25565"]).with_circuit_breaker(CircuitBreaker {
+                threshold: 1,
+                cooldown: Duration::from_secs(60),
+            });
+
+        // Neither name resolves
+        let route = router.resolve("");
+        assert!(router.connect(route, &health, "Notch").await.is_err());
+        assert!(!health.is_healthy("a:25565"));
+        assert!(!health.is_healthy("b:25565"));
+
+        // Not tried again until the cooldown ends
+        assert!(route
+            .candidates(router.backends(), &health, "Notch")
+            .is_empty());
+        let result = router.connect(route, &health, "Notch").await;
+        assert!(matches!(result, Err(error) if error.kind() == std::io::ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn test_protocol_versions() {
+        let versions: ProtocolVersions = serde_json::from_str(r#"[47, "107-340", 765]"#).unwrap();
+        assert!(versions.contains(47));
+        assert!(versions.contains(210));
+        assert!(versions.contains(765));
+        assert!(!versions.contains(48));
+        assert!(!versions.contains(766));
+
+        assert_eq!(
+            "47, 107-340,765".parse::<ProtocolVersions>().unwrap(),
+            versions
+        );
+        assert!("340-107".parse::<ProtocolVersions>().is_err());
+        assert!(serde_json::from_str::<ProtocolVersions>(r#"["1.8"]"#).is_err());
+    }
+
+    #[test]
+    fn test_route_versions() {
+        let route = Route::new(vec![], vec![], BalanceStrategy::First);
+        assert!(route.accepts_version(765));
+        assert!(!route.accepts_version(47));
+
+        let route = route.with_versions("47".parse().unwrap(), None);
+        assert!(route.accepts_version(47));
+        assert!(!route.accepts_version(765));
+    }
+}
@@ -0,0 +1,54 @@
+//! The version and build metadata of the binary, printed by `--version` so
+//! that support requests tell exactly which build is running.
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Set by the build script, `unknown` when built outside of a git checkout.
+pub const GIT_COMMIT: &str = env!("MC_PROXY_GIT_COMMIT");
+
+/// The optional cargo features the binary was built with.
+pub const FEATURES: &[&str] = &[
+    #[cfg(feature = "dotenv")]
+    "dotenv",
+    #[cfg(feature = "json-log")]
+    "json-log",
+    #[cfg(feature = "otel")]
+    "otel",
+    #[cfg(feature = "geoip")]
+    "geoip",
+];
+
+pub fn version() -> String {
+    let features = match FEATURES {
+        [] => "none".into(),
+        features => features.join(", "),
+    };
+
+    format!(
+        "{} {VERSION}\ncommit: {GIT_COMMIT}\nfeatures: {features}",
+        env!("CARGO_PKG_NAME"),
+    )
+}
+
+pub const USAGE: &str = "\
+Usage: mc-proxy [OPTIONS]
+
+The proxy is configured with environment variables, or with the file set in
+CONFIG_FILE, see .env.example.
+
+Options:
+  -h, --help     Print this message
+  -V, --version  Print the version and build information";
+
+#[cfg(test)]
+mod tests {
+    use super::{version, GIT_COMMIT, VERSION};
+
+    #[test]
+    fn test_version() {
+        let version = version();
+        assert!(version.starts_with(&format!("mc-proxy {VERSION}\n")));
+        assert!(version.contains(&format!("commit: {GIT_COMMIT}\n")));
+        assert!(!GIT_COMMIT.is_empty());
+    }
+}
@@ -0,0 +1,85 @@
+use std::{collections::HashSet, sync::RwLock};
+use uuid::Uuid;
+
+/// Players let through the whitelist, so that staff can still connect during
+/// maintenance. Bans still apply to them.
+///
+/// Entries are usernames or uuids. The uuid sent by the client in the login
+/// start can be forged, so uuid entries only let a player in once the relay
+/// sees the same uuid in the backend's login success. Changes made at runtime
+/// are not persisted.
+#[derive(Debug, Default)]
+pub struct WhitelistBypass {
+    entries: RwLock<Entries>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    /// Lowercase usernames
+    usernames: HashSet<String>,
+    uuids: HashSet<Uuid>,
+}
+
+impl WhitelistBypass {
+    pub fn new(entries: impl IntoIterator<Item = String>) -> Self {
+        let bypass = Self::default();
+        for entry in entries {
+            bypass.add(&entry);
+        }
+
+        bypass
+    }
+
+    /// Adds a username or uuid, returning whether it wasn't present.
+    pub fn add(&self, entry: &str) -> bool {
+        let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+
+        match Uuid::try_parse(entry) {
+            Ok(uuid) => entries.uuids.insert(uuid),
+            Err(_) => entries.usernames.insert(entry.to_lowercase()),
+        }
+    }
+
+    /// Removes a username or uuid, returning whether it was present.
+    pub fn remove(&self, entry: &str) -> bool {
+        let mut entries = self.entries.write().unwrap_or_else(|e| e.into_inner());
+
+        match Uuid::try_parse(entry) {
+            Ok(uuid) => entries.uuids.remove(&uuid),
+            Err(_) => entries.usernames.remove(&entry.to_lowercase()),
+        }
+    }
+
+    pub fn contains(&self, username: &str, uuid: Uuid) -> bool {
+        let entries = self.entries.read().unwrap_or_else(|e| e.into_inner());
+
+        entries.usernames.contains(&username.to_lowercase())
+            || (!uuid.is_nil() && entries.uuids.contains(&uuid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WhitelistBypass;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_usernames_and_uuids() {
+        let uuid = Uuid::new_v4();
+        let bypass = WhitelistBypass::new(["Admin".to_owned(), uuid.to_string()]);
+
+        assert!(bypass.contains("admin", Uuid::new_v4()));
+        assert!(bypass.contains("Renamed", uuid));
+        assert!(!bypass.contains("Notch", Uuid::new_v4()));
+        assert!(!bypass.contains("Notch", Uuid::nil()));
+
+        assert!(bypass.add("Notch"));
+        assert!(!bypass.add("notch"));
+        assert!(bypass.contains("Notch", Uuid::nil()));
+
+        assert!(bypass.remove("NOTCH"));
+        assert!(bypass.remove(&uuid.to_string()));
+        assert!(!bypass.remove(&uuid.to_string()));
+        assert!(!bypass.contains("Renamed", uuid));
+    }
+}
@@ -0,0 +1,273 @@
+//! Out-of-band HTTP admin API, running the same commands as the plugin channel
+//! without requiring a backend plugin.
+//!
+//! Commands are sent as `POST /command`, with the json of a [`CommandRequest`]
+//! as the body and `Authorization: Bearer <token>`. The response body is the
+//! json of the [`CommandResult`](mc_proxy_protocol::CommandResult).
+
+use super::{
+    handler::{handle_command, ReceivedAt},
+    into_command_result, CommandError,
+};
+use crate::{server::Server, state::GlobalSharedState, utils::BoxDynError};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::{
+    body::{Body, Bytes},
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    server::conn::http1,
+    service::service_fn,
+    Method, Request, Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use mc_proxy_protocol::{
+    server::{CommandRequest, CommandResponse},
+    ErrorCode,
+};
+use std::{convert::Infallible, sync::Arc};
+use tokio::{net::TcpListener, task::JoinSet};
+use tracing::Instrument;
+
+pub const COMMAND_PATH: &str = "/command";
+
+/// Larger requests are rejected before being decoded.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// Serves the admin API until the server shuts down. Requests still being
+/// handled are aborted when this returns.
+pub async fn serve_admin_api(listener: TcpListener, token: String, srv: Arc<Server>) {
+    let token: Arc<str> = token.into();
+    let mut shutdown = srv.subscribe_shutdown();
+    let mut connections = JoinSet::new();
+
+    loop {
+        let (conn, address) = tokio::select! {
+            v = listener.accept() => match v {
+                Ok(v) => v,
+                Err(error) => {
+                    tracing::error!(%error, "Admin API stopped accepting connections");
+                    return;
+                }
+            },
+            _ = shutdown.wait_for(Option::is_some) => return,
+            // Reaps the finished connections
+            Some(_) = connections.join_next() => continue,
+        };
+
+        let srv = srv.clone();
+        let token = token.clone();
+        let service = service_fn(move |req| {
+            let srv = srv.clone();
+            let token = token.clone();
+            async move { Ok::<_, Infallible>(handle_request(srv.global_state(), &token, req).await) }
+        });
+
+        connections.spawn(
+            async move {
+                let result = http1::Builder::new()
+                    .serve_connection(TokioIo::new(conn), service)
+                    .await;
+
+                if let Err(error) = result {
+                    tracing::debug!(%error, "Admin API connection failed");
+                }
+            }
+            .instrument(tracing::info_span!("admin_api", %address)),
+        );
+    }
+}
+
+async fn handle_request<B>(
+    state: &GlobalSharedState,
+    token: &str,
+    req: Request<B>,
+) -> Response<Full<Bytes>>
+where
+    B: Body,
+    B::Error: Into<BoxDynError>,
+{
+    let received = ReceivedAt::now();
+    if req.uri().path() != COMMAND_PATH {
+        return empty_response(StatusCode::NOT_FOUND);
+    }
+    if req.method() != Method::POST {
+        return empty_response(StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    let authorized = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|v| constant_time_eq(v.as_bytes(), token.as_bytes()));
+    if !authorized {
+        tracing::warn!("Admin API request with a missing or invalid token");
+        return command_response(Err(CommandError::Unauthorized));
+    }
+
+    let body = match Limited::new(req.into_body(), MAX_BODY_SIZE).collect().await {
+        Ok(v) => v.to_bytes(),
+        Err(_) => return empty_response(StatusCode::PAYLOAD_TOO_LARGE),
+    };
+
+    let command = match serde_json::from_slice::<'_, CommandRequest>(&body) {
+        Ok(v) => v,
+        Err(error) => {
+            tracing::warn!(%error, "Failed to decode admin API command");
+            return command_response(Err(CommandError::CommandDecodeError(error)));
+        }
+    };
+
+    tracing::info!(?command, "Incomming admin API command");
+
+    let res = handle_command(state, command, received).await;
+
+    let took = received.instant.elapsed();
+    tracing::info!(?took, "Handled admin API command");
+
+    command_response(res)
+}
+
+fn command_response(res: Result<CommandResponse, CommandError>) -> Response<Full<Bytes>> {
+    let status = match &res {
+        Ok(_) => StatusCode::OK,
+        Err(error) => status_code(error.code()),
+    };
+
+    let body = match serde_json::to_vec(&into_command_result(res)) {
+        Ok(v) => v,
+        Err(error) => {
+            tracing::error!(%error, "Failed to encode admin API response");
+            return empty_response(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body)))
+        .unwrap_or_else(|_| empty_response(StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+fn empty_response(status: StatusCode) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::default());
+    *response.status_mut() = status;
+    response
+}
+
+fn status_code(code: ErrorCode) -> StatusCode {
+    match code {
+        ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+        ErrorCode::PermissionDenied => StatusCode::FORBIDDEN,
+        ErrorCode::DecodeFailed
+        | ErrorCode::UnsupportedVersion
+        | ErrorCode::InvalidDuration
+        | ErrorCode::InvalidMessage
+        | ErrorCode::InvalidNetwork
+        | ErrorCode::InvalidBatch
+        | ErrorCode::InvalidLogLevel
+        | ErrorCode::InvalidPattern
+        | ErrorCode::UnknownBackend => StatusCode::BAD_REQUEST,
+        ErrorCode::NotFound => StatusCode::NOT_FOUND,
+        ErrorCode::Conflict => StatusCode::CONFLICT,
+        ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+        ErrorCode::DatabaseUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+        ErrorCode::UsernameResolutionFailed => StatusCode::BAD_GATEWAY,
+        ErrorCode::EncodeFailed
+        | ErrorCode::DatabaseError
+        | ErrorCode::InvalidData
+        | ErrorCode::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{handle_request, COMMAND_PATH};
+    use crate::{repository::user_bans::UserBansRepository, state::test_global_state};
+    use http_body_util::{BodyExt, Full};
+    use hyper::{body::Bytes, Method, Request, Response, StatusCode};
+
+    const TOKEN: &str = "admin-token";
+
+    fn request(path: &str, token: Option<&str>, body: &str) -> Request<Full<Bytes>> {
+        let mut builder = Request::builder().method(Method::POST).uri(path);
+        if let Some(token) = token {
+            builder = builder.header("Authorization", format!("Bearer {token}"));
+        }
+        builder
+            .body(Full::new(Bytes::from(body.to_owned())))
+            .unwrap()
+    }
+
+    async fn body_json(response: Response<Full<Bytes>>) -> serde_json::Value {
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_runs_commands() {
+        let state = test_global_state().await;
+
+        let req = request(
+            COMMAND_PATH,
+            Some(TOKEN),
+            r#"{"type":"BAN_PLAYER","data":{"username":"Notch","reason":"Admin"}}"#,
+        );
+        let response = handle_request(&state, TOKEN, req).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_json(response).await["type"], "SUCCESS");
+
+        let ban = state.user_bans.is_banned("Notch").await.unwrap().unwrap();
+        assert_eq!(ban.reason.as_deref(), Some("Admin"));
+
+        let req = request(
+            COMMAND_PATH,
+            Some(TOKEN),
+            r#"{"type":"IS_PLAYER_BANNED","data":{"username":"Notch"}}"#,
+        );
+        let json = body_json(handle_request(&state, TOKEN, req).await).await;
+        assert_eq!(json["data"]["data"]["banned"], true);
+    }
+
+    #[tokio::test]
+    async fn test_requires_token() {
+        let state = test_global_state().await;
+        let command = r#"{"type":"UNBAN_PLAYER","data":{"username":"Notch"}}"#;
+
+        for token in [None, Some("wrong"), Some("admin-toke")] {
+            let req = request(COMMAND_PATH, token, command);
+            let response = handle_request(&state, TOKEN, req).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+            let json = body_json(response).await;
+            assert_eq!(json["data"]["code"], "UNAUTHORIZED");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_requests() {
+        let state = test_global_state().await;
+
+        let req = request(COMMAND_PATH, Some(TOKEN), r#"{"type":"NOT_A_COMMAND"}"#);
+        let response = handle_request(&state, TOKEN, req).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(body_json(response).await["data"]["code"], "DECODE_FAILED");
+
+        let req = request("/other", Some(TOKEN), "");
+        let response = handle_request(&state, TOKEN, req).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let mut req = request(COMMAND_PATH, Some(TOKEN), "");
+        *req.method_mut() = Method::GET;
+        let response = handle_request(&state, TOKEN, req).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let body = "a".repeat(super::MAX_BODY_SIZE + 1);
+        let req = request(COMMAND_PATH, Some(TOKEN), &body);
+        let response = handle_request(&state, TOKEN, req).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
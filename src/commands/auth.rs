@@ -0,0 +1,320 @@
+use super::CommandError;
+use mc_proxy_protocol::{
+    auth::{self, Permission},
+    server::CommandRequestMessage,
+};
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// How many authentication failures are logged per [`FAILURE_LOG_WINDOW`].
+const FAILURE_LOG_LIMIT: u32 = 5;
+const FAILURE_LOG_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+struct RawCommand<'a> {
+    #[serde(borrow)]
+    command: &'a RawValue,
+}
+
+struct FailureLog {
+    window_start: Instant,
+    count: u32,
+}
+
+/// The secrets a backend signs its commands with and what it may run.
+pub struct CommandCredentials {
+    secret: Option<String>,
+    previous_secret: Option<(String, Instant)>,
+    permission: Permission,
+}
+
+impl CommandCredentials {
+    /// The `previous_secret` keeps being accepted for `grace_period` so that
+    /// secrets can be rotated without restarting the backends at the same time.
+    pub fn new(
+        secret: Option<String>,
+        previous_secret: Option<String>,
+        grace_period: Duration,
+        permission: Permission,
+    ) -> Self {
+        Self {
+            secret,
+            previous_secret: previous_secret.map(|v| (v, Instant::now() + grace_period)),
+            permission,
+        }
+    }
+
+    fn check_signature(
+        &self,
+        req: &CommandRequestMessage,
+        command_data: &[u8],
+    ) -> Result<(), CommandError> {
+        let Some(secret) = &self.secret else {
+            return Ok(());
+        };
+
+        let signature = req.hmac.as_deref().ok_or(CommandError::Unauthorized)?;
+        let raw = serde_json::from_slice::<'_, RawCommand>(command_data)
+            .map_err(CommandError::CommandDecodeError)?;
+        let command = raw.command.get();
+
+        if auth::verify(secret.as_bytes(), &req.id, command, signature) {
+            return Ok(());
+        }
+
+        match &self.previous_secret {
+            Some((previous, until))
+                if Instant::now() < *until
+                    && auth::verify(previous.as_bytes(), &req.id, command, signature) =>
+            {
+                tracing::debug!(id = %req.id, "Command signed with the previous secret");
+                Ok(())
+            }
+            _ => Err(CommandError::Unauthorized),
+        }
+    }
+}
+
+/// Authenticates the commands with the credentials of the backend they were
+/// received from, or with the default ones if the backend has none.
+pub struct CommandAuth {
+    default: CommandCredentials,
+    backends: HashMap<String, CommandCredentials>,
+    failures: Mutex<FailureLog>,
+}
+
+impl CommandAuth {
+    pub fn new(default: CommandCredentials) -> Self {
+        Self {
+            default,
+            backends: HashMap::new(),
+            failures: Mutex::new(FailureLog {
+                window_start: Instant::now(),
+                count: 0,
+            }),
+        }
+    }
+
+    /// Sets the credentials of the backend at `address`.
+    pub fn with_backend(mut self, address: String, credentials: CommandCredentials) -> Self {
+        self.backends.insert(address, credentials);
+        self
+    }
+
+    /// Whether the commands of any backend are authenticated.
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.default.secret.is_some() || self.backends.values().any(|v| v.secret.is_some())
+    }
+
+    /// Whether the commands of the backends without their own credentials are
+    /// authenticated.
+    #[inline]
+    pub fn is_default_enabled(&self) -> bool {
+        self.default.secret.is_some()
+    }
+
+    /// Checks the request signature and whether the backend is allowed to run
+    /// the command. `command_data` must be the raw message `req` was decoded
+    /// from, and `backend` the address of the backend that sent it.
+    pub fn authorize(
+        &self,
+        backend: &str,
+        req: &CommandRequestMessage,
+        command_data: &[u8],
+    ) -> Result<(), CommandError> {
+        let credentials = self.backends.get(backend).unwrap_or(&self.default);
+
+        if let Err(error) = credentials.check_signature(req, command_data) {
+            self.log_failure(backend, req, &error);
+            return Err(error);
+        }
+
+        let required = req.command.permission();
+        if required > credentials.permission {
+            self.log_failure(backend, req, &CommandError::PermissionDenied(required));
+            return Err(CommandError::PermissionDenied(required));
+        }
+
+        Ok(())
+    }
+
+    fn log_failure(&self, backend: &str, req: &CommandRequestMessage, error: &CommandError) {
+        let mut failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+
+        let now = Instant::now();
+        if now - failures.window_start >= FAILURE_LOG_WINDOW {
+            if failures.count > FAILURE_LOG_LIMIT {
+                tracing::warn!(
+                    suppressed = failures.count - FAILURE_LOG_LIMIT,
+                    "Command authentication failures were suppressed",
+                );
+            }
+            failures.window_start = now;
+            failures.count = 0;
+        }
+
+        failures.count += 1;
+        if failures.count <= FAILURE_LOG_LIMIT {
+            tracing::warn!(id = %req.id, backend, %error, "Command authentication failed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CommandAuth, CommandCredentials};
+    use crate::commands::CommandError;
+    use mc_proxy_protocol::{
+        auth::{sign, Permission},
+        id::RequestId,
+        server::CommandRequestMessage,
+    };
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    fn message(secret: Option<&str>, command: &str) -> (CommandRequestMessage, Vec<u8>) {
+        let id = RequestId::from(Uuid::new_v4());
+        let hmac = secret
+            .map(|secret| format!(r#","hmac":"{}""#, sign(secret.as_bytes(), &id, command)))
+            .unwrap_or_default();
+
+        let data = format!(r#"{{"id":"{id}"{hmac},"command":{command}}}"#).into_bytes();
+        (serde_json::from_slice(&data).unwrap(), data)
+    }
+
+    const BACKEND: &str = "127.0.0.1:25566";
+    const READ_COMMAND: &str = r#"{ "type": "GET_IP_BANS" }"#;
+    const WRITE_COMMAND: &str = r#"{"type":"UNBAN_PLAYER","data":{"username":"Notch"}}"#;
+
+    #[test]
+    fn test_disabled_auth() {
+        let auth = CommandAuth::new(CommandCredentials::new(
+            None,
+            None,
+            Duration::ZERO,
+            Permission::Full,
+        ));
+
+        let (req, data) = message(None, WRITE_COMMAND);
+        assert!(auth.authorize(BACKEND, &req, &data).is_ok());
+    }
+
+    #[test]
+    fn test_signature() {
+        let auth = CommandAuth::new(CommandCredentials::new(
+            Some("secret".into()),
+            None,
+            Duration::ZERO,
+            Permission::Full,
+        ));
+
+        let (req, data) = message(Some("secret"), READ_COMMAND);
+        assert!(auth.authorize(BACKEND, &req, &data).is_ok());
+
+        let (req, data) = message(None, READ_COMMAND);
+        assert!(matches!(
+            auth.authorize(BACKEND, &req, &data),
+            Err(CommandError::Unauthorized)
+        ));
+
+        let (req, data) = message(Some("wrong"), READ_COMMAND);
+        assert!(matches!(
+            auth.authorize(BACKEND, &req, &data),
+            Err(CommandError::Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn test_previous_secret_grace_period() {
+        let auth = CommandAuth::new(CommandCredentials::new(
+            Some("new".into()),
+            Some("old".into()),
+            Duration::from_secs(60),
+            Permission::Full,
+        ));
+
+        let (req, data) = message(Some("old"), WRITE_COMMAND);
+        assert!(auth.authorize(BACKEND, &req, &data).is_ok());
+        let (req, data) = message(Some("new"), WRITE_COMMAND);
+        assert!(auth.authorize(BACKEND, &req, &data).is_ok());
+
+        let auth = CommandAuth::new(CommandCredentials::new(
+            Some("new".into()),
+            Some("old".into()),
+            Duration::ZERO,
+            Permission::Full,
+        ));
+
+        let (req, data) = message(Some("old"), WRITE_COMMAND);
+        assert!(matches!(
+            auth.authorize(BACKEND, &req, &data),
+            Err(CommandError::Unauthorized)
+        ));
+    }
+
+    #[test]
+    fn test_read_only_permission() {
+        let auth = CommandAuth::new(CommandCredentials::new(
+            None,
+            None,
+            Duration::ZERO,
+            Permission::ReadOnly,
+        ));
+
+        let (req, data) = message(None, READ_COMMAND);
+        assert!(auth.authorize(BACKEND, &req, &data).is_ok());
+
+        let (req, data) = message(None, WRITE_COMMAND);
+        assert!(matches!(
+            auth.authorize(BACKEND, &req, &data),
+            Err(CommandError::PermissionDenied(Permission::Full))
+        ));
+    }
+
+    #[test]
+    fn test_backend_credentials() {
+        let community = "127.0.0.1:25567";
+        let auth = CommandAuth::new(CommandCredentials::new(
+            Some("secret".into()),
+            None,
+            Duration::ZERO,
+            Permission::Full,
+        ))
+        .with_backend(
+            community.into(),
+            CommandCredentials::new(
+                Some("community".into()),
+                None,
+                Duration::ZERO,
+                Permission::ReadOnly,
+            ),
+        );
+
+        let (req, data) = message(Some("community"), READ_COMMAND);
+        assert!(auth.authorize(community, &req, &data).is_ok());
+        assert!(matches!(
+            auth.authorize(BACKEND, &req, &data),
+            Err(CommandError::Unauthorized)
+        ));
+
+        let (req, data) = message(Some("secret"), READ_COMMAND);
+        assert!(matches!(
+            auth.authorize(community, &req, &data),
+            Err(CommandError::Unauthorized)
+        ));
+
+        let (req, data) = message(Some("community"), WRITE_COMMAND);
+        assert!(matches!(
+            auth.authorize(community, &req, &data),
+            Err(CommandError::PermissionDenied(Permission::Full))
+        ));
+        let (req, data) = message(Some("secret"), WRITE_COMMAND);
+        assert!(auth.authorize(BACKEND, &req, &data).is_ok());
+    }
+}
@@ -0,0 +1,306 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+
+const REQUEST_CHANNEL_SIZE: usize = 32;
+const RESPONSE_CHANNEL_SIZE: usize = 8;
+
+pub struct CommandEvent {
+    /// The id of the connection the request was received from
+    pub connection: u64,
+    /// The address of the backend the connection is proxied to
+    pub backend: Arc<str>,
+    pub data: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Routes {
+    connections: HashMap<u64, mpsc::Sender<Vec<u8>>>,
+    /// The backend each connection is proxied to
+    backends: HashMap<u64, Arc<str>>,
+    /// Responses waiting for a connection, each with all of its messages
+    pending: VecDeque<(Instant, Vec<Vec<u8>>)>,
+}
+
+/// Decouples the command handling from the connections that carry the commands.
+///
+/// Every backend connection registers itself to receive responses. Responses are
+/// sent back through the connection the request came from when possible, through
+/// any other registered connection otherwise, and are held for `buffer_time` if
+/// there is no connection at all. All the messages of a fragmented response go
+/// through the same connection.
+pub struct CommandDispatcher {
+    request_sender: mpsc::Sender<CommandEvent>,
+    routes: Mutex<Routes>,
+    buffer_time: Duration,
+    next_id: AtomicU64,
+}
+
+impl CommandDispatcher {
+    /// Returns the dispatcher and the receiving end of the incomming requests,
+    /// that must be consumed by the command handler task.
+    pub fn new(buffer_time: Duration) -> (Self, mpsc::Receiver<CommandEvent>) {
+        let (request_sender, request_receiver) = mpsc::channel(REQUEST_CHANNEL_SIZE);
+
+        let dispatcher = Self {
+            request_sender,
+            routes: Mutex::new(Routes::default()),
+            buffer_time,
+            next_id: AtomicU64::new(0),
+        };
+
+        (dispatcher, request_receiver)
+    }
+
+    /// Registers a connection to the backend at `backend`, returning its id and
+    /// the receiver of the responses it must deliver. Buffered responses are
+    /// delivered to it right away.
+    pub fn register(&self, backend: &str) -> (u64, mpsc::Receiver<Vec<u8>>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        let mut routes = self.lock_routes();
+        self.purge_expired(&mut routes);
+
+        // Large enough for every buffered message, so that none is left behind
+        let buffered: usize = routes.pending.iter().map(|(_, v)| v.len()).sum();
+        let (sender, receiver) = mpsc::channel(RESPONSE_CHANNEL_SIZE.max(buffered));
+        for (_, messages) in routes.pending.drain(..) {
+            for message in messages {
+                let _ = sender.try_send(message);
+            }
+        }
+
+        routes.connections.insert(id, sender);
+        routes.backends.insert(id, backend.into());
+        (id, receiver)
+    }
+
+    pub fn unregister(&self, id: u64) {
+        let mut routes = self.lock_routes();
+        routes.connections.remove(&id);
+        routes.backends.remove(&id);
+    }
+
+    /// Queues a request received from the given connection. Returns `false` if
+    /// the command handler task is not running.
+    pub async fn submit(&self, connection: u64, data: Vec<u8>) -> bool {
+        let backend = self
+            .lock_routes()
+            .backends
+            .get(&connection)
+            .cloned()
+            .unwrap_or_else(|| "".into());
+
+        self.request_sender
+            .send(CommandEvent {
+                connection,
+                backend,
+                data,
+            })
+            .await
+            .is_ok()
+    }
+
+    /// Routes the messages of a response, preferring the connection the request
+    /// came from. They are all sent through the same connection, waiting for it
+    /// to take them, and the rest of the response is dropped if it closes in
+    /// between.
+    pub async fn respond(&self, connection: u64, messages: Vec<Vec<u8>>) {
+        let Some(sender) = self.route(connection, &messages) else {
+            return;
+        };
+
+        let count = messages.len();
+        for (sent, message) in messages.into_iter().enumerate() {
+            if sender.send(message).await.is_err() {
+                tracing::warn!(
+                    connection,
+                    sent,
+                    count,
+                    "Connection closed while delivering the command response",
+                );
+                return;
+            }
+        }
+    }
+
+    /// Picks the connection the response goes through, buffering the response
+    /// if there is none.
+    fn route(&self, connection: u64, messages: &[Vec<u8>]) -> Option<mpsc::Sender<Vec<u8>>> {
+        let mut routes = self.lock_routes();
+
+        if let Some(sender) = routes.connections.get(&connection) {
+            if !sender.is_closed() {
+                return Some(sender.clone());
+            }
+        }
+
+        let rerouted = routes
+            .connections
+            .iter()
+            .find(|(id, sender)| **id != connection && !sender.is_closed());
+        if let Some((id, sender)) = rerouted {
+            tracing::debug!(
+                from = connection,
+                to = id,
+                "Command response rerouted to another connection",
+            );
+            return Some(sender.clone());
+        }
+
+        tracing::debug!(
+            connection,
+            "No connection available to deliver the command response, buffering it",
+        );
+
+        self.purge_expired(&mut routes);
+        routes
+            .pending
+            .push_back((Instant::now(), messages.to_vec()));
+        None
+    }
+
+    fn purge_expired(&self, routes: &mut Routes) {
+        let before = routes.pending.len();
+        routes
+            .pending
+            .retain(|(buffered_at, _)| buffered_at.elapsed() < self.buffer_time);
+
+        let expired = before - routes.pending.len();
+        if expired > 0 {
+            tracing::warn!(expired, "Buffered command responses expired undelivered");
+        }
+    }
+
+    #[inline]
+    fn lock_routes(&self) -> std::sync::MutexGuard<'_, Routes> {
+        self.routes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CommandDispatcher;
+    use std::time::Duration;
+
+    const BACKEND: &str = "127.0.0.1:25566";
+
+    #[tokio::test]
+    async fn test_respond_to_origin() {
+        let (dispatcher, _) = CommandDispatcher::new(Duration::from_secs(30));
+
+        let (id1, mut recv1) = dispatcher.register(BACKEND);
+        let (id2, mut recv2) = dispatcher.register(BACKEND);
+        assert_ne!(id1, id2);
+
+        dispatcher.respond(id2, vec![vec![2]]).await;
+        assert_eq!(recv2.try_recv().unwrap(), vec![2]);
+        assert!(recv1.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reroute_when_origin_is_gone() {
+        let (dispatcher, _) = CommandDispatcher::new(Duration::from_secs(30));
+
+        let (id1, recv1) = dispatcher.register(BACKEND);
+        let (_, mut recv2) = dispatcher.register(BACKEND);
+
+        dispatcher.unregister(id1);
+        drop(recv1);
+
+        dispatcher.respond(id1, vec![vec![1]]).await;
+        assert_eq!(recv2.try_recv().unwrap(), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn test_buffer_until_registered() {
+        let (dispatcher, _) = CommandDispatcher::new(Duration::from_secs(30));
+
+        dispatcher.respond(0, vec![vec![1]]).await;
+        // More fragments than a connection usually holds
+        let fragments: Vec<_> = (2..20).map(|v| vec![v]).collect();
+        dispatcher.respond(0, fragments).await;
+
+        let (_, mut recv) = dispatcher.register(BACKEND);
+        for v in 1..20 {
+            assert_eq!(recv.try_recv().unwrap(), vec![v]);
+        }
+        assert!(recv.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fragments_go_through_one_connection() {
+        let (dispatcher, _) = CommandDispatcher::new(Duration::from_secs(30));
+
+        let (id1, mut recv1) = dispatcher.register(BACKEND);
+        let (_, mut recv2) = dispatcher.register(BACKEND);
+
+        // Waits for the connection to take the fragments instead of spilling
+        // them over the other one
+        let fragments: Vec<_> = (0..20).map(|v| vec![v]).collect();
+        let reader = tokio::spawn(async move {
+            let mut received = Vec::new();
+            while let Some(v) = recv1.recv().await {
+                received.push(v);
+                if received.len() == 20 {
+                    break;
+                }
+            }
+            received
+        });
+        dispatcher.respond(id1, fragments.clone()).await;
+
+        assert_eq!(reader.await.unwrap(), fragments);
+        assert!(recv2.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_closed_connection_drops_the_response() {
+        let (dispatcher, _) = CommandDispatcher::new(Duration::from_secs(30));
+
+        let (id, mut recv) = dispatcher.register(BACKEND);
+        let (_, mut other) = dispatcher.register(BACKEND);
+
+        let respond = dispatcher.respond(id, (0..20).map(|v| vec![v]).collect());
+        let close = async {
+            assert_eq!(recv.recv().await.unwrap(), vec![0]);
+            drop(recv);
+        };
+        tokio::join!(respond, close);
+
+        // Not finished through another connection
+        assert!(other.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_buffered_responses_expire() {
+        let (dispatcher, _) = CommandDispatcher::new(Duration::ZERO);
+
+        dispatcher.respond(0, vec![vec![1]]).await;
+
+        let (_, mut recv) = dispatcher.register(BACKEND);
+        assert!(recv.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_submit() {
+        let (dispatcher, mut requests) = CommandDispatcher::new(Duration::from_secs(30));
+        let (id, _recv) = dispatcher.register(BACKEND);
+
+        assert!(dispatcher.submit(id, vec![1, 2, 3]).await);
+
+        let event = requests.recv().await.unwrap();
+        assert_eq!(event.connection, id);
+        assert_eq!(&*event.backend, BACKEND);
+        assert_eq!(event.data, vec![1, 2, 3]);
+
+        drop(requests);
+        assert!(!dispatcher.submit(id, Vec::new()).await);
+    }
+}
@@ -1,50 +1,186 @@
-use super::{
-    server::{
-        ChangedMessage, CommandRequest, CommandRequestMessage, CommandResponse,
-        CommandResponseMessage, GetIpBansResponse, GetPlayerBansResponse, IpMessage,
-        IsBannedMessage, IsWhitelistEnabledResponse, IsWhitelistedResponse, UsernameMessage,
-        WhitelistGetAllResponse,
-    },
-    CommandError,
-};
+use super::{dispatcher::CommandEvent, into_command_result, CommandError};
 use crate::{
+    actions::PlayerAction,
     repository::{
-        ip_bans::IpBansRepository, user_bans::UserBansRepository, whitelist::WhitelistRepository,
+        ip_bans::IpBansRepository,
+        stats::StatsRepository,
+        user_bans::UserBansRepository,
+        user_ip_bans::{self, UserIpBansRepository},
+        whitelist::WhitelistRepository,
     },
     state::GlobalSharedState,
+    utils::{logging, rate_limit::RateLimiter},
+};
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+use mc_proxy_protocol::{
+    fragment,
+    id::RequestId,
+    negotiate_version,
+    server::{
+        BackendHealth, ChangedMessage, CommandRequest, CommandRequestMessage, CommandResponse,
+        CommandResponseMessage, DailyStats, DescriptionMessage, FreezePlayerRequest,
+        GetBackendHealthResponse, GetIpBansResponse, GetLiveStatsResponse, GetPlayerBansResponse,
+        GetStatsResponse, GetUserIpBansResponse, GetVersionResponse, HelloRequest, HelloResponse,
+        IpBan, IpMessage, IsBannedMessage, IsWhitelistEnabledResponse, IsWhitelistedResponse,
+        KickedMessage, LogLevelMessage, PingRequest, PingResponse, PlayerBan,
+        TransferPlayerRequest, UserIpBan, UserIpMessage, UsernameMessage, WhitelistAddRequest,
+        WhitelistBypassMessage, WhitelistEntry, WhitelistGetAllResponse,
+        WhitelistGetPatternsResponse, WhitelistPatternEntry, WhitelistPatternGroupMessage,
+        WhitelistPatternMessage, WhitelistPatternRequest, MAX_BATCH_SIZE,
+    },
+    CommandResult, FRAGMENT_PROTOCOL_VERSION, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
 };
-use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
-use uuid::Uuid;
 
+/// Responses carry the command timing since this protocol version.
+const TIMING_PROTOCOL_VERSION: u32 = 4;
+
+/// Bounds of the commands received from the backend connections.
+#[derive(Debug, Clone, Copy)]
+pub struct CommandLimits {
+    /// Larger commands are dropped without being decoded
+    pub max_size: usize,
+    /// Commands accepted per second from each connection
+    pub per_second: u32,
+}
+
+/// Drops the commands that exceed the [`CommandLimits`].
+struct CommandFilter {
+    limits: CommandLimits,
+    limiters: HashMap<u64, RateLimiter>,
+}
+
+impl CommandFilter {
+    fn new(limits: CommandLimits) -> Self {
+        Self {
+            limits,
+            limiters: HashMap::new(),
+        }
+    }
+
+    fn accept(&mut self, event: &CommandEvent) -> bool {
+        if event.data.len() > self.limits.max_size {
+            tracing::warn!(
+                connection = event.connection,
+                size = event.data.len(),
+                max_size = self.limits.max_size,
+                "Dropped oversized command",
+            );
+            return false;
+        }
+
+        if !self.limiters.contains_key(&event.connection) {
+            // Forgets the closed connections
+            self.limiters.retain(|_, limiter| !limiter.is_idle());
+        }
+
+        let limiter = self
+            .limiters
+            .entry(event.connection)
+            .or_insert_with(|| RateLimiter::new(self.limits.per_second, Duration::from_secs(1)));
+        if !limiter.try_acquire() {
+            tracing::warn!(
+                connection = event.connection,
+                "Dropped rate limited command"
+            );
+            return false;
+        }
+
+        true
+    }
+
+    /// Charges the commands of a batch past the first one, which was charged
+    /// by [`Self::accept`] before the batch was decoded.
+    fn accept_commands(&mut self, connection: u64, count: usize) -> bool {
+        let Some(limiter) = self.limiters.get(&connection) else {
+            return true;
+        };
+
+        let extra = u32::try_from(count.saturating_sub(1)).unwrap_or(u32::MAX);
+        if !limiter.try_acquire_many(extra) {
+            tracing::warn!(connection, count, "Rejected rate limited batch");
+            return false;
+        }
+
+        true
+    }
+}
+
+/// Handles the commands received by every connection, routing the responses
+/// through the [`CommandDispatcher`](super::dispatcher::CommandDispatcher).
 pub async fn proxy_command_events(
     state: &GlobalSharedState,
-    mut request_recv: mpsc::Receiver<Vec<u8>>,
-    response_sender: mpsc::Sender<Vec<u8>>,
+    mut request_recv: mpsc::Receiver<CommandEvent>,
+    limits: CommandLimits,
 ) {
-    loop {
-        let request = match request_recv.recv().await {
-            Some(v) => v,
-            None => break,
-        };
-        let response = handle_command_data(state, &request).await;
-        if response_sender.send(response).await.is_err() {
-            break;
+    let mut filter = CommandFilter::new(limits);
+
+    while let Some(event) = request_recv.recv().await {
+        if !filter.accept(&event) {
+            continue;
         }
+
+        let messages = handle_command_data(state, &event.backend, &event.data, |command| {
+            filter.accept_commands(event.connection, command.count())
+        })
+        .await;
+        state
+            .command_dispatcher
+            .respond(event.connection, messages)
+            .await;
     }
 }
 
-pub async fn handle_command_data(state: &GlobalSharedState, command_data: &[u8]) -> Vec<u8> {
-    match serde_json::from_slice::<'_, CommandRequestMessage>(&command_data) {
+/// Handles the command received from the backend at `backend` and returns the
+/// plugin messages that must be sent back, more than one if the response had to
+/// be fragmented.
+///
+/// Authorized commands are only run if `accept` lets them through, so that
+/// batches can be rate limited by the commands they hold.
+pub async fn handle_command_data(
+    state: &GlobalSharedState,
+    backend: &str,
+    command_data: &[u8],
+    accept: impl FnOnce(&CommandRequest) -> bool,
+) -> Vec<Vec<u8>> {
+    let received = ReceivedAt::now();
+    let (id, version, response) = match serde_json::from_slice::<'_, CommandRequestMessage>(
+        &command_data,
+    ) {
         Ok(req) => {
             tracing::info!(id = %req.id, command = ?req.command, "Incomming command");
 
-            let start = Instant::now();
-            let res = handle_command(state, req.command).await;
+            let version = req.version.unwrap_or(MIN_PROTOCOL_VERSION);
+            let negotiated = negotiate_version(version);
+
+            let res = if negotiated.is_none() {
+                tracing::warn!(id = %req.id, version, "Command sent with unsupported protocol version");
+                Err(CommandError::UnsupportedVersion(version))
+            } else if let Err(error) = state.command_auth.authorize(backend, &req, command_data) {
+                Err(error)
+            } else if !accept(&req.command) {
+                Err(CommandError::RateLimited)
+            } else {
+                handle_command(state, req.command, received).await
+            };
+
+            let took = received.instant.elapsed();
+            // Older plugins reject the messages with unknown fields
+            let timed = negotiated.is_some_and(|v| v >= TIMING_PROTOCOL_VERSION);
+
+            // Peers that weren't negotiated with are answered in their version
+            let version = negotiated.unwrap_or(version);
 
             let v = CommandResponseMessage {
                 id: req.id,
-                result: res.into(),
+                result: versioned_result(res, version),
+                took_micros: timed.then_some(took.as_micros() as u64),
+                handled_at: timed.then(|| Utc::now().timestamp_millis()),
             };
 
             let res = serde_json::to_vec(&v).unwrap_or_else(|error| {
@@ -52,24 +188,123 @@ pub async fn handle_command_data(state: &GlobalSharedState, command_data: &[u8])
 
                 serde_json::to_vec(&CommandResponseMessage {
                     id: req.id,
-                    result: Err(CommandError::CommandEncodeError(error)).into(),
+                    result: versioned_result(Err(CommandError::CommandEncodeError(error)), version),
+                    took_micros: v.took_micros,
+                    handled_at: v.handled_at,
                 })
                 .unwrap_or_else(|_| Vec::new())
             });
 
-            let took = Instant::now() - start;
             tracing::info!(id = %req.id, ?took, "Handled command");
 
-            res
+            (req.id, version, res)
         }
         Err(error) => {
             tracing::error!(%error, "Failed to decode incomming command");
 
-            serde_json::to_vec(&CommandResponseMessage {
-                id: Uuid::nil(),
-                result: Err(CommandError::CommandDecodeError(error)).into(),
+            let (id, version) = recover_request(command_data);
+            let res = serde_json::to_vec(&CommandResponseMessage {
+                id,
+                result: versioned_result(Err(CommandError::CommandDecodeError(error)), version),
+                took_micros: None,
+                handled_at: None,
             })
-            .unwrap_or_else(|_| Vec::new())
+            .unwrap_or_else(|_| Vec::new());
+
+            (id, version, res)
+        }
+    };
+
+    fragment_response(id, version, response)
+}
+
+fn fragment_response(id: RequestId, version: u32, response: Vec<u8>) -> Vec<Vec<u8>> {
+    if response.len() <= fragment::MAX_MESSAGE_SIZE {
+        return vec![response];
+    }
+
+    // Older plugins can't reassemble the fragments
+    if version < FRAGMENT_PROTOCOL_VERSION {
+        tracing::warn!(%id, size = response.len(), "Command response too large to be sent");
+
+        let error = CommandError::ResponseTooLarge(response.len());
+        let response = serde_json::to_vec(&CommandResponseMessage {
+            id,
+            result: versioned_result(Err(error), version),
+            took_micros: None,
+            handled_at: None,
+        })
+        .unwrap_or_else(|_| Vec::new());
+
+        return vec![response];
+    }
+
+    let response = match String::from_utf8(response) {
+        Ok(v) => v,
+        Err(error) => {
+            tracing::error!(%error, "Command response is not valid utf8");
+            return Vec::new();
+        }
+    };
+
+    let fragments = fragment::split(id, &response);
+    tracing::debug!(%id, count = fragments.len(), "Command response fragmented");
+
+    fragments
+        .iter()
+        .filter_map(|fragment| {
+            serde_json::to_vec(fragment)
+                .map_err(|error| {
+                    tracing::error!(%error, "Failed to encode command response fragment");
+                })
+                .ok()
+        })
+        .collect()
+}
+
+fn versioned_result(
+    result: Result<CommandResponse, CommandError>,
+    version: u32,
+) -> CommandResult<CommandResponse> {
+    let mut result = into_command_result(result);
+    result.downgrade(version);
+    result
+}
+
+/// Tries to extract the id and version of a request that could not be fully
+/// decoded, which usually means it was sent by a plugin speaking a different
+/// protocol version, so that the error response can still be correlated and
+/// understood.
+fn recover_request(command_data: &[u8]) -> (RequestId, u32) {
+    let value = serde_json::from_slice::<'_, serde_json::Value>(command_data).ok();
+
+    let id = value
+        .as_ref()
+        .and_then(|v| v.get("id")?.as_str()?.parse().ok())
+        .unwrap_or(RequestId::NIL);
+    let version = value
+        .as_ref()
+        .and_then(|v| v.get("version")?.as_u64()?.try_into().ok())
+        .unwrap_or(MIN_PROTOCOL_VERSION);
+
+    (id, version)
+}
+
+/// When a command was received, for the responses reporting how long the proxy
+/// took to handle it.
+#[derive(Debug, Clone, Copy)]
+pub struct ReceivedAt {
+    pub instant: Instant,
+    /// Unix timestamp in milliseconds
+    pub timestamp: i64,
+}
+
+impl ReceivedAt {
+    #[inline]
+    pub fn now() -> Self {
+        Self {
+            instant: Instant::now(),
+            timestamp: Utc::now().timestamp_millis(),
         }
     }
 }
@@ -77,8 +312,83 @@ pub async fn handle_command_data(state: &GlobalSharedState, command_data: &[u8])
 pub async fn handle_command(
     state: &GlobalSharedState,
     command: CommandRequest,
+    received: ReceivedAt,
 ) -> Result<CommandResponse, CommandError> {
     match command {
+        CommandRequest::Batch(commands) => {
+            validate_batch(&commands)?;
+            Ok(handle_batch(state, commands, received).await)
+        }
+        command => handle_single_command(state, command, received).await,
+    }
+}
+
+/// Rejects the whole batch before any of its commands runs.
+fn validate_batch(commands: &[CommandRequest]) -> Result<(), CommandError> {
+    if commands.len() > MAX_BATCH_SIZE {
+        return Err(CommandError::BatchTooLarge(commands.len()));
+    }
+    if commands
+        .iter()
+        .any(|v| matches!(v, CommandRequest::Batch(_)))
+    {
+        return Err(CommandError::NestedBatch);
+    }
+
+    Ok(())
+}
+
+/// Runs the commands one after the other, without a transaction: a failure
+/// stops the batch but leaves the commands that already ran applied.
+async fn handle_batch(
+    state: &GlobalSharedState,
+    commands: Vec<CommandRequest>,
+    received: ReceivedAt,
+) -> CommandResponse {
+    let mut results = Vec::with_capacity(commands.len());
+
+    for command in commands {
+        let res = handle_single_command(state, command, received).await;
+        let failed = res.is_err();
+
+        results.push(into_command_result(res));
+        if failed {
+            tracing::debug!(ran = results.len(), "Batch stopped by a failed command");
+            break;
+        }
+    }
+
+    CommandResponse::Batch(results)
+}
+
+async fn handle_single_command(
+    state: &GlobalSharedState,
+    command: CommandRequest,
+    received: ReceivedAt,
+) -> Result<CommandResponse, CommandError> {
+    match command {
+        CommandRequest::Batch(_) => Err(CommandError::NestedBatch),
+        CommandRequest::Hello(HelloRequest { version }) => {
+            let negotiated =
+                negotiate_version(version).ok_or(CommandError::UnsupportedVersion(version))?;
+
+            Ok(CommandResponse::Hello(HelloResponse {
+                version: negotiated,
+                min_version: MIN_PROTOCOL_VERSION,
+                max_version: PROTOCOL_VERSION,
+            }))
+        }
+        CommandRequest::Ping(PingRequest { payload }) => Ok(CommandResponse::Ping(PingResponse {
+            payload,
+            received_at: received.timestamp,
+            processing_time: received.instant.elapsed().as_micros() as u64,
+        })),
+        CommandRequest::GetVersion => Ok(CommandResponse::GetVersion(GetVersionResponse {
+            proxy_version: env!("CARGO_PKG_VERSION").into(),
+            protocol_version: PROTOCOL_VERSION,
+            min_protocol_version: MIN_PROTOCOL_VERSION,
+            features: enabled_features(state),
+        })),
         CommandRequest::BanPlayer(ban_player) => {
             let duration = ban_player.duration.map(Duration::from_millis);
 
@@ -89,6 +399,20 @@ pub async fn handle_command(
 
             Ok(CommandResponse::BanPlayer)
         }
+        CommandRequest::BanAndKickPlayer(ban_player) => {
+            let duration = ban_player.duration.map(Duration::from_millis);
+
+            // Banned first, so that the player can't log in again after the
+            // kick
+            let ban = state
+                .user_bans
+                .add_ban(&ban_player.username, duration, ban_player.reason)
+                .await?;
+            let reason = state.messages.banned(&ban);
+            let kicked = state.player_actions.kick(&ban_player.username, reason);
+
+            Ok(CommandResponse::BanAndKickPlayer(KickedMessage { kicked }))
+        }
         CommandRequest::UnbanPlayer(UsernameMessage { username }) => {
             let changed = state.user_bans.remove_ban(&username).await?.is_some();
 
@@ -99,6 +423,21 @@ pub async fn handle_command(
 
             Ok(CommandResponse::IsPlayerBanned(IsBannedMessage { banned }))
         }
+        CommandRequest::GetPlayerBan(UsernameMessage { username }) => {
+            let ban = state
+                .user_bans
+                .is_banned(&username)
+                .await?
+                .map(|v| PlayerBan {
+                    username: v.username,
+                    created_at: v.created_at.timestamp_millis(),
+                    expiration: v.expiration.map(|v| v.timestamp_millis()),
+                    remaining: v.expiration.map(remaining_millis),
+                    reason: v.reason,
+                });
+
+            Ok(CommandResponse::GetPlayerBan(ban))
+        }
         CommandRequest::GetPlayerBans => {
             let bans = state
                 .user_bans
@@ -132,6 +471,17 @@ pub async fn handle_command(
 
             Ok(CommandResponse::IsIpBanned(IsBannedMessage { banned }))
         }
+        CommandRequest::GetIpBan(IpMessage { ip }) => {
+            let ban = state.ip_bans.is_banned(ip).await?.map(|v| IpBan {
+                ip: v.ip,
+                created_at: v.created_at.timestamp_millis(),
+                expiration: v.expiration.map(|v| v.timestamp_millis()),
+                remaining: v.expiration.map(remaining_millis),
+                reason: v.reason,
+            });
+
+            Ok(CommandResponse::GetIpBan(ban))
+        }
         CommandRequest::GetIpBans => {
             let bans = state
                 .ip_bans
@@ -143,6 +493,46 @@ pub async fn handle_command(
 
             Ok(CommandResponse::GetIpBans(GetIpBansResponse { bans }))
         }
+        CommandRequest::BanUserIp(ban) => {
+            let network = parse_network(&ban.network)?;
+            let duration = ban.duration.map(Duration::from_millis);
+
+            state
+                .user_ip_bans
+                .add_ban(&ban.username, network, duration, ban.reason)
+                .await?;
+
+            Ok(CommandResponse::BanUserIp)
+        }
+        CommandRequest::UnbanUserIp(UserIpMessage { username, network }) => {
+            let network = parse_network(&network)?;
+            let changed = state
+                .user_ip_bans
+                .remove_ban(&username, network)
+                .await?
+                .is_some();
+
+            Ok(CommandResponse::UnbanUserIp(ChangedMessage { changed }))
+        }
+        CommandRequest::GetUserIpBans => {
+            let bans = state
+                .user_ip_bans
+                .get_bans()
+                .await?
+                .into_iter()
+                .map(|v| UserIpBan {
+                    username: v.username,
+                    network: v.network.to_string(),
+                    created_at: v.created_at.timestamp_millis(),
+                    expiration: v.expiration.map(|v| v.timestamp_millis()),
+                    reason: v.reason,
+                })
+                .collect();
+
+            Ok(CommandResponse::GetUserIpBans(GetUserIpBansResponse {
+                bans,
+            }))
+        }
         CommandRequest::SetWhitelistEnabled(set_enabled) => {
             let before_enabled = state.whitelist.is_enabled().await?;
             state.whitelist.set_enabled(set_enabled.enabled).await?;
@@ -165,8 +555,13 @@ pub async fn handle_command(
                 whitelisted,
             }))
         }
-        CommandRequest::WhitelistAddPlayer(UsernameMessage { username }) => {
-            let result = state.whitelist.add(&username).await?;
+        CommandRequest::WhitelistAddPlayer(WhitelistAddRequest { username, duration }) => {
+            let duration = duration.map(Duration::from_millis);
+            let uuid = match &state.username_resolver {
+                Some(resolver) => Some(resolver.resolve(&username).await?),
+                None => None,
+            };
+            let result = state.whitelist.add(&username, uuid, duration).await?;
 
             Ok(CommandResponse::WhitelistAddPlayer(ChangedMessage {
                 changed: result.is_changed(),
@@ -180,11 +575,863 @@ pub async fn handle_command(
             }))
         }
         CommandRequest::WhitelistGetAll => {
-            let whitelist = state.whitelist.get_all().await?;
+            let entries: Vec<_> = state
+                .whitelist
+                .get_all()
+                .await?
+                .into_iter()
+                .map(|v| WhitelistEntry {
+                    username: v.username,
+                    created_at: v.created_at.timestamp_millis(),
+                    expiration: v.expiration.map(|v| v.timestamp_millis()),
+                })
+                .collect();
 
             Ok(CommandResponse::WhitelistGetAll(WhitelistGetAllResponse {
-                whitelist,
+                whitelist: None,
+                entries,
+            }))
+        }
+        CommandRequest::WhitelistBypassAdd(WhitelistBypassMessage { entry }) => {
+            Ok(CommandResponse::WhitelistBypassAdd(ChangedMessage {
+                changed: state.whitelist_bypass.add(&entry),
+            }))
+        }
+        CommandRequest::WhitelistBypassRemove(WhitelistBypassMessage { entry }) => {
+            Ok(CommandResponse::WhitelistBypassRemove(ChangedMessage {
+                changed: state.whitelist_bypass.remove(&entry),
+            }))
+        }
+        CommandRequest::WhitelistAddPattern(WhitelistPatternRequest { pattern, tag }) => {
+            let result = state
+                .whitelist
+                .add_pattern(&pattern.parse()?, tag.as_deref())
+                .await?;
+
+            Ok(CommandResponse::WhitelistAddPattern(ChangedMessage {
+                changed: result.is_changed(),
+            }))
+        }
+        CommandRequest::WhitelistRemovePattern(WhitelistPatternMessage { pattern }) => {
+            let result = state.whitelist.remove_pattern(&pattern.parse()?).await?;
+
+            Ok(CommandResponse::WhitelistRemovePattern(ChangedMessage {
+                changed: result.is_changed(),
+            }))
+        }
+        CommandRequest::WhitelistRemovePatternGroup(WhitelistPatternGroupMessage { tag }) => {
+            let result = state.whitelist.remove_pattern_group(&tag).await?;
+
+            Ok(CommandResponse::WhitelistRemovePatternGroup(
+                ChangedMessage {
+                    changed: result.is_changed(),
+                },
+            ))
+        }
+        CommandRequest::WhitelistGetPatterns => {
+            let patterns = state
+                .whitelist
+                .get_patterns()
+                .await?
+                .into_iter()
+                .map(|v| WhitelistPatternEntry {
+                    pattern: v.pattern.to_string(),
+                    tag: v.tag,
+                })
+                .collect();
+
+            Ok(CommandResponse::WhitelistGetPatterns(
+                WhitelistGetPatternsResponse { patterns },
+            ))
+        }
+        CommandRequest::GetStats => {
+            state.stats.flush().await?;
+            let stats = state.stats.repository().get_stats().await?;
+
+            Ok(CommandResponse::GetStats(GetStatsResponse {
+                unique_players: stats.unique_players,
+                peak_players: stats.peak_players,
+                peak_at: stats.peak_at.map(|v| v.timestamp_millis()),
+                days: stats
+                    .days
+                    .into_iter()
+                    .map(|v| DailyStats {
+                        day: v.day.to_string(),
+                        logins: v.logins,
+                        unique_players: v.unique_players,
+                        peak_players: v.peak_players,
+                        peak_at: v.peak_at.map(|v| v.timestamp_millis()),
+                    })
+                    .collect(),
+                connections: state
+                    .stats
+                    .connections()
+                    .into_iter()
+                    .map(|(k, v)| (k.to_owned(), v))
+                    .collect(),
+            }))
+        }
+        CommandRequest::GetLiveStats => {
+            let live = state.stats.live();
+            let online_players = state.read_online_players().await.len() as u64;
+
+            Ok(CommandResponse::GetLiveStats(GetLiveStatsResponse {
+                online_players,
+                connections: live.connections,
+                status_pings: live.status_pings,
+                bytes_proxied: live.bytes_proxied,
+                uptime_secs: live.uptime.as_secs(),
+                since: live.reset_at.timestamp_millis(),
+            }))
+        }
+        CommandRequest::ResetLiveStats => {
+            state.stats.reset_live();
+            tracing::info!("Live stats reset");
+
+            Ok(CommandResponse::ResetLiveStats)
+        }
+        CommandRequest::GetBackendHealth => {
+            let mut backends: Vec<_> = state
+                .backend_health
+                .get_all()
+                .into_iter()
+                .map(|(address, v)| BackendHealth {
+                    address,
+                    healthy: v.healthy,
+                    consecutive_failures: v.consecutive_failures,
+                    last_check: v.last_check.map(|v| v.timestamp_millis()),
+                    latency: v.latency.map(|v| v.as_millis() as u64),
+                    last_error: v.last_error,
+                    tripped_until: v.tripped_until.map(|v| v.timestamp_millis()),
+                })
+                .collect();
+            backends.sort_by(|a, b| a.address.cmp(&b.address));
+
+            Ok(CommandResponse::GetBackendHealth(
+                GetBackendHealthResponse { backends },
+            ))
+        }
+        CommandRequest::SetDescription(DescriptionMessage { message }) => {
+            let message = serde_json::from_value(message).map_err(CommandError::InvalidMessage)?;
+            state.set_server_description(message).await?;
+
+            Ok(CommandResponse::SetDescription)
+        }
+        CommandRequest::GetDescription => {
+            let message = serde_json::to_value(state.server_description().await)
+                .map_err(CommandError::CommandEncodeError)?;
+
+            Ok(CommandResponse::GetDescription(DescriptionMessage {
+                message,
             }))
         }
+        CommandRequest::ResetDescription => {
+            state.reset_server_description().await?;
+
+            Ok(CommandResponse::ResetDescription)
+        }
+        CommandRequest::ClearSessionLock(UsernameMessage { username }) => {
+            let changed = match &state.session_lock {
+                Some(session_lock) => session_lock.clear(&username).await?,
+                None => false,
+            };
+
+            Ok(CommandResponse::ClearSessionLock(ChangedMessage {
+                changed,
+            }))
+        }
+        CommandRequest::TransferPlayer(TransferPlayerRequest { username, backend }) => {
+            if !state.backend_health.contains(&backend) {
+                return Err(CommandError::UnknownBackend(backend));
+            }
+            let changed = state.player_actions.transfer(&username, backend);
+
+            Ok(CommandResponse::TransferPlayer(ChangedMessage { changed }))
+        }
+        CommandRequest::FreezePlayer(FreezePlayerRequest { username, frozen }) => {
+            let changed = state
+                .player_actions
+                .send(&username, PlayerAction::Freeze(frozen));
+            if changed {
+                tracing::info!(username, frozen, "Changed frozen state of player");
+            }
+
+            Ok(CommandResponse::FreezePlayer(ChangedMessage { changed }))
+        }
+        CommandRequest::SetLogLevel(LogLevelMessage { level }) => {
+            logging::set_log_level(&level).map_err(CommandError::InvalidLogLevel)?;
+            tracing::info!(level, "Log level changed");
+
+            Ok(CommandResponse::SetLogLevel)
+        }
+    }
+}
+
+/// Milliseconds until `expiration`, zero once it passed.
+fn remaining_millis(expiration: DateTime<Utc>) -> u64 {
+    (expiration - Utc::now()).num_milliseconds().max(0) as u64
+}
+
+fn enabled_features(state: &GlobalSharedState) -> Vec<String> {
+    [
+        ("command_auth", state.command_auth.is_enabled()),
+        ("online_mode", state.username_resolver.is_some()),
+        ("session_ip_lock", state.session_lock.is_some()),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(name, _)| name.to_owned())
+    .collect()
+}
+
+/// Single IPs ban only that address.
+fn parse_network(network: &str) -> Result<IpNet, CommandError> {
+    user_ip_bans::parse_network(network).map_err(|_| CommandError::InvalidNetwork(network.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        fragment_response, handle_command, handle_command_data, CommandFilter, CommandLimits,
+        ReceivedAt,
+    };
+    use crate::{
+        actions::PlayerAction,
+        backend::health::BackendHealthMap,
+        commands::{
+            auth::{CommandAuth, CommandCredentials},
+            dispatcher::CommandEvent,
+            CommandError,
+        },
+        repository::{
+            ip_bans::IpBansRepository, user_bans::UserBansRepository,
+            whitelist::WhitelistRepository,
+        },
+        resolver::tests::MockResolver,
+        state::test_global_state,
+    };
+    use mc_proxy_protocol::{
+        auth::{sign, Permission},
+        fragment::MAX_MESSAGE_SIZE,
+        id::RequestId,
+        server::{
+            BanPlayerRequest, BanUserIpRequest, ChangedMessage, CommandRequest, CommandResponse,
+            CommandResponseMessage, DescriptionMessage, FreezePlayerRequest, IpMessage,
+            KickedMessage, PingRequest, TransferPlayerRequest, UserIpMessage, UsernameMessage,
+            WhitelistAddRequest, WhitelistPatternRequest, MAX_BATCH_SIZE,
+        },
+        CommandResult, ErrorCode, ErrorMessage, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+    };
+    use minecraft_protocol::data::chat::Message;
+    use serde_json::json;
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    const BACKEND: &str = "127.0.0.1:25566";
+
+    fn event(connection: u64, size: usize) -> CommandEvent {
+        CommandEvent {
+            connection,
+            backend: BACKEND.into(),
+            data: vec![b' '; size],
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_command_limits() {
+        let mut filter = CommandFilter::new(CommandLimits {
+            max_size: 64,
+            per_second: 2,
+        });
+
+        assert!(!filter.accept(&event(0, 65)));
+        assert!(filter.accept(&event(0, 64)));
+        assert!(filter.accept(&event(0, 1)));
+        assert!(!filter.accept(&event(0, 1)));
+
+        // Limited by connection
+        assert!(filter.accept(&event(1, 1)));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(filter.accept(&event(0, 1)));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(filter.accept(&event(2, 1)));
+        assert_eq!(filter.limiters.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batches_are_charged_by_command() {
+        let mut filter = CommandFilter::new(CommandLimits {
+            max_size: 1024,
+            per_second: 4,
+        });
+
+        assert!(filter.accept(&event(1, 1)));
+        assert!(filter.accept_commands(1, 3));
+        assert!(filter.accept(&event(1, 1)));
+        assert!(!filter.accept_commands(1, 2));
+    }
+
+    async fn error_code(request: &str) -> ErrorCode {
+        let state = test_global_state().await;
+        let messages = handle_command_data(&state, BACKEND, request.as_bytes(), |_| true).await;
+
+        let response: CommandResponseMessage = serde_json::from_slice(&messages[0]).unwrap();
+        match response.result {
+            CommandResult::Error(error) => error.code,
+            CommandResult::Success(v) => panic!("Expected error, got {v:?}"),
+        }
+    }
+
+    #[test]
+    fn test_fragments_need_protocol_version_2() {
+        let id = RequestId::from(Uuid::new_v4());
+        let response = serde_json::to_vec(&"a".repeat(MAX_MESSAGE_SIZE)).unwrap();
+
+        let messages = fragment_response(id, 1, response.clone());
+        assert_eq!(messages.len(), 1);
+        let message: CommandResponseMessage = serde_json::from_slice(&messages[0]).unwrap();
+        assert_eq!(message.id, id);
+        assert!(matches!(message.result, CommandResult::Error(_)));
+
+        assert!(fragment_response(id, 2, response).len() > 1);
+    }
+
+    #[tokio::test]
+    async fn test_decode_error_code() {
+        let code =
+            error_code(r#"{"id":"67e55044-10b1-426f-9247-bb680e5fe0c8","version":3,"command":{}}"#)
+                .await;
+        assert_eq!(code, ErrorCode::DecodeFailed);
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_version_has_no_code() {
+        let code = error_code(
+            r#"{
+                "id": "67e55044-10b1-426f-9247-bb680e5fe0c8",
+                "version": 0,
+                "command": { "type": "GET_PLAYER_BANS" }
+            }"#,
+        )
+        .await;
+        // Older than the error codes
+        assert_eq!(code, ErrorCode::Unknown);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_log_level_code() {
+        let code = error_code(
+            r#"{
+                "id": "67e55044-10b1-426f-9247-bb680e5fe0c8",
+                "version": 3,
+                "command": { "type": "SET_LOG_LEVEL", "data": { "level": "mc_proxy=loud" } }
+            }"#,
+        )
+        .await;
+        assert_eq!(code, ErrorCode::InvalidLogLevel);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_pattern_code() {
+        let code = error_code(
+            r#"{
+                "id": "67e55044-10b1-426f-9247-bb680e5fe0c8",
+                "version": 3,
+                "command": { "type": "WHITELIST_ADD_PATTERN", "data": { "pattern": "event-*" } }
+            }"#,
+        )
+        .await;
+        assert_eq!(code, ErrorCode::InvalidPattern);
+    }
+
+    #[tokio::test]
+    async fn test_transfer_player() {
+        let mut state = test_global_state().await;
+        state.backend_health = BackendHealthMap::new(["lobby:25565"]);
+        let transfer = |backend: &str| {
+            CommandRequest::TransferPlayer(TransferPlayerRequest {
+                username: "Notch".into(),
+                backend: backend.into(),
+            })
+        };
+
+        let error = handle_command(&state, transfer("unknown:25565"), ReceivedAt::now())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, CommandError::UnknownBackend(_)));
+
+        // Not online
+        let response = handle_command(&state, transfer("lobby:25565"), ReceivedAt::now()).await;
+        assert!(matches!(
+            response.unwrap(),
+            CommandResponse::TransferPlayer(ChangedMessage { changed: false })
+        ));
+
+        let mut actions = state.player_actions.register("Notch");
+        let response = handle_command(&state, transfer("lobby:25565"), ReceivedAt::now()).await;
+        assert!(matches!(
+            response.unwrap(),
+            CommandResponse::TransferPlayer(ChangedMessage { changed: true })
+        ));
+        assert_eq!(actions.try_recv().unwrap(), PlayerAction::Transfer);
+        assert_eq!(
+            state.player_actions.take_transfer("Notch").unwrap(),
+            "lobby:25565"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_freeze_player() {
+        let state = test_global_state().await;
+        let freeze = |frozen| {
+            CommandRequest::FreezePlayer(FreezePlayerRequest {
+                username: "Notch".into(),
+                frozen,
+            })
+        };
+
+        // Not online
+        let response = handle_command(&state, freeze(true), ReceivedAt::now()).await;
+        assert!(matches!(
+            response.unwrap(),
+            CommandResponse::FreezePlayer(ChangedMessage { changed: false })
+        ));
+
+        let mut actions = state.player_actions.register("Notch");
+        for frozen in [true, false] {
+            let response = handle_command(&state, freeze(frozen), ReceivedAt::now()).await;
+            assert!(matches!(
+                response.unwrap(),
+                CommandResponse::FreezePlayer(ChangedMessage { changed: true })
+            ));
+            assert_eq!(actions.try_recv().unwrap(), PlayerAction::Freeze(frozen));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_ban_details() {
+        let state = test_global_state().await;
+        let get_player_ban = || {
+            CommandRequest::GetPlayerBan(UsernameMessage {
+                username: "Notch".into(),
+            })
+        };
+
+        let response = handle_command(&state, get_player_ban(), ReceivedAt::now()).await;
+        assert!(matches!(
+            response.unwrap(),
+            CommandResponse::GetPlayerBan(None)
+        ));
+
+        state
+            .user_bans
+            .add_ban(
+                "Notch",
+                Some(Duration::from_secs(3600)),
+                Some("Hacks".into()),
+            )
+            .await
+            .unwrap();
+        let response = handle_command(&state, get_player_ban(), ReceivedAt::now())
+            .await
+            .unwrap();
+        let CommandResponse::GetPlayerBan(Some(ban)) = response else {
+            panic!("Unexpected response {response:?}");
+        };
+        assert_eq!(ban.reason.as_deref(), Some("Hacks"));
+        let remaining = ban.remaining.unwrap();
+        assert!(
+            remaining > 3_500_000 && remaining <= 3_600_000,
+            "{remaining}"
+        );
+
+        let ip = "203.0.113.7".parse().unwrap();
+        state.ip_bans.add_ban(ip, None, None).await.unwrap();
+        let response = handle_command(
+            &state,
+            CommandRequest::GetIpBan(IpMessage { ip }),
+            ReceivedAt::now(),
+        )
+        .await;
+        let CommandResponse::GetIpBan(Some(ban)) = response.unwrap() else {
+            panic!("Expected an IP ban");
+        };
+        assert_eq!(ban.ip, ip);
+        assert_eq!(ban.expiration, None);
+        assert_eq!(ban.remaining, None);
+    }
+
+    #[tokio::test]
+    async fn test_ban_and_kick_player() {
+        let state = test_global_state().await;
+        let ban = |username: &str| {
+            CommandRequest::BanAndKickPlayer(BanPlayerRequest {
+                username: username.into(),
+                duration: None,
+                reason: Some("Hacks".into()),
+            })
+        };
+
+        let mut actions = state.player_actions.register("Notch");
+        let response = handle_command(&state, ban("Notch"), ReceivedAt::now()).await;
+        assert!(matches!(
+            response.unwrap(),
+            CommandResponse::BanAndKickPlayer(KickedMessage { kicked: true })
+        ));
+        let data = state.user_bans.is_banned("Notch").await.unwrap().unwrap();
+        assert_eq!(
+            actions.try_recv().unwrap(),
+            PlayerAction::Disconnect(Box::new(state.messages.banned(&data)))
+        );
+
+        // Not online
+        let response = handle_command(&state, ban("jeb_"), ReceivedAt::now()).await;
+        assert!(matches!(
+            response.unwrap(),
+            CommandResponse::BanAndKickPlayer(KickedMessage { kicked: false })
+        ));
+        assert!(state.user_bans.is_banned("jeb_").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_response_timing() {
+        let state = test_global_state().await;
+        let before = chrono::Utc::now().timestamp_millis();
+
+        for (version, timed) in [(3, false), (4, true)] {
+            let request = json!({
+                "id": Uuid::new_v4(),
+                "version": version,
+                "command": { "type": "GET_PLAYER_BANS" }
+            });
+            let messages =
+                handle_command_data(&state, BACKEND, request.to_string().as_bytes(), |_| true)
+                    .await;
+
+            let response: CommandResponseMessage = serde_json::from_slice(&messages[0]).unwrap();
+            assert_eq!(response.took_micros.is_some(), timed);
+            assert_eq!(response.handled_at.is_some(), timed);
+            if let Some(handled_at) = response.handled_at {
+                assert!(handled_at >= before);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ping() {
+        let state = test_global_state().await;
+
+        let received = ReceivedAt::now();
+        // Spent queued before the command was handled
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let request = CommandRequest::Ping(PingRequest {
+            payload: "probe-42".into(),
+        });
+        let elapsed = received.instant.elapsed();
+        match handle_command(&state, request, received).await.unwrap() {
+            CommandResponse::Ping(response) => {
+                assert_eq!(response.payload, "probe-42");
+                assert_eq!(response.received_at, received.timestamp);
+                assert!(response.processing_time >= elapsed.as_micros() as u64);
+                assert!(response.processing_time <= received.instant.elapsed().as_micros() as u64);
+            }
+            response => panic!("Unexpected response {response:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batch() {
+        let state = test_global_state().await;
+
+        let ban = CommandRequest::BanPlayer(BanPlayerRequest {
+            username: "Notch".into(),
+            duration: None,
+            reason: None,
+        });
+        let pattern = CommandRequest::WhitelistAddPattern(WhitelistPatternRequest {
+            pattern: "event-*".into(),
+            tag: None,
+        });
+        let whitelist = CommandRequest::WhitelistAddPlayer(WhitelistAddRequest {
+            username: "Notch".into(),
+            duration: None,
+        });
+        let batch = CommandRequest::Batch(vec![ban, pattern, whitelist]);
+
+        let results = match handle_command(&state, batch, ReceivedAt::now())
+            .await
+            .unwrap()
+        {
+            CommandResponse::Batch(v) => v,
+            v => panic!("Unexpected response {v:?}"),
+        };
+        assert_eq!(results.len(), 2);
+        assert!(matches!(
+            results[0],
+            CommandResult::Success(CommandResponse::BanPlayer)
+        ));
+        assert!(matches!(
+            &results[1],
+            CommandResult::Error(error) if error.code == ErrorCode::InvalidPattern
+        ));
+
+        // Stopped before the last command, without undoing the first one
+        assert!(state.user_bans.is_banned("Notch").await.unwrap().is_some());
+        assert!(!state.whitelist.is_whitelisted("Notch").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_batch_does_not_run() {
+        let state = test_global_state().await;
+
+        let ban = CommandRequest::BanPlayer(BanPlayerRequest {
+            username: "Notch".into(),
+            duration: None,
+            reason: None,
+        });
+        let nested = CommandRequest::Batch(vec![ban.clone(), CommandRequest::Batch(Vec::new())]);
+        let result = handle_command(&state, nested, ReceivedAt::now()).await;
+        assert!(matches!(result, Err(CommandError::NestedBatch)));
+
+        let large = CommandRequest::Batch(vec![ban; MAX_BATCH_SIZE + 1]);
+        let result = handle_command(&state, large, ReceivedAt::now()).await;
+        assert!(matches!(result, Err(CommandError::BatchTooLarge(_))));
+
+        assert!(state.user_bans.is_banned("Notch").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_get_version() {
+        let mut state = test_global_state().await;
+
+        let features = |response| match response {
+            CommandResponse::GetVersion(response) => {
+                assert_eq!(response.proxy_version, env!("CARGO_PKG_VERSION"));
+                assert_eq!(response.protocol_version, PROTOCOL_VERSION);
+                assert_eq!(response.min_protocol_version, MIN_PROTOCOL_VERSION);
+                response.features
+            }
+            response => panic!("Unexpected response {response:?}"),
+        };
+
+        let response = handle_command(&state, CommandRequest::GetVersion, ReceivedAt::now()).await;
+        assert!(features(response.unwrap()).is_empty());
+
+        state.command_auth = CommandAuth::new(CommandCredentials::new(
+            Some("secret".into()),
+            None,
+            Duration::ZERO,
+            Permission::Full,
+        ));
+        state.username_resolver = Some(Box::new(MockResolver::default()));
+        let response = handle_command(&state, CommandRequest::GetVersion, ReceivedAt::now()).await;
+        assert_eq!(features(response.unwrap()), ["command_auth", "online_mode"]);
+    }
+
+    /// Commands added in newer protocol versions, or with fields this proxy
+    /// doesn't know about, must still be answered so that plugins don't wait
+    /// for a response forever.
+    #[tokio::test]
+    async fn test_unknown_commands_are_answered() {
+        let state = test_global_state().await;
+        let id = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+
+        for command in [
+            r#"{"type":"SOME_FUTURE_COMMAND"}"#,
+            r#"{"type":"PING","data":{"payload":"probe","future_field":1}}"#,
+        ] {
+            let request = format!(r#"{{"id":"{id}","version":3,"command":{command}}}"#);
+            let messages = handle_command_data(&state, BACKEND, request.as_bytes(), |_| true).await;
+            assert_eq!(messages.len(), 1);
+
+            let response: CommandResponseMessage = serde_json::from_slice(&messages[0]).unwrap();
+            assert_eq!(response.id, id.parse::<RequestId>().unwrap());
+            assert!(matches!(
+                response.result,
+                CommandResult::Error(ErrorMessage {
+                    code: ErrorCode::DecodeFailed,
+                    ..
+                })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unsigned_commands_are_not_run() {
+        let mut state = test_global_state().await;
+        state.command_auth = CommandAuth::new(CommandCredentials::new(
+            Some("secret".into()),
+            None,
+            Duration::ZERO,
+            Permission::Full,
+        ));
+
+        let id = RequestId::from(Uuid::new_v4());
+        let command = r#"{"type":"BAN_PLAYER","data":{"username":"Notch"}}"#;
+
+        for hmac in [None, Some(sign(b"wrong", &id, command))] {
+            let hmac = hmac
+                .map(|v| format!(r#","hmac":"{v}""#))
+                .unwrap_or_default();
+            let request = format!(r#"{{"id":"{id}"{hmac},"version":3,"command":{command}}}"#);
+
+            let messages = handle_command_data(&state, BACKEND, request.as_bytes(), |_| true).await;
+            let response: CommandResponseMessage = serde_json::from_slice(&messages[0]).unwrap();
+            assert_eq!(response.id, id);
+            assert!(matches!(
+                response.result,
+                CommandResult::Error(ErrorMessage {
+                    code: ErrorCode::Unauthorized,
+                    ..
+                })
+            ));
+            assert!(state.user_bans.is_banned("Notch").await.unwrap().is_none());
+        }
+
+        let hmac = sign(b"secret", &id, command);
+        let request = format!(r#"{{"id":"{id}","hmac":"{hmac}","command":{command}}}"#);
+        handle_command_data(&state, BACKEND, request.as_bytes(), |_| true).await;
+        assert!(state.user_bans.is_banned("Notch").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_whitelist_add_resolves_uuid() {
+        let mut state = test_global_state().await;
+
+        let uuid = Uuid::new_v4();
+        state.username_resolver = Some(Box::new(MockResolver {
+            players: [("Notch".to_string(), uuid)].into(),
+            ..Default::default()
+        }));
+
+        let add = |username: &str| {
+            CommandRequest::WhitelistAddPlayer(WhitelistAddRequest {
+                username: username.into(),
+                duration: None,
+            })
+        };
+
+        handle_command(&state, add("Notch"), ReceivedAt::now())
+            .await
+            .unwrap();
+        assert!(state
+            .whitelist
+            .is_player_whitelisted("Renamed", uuid)
+            .await
+            .unwrap());
+
+        let error = handle_command(&state, add("Herobrine"), ReceivedAt::now())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, CommandError::ResolveError(_)));
+        assert_eq!(error.code(), ErrorCode::UsernameResolutionFailed);
+        assert!(!state.whitelist.is_whitelisted("Herobrine").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_set_description() {
+        let state = test_global_state().await;
+
+        let message = json!({ "text": "Maintenance", "color": "red" });
+        let request = CommandRequest::SetDescription(DescriptionMessage {
+            message: message.clone(),
+        });
+        handle_command(&state, request, ReceivedAt::now())
+            .await
+            .unwrap();
+
+        let response = handle_command(&state, CommandRequest::GetDescription, ReceivedAt::now())
+            .await
+            .unwrap();
+        let CommandResponse::GetDescription(description) = response else {
+            panic!("Unexpected response {response:?}");
+        };
+        assert_eq!(description.message, message);
+
+        let request = CommandRequest::SetDescription(DescriptionMessage {
+            message: json!("Plain text"),
+        });
+        handle_command(&state, request, ReceivedAt::now())
+            .await
+            .unwrap();
+        assert_eq!(
+            state.server_description().await,
+            Message::Plain("Plain text".into())
+        );
+
+        handle_command(&state, CommandRequest::ResetDescription, ReceivedAt::now())
+            .await
+            .unwrap();
+        assert_eq!(
+            state.server_description().await,
+            Message::from_str("Minecraft Server")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_invalid_description() {
+        let state = test_global_state().await;
+        let before = state.server_description().await;
+
+        let request = CommandRequest::SetDescription(DescriptionMessage {
+            message: json!({ "text": "Maintenance", "color": "not_a_color" }),
+        });
+        let error = handle_command(&state, request, ReceivedAt::now())
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.code(), ErrorCode::InvalidMessage);
+        assert_eq!(state.server_description().await, before);
+    }
+
+    #[tokio::test]
+    async fn test_user_ip_bans() {
+        let state = test_global_state().await;
+
+        let ban = |network: &str| {
+            CommandRequest::BanUserIp(BanUserIpRequest {
+                username: "Notch".into(),
+                network: network.into(),
+                duration: None,
+                reason: None,
+            })
+        };
+
+        let error = handle_command(&state, ban("10.0.0.0/33"), ReceivedAt::now())
+            .await
+            .unwrap_err();
+        assert_eq!(error.code(), ErrorCode::InvalidNetwork);
+
+        handle_command(&state, ban("10.1.2.3/16"), ReceivedAt::now())
+            .await
+            .unwrap();
+        handle_command(&state, ban("192.0.2.1"), ReceivedAt::now())
+            .await
+            .unwrap();
+
+        let response = handle_command(&state, CommandRequest::GetUserIpBans, ReceivedAt::now())
+            .await
+            .unwrap();
+        let CommandResponse::GetUserIpBans(response) = response else {
+            panic!("Unexpected response {response:?}");
+        };
+        let mut networks: Vec<_> = response.bans.into_iter().map(|v| v.network).collect();
+        networks.sort();
+        assert_eq!(networks, ["10.1.0.0/16", "192.0.2.1/32"]);
+
+        let unban = CommandRequest::UnbanUserIp(UserIpMessage {
+            username: "Notch".into(),
+            network: "10.1.0.0/16".into(),
+        });
+        let response = handle_command(&state, unban, ReceivedAt::now())
+            .await
+            .unwrap();
+        assert!(matches!(
+            response,
+            CommandResponse::UnbanUserIp(v) if v.changed
+        ));
     }
 }
@@ -1,8 +1,15 @@
-use crate::repository::RepositoryError;
-use serde::{Deserialize, Serialize};
+use crate::{
+    repository::{whitelist::InvalidWhitelistPattern, RepositoryError},
+    resolver::ResolveError,
+};
+use mc_proxy_protocol::{
+    auth::Permission, server::MAX_BATCH_SIZE, CommandResult, ErrorCode, ErrorMessage,
+};
 
+pub mod admin;
+pub mod auth;
+pub mod dispatcher;
 pub mod handler;
-pub mod server;
 
 #[derive(Debug, thiserror::Error)]
 pub enum CommandError {
@@ -10,49 +17,103 @@ pub enum CommandError {
     CommandDecodeError(serde_json::Error),
     #[error("Command encode failed: {0}")]
     CommandEncodeError(serde_json::Error),
+    #[error("The response is {0} bytes long, too large for a single plugin message")]
+    ResponseTooLarge(usize),
     #[error("Internal repository error: {0}")]
     RepositoryError(#[from] RepositoryError),
+    #[error("Failed to resolve username: {0}")]
+    ResolveError(#[from] ResolveError),
 
     #[error("The provided duration is invalid")]
     InvalidDuration,
+    #[error("The provided chat component is invalid: {0}")]
+    InvalidMessage(serde_json::Error),
+    #[error("The provided network `{0}` is not a valid IP or CIDR")]
+    InvalidNetwork(String),
+    #[error("Protocol version {0} is not supported")]
+    UnsupportedVersion(u32),
+    #[error("The command signature is missing or invalid")]
+    Unauthorized,
+    #[error("The command requires the `{0}` permission")]
+    PermissionDenied(Permission),
+    #[error("Batches can't be nested")]
+    NestedBatch,
+    #[error("Batches can't hold more than {max} commands, got {0}", max = MAX_BATCH_SIZE)]
+    BatchTooLarge(usize),
+    #[error("Too many commands were sent")]
+    RateLimited,
+    #[error("The provided log level is invalid: {0}")]
+    InvalidLogLevel(tracing_subscriber::filter::ParseError),
+    #[error("{0}")]
+    InvalidPattern(#[from] InvalidWhitelistPattern),
+    #[error("The backend `{0}` is not configured")]
+    UnknownBackend(String),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(
-    tag = "type",
-    content = "data",
-    rename_all = "SCREAMING_SNAKE_CASE",
-    deny_unknown_fields
-)]
-pub enum CommandResult<T> {
-    Success(T),
-    Error(ErrorMessage),
+impl CommandError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            CommandError::CommandDecodeError(_) => ErrorCode::DecodeFailed,
+            CommandError::CommandEncodeError(_) | CommandError::ResponseTooLarge(_) => {
+                ErrorCode::EncodeFailed
+            }
+            CommandError::RepositoryError(error) => error.code(),
+            CommandError::ResolveError(_) => ErrorCode::UsernameResolutionFailed,
+            CommandError::InvalidDuration => ErrorCode::InvalidDuration,
+            CommandError::InvalidMessage(_) => ErrorCode::InvalidMessage,
+            CommandError::InvalidNetwork(_) => ErrorCode::InvalidNetwork,
+            CommandError::UnsupportedVersion(_) => ErrorCode::UnsupportedVersion,
+            CommandError::Unauthorized => ErrorCode::Unauthorized,
+            CommandError::PermissionDenied(_) => ErrorCode::PermissionDenied,
+            CommandError::NestedBatch | CommandError::BatchTooLarge(_) => ErrorCode::InvalidBatch,
+            CommandError::RateLimited => ErrorCode::RateLimited,
+            CommandError::InvalidLogLevel(_) => ErrorCode::InvalidLogLevel,
+            CommandError::InvalidPattern(_) => ErrorCode::InvalidPattern,
+            CommandError::UnknownBackend(_) => ErrorCode::UnknownBackend,
+        }
+    }
 }
 
-impl<T, E> From<Result<T, E>> for CommandResult<T>
-where
-    E: ToString,
-{
+impl From<CommandError> for ErrorMessage {
     #[inline]
-    fn from(value: Result<T, E>) -> Self {
-        match value {
-            Ok(v) => Self::Success(v),
-            Err(err) => Self::Error(ErrorMessage::from(err)),
-        }
+    fn from(value: CommandError) -> Self {
+        ErrorMessage::new(value.code(), value)
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(deny_unknown_fields)]
-pub struct ErrorMessage {
-    pub error: String,
+#[inline]
+pub fn into_command_result<T>(result: Result<T, CommandError>) -> CommandResult<T> {
+    result.into()
 }
 
-impl<T: ToString> From<T> for ErrorMessage {
-    #[inline]
-    fn from(value: T) -> Self {
-        Self {
-            error: value.to_string(),
-        }
+#[cfg(test)]
+mod tests {
+    use super::CommandError;
+    use crate::repository::RepositoryError;
+    use mc_proxy_protocol::{ErrorCode, ErrorMessage};
+
+    #[test]
+    fn test_error_codes() {
+        let message = ErrorMessage::from(CommandError::InvalidDuration);
+        assert_eq!(message.code, ErrorCode::InvalidDuration);
+        assert_eq!(message.error, "The provided duration is invalid");
+
+        let error = RepositoryError::from(sqlx::Error::PoolTimedOut);
+        assert_eq!(
+            CommandError::RepositoryError(error).code(),
+            ErrorCode::DatabaseUnavailable
+        );
+
+        let error = RepositoryError::from(sqlx::Error::RowNotFound);
+        assert_eq!(
+            CommandError::RepositoryError(error).code(),
+            ErrorCode::NotFound
+        );
+
+        let error = RepositoryError::from(sqlx::Error::Protocol("Unexpected".into()));
+        assert_eq!(
+            CommandError::RepositoryError(error).code(),
+            ErrorCode::DatabaseError
+        );
     }
 }
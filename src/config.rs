@@ -1,35 +1,1218 @@
-use crate::utils::{self, env, BoxDynError};
-use minecraft_protocol::data::chat::Message;
+use crate::{
+    backend::route::{BalanceStrategy, ProtocolVersions},
+    handler::{
+        brand::{BrandMode, BrandRewrite},
+        messages::DisconnectMessages,
+    },
+    repository::{health::BanCheckFailure, user_ip_bans::parse_network},
+    utils::{
+        self,
+        config::OneOrMany,
+        env,
+        logging::{LogFormat, LogOptions},
+        BoxDynError,
+    },
+};
+use mc_proxy_protocol::auth::Permission;
+use minecraft_protocol::data::chat::{legacy_to_message, Message};
 use serde::Deserialize;
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    path::Path,
+};
+
+/// The maximum length of the status response json, which must also fit the
+/// player count and version around the description.
+const MAX_STATUS_LENGTH: usize = 32767;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
-    #[serde(default = "default_listen_addr")]
-    pub listen_addr: SocketAddr,
-    pub proxied_addr: String,
+    /// One or more addresses to accept connections on
+    #[serde(alias = "listen_addr", default = "default_listen_addrs")]
+    pub listen_addrs: OneOrMany<SocketAddr>,
+    /// Path of a unix socket to also accept connections on
+    #[serde(default)]
+    pub listen_unix: Option<String>,
+    /// Whether the connections of the unix socket start with a PROXY protocol
+    /// header. Their clients have no address otherwise
+    #[serde(default = "default_listen_unix_proxy_protocol")]
+    pub listen_unix_proxy_protocol: bool,
+    /// Whether the connections of the TCP listeners start with a PROXY
+    /// protocol header, as sent by TCP load balancers, whose address is used
+    /// instead of the socket one. Connections without it are rejected
+    #[serde(default)]
+    pub receive_proxy_protocol: bool,
+    /// One or more backend addresses, used when no route matches the hostname
+    pub proxied_addr: OneOrMany<String>,
+    #[serde(default)]
+    pub balance_strategy: BalanceStrategy,
+    /// Backends selected by the hostname the players connect with
+    #[serde(default)]
+    pub routes: Vec<RouteConfig>,
+    /// Protocol versions players can log in with, unless their route sets its own
+    #[serde(default)]
+    pub protocol_versions: ProtocolVersions,
     pub sqlite_file: String,
-    pub server_status: Message,
+    /// How many sqlite connections the ban, whitelist and stats queries are
+    /// spread over
+    #[serde(default = "default_sqlite_max_connections")]
+    pub sqlite_max_connections: u32,
+    /// How many IP and username ban lookups are cached in memory, `0`
+    /// disables the cache
+    #[serde(default)]
+    pub ban_cache_size: usize,
+    /// Seconds after which cached ban lookups are read from the database
+    /// again, bounding how late the bans of other proxies are seen
+    #[serde(default = "default_ban_cache_ttl")]
+    pub ban_cache_ttl: u64,
+    /// Whether connections are rejected or let through when their bans can't
+    /// be checked because the database is unavailable
+    #[serde(default)]
+    pub ban_check_failure: BanCheckFailure,
+    /// The description of the status responses, required unless
+    /// `motd_legacy` is set
+    #[serde(default)]
+    pub server_status: Option<Message>,
+    /// The description as plain text with `&` or `§` color and formatting
+    /// codes, used instead of `server_status`
+    #[serde(default)]
+    pub motd_legacy: Option<String>,
+    /// Sent as `enforcesSecureChat` in the status responses when set, clients
+    /// warn about unsigned chat otherwise
+    #[serde(default)]
+    pub status_enforces_secure_chat: Option<bool>,
+    /// Sent as `previewsChat` in the status responses when set
+    #[serde(default)]
+    pub status_previews_chat: Option<bool>,
+    /// Sent to the connected players when the proxy shuts down
+    #[serde(default = "default_shutdown_message")]
+    pub shutdown_message: Message,
+    /// Hostnames clients must connect with, any hostname is accepted when unset
+    #[serde(default)]
+    pub allowed_hostnames: Option<Vec<String>>,
+    /// Networks or single IPs connections are only accepted from, before the
+    /// handshake. Any address is accepted when unset
+    #[serde(default)]
+    pub ip_allowlist: Option<Vec<String>>,
+    /// Port clients must connect with, as sent in the handshake. Any port is
+    /// accepted when unset
+    #[serde(default)]
+    pub expected_port: Option<u16>,
+    /// Sent to players logging in with a hostname or port that is not allowed
+    #[serde(default = "default_hostname_rejected_message")]
+    pub hostname_rejected_message: Message,
+    /// MaxMind database the client addresses are looked up in, either a
+    /// country or an ASN one. Requires the `geoip` feature
+    #[serde(default)]
+    pub geoip_db_path: Option<String>,
+    /// ISO country codes whose connections are rejected
+    #[serde(default)]
+    pub geoip_blocked_countries: Vec<String>,
+    /// Autonomous system numbers whose connections are rejected
+    #[serde(default)]
+    pub geoip_blocked_asns: Vec<u32>,
+    /// Plugin message channels forwarded between players and backends, all of
+    /// them when unset. A trailing `*` matches any channel with that prefix
+    #[serde(default)]
+    pub allowed_plugin_channels: Option<Vec<String>>,
+    /// Plugin message channels that are never forwarded
+    #[serde(default)]
+    pub denied_plugin_channels: Vec<String>,
+    /// Rewrites the brand of the backend seen by the players
+    #[serde(default)]
+    pub server_brand: BrandRewrite,
+    /// Rewrites the brand of the players seen by the backend
+    #[serde(default)]
+    pub client_brand: BrandRewrite,
+    /// Seconds after disconnecting during which a player can only log in again
+    /// from the same IP, disabled if unset
+    #[serde(default)]
+    pub session_ip_lock_secs: Option<u64>,
+    /// Usernames the session IP lock doesn't apply to
+    #[serde(default)]
+    pub session_ip_lock_bypass: Vec<String>,
+    /// Seconds the online players are kept in the database for the other
+    /// proxies sharing it, refreshed while they are online. The players are
+    /// only tracked by this proxy if unset
+    #[serde(default)]
+    pub presence_ttl_secs: Option<u64>,
+    /// Players relayed at once, the others wait in the login screen for a
+    /// slot to free. Unlimited if unset
+    #[serde(default)]
+    pub queue_slots: Option<usize>,
+    /// Seconds between the position updates sent to the waiting players
+    #[serde(default = "default_queue_update_interval")]
+    pub queue_update_interval: u64,
+    /// Sent to the waiting players, `%position%` is replaced by their
+    /// position in the queue
+    #[serde(default = "default_queue_message")]
+    pub queue_message: Message,
+    /// Usernames or uuids of the players let through the whitelist, bans
+    /// still apply to them
+    #[serde(default)]
+    pub whitelist_bypass: Vec<String>,
+    /// Sent to players rejected by the session IP lock
+    #[serde(default = "default_session_ip_lock_message")]
+    pub session_ip_lock_message: Message,
+    /// Sent to players logging in with the username or uuid of an online player
+    #[serde(default = "default_msg_already_logged_in")]
+    pub msg_already_logged_in: Message,
+    /// Sent to banned players, `%reason%` is replaced by the reason of the ban,
+    /// `%expires%` by the time left until it expires and `%created%` by the
+    /// date it was created on
+    #[serde(default = "default_msg_banned")]
+    pub msg_banned: Message,
+    /// Sent to players that are not whitelisted
+    #[serde(default = "default_msg_not_whitelisted")]
+    pub msg_not_whitelisted: Message,
+    /// Sent to players logging in with an unsupported minecraft version
+    #[serde(default = "default_msg_version_rejected")]
+    pub msg_version_rejected: Message,
+    /// Sent to players older than 1.20.5 transferred to another backend,
+    /// which must reconnect to join it
+    #[serde(default = "default_msg_transfer")]
+    pub msg_transfer: Message,
+    /// Sent to players logging in while their backend can't be reached
+    #[serde(default = "default_msg_backend_unavailable")]
+    pub msg_backend_unavailable: Message,
+    /// Seconds to wait for the players to disconnect when shutting down
+    #[serde(default = "default_shutdown_timeout")]
+    pub shutdown_timeout: u64,
+    /// Seconds a client has to send the handshake
+    #[serde(default = "default_handshake_timeout")]
+    pub handshake_timeout: u64,
+    /// Seconds a status connection is kept open
+    #[serde(default = "default_status_timeout")]
+    pub status_timeout: u64,
+    /// Seconds a client has to send the login start after the handshake
+    #[serde(default = "default_login_start_timeout")]
+    pub login_start_timeout: u64,
+    /// Seconds to wait for the backend when a player logs in
+    #[serde(default = "default_backend_connect_timeout")]
+    pub backend_connect_timeout: u64,
+    /// Packets a client can send after the handshake before it logs in, or
+    /// during the status exchange, before being disconnected
+    #[serde(default = "default_pre_login_max_packets")]
+    pub pre_login_max_packets: u32,
+    /// Bytes those packets can add up to
+    #[serde(default = "default_pre_login_max_bytes")]
+    pub pre_login_max_bytes: usize,
+
+    /// Secret used to authenticate the commands sent by the backend, commands
+    /// are not authenticated if unset
+    #[serde(default)]
+    pub command_secret: Option<String>,
+    /// Secret still accepted for `command_secret_grace_period` seconds after
+    /// startup, allowing the secret to be rotated
+    #[serde(default)]
+    pub command_previous_secret: Option<String>,
+    #[serde(default = "default_command_secret_grace_period")]
+    pub command_secret_grace_period: u64,
+    #[serde(default = "default_command_permission")]
+    pub command_permission: Permission,
+    /// Secrets and permissions of the commands sent by specific backends,
+    /// keyed by their address. Other backends use the `command_*` ones above
+    #[serde(default)]
+    pub command_backends: HashMap<String, CommandBackendConfig>,
+    /// For how many seconds command responses are held when there is no
+    /// connection to deliver them through
+    #[serde(default = "default_command_response_buffer_time")]
+    pub command_response_buffer_time: u64,
+    /// Commands larger than this many bytes are dropped
+    #[serde(default = "default_command_max_size")]
+    pub command_max_size: usize,
+    /// Commands accepted per second from each backend connection, the excess
+    /// is dropped. Each command of a batch counts as one
+    #[serde(default = "default_command_rate_limit")]
+    pub command_rate_limit: u32,
+    /// Address of the HTTP admin API, which accepts the same commands as the
+    /// plugin channel. Disabled if unset
+    #[serde(default)]
+    pub admin_addr: Option<SocketAddr>,
+    /// Bearer token required by the admin API
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    /// How often, in seconds, the player stats are written to the database
+    #[serde(default = "default_stats_flush_interval")]
+    pub stats_flush_interval: u64,
+    /// How often, in seconds, the buffered session records are written to the
+    /// database
+    #[serde(default = "default_write_flush_interval")]
+    pub write_flush_interval: u64,
+
+    #[serde(default = "default_listen_backlog")]
+    pub listen_backlog: u32,
+    /// Disables Nagle's algorithm on client and backend connections
+    #[serde(default = "default_tcp_nodelay")]
+    pub tcp_nodelay: bool,
+    /// Idle seconds before TCP keepalive probes are sent, `0` disables keepalive
+    #[serde(default = "default_tcp_keepalive_secs")]
+    pub tcp_keepalive_secs: u64,
+    /// Size in bytes of the kernel send buffer of client and backend
+    /// connections, `0` keeps the OS default
+    #[serde(default)]
+    pub tcp_send_buffer_size: usize,
+    /// Size in bytes of the kernel receive buffer of client and backend
+    /// connections, `0` keeps the OS default
+    #[serde(default)]
+    pub tcp_recv_buffer_size: usize,
+
+    /// How many idle connections to the backend are kept open, `0` disables pooling
+    #[serde(default)]
+    pub backend_pool_size: usize,
+    /// Seconds after which idle pooled connections are replaced
+    #[serde(default = "default_backend_pool_idle_secs")]
+    pub backend_pool_idle_secs: u64,
+    /// Milliseconds waited before trying the next backend after a failed
+    /// connection, doubled on every attempt and partly random, `0` disables it
+    #[serde(default = "default_backend_retry_backoff_ms")]
+    pub backend_retry_backoff_ms: u64,
+    /// Upper bound in milliseconds of the delay between backend attempts
+    #[serde(default = "default_backend_retry_backoff_max_ms")]
+    pub backend_retry_backoff_max_ms: u64,
+    /// Failed player connections in a row after which new players skip a
+    /// backend for `backend_circuit_breaker_cooldown` seconds
+    #[serde(default = "default_backend_circuit_breaker_threshold")]
+    pub backend_circuit_breaker_threshold: u32,
+    #[serde(default = "default_backend_circuit_breaker_cooldown")]
+    pub backend_circuit_breaker_cooldown: u64,
+
+    /// How often, in seconds, the backends are pinged
+    #[serde(default = "default_health_check_interval")]
+    pub health_check_interval: u64,
+    /// Consecutive failed pings after which a backend is considered down
+    #[serde(default = "default_health_check_failure_threshold")]
+    pub health_check_failure_threshold: u32,
+
+    /// Whitelisted usernames are resolved with the Mojang API and pinned to
+    /// the uuid of their account
+    #[serde(default)]
+    pub online_mode: bool,
+    /// Seconds for which resolved usernames are cached
+    #[serde(default = "default_username_cache_ttl")]
+    pub username_cache_ttl: u64,
+    /// Maximum username lookups sent to the Mojang API per minute
+    #[serde(default = "default_username_lookup_rate_limit")]
+    pub username_lookup_rate_limit: u32,
+
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// Log filter directives in the `RUST_LOG` syntax, which is used when
+    /// unset. Reloaded from the config on SIGHUP
+    #[serde(default)]
+    pub log_level: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteConfig {
+    pub hosts: Vec<String>,
+    pub backends: OneOrMany<String>,
+    #[serde(default)]
+    pub strategy: BalanceStrategy,
+    /// Protocol versions accepted by the backends of the route, the global
+    /// `protocol_versions` if unset
+    #[serde(default)]
+    pub protocol_versions: Option<ProtocolVersions>,
+    /// Sent to players logging in with other versions, `msg_version_rejected`
+    /// if unset
+    #[serde(default)]
+    pub version_rejected_message: Option<Message>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CommandBackendConfig {
+    pub secret: String,
+    /// Accepted for `command_secret_grace_period` seconds after startup
+    #[serde(default)]
+    pub previous_secret: Option<String>,
+    #[serde(default = "default_command_permission")]
+    pub permission: Permission,
 }
 
 impl utils::Config for Config {
     fn from_env_var() -> Result<Self, BoxDynError> {
         Ok(Self {
-            listen_addr: env::get_parsed_or("LISTEN_ADDR", default_listen_addr())?,
-            proxied_addr: env::get("PROXIED_ADDR")?,
-            sqlite_file: env::get_or("SQLITE_FILE", "proxy.sqlite".into()),
-            server_status: serde_json::from_str(&env::get("SERVER_STATUS")?)?,
+            listen_addrs: env::get_parsed_or("LISTEN_ADDR", default_listen_addrs())?,
+            listen_unix: env::get_optional("LISTEN_UNIX")?,
+            listen_unix_proxy_protocol: env::get_parsed_or(
+                "LISTEN_UNIX_PROXY_PROTOCOL",
+                default_listen_unix_proxy_protocol(),
+            )?,
+            receive_proxy_protocol: env::get_parsed_or("RECEIVE_PROXY_PROTOCOL", false)?,
+            proxied_addr: env::get_parsed("PROXIED_ADDR")?,
+            balance_strategy: env::get_parsed_or("BALANCE_STRATEGY", BalanceStrategy::default())?,
+            routes: Vec::new(),
+            protocol_versions: env::get_parsed_or(
+                "PROTOCOL_VERSIONS",
+                ProtocolVersions::default(),
+            )?,
+            sqlite_file: env::get_or("SQLITE_FILE", "proxy.sqlite".into())?,
+            sqlite_max_connections: env::get_parsed_or(
+                "SQLITE_MAX_CONNECTIONS",
+                default_sqlite_max_connections(),
+            )?,
+            ban_cache_size: env::get_parsed_or("BAN_CACHE_SIZE", 0)?,
+            ban_cache_ttl: env::get_parsed_or("BAN_CACHE_TTL", default_ban_cache_ttl())?,
+            ban_check_failure: env::get_parsed_or("BAN_CHECK_FAILURE", BanCheckFailure::default())?,
+            server_status: match env::get_optional("SERVER_STATUS")? {
+                Some(v) => Some(serde_json::from_str(&v)?),
+                None => None,
+            },
+            motd_legacy: env::get_optional("MOTD_LEGACY")?,
+            status_enforces_secure_chat: match env::get_optional("STATUS_ENFORCES_SECURE_CHAT")? {
+                Some(_) => Some(env::get_parsed("STATUS_ENFORCES_SECURE_CHAT")?),
+                None => None,
+            },
+            status_previews_chat: match env::get_optional("STATUS_PREVIEWS_CHAT")? {
+                Some(_) => Some(env::get_parsed("STATUS_PREVIEWS_CHAT")?),
+                None => None,
+            },
+            shutdown_message: message_from_env("SHUTDOWN_MESSAGE", default_shutdown_message)?,
+            shutdown_timeout: env::get_parsed_or("SHUTDOWN_TIMEOUT", default_shutdown_timeout())?,
+            handshake_timeout: env::get_parsed_or(
+                "HANDSHAKE_TIMEOUT",
+                default_handshake_timeout(),
+            )?,
+            status_timeout: env::get_parsed_or("STATUS_TIMEOUT", default_status_timeout())?,
+            login_start_timeout: env::get_parsed_or(
+                "LOGIN_START_TIMEOUT",
+                default_login_start_timeout(),
+            )?,
+            backend_connect_timeout: env::get_parsed_or(
+                "BACKEND_CONNECT_TIMEOUT",
+                default_backend_connect_timeout(),
+            )?,
+            pre_login_max_packets: env::get_parsed_or(
+                "PRE_LOGIN_MAX_PACKETS",
+                default_pre_login_max_packets(),
+            )?,
+            pre_login_max_bytes: env::get_parsed_or(
+                "PRE_LOGIN_MAX_BYTES",
+                default_pre_login_max_bytes(),
+            )?,
+            allowed_hostnames: env::get_optional("ALLOWED_HOSTNAMES")?.map(|v| split_list(&v)),
+            ip_allowlist: env::get_optional("IP_ALLOWLIST")?.map(|v| split_list(&v)),
+            expected_port: match env::get_optional("EXPECTED_PORT")? {
+                Some(_) => Some(env::get_parsed("EXPECTED_PORT")?),
+                None => None,
+            },
+            geoip_db_path: env::get_optional("GEOIP_DB_PATH")?,
+            geoip_blocked_countries: env::get_optional("GEOIP_BLOCKED_COUNTRIES")?
+                .map(|v| split_list(&v))
+                .unwrap_or_default(),
+            geoip_blocked_asns: match env::get_optional("GEOIP_BLOCKED_ASNS")? {
+                Some(v) => split_list(&v)
+                    .iter()
+                    .map(|v| v.parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|error| {
+                        env::EnvError::ParseError("GEOIP_BLOCKED_ASNS", Box::new(error))
+                    })?,
+                None => Vec::new(),
+            },
+            allowed_plugin_channels: env::get_optional("ALLOWED_PLUGIN_CHANNELS")?
+                .map(|v| split_list(&v)),
+            denied_plugin_channels: env::get_optional("DENIED_PLUGIN_CHANNELS")?
+                .map(|v| split_list(&v))
+                .unwrap_or_default(),
+            server_brand: brand_rewrite_from_env("SERVER_BRAND_REWRITE", "SERVER_BRAND_TEXT")?,
+            client_brand: brand_rewrite_from_env("CLIENT_BRAND_REWRITE", "CLIENT_BRAND_TEXT")?,
+            session_ip_lock_secs: match env::get_optional("SESSION_IP_LOCK_SECS")? {
+                Some(_) => Some(env::get_parsed("SESSION_IP_LOCK_SECS")?),
+                None => None,
+            },
+            session_ip_lock_bypass: env::get_optional("SESSION_IP_LOCK_BYPASS")?
+                .map(|v| split_list(&v))
+                .unwrap_or_default(),
+            presence_ttl_secs: match env::get_optional("PRESENCE_TTL_SECS")? {
+                Some(_) => Some(env::get_parsed("PRESENCE_TTL_SECS")?),
+                None => None,
+            },
+            queue_slots: match env::get_optional("QUEUE_SLOTS")? {
+                Some(_) => Some(env::get_parsed("QUEUE_SLOTS")?),
+                None => None,
+            },
+            queue_update_interval: env::get_parsed_or(
+                "QUEUE_UPDATE_INTERVAL",
+                default_queue_update_interval(),
+            )?,
+            queue_message: message_from_env("QUEUE_MESSAGE", default_queue_message)?,
+            whitelist_bypass: env::get_optional("WHITELIST_BYPASS")?
+                .map(|v| split_list(&v))
+                .unwrap_or_default(),
+            session_ip_lock_message: message_from_env(
+                "SESSION_IP_LOCK_MESSAGE",
+                default_session_ip_lock_message,
+            )?,
+            hostname_rejected_message: message_from_env(
+                "HOSTNAME_REJECTED_MESSAGE",
+                default_hostname_rejected_message,
+            )?,
+            msg_already_logged_in: message_from_env(
+                "MSG_ALREADY_LOGGED_IN",
+                default_msg_already_logged_in,
+            )?,
+            msg_banned: message_from_env("MSG_BANNED", default_msg_banned)?,
+            msg_not_whitelisted: message_from_env(
+                "MSG_NOT_WHITELISTED",
+                default_msg_not_whitelisted,
+            )?,
+            msg_version_rejected: message_from_env(
+                "MSG_VERSION_REJECTED",
+                default_msg_version_rejected,
+            )?,
+            msg_transfer: message_from_env("MSG_TRANSFER", default_msg_transfer)?,
+            msg_backend_unavailable: message_from_env(
+                "MSG_BACKEND_UNAVAILABLE",
+                default_msg_backend_unavailable,
+            )?,
+            command_secret: env::get_optional("COMMAND_SECRET")?,
+            command_previous_secret: env::get_optional("COMMAND_PREVIOUS_SECRET")?,
+            command_secret_grace_period: env::get_parsed_or(
+                "COMMAND_SECRET_GRACE_PERIOD",
+                default_command_secret_grace_period(),
+            )?,
+            command_permission: env::get_parsed_or(
+                "COMMAND_PERMISSION",
+                default_command_permission(),
+            )?,
+            command_backends: HashMap::new(),
+            admin_addr: match env::get_optional("ADMIN_ADDR")? {
+                Some(_) => Some(env::get_parsed("ADMIN_ADDR")?),
+                None => None,
+            },
+            admin_token: env::get_optional("ADMIN_TOKEN")?,
+            command_max_size: env::get_parsed_or("COMMAND_MAX_SIZE", default_command_max_size())?,
+            command_rate_limit: env::get_parsed_or(
+                "COMMAND_RATE_LIMIT",
+                default_command_rate_limit(),
+            )?,
+            command_response_buffer_time: env::get_parsed_or(
+                "COMMAND_RESPONSE_BUFFER_TIME",
+                default_command_response_buffer_time(),
+            )?,
+            stats_flush_interval: env::get_parsed_or(
+                "STATS_FLUSH_INTERVAL",
+                default_stats_flush_interval(),
+            )?,
+            write_flush_interval: env::get_parsed_or(
+                "WRITE_FLUSH_INTERVAL",
+                default_write_flush_interval(),
+            )?,
+            listen_backlog: env::get_parsed_or("LISTEN_BACKLOG", default_listen_backlog())?,
+            tcp_nodelay: env::get_parsed_or("TCP_NODELAY", default_tcp_nodelay())?,
+            tcp_keepalive_secs: env::get_parsed_or(
+                "TCP_KEEPALIVE_SECS",
+                default_tcp_keepalive_secs(),
+            )?,
+            tcp_send_buffer_size: env::get_parsed_or("TCP_SEND_BUFFER_SIZE", 0)?,
+            tcp_recv_buffer_size: env::get_parsed_or("TCP_RECV_BUFFER_SIZE", 0)?,
+            backend_pool_size: env::get_parsed_or("BACKEND_POOL_SIZE", 0)?,
+            backend_pool_idle_secs: env::get_parsed_or(
+                "BACKEND_POOL_IDLE_SECS",
+                default_backend_pool_idle_secs(),
+            )?,
+            backend_retry_backoff_ms: env::get_parsed_or(
+                "BACKEND_RETRY_BACKOFF_MS",
+                default_backend_retry_backoff_ms(),
+            )?,
+            backend_retry_backoff_max_ms: env::get_parsed_or(
+                "BACKEND_RETRY_BACKOFF_MAX_MS",
+                default_backend_retry_backoff_max_ms(),
+            )?,
+            backend_circuit_breaker_threshold: env::get_parsed_or(
+                "BACKEND_CIRCUIT_BREAKER_THRESHOLD",
+                default_backend_circuit_breaker_threshold(),
+            )?,
+            backend_circuit_breaker_cooldown: env::get_parsed_or(
+                "BACKEND_CIRCUIT_BREAKER_COOLDOWN",
+                default_backend_circuit_breaker_cooldown(),
+            )?,
+            health_check_interval: env::get_parsed_or(
+                "HEALTH_CHECK_INTERVAL",
+                default_health_check_interval(),
+            )?,
+            health_check_failure_threshold: env::get_parsed_or(
+                "HEALTH_CHECK_FAILURE_THRESHOLD",
+                default_health_check_failure_threshold(),
+            )?,
+            online_mode: env::get_parsed_or("ONLINE_MODE", false)?,
+            username_cache_ttl: env::get_parsed_or(
+                "USERNAME_CACHE_TTL",
+                default_username_cache_ttl(),
+            )?,
+            username_lookup_rate_limit: env::get_parsed_or(
+                "USERNAME_LOOKUP_RATE_LIMIT",
+                default_username_lookup_rate_limit(),
+            )?,
+            log_format: env::get_parsed_or("LOG_FORMAT", LogFormat::default())?,
+            log_level: env::get_optional("LOG_LEVEL")?,
         })
     }
+
+    fn log_options(&self) -> LogOptions {
+        LogOptions {
+            format: self.log_format,
+            level: self.log_level.clone(),
+        }
+    }
+
+    fn validate(&self) -> Result<(), BoxDynError> {
+        let errors = self.validation_errors();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Box::new(ConfigErrors(errors)))
+        }
+    }
+}
+
+/// A config value that can't be used, named after its field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Every invalid value of the config, reported together so that they can be
+/// fixed in one go.
+#[derive(Debug)]
+pub struct ConfigErrors(pub Vec<FieldError>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for error in &self.0 {
+            write!(f, "\n  {}: {}", error.field, error.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+impl Config {
+    /// The description of the status responses, parsed from `motd_legacy`
+    /// when set.
+    pub fn server_description(&self) -> Message {
+        match (&self.motd_legacy, &self.server_status) {
+            (Some(motd), _) => legacy_to_message(motd),
+            (None, Some(message)) => message.clone(),
+            (None, None) => Message::from_str(""),
+        }
+    }
+
+    fn validation_errors(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        let listen_addrs = self.listen_addrs.clone().into_vec();
+
+        if listen_addrs.is_empty() && self.listen_unix.is_none() {
+            errors.push(FieldError::new(
+                "listen_addrs",
+                "at least one address is required",
+            ));
+        }
+        if let Some(path) = &self.listen_unix {
+            if let Err(message) = check_writable_parent(path) {
+                errors.push(FieldError::new("listen_unix", message));
+            }
+        }
+
+        let proxied_addrs = self.proxied_addr.clone().into_vec();
+        if proxied_addrs.is_empty() {
+            errors.push(FieldError::new(
+                "proxied_addr",
+                "at least one address is required",
+            ));
+        }
+        check_backends("proxied_addr", &proxied_addrs, &listen_addrs, &mut errors);
+
+        for (i, route) in self.routes.iter().enumerate() {
+            let backends = route.backends.clone().into_vec();
+            if route.hosts.is_empty() {
+                errors.push(FieldError::new(
+                    format!("routes[{i}].hosts"),
+                    "at least one host is required",
+                ));
+            }
+            if backends.is_empty() {
+                errors.push(FieldError::new(
+                    format!("routes[{i}].backends"),
+                    "at least one address is required",
+                ));
+            }
+            if route
+                .protocol_versions
+                .as_ref()
+                .is_some_and(|v| v.is_empty())
+            {
+                errors.push(FieldError::new(
+                    format!("routes[{i}].protocol_versions"),
+                    "at least one version is required",
+                ));
+            }
+            check_backends(
+                &format!("routes[{i}].backends"),
+                &backends,
+                &listen_addrs,
+                &mut errors,
+            );
+        }
+
+        for address in self.command_backends.keys() {
+            let known = proxied_addrs.contains(address)
+                || self
+                    .routes
+                    .iter()
+                    .any(|route| route.backends.clone().into_vec().contains(address));
+            if !known {
+                errors.push(FieldError::new(
+                    format!("command_backends.{address}"),
+                    "not one of the backends of the proxy",
+                ));
+            }
+        }
+
+        if let Some(addr) = self.admin_addr {
+            if self.admin_token.as_deref().is_none_or(str::is_empty) {
+                errors.push(FieldError::new(
+                    "admin_token",
+                    "required when the admin API is enabled",
+                ));
+            }
+            if listen_addrs.contains(&addr) {
+                errors.push(FieldError::new(
+                    "admin_addr",
+                    "must not be one of the listen addresses",
+                ));
+            }
+        }
+
+        if self.protocol_versions.is_empty() {
+            errors.push(FieldError::new(
+                "protocol_versions",
+                "at least one version is required",
+            ));
+        }
+
+        if let Err(message) = check_writable_parent(&self.sqlite_file) {
+            errors.push(FieldError::new("sqlite_file", message));
+        }
+
+        match (&self.server_status, &self.motd_legacy) {
+            (None, None) => errors.push(FieldError::new(
+                "server_status",
+                "required unless motd_legacy is set",
+            )),
+            (Some(_), Some(_)) => errors.push(FieldError::new(
+                "motd_legacy",
+                "can't be set along with server_status",
+            )),
+            _ => {}
+        }
+
+        let description_field = match self.motd_legacy {
+            Some(_) => "motd_legacy",
+            None => "server_status",
+        };
+        match serde_json::to_string(&self.server_description()) {
+            Ok(v) if v.len() > MAX_STATUS_LENGTH => errors.push(FieldError::new(
+                description_field,
+                format!("the description can't be longer than {MAX_STATUS_LENGTH} bytes"),
+            )),
+            Ok(_) => {}
+            Err(error) => errors.push(FieldError::new(description_field, error.to_string())),
+        }
+
+        let positive = [
+            ("sqlite_max_connections", self.sqlite_max_connections.into()),
+            ("handshake_timeout", self.handshake_timeout),
+            ("status_timeout", self.status_timeout),
+            ("login_start_timeout", self.login_start_timeout),
+            ("backend_connect_timeout", self.backend_connect_timeout),
+            ("pre_login_max_packets", self.pre_login_max_packets.into()),
+            ("pre_login_max_bytes", self.pre_login_max_bytes as u64),
+            ("stats_flush_interval", self.stats_flush_interval),
+            ("write_flush_interval", self.write_flush_interval),
+            ("ban_cache_ttl", self.ban_cache_ttl),
+            ("command_max_size", self.command_max_size as u64),
+            ("command_rate_limit", self.command_rate_limit.into()),
+            ("health_check_interval", self.health_check_interval),
+            ("listen_backlog", self.listen_backlog.into()),
+            (
+                "health_check_failure_threshold",
+                self.health_check_failure_threshold.into(),
+            ),
+            (
+                "backend_circuit_breaker_threshold",
+                self.backend_circuit_breaker_threshold.into(),
+            ),
+            (
+                "backend_circuit_breaker_cooldown",
+                self.backend_circuit_breaker_cooldown,
+            ),
+        ];
+        for (field, value) in positive {
+            if value == 0 {
+                errors.push(FieldError::new(field, "must be greater than 0"));
+            }
+        }
+
+        if self.listen_backlog > i32::MAX as u32 {
+            errors.push(FieldError::new(
+                "listen_backlog",
+                format!("can't be greater than {}", i32::MAX),
+            ));
+        }
+        for (field, size) in [
+            ("tcp_send_buffer_size", self.tcp_send_buffer_size),
+            ("tcp_recv_buffer_size", self.tcp_recv_buffer_size),
+        ] {
+            if size > i32::MAX as usize {
+                errors.push(FieldError::new(
+                    field,
+                    format!("can't be greater than {}", i32::MAX),
+                ));
+            }
+        }
+        if let Some(level) = &self.log_level {
+            if let Err(error) = tracing_subscriber::EnvFilter::try_new(level) {
+                errors.push(FieldError::new("log_level", error.to_string()));
+            }
+        }
+        if self.session_ip_lock_secs == Some(0) {
+            errors.push(FieldError::new(
+                "session_ip_lock_secs",
+                "must be greater than 0, leave it unset to disable the lock",
+            ));
+        }
+        if self.presence_ttl_secs == Some(0) {
+            errors.push(FieldError::new(
+                "presence_ttl_secs",
+                "must be greater than 0, leave it unset to not share the online players",
+            ));
+        }
+        if self.queue_slots == Some(0) {
+            errors.push(FieldError::new(
+                "queue_slots",
+                "must be greater than 0, leave it unset to disable the queue",
+            ));
+        }
+        // Clients time out after 30 seconds without receiving packets
+        if self.queue_update_interval == 0 || self.queue_update_interval >= 30 {
+            errors.push(FieldError::new(
+                "queue_update_interval",
+                "must be between 1 and 29",
+            ));
+        }
+        if let Some(allowlist) = &self.ip_allowlist {
+            if allowlist.is_empty() {
+                errors.push(FieldError::new(
+                    "ip_allowlist",
+                    "at least one network is required, leave it unset to accept any address",
+                ));
+            }
+            for (i, network) in allowlist.iter().enumerate() {
+                if parse_network(network).is_err() {
+                    errors.push(FieldError::new(
+                        format!("ip_allowlist[{i}]"),
+                        format!("`{network}` is not a network nor an IP"),
+                    ));
+                }
+            }
+        }
+        if self.geoip_db_path.is_some() && !cfg!(feature = "geoip") {
+            errors.push(FieldError::new(
+                "geoip_db_path",
+                "requires building with the geoip feature",
+            ));
+        }
+        if self.geoip_db_path.is_none()
+            && !(self.geoip_blocked_countries.is_empty() && self.geoip_blocked_asns.is_empty())
+        {
+            errors.push(FieldError::new(
+                "geoip_db_path",
+                "is required to block countries or networks",
+            ));
+        }
+        if self.expected_port == Some(0) {
+            errors.push(FieldError::new(
+                "expected_port",
+                "must be greater than 0, leave it unset to accept any port",
+            ));
+        }
+        if self.backend_pool_size > 0 && self.backend_pool_idle_secs == 0 {
+            errors.push(FieldError::new(
+                "backend_pool_idle_secs",
+                "must be greater than 0 when pooling is enabled",
+            ));
+        }
+        if self.backend_retry_backoff_max_ms < self.backend_retry_backoff_ms {
+            errors.push(FieldError::new(
+                "backend_retry_backoff_max_ms",
+                "can't be lower than backend_retry_backoff_ms",
+            ));
+        }
+        if self.online_mode && self.username_lookup_rate_limit == 0 {
+            errors.push(FieldError::new(
+                "username_lookup_rate_limit",
+                "must be greater than 0 when online mode is enabled",
+            ));
+        }
+
+        errors
+    }
+}
+
+/// Backends must be `host:port` and must not point back to the proxy itself.
+fn check_backends(
+    field: &str,
+    backends: &[String],
+    listen_addrs: &[SocketAddr],
+    errors: &mut Vec<FieldError>,
+) {
+    for backend in backends {
+        let Some((host, port)) = split_host_port(backend) else {
+            errors.push(FieldError::new(
+                field,
+                format!("`{backend}` is not a valid host:port address"),
+            ));
+            continue;
+        };
+
+        if listen_addrs.iter().any(|v| points_to(*v, host, port)) {
+            errors.push(FieldError::new(
+                field,
+                format!("`{backend}` is one of the listen addresses of the proxy"),
+            ));
+        }
+    }
+}
+
+fn split_host_port(address: &str) -> Option<(&str, u16)> {
+    let (host, port) = address.rsplit_once(':')?;
+    let port = port.parse().ok()?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|v| v.strip_suffix(']'))
+        .unwrap_or(host);
+
+    if host.is_empty() || (host.contains(':') && host.parse::<IpAddr>().is_err()) {
+        return None;
+    }
+
+    Some((host, port))
+}
+
+/// Whether connecting to `host:port` reaches the listener bound to `listen`.
+fn points_to(listen: SocketAddr, host: &str, port: u16) -> bool {
+    if listen.port() != port {
+        return false;
+    }
+
+    let ip = if host.eq_ignore_ascii_case("localhost") {
+        IpAddr::V4(Ipv4Addr::LOCALHOST)
+    } else {
+        match host.parse::<IpAddr>() {
+            Ok(v) => v.to_canonical(),
+            Err(_) => return false,
+        }
+    };
+
+    let listen_ip = listen.ip().to_canonical();
+    ip == listen_ip || (listen_ip.is_unspecified() && (ip.is_loopback() || ip.is_unspecified()))
+}
+
+/// The file and its missing directories are created on startup, so only the
+/// closest existing directory must accept new files.
+fn check_writable_parent(file: &str) -> Result<(), String> {
+    let mut parent = match Path::new(file).parent() {
+        Some(v) if !v.as_os_str().is_empty() => v,
+        _ => Path::new("."),
+    };
+
+    loop {
+        match fs::metadata(parent) {
+            Ok(v) if v.is_dir() => break,
+            Ok(_) => return Err(format!("`{}` is not a directory", parent.display())),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                let next = match parent.parent() {
+                    Some(v) if !v.as_os_str().is_empty() => v,
+                    _ => Path::new("."),
+                };
+                // The working directory itself was deleted
+                if next == parent {
+                    return Err(format!("directory `{}`: {error}", parent.display()));
+                }
+                parent = next;
+            }
+            Err(error) => return Err(format!("directory `{}`: {error}", parent.display())),
+        }
+    }
+
+    let probe = parent.join(format!(".mc-proxy-{}.probe", std::process::id()));
+    match fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = fs::remove_file(&probe);
+            Ok(())
+        }
+        Err(error) => Err(format!(
+            "directory `{}` is not writable: {error}",
+            parent.display()
+        )),
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',').map(|v| v.trim().to_owned()).collect()
+}
+
+/// Messages are given as chat component json.
+fn message_from_env(key: &'static str, default: fn() -> Message) -> Result<Message, BoxDynError> {
+    match env::get_optional(key)? {
+        Some(v) => Ok(serde_json::from_str(&v)?),
+        None => Ok(default()),
+    }
+}
+
+/// The text is only required when the mode is not `off`.
+fn brand_rewrite_from_env(
+    mode_key: &'static str,
+    text_key: &'static str,
+) -> Result<BrandRewrite, BoxDynError> {
+    let mode = env::get_parsed_or(mode_key, BrandMode::Off)?;
+    if mode == BrandMode::Off {
+        return Ok(BrandRewrite::Off);
+    }
+
+    Ok(BrandRewrite::new(mode, env::get_maybe_file(text_key)?))
+}
+
+const fn default_listen_addrs() -> OneOrMany<SocketAddr> {
+    OneOrMany::One(SocketAddr::V4(SocketAddrV4::new(
+        Ipv4Addr::new(0, 0, 0, 0),
+        25565,
+    )))
+}
+
+fn default_shutdown_message() -> Message {
+    Message::from_str("Server restarting")
+}
+
+fn default_hostname_rejected_message() -> Message {
+    Message::from_str("Please connect using the server address")
+}
+
+fn default_session_ip_lock_message() -> Message {
+    Message::from_str("You recently played from another location, try again later")
+}
+
+fn default_msg_already_logged_in() -> Message {
+    DisconnectMessages::default().already_logged_in
+}
+
+fn default_msg_banned() -> Message {
+    DisconnectMessages::default().banned
+}
+
+fn default_msg_not_whitelisted() -> Message {
+    DisconnectMessages::default().not_whitelisted
+}
+
+fn default_msg_version_rejected() -> Message {
+    DisconnectMessages::default().version_rejected
+}
+
+fn default_msg_transfer() -> Message {
+    DisconnectMessages::default().transfer
+}
+
+fn default_msg_backend_unavailable() -> Message {
+    DisconnectMessages::default().backend_unavailable
+}
+
+const fn default_shutdown_timeout() -> u64 {
+    10
+}
+
+const fn default_handshake_timeout() -> u64 {
+    5
+}
+
+const fn default_queue_update_interval() -> u64 {
+    5
+}
+
+fn default_queue_message() -> Message {
+    Message::from_str("You are #%position% in the queue")
+}
+
+const fn default_status_timeout() -> u64 {
+    10
+}
+
+const fn default_login_start_timeout() -> u64 {
+    10
+}
+
+const fn default_backend_connect_timeout() -> u64 {
+    10
+}
+
+const fn default_pre_login_max_packets() -> u32 {
+    4
+}
+
+const fn default_pre_login_max_bytes() -> usize {
+    8192
+}
+
+const fn default_command_secret_grace_period() -> u64 {
+    60 * 60
+}
+
+const fn default_command_permission() -> Permission {
+    Permission::Full
+}
+
+const fn default_command_response_buffer_time() -> u64 {
+    30
+}
+
+const fn default_stats_flush_interval() -> u64 {
+    60
+}
+
+const fn default_command_max_size() -> usize {
+    32767
+}
+
+const fn default_command_rate_limit() -> u32 {
+    20
+}
+
+const fn default_write_flush_interval() -> u64 {
+    1
+}
+
+const fn default_listen_backlog() -> u32 {
+    1024
+}
+
+const fn default_listen_unix_proxy_protocol() -> bool {
+    true
+}
+
+const fn default_tcp_nodelay() -> bool {
+    true
+}
+
+const fn default_tcp_keepalive_secs() -> u64 {
+    60
+}
+
+const fn default_sqlite_max_connections() -> u32 {
+    10
+}
+
+const fn default_ban_cache_ttl() -> u64 {
+    10
+}
+
+const fn default_backend_pool_idle_secs() -> u64 {
+    30
+}
+
+const fn default_backend_retry_backoff_ms() -> u64 {
+    100
+}
+
+const fn default_backend_retry_backoff_max_ms() -> u64 {
+    2000
+}
+
+const fn default_backend_circuit_breaker_threshold() -> u32 {
+    5
+}
+
+const fn default_backend_circuit_breaker_cooldown() -> u64 {
+    10
+}
+
+const fn default_health_check_interval() -> u64 {
+    10
+}
+
+const fn default_health_check_failure_threshold() -> u32 {
+    3
+}
+
+const fn default_username_cache_ttl() -> u64 {
+    24 * 60 * 60
 }
 
-const fn default_listen_addr() -> SocketAddr {
-    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 25565))
+const fn default_username_lookup_rate_limit() -> u32 {
+    60
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Config;
+    use super::{Config, ConfigErrors, OneOrMany};
+    use crate::utils::Config as _;
+    use minecraft_protocol::data::chat::{legacy_to_message, Message};
+    use std::net::SocketAddr;
+
+    fn config_with(listen: &str) -> Config {
+        let json = format!(
+            r#"{{
+                {listen}
+                "proxied_addr": "localhost:25566",
+                "sqlite_file": "proxy.sqlite",
+                "server_status": "Minecraft Server"
+            }}"#
+        );
+
+        serde_json::from_str(&json).unwrap()
+    }
+
+    fn listen_addrs(config: Config) -> Vec<SocketAddr> {
+        config.listen_addrs.into_vec()
+    }
+
+    #[test]
+    fn test_single_listen_addr() {
+        let config = config_with(r#""listen_addr": "127.0.0.1:25565","#);
+        assert_eq!(listen_addrs(config), ["127.0.0.1:25565".parse().unwrap()]);
+    }
+
+    #[test]
+    fn test_many_listen_addrs() {
+        let config = config_with(r#""listen_addrs": ["0.0.0.0:25565", "[::]:25565"],"#);
+        assert_eq!(
+            listen_addrs(config),
+            [
+                "0.0.0.0:25565".parse::<SocketAddr>().unwrap(),
+                "[::]:25565".parse().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_default_listen_addr() {
+        let config = config_with("");
+        assert_eq!(listen_addrs(config), ["0.0.0.0:25565".parse().unwrap()]);
+    }
 
     #[test]
     fn assert_json_config_parses() {
@@ -38,4 +1221,297 @@ mod tests {
         serde_json::from_str::<'_, Config>(CONFIG_FILE)
             .expect("Failed to parse config.example.json");
     }
+
+    #[test]
+    fn assert_toml_config_parses() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/config.example.toml");
+        let config = Config::from_file(path.into()).expect("Failed to parse config.example.toml");
+
+        assert_eq!(config.routes.len(), 2);
+        assert!(config.routes[1]
+            .protocol_versions
+            .as_ref()
+            .unwrap()
+            .contains(47));
+        assert!(config.routes[1].version_rejected_message.is_some());
+    }
+
+    /// The fields of the validation errors of the config
+    fn invalid_fields(config: &Config) -> Vec<String> {
+        config
+            .validation_errors()
+            .into_iter()
+            .map(|v| v.field)
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_config() {
+        let config = config_with("");
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_proxied_addr_requires_port() {
+        let mut config = config_with("");
+        config.proxied_addr = OneOrMany::Many(vec![
+            "localhost".into(),
+            "[::1]:25566".into(),
+            "::1".into(),
+            "host:port".into(),
+        ]);
+
+        assert_eq!(
+            invalid_fields(&config),
+            ["proxied_addr", "proxied_addr", "proxied_addr"]
+        );
+    }
+
+    #[test]
+    fn test_listen_unix() {
+        let mut config = config_with(r#""listen_addrs": [],"#);
+        assert_eq!(invalid_fields(&config), ["listen_addrs"]);
+
+        // The PROXY protocol is set separately from the TCP listeners
+        config.listen_unix = Some("mc-proxy.sock".into());
+        assert!(config.validate().is_ok());
+        assert!(config.listen_unix_proxy_protocol);
+        assert!(!config.receive_proxy_protocol);
+
+        config.listen_unix = Some("Cargo.toml/mc-proxy.sock".into());
+        assert_eq!(invalid_fields(&config), ["listen_unix"]);
+    }
+
+    #[test]
+    fn test_proxied_addr_is_not_listen_addr() {
+        let mut config = config_with(r#""listen_addr": "0.0.0.0:25565","#);
+        config.proxied_addr = OneOrMany::One("localhost:25565".into());
+        assert_eq!(invalid_fields(&config), ["proxied_addr"]);
+
+        config.proxied_addr = OneOrMany::One("127.0.0.1:25566".into());
+        assert!(invalid_fields(&config).is_empty());
+
+        let mut config = config_with(r#""listen_addr": "127.0.0.1:25565","#);
+        config.proxied_addr = OneOrMany::One("192.0.2.1:25565".into());
+        assert!(invalid_fields(&config).is_empty());
+    }
+
+    #[test]
+    fn test_route_backends() {
+        let json = r#"{
+            "proxied_addr": "localhost:25566",
+            "sqlite_file": "proxy.sqlite",
+            "server_status": "Minecraft Server",
+            "routes": [
+                { "hosts": ["lobby.example.com"], "backends": "localhost:25567" },
+                { "hosts": [], "backends": ["localhost"], "protocol_versions": [] }
+            ]
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            invalid_fields(&config),
+            [
+                "routes[1].hosts",
+                "routes[1].protocol_versions",
+                "routes[1].backends"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_command_backends() {
+        let json = r#"{
+            "proxied_addr": "localhost:25566",
+            "sqlite_file": "proxy.sqlite",
+            "server_status": "Minecraft Server",
+            "routes": [{ "hosts": ["lobby.example.com"], "backends": "localhost:25567" }],
+            "command_backends": {
+                "localhost:25567": { "secret": "lobby", "permission": "read_only" },
+                "localhost:25568": { "secret": "unknown" }
+            }
+        }"#;
+        let config: Config = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            invalid_fields(&config),
+            ["command_backends.localhost:25568"]
+        );
+    }
+
+    #[test]
+    fn test_sqlite_directory() {
+        let mut config = config_with("");
+        config.sqlite_file = "Cargo.toml/proxy.sqlite".into();
+        assert_eq!(invalid_fields(&config), ["sqlite_file"]);
+
+        // Created on startup
+        config.sqlite_file = "does-not-exist/data/proxy.sqlite".into();
+        assert!(invalid_fields(&config).is_empty());
+
+        config.sqlite_file = std::env::temp_dir()
+            .join("proxy.sqlite")
+            .to_string_lossy()
+            .into_owned();
+        assert!(invalid_fields(&config).is_empty());
+    }
+
+    #[test]
+    fn test_admin_api_requires_token() {
+        let mut config = config_with(r#""listen_addr": "0.0.0.0:25565","#);
+        config.admin_addr = Some("0.0.0.0:25565".parse().unwrap());
+        assert_eq!(invalid_fields(&config), ["admin_token", "admin_addr"]);
+
+        config.admin_addr = Some("127.0.0.1:8080".parse().unwrap());
+        config.admin_token = Some("token".into());
+        assert!(invalid_fields(&config).is_empty());
+    }
+
+    #[test]
+    fn test_server_status_length() {
+        let json = format!(
+            r#"{{
+                "proxied_addr": "localhost:25566",
+                "sqlite_file": "proxy.sqlite",
+                "server_status": "{}"
+            }}"#,
+            "a".repeat(40_000)
+        );
+        let config: Config = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(invalid_fields(&config), ["server_status"]);
+    }
+
+    #[test]
+    fn test_motd_legacy() {
+        let json = r#"{
+            "proxied_addr": "localhost:25566",
+            "sqlite_file": "proxy.sqlite",
+            "motd_legacy": "&6Minecraft &lServer"
+        }"#;
+        let mut config: Config = serde_json::from_str(json).unwrap();
+
+        assert!(invalid_fields(&config).is_empty());
+        assert_eq!(
+            config.server_description(),
+            legacy_to_message("&6Minecraft &lServer")
+        );
+        assert_eq!(
+            config.server_description().to_plain_text(),
+            "Minecraft Server"
+        );
+
+        config.server_status = Some(Message::from_str("Minecraft Server"));
+        assert_eq!(invalid_fields(&config), ["motd_legacy"]);
+
+        config.server_status = None;
+        config.motd_legacy = None;
+        assert_eq!(invalid_fields(&config), ["server_status"]);
+
+        config.motd_legacy = Some("&a".repeat(40_000));
+        assert!(invalid_fields(&config).is_empty());
+        config.motd_legacy = Some("a".repeat(40_000));
+        assert_eq!(invalid_fields(&config), ["motd_legacy"]);
+    }
+
+    #[test]
+    fn test_numeric_limits() {
+        let mut config = config_with("");
+        config.sqlite_max_connections = 0;
+        config.handshake_timeout = 0;
+        config.pre_login_max_packets = 0;
+        config.stats_flush_interval = 0;
+        config.write_flush_interval = 0;
+        config.ban_cache_ttl = 0;
+        config.backend_circuit_breaker_threshold = 0;
+        config.listen_backlog = u32::MAX;
+        config.tcp_recv_buffer_size = usize::MAX;
+        config.session_ip_lock_secs = Some(0);
+        config.presence_ttl_secs = Some(0);
+        config.queue_slots = Some(0);
+        config.queue_update_interval = 30;
+        config.expected_port = Some(0);
+        config.backend_pool_size = 4;
+        config.backend_pool_idle_secs = 0;
+        config.backend_retry_backoff_ms = 500;
+        config.backend_retry_backoff_max_ms = 100;
+        config.online_mode = true;
+        config.username_lookup_rate_limit = 0;
+
+        assert_eq!(
+            invalid_fields(&config),
+            [
+                "sqlite_max_connections",
+                "handshake_timeout",
+                "pre_login_max_packets",
+                "stats_flush_interval",
+                "write_flush_interval",
+                "ban_cache_ttl",
+                "backend_circuit_breaker_threshold",
+                "listen_backlog",
+                "tcp_recv_buffer_size",
+                "session_ip_lock_secs",
+                "presence_ttl_secs",
+                "queue_slots",
+                "queue_update_interval",
+                "expected_port",
+                "backend_pool_idle_secs",
+                "backend_retry_backoff_max_ms",
+                "username_lookup_rate_limit",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_ip_allowlist() {
+        let mut config = config_with("");
+        config.ip_allowlist = Some(vec!["10.0.0.0/8".into(), "203.0.113.7".into()]);
+        assert!(invalid_fields(&config).is_empty());
+
+        config.ip_allowlist = Some(vec!["10.0.0.0/8".into(), "10.0.0.0/64".into()]);
+        assert_eq!(invalid_fields(&config), ["ip_allowlist[1]"]);
+
+        config.ip_allowlist = Some(Vec::new());
+        assert_eq!(invalid_fields(&config), ["ip_allowlist"]);
+    }
+
+    #[test]
+    fn test_geoip_blocklist_requires_database() {
+        let mut config = config_with("");
+        config.geoip_blocked_asns = vec![64496];
+        assert_eq!(invalid_fields(&config), ["geoip_db_path"]);
+
+        config.geoip_db_path = Some("GeoLite2-ASN.mmdb".into());
+        let expected: &[&str] = if cfg!(feature = "geoip") {
+            &[]
+        } else {
+            &["geoip_db_path"]
+        };
+        assert_eq!(invalid_fields(&config), expected);
+    }
+
+    #[test]
+    fn test_invalid_log_level() {
+        let mut config = config_with("");
+        config.log_level = Some("info,mc_proxy=debug".into());
+        assert!(invalid_fields(&config).is_empty());
+
+        config.log_level = Some("mc_proxy=loud".into());
+        assert_eq!(invalid_fields(&config), ["log_level"]);
+    }
+
+    #[test]
+    fn test_errors_are_reported_together() {
+        let mut config = config_with("");
+        config.proxied_addr = OneOrMany::One("localhost".into());
+        config.status_timeout = 0;
+
+        let error = config.validate().unwrap_err();
+        let errors = error.downcast_ref::<ConfigErrors>().unwrap();
+        assert_eq!(errors.0.len(), 2);
+
+        let message = error.to_string();
+        assert!(message.contains("proxied_addr: `localhost`"));
+        assert!(message.contains("status_timeout: must be greater than 0"));
+    }
 }
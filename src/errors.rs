@@ -14,6 +14,20 @@ pub enum AppError {
     #[error("Internal repository error: {0}")]
     RepositoryError(#[from] RepositoryError),
 
+    #[error("Timed out waiting for the client")]
+    Timeout,
+
     #[error("Command error:")]
     CommandError(#[from] CommandError),
 }
+
+impl AppError {
+    /// Whether the peer closed the connection.
+    pub fn is_eof_error(&self) -> bool {
+        match self {
+            AppError::IoError(error) => error.kind() == std::io::ErrorKind::UnexpectedEof,
+            AppError::PacketDecodeError(error) => error.is_eof_error(),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Rejects connections coming from blocked countries or networks, looked up
+//! in a MaxMind database.
+
+use maxminddb::{MaxMindDBError, Reader};
+use serde::Deserialize;
+use std::{collections::HashSet, fmt, net::IpAddr, path::Path};
+
+/// The fields used from either a country or an ASN database, databases
+/// combining both are supported as well.
+#[derive(Debug, Default, Deserialize)]
+struct GeoRecord {
+    country: Option<Country>,
+    autonomous_system_number: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Country {
+    iso_code: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GeoRejection {
+    Country(String),
+    Asn(u32),
+}
+
+impl fmt::Display for GeoRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeoRejection::Country(code) => write!(f, "country {code}"),
+            GeoRejection::Asn(asn) => write!(f, "AS{asn}"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct GeoBlocklist {
+    /// ISO 3166-1 alpha-2 codes, in uppercase
+    countries: HashSet<String>,
+    asns: HashSet<u32>,
+}
+
+impl GeoBlocklist {
+    fn check(&self, record: &GeoRecord) -> Option<GeoRejection> {
+        let country = record.country.as_ref().and_then(|v| v.iso_code.as_ref());
+        if let Some(code) = country.filter(|v| self.countries.contains(*v)) {
+            return Some(GeoRejection::Country(code.clone()));
+        }
+
+        record
+            .autonomous_system_number
+            .filter(|v| self.asns.contains(v))
+            .map(GeoRejection::Asn)
+    }
+}
+
+/// The whole database is read into memory when loaded, lookups don't touch
+/// the disk.
+pub struct GeoFilter {
+    reader: Reader<Vec<u8>>,
+    blocklist: GeoBlocklist,
+}
+
+impl GeoFilter {
+    pub fn open(
+        path: impl AsRef<Path>,
+        countries: impl IntoIterator<Item = String>,
+        asns: impl IntoIterator<Item = u32>,
+    ) -> Result<Self, MaxMindDBError> {
+        Ok(Self {
+            reader: Reader::open_readfile(path)?,
+            blocklist: GeoBlocklist {
+                countries: countries.into_iter().map(|v| v.to_uppercase()).collect(),
+                asns: asns.into_iter().collect(),
+            },
+        })
+    }
+
+    /// Why the address is blocked, if it is. Addresses missing from the
+    /// database, like private ones, are never blocked.
+    pub fn check(&self, ip: IpAddr) -> Option<GeoRejection> {
+        let record = match self.reader.lookup::<GeoRecord>(ip.to_canonical()) {
+            Ok(v) => v,
+            Err(MaxMindDBError::AddressNotFoundError(_)) => return None,
+            Err(error) => {
+                tracing::warn!(%error, %ip, "Failed to look up address in the GeoIP database");
+                return None;
+            }
+        };
+
+        self.blocklist.check(&record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Country, GeoBlocklist, GeoRecord, GeoRejection};
+
+    fn record(country: Option<&str>, asn: Option<u32>) -> GeoRecord {
+        GeoRecord {
+            country: Some(Country {
+                iso_code: country.map(Into::into),
+            }),
+            autonomous_system_number: asn,
+        }
+    }
+
+    #[test]
+    fn test_blocklist() {
+        let blocklist = GeoBlocklist {
+            countries: ["XX".to_string()].into(),
+            asns: [64496].into(),
+        };
+
+        assert_eq!(
+            blocklist.check(&record(Some("XX"), None)),
+            Some(GeoRejection::Country("XX".into()))
+        );
+        assert_eq!(
+            blocklist.check(&record(Some("BR"), Some(64496))),
+            Some(GeoRejection::Asn(64496))
+        );
+        assert_eq!(blocklist.check(&record(Some("BR"), Some(64497))), None);
+        assert_eq!(blocklist.check(&record(None, None)), None);
+        assert_eq!(blocklist.check(&GeoRecord::default()), None);
+    }
+}
@@ -0,0 +1,142 @@
+use minecraft_protocol::{decoder::DecoderReadExt, encoder::EncoderWriteExt};
+use serde::Deserialize;
+use std::{io::Cursor, str::FromStr};
+
+/// Channel of the plugin message carrying the client or server brand, a
+/// single string like `vanilla` or `Paper`.
+pub const BRAND_CHANNEL: &str = "minecraft:brand";
+
+const MAX_BRAND_LENGTH: u16 = 32767;
+
+/// How the brand sent through the proxy is rewritten.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(tag = "mode", content = "text", rename_all = "lowercase")]
+pub enum BrandRewrite {
+    /// Forwarded untouched
+    #[default]
+    Off,
+    /// The text is appended to the brand
+    Append(String),
+    /// The brand is replaced by the text
+    Replace(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid brand rewrite mode `{0}`, expected `off`, `append` or `replace`")]
+pub struct ParseBrandModeError(String);
+
+/// The mode of a [`BrandRewrite`], without its text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrandMode {
+    Off,
+    Append,
+    Replace,
+}
+
+impl FromStr for BrandMode {
+    type Err = ParseBrandModeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(BrandMode::Off),
+            "append" => Ok(BrandMode::Append),
+            "replace" => Ok(BrandMode::Replace),
+            _ => Err(ParseBrandModeError(s.into())),
+        }
+    }
+}
+
+impl BrandRewrite {
+    pub fn new(mode: BrandMode, text: String) -> Self {
+        match mode {
+            BrandMode::Off => BrandRewrite::Off,
+            BrandMode::Append => BrandRewrite::Append(text),
+            BrandMode::Replace => BrandRewrite::Replace(text),
+        }
+    }
+
+    pub fn apply(&self, brand: &str) -> String {
+        match self {
+            BrandRewrite::Off => brand.to_owned(),
+            BrandRewrite::Append(text) => format!("{brand}{text}"),
+            BrandRewrite::Replace(text) => text.clone(),
+        }
+    }
+
+    /// Rewrites the payload of a brand plugin message. Returns `None` if the
+    /// rewrite is off or the payload is not a valid brand, in which case it
+    /// must be forwarded as is.
+    pub fn rewrite_payload(&self, data: &[u8]) -> Option<Vec<u8>> {
+        if *self == BrandRewrite::Off {
+            return None;
+        }
+
+        let mut cursor = Cursor::new(data);
+        let brand = cursor.read_string(MAX_BRAND_LENGTH).ok()?;
+        if cursor.position() as usize != data.len() {
+            return None;
+        }
+
+        let mut payload = Vec::new();
+        payload
+            .write_string(&self.apply(&brand), MAX_BRAND_LENGTH)
+            .ok()?;
+
+        Some(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BrandMode, BrandRewrite};
+    use minecraft_protocol::{decoder::DecoderReadExt, encoder::EncoderWriteExt};
+    use std::io::Cursor;
+
+    fn payload(brand: &str) -> Vec<u8> {
+        let mut vec = Vec::new();
+        vec.write_string(brand, 32767).unwrap();
+        vec
+    }
+
+    fn brand(payload: &[u8]) -> String {
+        Cursor::new(payload).read_string(32767).unwrap()
+    }
+
+    #[test]
+    fn test_rewrite_modes() {
+        let data = payload("Paper");
+
+        assert!(BrandRewrite::Off.rewrite_payload(&data).is_none());
+
+        let append = BrandRewrite::Append(" via Basileia".into());
+        assert_eq!(
+            brand(&append.rewrite_payload(&data).unwrap()),
+            "Paper via Basileia"
+        );
+
+        let replace = BrandRewrite::Replace("vanilla".into());
+        assert_eq!(brand(&replace.rewrite_payload(&data).unwrap()), "vanilla");
+    }
+
+    #[test]
+    fn test_invalid_payload_is_kept() {
+        let rewrite = BrandRewrite::Replace("vanilla".into());
+
+        assert!(rewrite.rewrite_payload(&[]).is_none());
+        assert!(rewrite.rewrite_payload(&[0x05, b'a']).is_none());
+
+        let mut trailing = payload("Paper");
+        trailing.push(0);
+        assert!(rewrite.rewrite_payload(&trailing).is_none());
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!("Append".parse::<BrandMode>().unwrap(), BrandMode::Append);
+        assert!("prepend".parse::<BrandMode>().is_err());
+        assert_eq!(
+            BrandRewrite::new(BrandMode::Replace, "x".into()),
+            BrandRewrite::Replace("x".into())
+        );
+    }
+}
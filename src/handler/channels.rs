@@ -0,0 +1,68 @@
+/// Decides which plugin message channels are forwarded between the players
+/// and the backends.
+///
+/// Channels ending with `*` match every channel starting with the rest of the
+/// pattern, so `fml:*` matches all of the forge channels.
+#[derive(Debug, Default)]
+pub struct ChannelFilter {
+    /// Only these channels are forwarded, when set
+    allowed: Option<Vec<String>>,
+    /// Never forwarded, even if allowed
+    denied: Vec<String>,
+}
+
+impl ChannelFilter {
+    pub fn new(allowed: Option<Vec<String>>, denied: Vec<String>) -> Self {
+        Self { allowed, denied }
+    }
+
+    pub fn is_allowed(&self, channel: &str) -> bool {
+        let allowed = self
+            .allowed
+            .as_ref()
+            .is_none_or(|v| v.iter().any(|pattern| matches(pattern, channel)));
+
+        allowed && !self.denied.iter().any(|pattern| matches(pattern, channel))
+    }
+}
+
+fn matches(pattern: &str, channel: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => channel.starts_with(prefix),
+        None => pattern == channel,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChannelFilter;
+
+    #[test]
+    fn test_everything_allowed_by_default() {
+        let filter = ChannelFilter::default();
+        assert!(filter.is_allowed("minecraft:brand"));
+        assert!(filter.is_allowed("fml:handshake"));
+    }
+
+    #[test]
+    fn test_denied_channels() {
+        let filter = ChannelFilter::new(None, vec!["fml:*".into(), "bungeecord:main".into()]);
+
+        assert!(filter.is_allowed("minecraft:brand"));
+        assert!(!filter.is_allowed("fml:handshake"));
+        assert!(!filter.is_allowed("bungeecord:main"));
+        assert!(filter.is_allowed("bungeecord:other"));
+    }
+
+    #[test]
+    fn test_allowed_channels() {
+        let filter = ChannelFilter::new(
+            Some(vec!["minecraft:*".into()]),
+            vec!["minecraft:debug".into()],
+        );
+
+        assert!(filter.is_allowed("minecraft:brand"));
+        assert!(!filter.is_allowed("minecraft:debug"));
+        assert!(!filter.is_allowed("fml:handshake"));
+    }
+}
@@ -1,19 +1,86 @@
-use crate::utils::read_packet;
+use crate::utils::read_packet_length;
 use minecraft_protocol::{
     codec::ProtocolState,
+    data::chat::Message,
     decoder::Decoder,
     error::DecodeError,
     packet::handshake::{Handshake, HandshakeServerBoundPacket},
 };
-use std::io::Cursor;
-use tokio::io::AsyncRead;
+use std::{
+    collections::{HashMap, HashSet},
+    io::Cursor,
+    net::IpAddr,
+    sync::Mutex,
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// The handshake carries at most a 255 characters long hostname, so anything
+/// larger is not a minecraft client.
+pub const MAX_HANDSHAKE_LENGTH: usize = 1024;
+
+/// How long a client has to send the whole handshake by default.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, thiserror::Error)]
+pub enum HandshakeError {
+    #[error("Failed to decode handshake: {0}")]
+    DecodeError(#[from] DecodeError),
+    #[error("Handshake was not sent in time")]
+    Timeout,
+    #[error("Client speaks another protocol: {0}")]
+    UnknownProtocol(&'static str),
+}
 
+impl HandshakeError {
+    /// Whether the error is likely caused by something that isn't a
+    /// minecraft client, like port scanners.
+    pub fn is_noise(&self) -> bool {
+        match self {
+            HandshakeError::DecodeError(error) => {
+                error.is_eof_error()
+                    || matches!(
+                        error,
+                        DecodeError::InvalidPacketLength | DecodeError::VarIntTooLong { .. }
+                    )
+            }
+            HandshakeError::Timeout | HandshakeError::UnknownProtocol(_) => true,
+        }
+    }
+}
+
+/// Reads the handshake, which must be fully sent before `timeout` elapses.
 pub async fn handle_handshake<R: AsyncRead + Unpin + Send>(
     client_read: &mut R,
-) -> Result<Handshake, DecodeError> {
-    let vec = read_packet(client_read, false)
-        .await?
-        .ok_or(DecodeError::InvalidPacketLength)?;
+    timeout: Duration,
+) -> Result<Handshake, HandshakeError> {
+    tokio::time::timeout(timeout, read_handshake(client_read))
+        .await
+        .map_err(|_| HandshakeError::Timeout)?
+}
+
+async fn read_handshake<R: AsyncRead + Unpin + Send>(
+    client_read: &mut R,
+) -> Result<Handshake, HandshakeError> {
+    let length = read_packet_length(client_read, MAX_HANDSHAKE_LENGTH).await?;
+    if length == 0 {
+        return Err(DecodeError::InvalidPacketLength.into());
+    }
+
+    // Checking the packet id before reading the rest of the frame allows
+    // other protocols to be rejected without waiting for more data
+    let packet_id = client_read.read_u8().await.map_err(DecodeError::from)?;
+    if packet_id != 0x00 {
+        return Err(HandshakeError::UnknownProtocol(guess_protocol(
+            length, packet_id,
+        )));
+    }
+
+    let mut vec = vec![0; length];
+    client_read
+        .read_exact(&mut vec[1..])
+        .await
+        .map_err(DecodeError::from)?;
     let mut cursor = Cursor::new(vec);
 
     let packet = HandshakeServerBoundPacket::decode(&mut cursor)?;
@@ -30,3 +97,296 @@ pub async fn handle_handshake<R: AsyncRead + Unpin + Send>(
 
     Ok(handshake_packet)
 }
+
+/// Names the protocol of a connection from its first two bytes, read as the
+/// length and id of a minecraft packet.
+fn guess_protocol(length: usize, packet_id: u8) -> &'static str {
+    match (length, packet_id) {
+        (0x16, 0x03) => "tls",
+        (0xFE, 0xFA) | (0x7E, 0xFA) => "legacy ping",
+        (length, id) if is_ascii_letter(length) && id.is_ascii_uppercase() => "http",
+        _ => "unknown",
+    }
+}
+
+#[inline]
+fn is_ascii_letter(length: usize) -> bool {
+    u8::try_from(length).is_ok_and(|v| v.is_ascii_uppercase())
+}
+
+/// Strips the data forge and other mods append to the hostname, the port some
+/// clients include and the trailing dot of fully qualified names.
+pub fn normalize_host(host: &str) -> String {
+    let host = host.split('\0').next().unwrap_or_default();
+
+    let host = match host.rsplit_once(':') {
+        Some((name, port))
+            if port.parse::<u16>().is_ok() && (!name.contains(':') || name.ends_with(']')) =>
+        {
+            name
+        }
+        _ => host,
+    };
+
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostRejection {
+    /// The client connected to an IP address instead of a hostname
+    IpAddress,
+    /// The hostname is not one of the allowed ones
+    UnknownHost,
+    /// The port is not the expected one
+    WrongPort,
+}
+
+impl HostRejection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HostRejection::IpAddress => "ip_address",
+            HostRejection::UnknownHost => "unknown_host",
+            HostRejection::WrongPort => "wrong_port",
+        }
+    }
+}
+
+/// The hostnames and port clients must use to connect, so that scanners
+/// reaching the proxy by its IP address are turned away during the handshake.
+pub struct HostAllowlist {
+    /// Any hostname is accepted when unset
+    hosts: Option<HashSet<String>>,
+    /// Any port is accepted when unset
+    port: Option<u16>,
+    rejected_message: Message,
+}
+
+impl HostAllowlist {
+    pub fn new(hosts: Option<Vec<String>>, port: Option<u16>, rejected_message: Message) -> Self {
+        Self {
+            hosts: hosts.map(|hosts| hosts.iter().map(|v| normalize_host(v)).collect()),
+            port,
+            rejected_message,
+        }
+    }
+
+    /// The disconnect reason sent to rejected logins.
+    #[inline]
+    pub fn rejected_message(&self) -> &Message {
+        &self.rejected_message
+    }
+
+    /// Checks the `server_addr` and `server_port` sent in the handshake.
+    pub fn check(&self, server_addr: &str, server_port: u16) -> Result<(), HostRejection> {
+        self.check_host(server_addr)?;
+
+        match self.port {
+            Some(port) if port != server_port => Err(HostRejection::WrongPort),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_host(&self, server_addr: &str) -> Result<(), HostRejection> {
+        let Some(hosts) = &self.hosts else {
+            return Ok(());
+        };
+        let host = normalize_host(server_addr);
+
+        if hosts.contains(&host) {
+            Ok(())
+        } else if host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .is_ok()
+        {
+            Err(HostRejection::IpAddress)
+        } else {
+            Err(HostRejection::UnknownHost)
+        }
+    }
+}
+
+/// Counts rejected handshakes per IP so that scanners hammering the proxy
+/// don't flood the logs.
+#[derive(Default)]
+pub struct HandshakeRejections {
+    counts: Mutex<HashMap<IpAddr, u64>>,
+}
+
+impl HandshakeRejections {
+    /// Addresses tracked before the counters are reset.
+    const MAX_TRACKED: usize = 4096;
+
+    #[inline]
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Records a rejected handshake, returning how many were rejected from the
+    /// address if this one should be logged. Only the first rejection and every
+    /// hundredth after it are logged.
+    pub fn record(&self, ip: IpAddr) -> Option<u64> {
+        let mut counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+
+        if counts.len() >= Self::MAX_TRACKED && !counts.contains_key(&ip) {
+            counts.clear();
+        }
+
+        let count = counts.entry(ip).or_default();
+        *count += 1;
+
+        (*count == 1 || count.is_multiple_of(100)).then_some(*count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        handle_handshake, normalize_host, HandshakeError, HandshakeRejections, HostAllowlist,
+        HostRejection, HANDSHAKE_TIMEOUT,
+    };
+    use crate::utils::write_packet;
+    use minecraft_protocol::{
+        data::chat::Message,
+        error::DecodeError,
+        packet::handshake::{Handshake, HandshakeServerBoundPacket, NextState},
+    };
+    use std::net::{IpAddr, Ipv4Addr};
+    use tokio::io::{duplex, AsyncWriteExt};
+
+    async fn handshake_from(data: &[u8]) -> Result<Handshake, HandshakeError> {
+        let (mut client, mut server) = duplex(4096);
+        client.write_all(data).await.unwrap();
+
+        handle_handshake(&mut server, HANDSHAKE_TIMEOUT).await
+    }
+
+    #[tokio::test]
+    async fn test_valid_handshake() {
+        let packet = HandshakeServerBoundPacket::Handshake(Handshake {
+            protocol_version: 765,
+            server_addr: "localhost".into(),
+            server_port: 25565,
+            next_state: NextState::Login,
+        });
+
+        let mut data = Vec::new();
+        write_packet(&mut data, &packet).await.unwrap();
+
+        let handshake = handshake_from(&data).await.unwrap();
+        assert_eq!(handshake.server_addr, "localhost");
+    }
+
+    #[tokio::test]
+    async fn test_rejects_http() {
+        let result = handshake_from(b"GET / HTTP/1.1\r\n\r\n").await;
+        assert!(matches!(
+            result,
+            Err(HandshakeError::UnknownProtocol("http"))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_tls() {
+        let result = handshake_from(&[0x16, 0x03, 0x01, 0x02, 0x00]).await;
+        assert!(matches!(
+            result,
+            Err(HandshakeError::UnknownProtocol("tls"))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_large_frame_early() {
+        // Only the length is sent, the proxy must not wait for the payload
+        let result = handshake_from(&[0xFF, 0xFF, 0x7F]).await;
+        assert!(matches!(
+            result,
+            Err(HandshakeError::DecodeError(
+                DecodeError::InvalidPacketLength
+            ))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_handshake_timeout() {
+        let (_client, mut server) = duplex(64);
+
+        let result = handle_handshake(&mut server, HANDSHAKE_TIMEOUT).await;
+        assert!(matches!(result, Err(HandshakeError::Timeout)));
+    }
+
+    #[test]
+    fn test_rejections_are_sampled() {
+        let rejections = HandshakeRejections::new();
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        assert_eq!(rejections.record(ip), Some(1));
+        assert!((2..100).all(|_| rejections.record(ip).is_none()));
+        assert_eq!(rejections.record(ip), Some(100));
+    }
+
+    #[test]
+    fn test_normalize_host() {
+        assert_eq!(normalize_host("play.example.com"), "play.example.com");
+        assert_eq!(normalize_host("Play.Example.COM"), "play.example.com");
+        assert_eq!(normalize_host("play.example.com."), "play.example.com");
+        assert_eq!(
+            normalize_host("play.example.com\0FML3\0"),
+            "play.example.com"
+        );
+        assert_eq!(
+            normalize_host("PLAY.example.com.\0FML2\0"),
+            "play.example.com"
+        );
+        assert_eq!(normalize_host("play.example.com:25565"), "play.example.com");
+        assert_eq!(normalize_host("[::1]:25565"), "[::1]");
+        assert_eq!(normalize_host("::1"), "::1");
+    }
+
+    #[test]
+    fn test_host_allowlist() {
+        let allowlist = HostAllowlist::new(
+            Some(vec!["Play.Example.com".into()]),
+            None,
+            Message::from_str("Nope"),
+        );
+
+        assert_eq!(allowlist.check("play.example.com", 25565), Ok(()));
+        assert_eq!(allowlist.check("PLAY.EXAMPLE.COM.\0FML3\0", 1), Ok(()));
+        assert_eq!(
+            allowlist.check("other.example.com", 25565),
+            Err(HostRejection::UnknownHost)
+        );
+        assert_eq!(
+            allowlist.check("203.0.113.7", 25565),
+            Err(HostRejection::IpAddress)
+        );
+        assert_eq!(allowlist.check("::1", 25565), Err(HostRejection::IpAddress));
+        assert_eq!(
+            allowlist.check("[::1]:25565", 25565),
+            Err(HostRejection::IpAddress)
+        );
+    }
+
+    #[test]
+    fn test_expected_port() {
+        let allowlist = HostAllowlist::new(None, Some(25565), Message::from_str("Nope"));
+
+        assert_eq!(allowlist.check("203.0.113.7", 25565), Ok(()));
+        assert_eq!(
+            allowlist.check("play.example.com", 25566),
+            Err(HostRejection::WrongPort)
+        );
+
+        let allowlist = HostAllowlist::new(
+            Some(vec!["play.example.com".into()]),
+            Some(25565),
+            Message::from_str("Nope"),
+        );
+        assert_eq!(
+            allowlist.check("203.0.113.7", 25566),
+            Err(HostRejection::IpAddress)
+        );
+    }
+}
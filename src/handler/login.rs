@@ -1,32 +1,71 @@
 use crate::{
     errors::AppError,
-    repository::user_bans::UserBansRepository,
-    state::GlobalSharedState,
-    utils::{read_packet, write_packet},
+    repository::{
+        user_bans::UserBansRepository, user_ip_bans::UserIpBansRepository,
+        whitelist::WhitelistRepository, RepositoryError,
+    },
+    state::{GlobalSharedState, OnlinePlayerGuard},
+    utils::{write_packet, PacketBudget},
 };
 use minecraft_protocol::{
     codec::ProtocolState,
-    decoder::Decoder,
+    data::chat::Message,
     packet::login::{LoginClientBoundPacket, LoginDisconnect, LoginServerBoundPacket, LoginStart},
 };
-use std::io::Cursor;
+use std::{io::Cursor, net::IpAddr, time::Duration};
 use tokio::io::{AsyncRead, AsyncWrite};
+use uuid::Uuid;
 
-const PLAYER_EXISTS_MSG: &'static str =
-    r#"{"text":"There is already a logged in player with this username"}"#;
+/// Why a player wasn't allowed to log in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoginRejection {
+    /// The client sent something other than a login start
+    UnexpectedPacket,
+    AlreadyOnline,
+    Banned,
+    NotWhitelisted,
+    SessionLocked,
+}
+
+impl LoginRejection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoginRejection::UnexpectedPacket => "unexpected_packet",
+            LoginRejection::AlreadyOnline => "already_online",
+            LoginRejection::Banned => "banned",
+            LoginRejection::NotWhitelisted => "not_whitelisted",
+            LoginRejection::SessionLocked => "session_locked",
+        }
+    }
+}
 
+/// Reads the login start and checks whether the player can log in, reserving
+/// its username if so. The reservation is held by the returned guard.
+///
+/// The packet is decoded with the shape sent by clients of `protocol_version`,
+/// older clients don't send their uuid, in which case it's nil.
+///
+/// Only reading the packet is subject to `timeout`, so that the reservation
+/// is never left behind by a cancelled check.
 pub async fn handle_login_start<C: AsyncRead + AsyncWrite + Unpin + Send>(
     global_state: &GlobalSharedState,
     conn: &mut C,
-) -> Result<Option<LoginStart>, AppError> {
-    let vec = match read_packet(conn, false).await? {
-        Some(v) => v,
-        None => return Ok(None),
+    ip: IpAddr,
+    protocol_version: i32,
+    timeout: Duration,
+    mut budget: PacketBudget,
+) -> Result<Result<(LoginStart, OnlinePlayerGuard), LoginRejection>, AppError> {
+    let vec = match tokio::time::timeout(timeout, budget.read_packet(conn)).await {
+        Ok(v) => match v? {
+            Some(v) => v,
+            None => return Ok(Err(LoginRejection::UnexpectedPacket)),
+        },
+        Err(_) => return Err(AppError::Timeout),
     };
 
     let mut cursor = Cursor::new(vec);
 
-    let packet = LoginServerBoundPacket::decode(&mut cursor)?;
+    let packet = LoginServerBoundPacket::decode_versioned(&mut cursor, protocol_version)?;
 
     tracing::trace!(
         current_state = ?ProtocolState::Login,
@@ -34,41 +73,517 @@ pub async fn handle_login_start<C: AsyncRead + AsyncWrite + Unpin + Send>(
         "Incomming client packet",
     );
 
-    if let LoginServerBoundPacket::LoginStart(login_start) = packet {
-        let exists = global_state.exists_online_player(&login_start.name).await;
-
-        if exists {
-            tracing::info!(
-                username = login_start.name,
-                "A player with this username is already connected"
-            );
-
-            let packet = LoginClientBoundPacket::LoginDisconnect(LoginDisconnect {
-                reason: PLAYER_EXISTS_MSG.into(),
-            });
-            let _ = write_packet(conn, &packet).await.map_err(|error| {
-                tracing::warn!(%error, "Failed to send disconnect message to client");
-            });
-        } else {
-            let ban = global_state.user_bans.is_banned(&login_start.name).await?;
-
-            if let Some(ban) = ban {
-                let reason = if let Some(reason) = ban.reason {
-                    format!("Banned! Reason: {reason}")
-                } else {
-                    "Banned!".into()
-                };
-
-                let packet = LoginClientBoundPacket::LoginDisconnect(LoginDisconnect { reason });
-                let _ = write_packet(conn, &packet).await.map_err(|error| {
-                    tracing::warn!(%error, "Failed to send disconnect message to client");
-                });
-
-                return Ok(None);
-            }
-            return Ok(Some(login_start));
+    let LoginServerBoundPacket::LoginStart(login_start) = packet else {
+        return Ok(Err(LoginRejection::UnexpectedPacket));
+    };
+
+    let reserved = global_state
+        .try_reserve_player(&login_start.name, &login_start.uuid)
+        .await;
+
+    if !reserved {
+        tracing::info!(
+            username = login_start.name,
+            "A player with this username is already connected"
+        );
+
+        let packet = LoginClientBoundPacket::LoginDisconnect(LoginDisconnect {
+            reason: global_state.messages.already_logged_in.clone(),
+        });
+        let _ = write_packet(conn, &packet).await.map_err(|error| {
+            tracing::warn!(%error, "Failed to send disconnect message to client");
+        });
+
+        return Ok(Err(LoginRejection::AlreadyOnline));
+    }
+
+    let guard = global_state.guard_online_player(login_start.name.clone());
+
+    let rejection = match check_login(global_state, &login_start, ip).await {
+        Ok(v) => v,
+        Err(error) => {
+            global_state.release_online_player(guard).await;
+            return Err(error.into());
+        }
+    };
+
+    if let Some((rejection, reason)) = rejection {
+        global_state.release_online_player(guard).await;
+
+        let packet = LoginClientBoundPacket::LoginDisconnect(LoginDisconnect { reason });
+        let _ = write_packet(conn, &packet).await.map_err(|error| {
+            tracing::warn!(%error, "Failed to send disconnect message to client");
+        });
+
+        return Ok(Err(rejection));
+    }
+
+    Ok(Ok((login_start, guard)))
+}
+
+/// Runs the checks a player must pass to log in, returning the rejection and
+/// disconnect reason of the first one that fails.
+///
+/// The checks run after the username is reserved, so players that are already
+/// online are told so first, and in a fixed order, so the client always sees
+/// the message of the most general rule that applies to it:
+///
+/// 1. User ban, by username.
+/// 2. User IP ban, by username and network.
+/// 3. Whitelist.
+/// 4. Session lock.
+///
+/// IP bans are checked once the connection is accepted, before the handshake.
+/// Failed ban lookups follow the configured
+/// [`BanCheckFailure`](crate::repository::health::BanCheckFailure) policy,
+/// the other checks always reject the login when the database fails.
+async fn check_login(
+    global_state: &GlobalSharedState,
+    login_start: &LoginStart,
+    ip: IpAddr,
+) -> Result<Option<(LoginRejection, Message)>, RepositoryError> {
+    let username = login_start.name.as_str();
+    let messages = &global_state.messages;
+    let health = &global_state.database_health;
+
+    if let Some(ban) = health.check_ban(global_state.user_bans.is_banned(username).await)? {
+        tracing::info!(username, "Player is banned");
+        return Ok(Some((LoginRejection::Banned, messages.banned(&ban))));
+    }
+
+    let ban = health.check_ban(global_state.user_ip_bans.is_banned(username, ip).await)?;
+    if let Some(ban) = ban {
+        tracing::info!(username, %ip, network = %ban.network, "Player is banned on this network");
+        return Ok(Some((LoginRejection::Banned, messages.banned(&ban))));
+    }
+
+    if !is_whitelisted(global_state, username, login_start.uuid).await? {
+        tracing::info!(username, uuid = %login_start.uuid, "Player is not whitelisted");
+        return Ok(Some((
+            LoginRejection::NotWhitelisted,
+            messages.not_whitelisted.clone(),
+        )));
+    }
+
+    if let Some(session_lock) = &global_state.session_lock {
+        if !session_lock.check(username, ip).await? {
+            tracing::info!(username, %ip, "Player reconnected from another IP too soon");
+            return Ok(Some((
+                LoginRejection::SessionLocked,
+                session_lock.message().clone(),
+            )));
         }
     }
 
     Ok(None)
 }
+
+/// Entries pinned to an account are matched by the uuid sent by the client
+/// during the login start, so the relay checks again with the uuid the
+/// backend confirms in the login success.
+pub(crate) async fn is_whitelisted(
+    global_state: &GlobalSharedState,
+    username: &str,
+    uuid: Uuid,
+) -> Result<bool, RepositoryError> {
+    if global_state.whitelist_bypass.contains(username, uuid) {
+        return Ok(true);
+    }
+
+    if !global_state.whitelist.is_enabled().await? {
+        return Ok(true);
+    }
+
+    global_state
+        .whitelist
+        .is_player_whitelisted(username, uuid)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{handle_login_start, LoginRejection};
+    use crate::{
+        errors::AppError,
+        repository::{
+            kv::SqlxKeyValueRepository, user_bans::UserBansRepository,
+            user_ip_bans::UserIpBansRepository, whitelist::WhitelistRepository,
+            write_behind::WriteBehindKeyValue,
+        },
+        session::SessionLock,
+        state::test_global_state,
+        utils::{write_packet, PacketBudget},
+    };
+    use minecraft_protocol::{
+        data::chat::Message,
+        error::DecodeError,
+        packet::login::{LoginServerBoundPacket, LoginStart, VersionedLoginStart},
+    };
+    use sqlx::{migrate, SqlitePool};
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::Duration,
+    };
+    use tokio::io::{AsyncReadExt, DuplexStream};
+    use uuid::Uuid;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const TIMEOUT: Duration = Duration::from_secs(5);
+    const PROTOCOL_VERSION: i32 = 765;
+
+    /// Returns the client and proxy ends of a connection that sent a login start
+    async fn fake_connection(name: &str) -> (DuplexStream, DuplexStream) {
+        fake_connection_with_uuid(name, Uuid::new_v4()).await
+    }
+
+    async fn fake_connection_with_uuid(name: &str, uuid: Uuid) -> (DuplexStream, DuplexStream) {
+        let (mut client, server) = tokio::io::duplex(1024);
+
+        let packet = LoginServerBoundPacket::LoginStart(LoginStart {
+            name: name.into(),
+            uuid,
+        });
+        write_packet(&mut client, &packet).await.unwrap();
+
+        (client, server)
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_logins_with_same_username() {
+        let state = test_global_state().await;
+
+        let (_client1, mut conn1) = fake_connection("Notch").await;
+        let (_client2, mut conn2) = fake_connection("Notch").await;
+
+        let (r1, r2) = tokio::join!(
+            handle_login_start(
+                &state,
+                &mut conn1,
+                LOCALHOST,
+                PROTOCOL_VERSION,
+                TIMEOUT,
+                PacketBudget::default()
+            ),
+            handle_login_start(
+                &state,
+                &mut conn2,
+                LOCALHOST,
+                PROTOCOL_VERSION,
+                TIMEOUT,
+                PacketBudget::default()
+            ),
+        );
+        let (r1, r2) = (r1.unwrap(), r2.unwrap());
+
+        assert!(r1.is_ok() != r2.is_ok(), "exactly one login must win");
+        assert!(!state.try_reserve_player("Notch", &Uuid::nil()).await);
+
+        let (_, guard) = r1.or(r2).unwrap();
+        state.release_online_player(guard).await;
+        let (_client3, mut conn3) = fake_connection("Notch").await;
+        assert!(handle_login_start(
+            &state,
+            &mut conn3,
+            LOCALHOST,
+            PROTOCOL_VERSION,
+            TIMEOUT,
+            PacketBudget::default()
+        )
+        .await
+        .unwrap()
+        .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_login_start_too_large() {
+        let state = test_global_state().await;
+        let (_client, mut conn) = fake_connection("Notch").await;
+
+        let result = handle_login_start(
+            &state,
+            &mut conn,
+            LOCALHOST,
+            PROTOCOL_VERSION,
+            TIMEOUT,
+            PacketBudget::new(4, 8),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(AppError::PacketDecodeError(
+                DecodeError::InvalidPacketLength
+            ))
+        ));
+        // The username was never reserved
+        assert!(state.try_reserve_player("Notch", &Uuid::nil()).await);
+    }
+
+    #[tokio::test]
+    async fn test_login_without_uuid() {
+        let state = test_global_state().await;
+
+        // 1.18.2 clients only send their username
+        let (mut client, mut conn) = tokio::io::duplex(1024);
+        let login_start = LoginStart {
+            name: "Notch".into(),
+            uuid: Uuid::nil(),
+        };
+        let packet = VersionedLoginStart {
+            login_start: &login_start,
+            protocol_version: 758,
+        };
+        write_packet(&mut client, &packet).await.unwrap();
+
+        let (result, _guard) = handle_login_start(
+            &state,
+            &mut conn,
+            LOCALHOST,
+            758,
+            TIMEOUT,
+            PacketBudget::default(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(result.name, "Notch");
+        assert!(result.uuid.is_nil());
+    }
+
+    #[tokio::test]
+    async fn test_login_with_online_uuid() {
+        let state = test_global_state().await;
+        let uuid = Uuid::new_v4();
+        assert!(state.add_online_player("Notch".into(), uuid).await);
+
+        let (_client, mut conn) = fake_connection_with_uuid("notch", uuid).await;
+        let result = handle_login_start(
+            &state,
+            &mut conn,
+            LOCALHOST,
+            PROTOCOL_VERSION,
+            TIMEOUT,
+            PacketBudget::default(),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(result, Err(LoginRejection::AlreadyOnline)));
+    }
+
+    #[tokio::test]
+    async fn test_whitelist_prefers_uuid() {
+        let state = test_global_state().await;
+
+        let uuid = Uuid::new_v4();
+        state
+            .whitelist
+            .add("Notch", Some(uuid), None)
+            .await
+            .unwrap();
+        state.whitelist.add("jeb_", None, None).await.unwrap();
+        state.whitelist.set_enabled(true).await.unwrap();
+
+        let cases = [
+            ("Renamed", uuid, true),
+            ("Notch", Uuid::new_v4(), false),
+            ("jeb_", Uuid::new_v4(), true),
+            ("Herobrine", Uuid::new_v4(), false),
+        ];
+
+        for (name, uuid, allowed) in cases {
+            let (_client, mut conn) = fake_connection_with_uuid(name, uuid).await;
+            let result = handle_login_start(
+                &state,
+                &mut conn,
+                LOCALHOST,
+                PROTOCOL_VERSION,
+                TIMEOUT,
+                PacketBudget::default(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(result.is_ok(), allowed, "{name}");
+
+            state.remove_online_player(name).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_whitelist_bypass() {
+        let state = test_global_state().await;
+        state.whitelist.set_enabled(true).await.unwrap();
+        state.whitelist_bypass.add("Admin");
+        state.whitelist_bypass.add("Griefer");
+        state
+            .user_bans
+            .add_ban("Griefer", None, None)
+            .await
+            .unwrap();
+
+        let cases = [
+            ("admin", Ok(())),
+            ("Notch", Err(LoginRejection::NotWhitelisted)),
+            ("Griefer", Err(LoginRejection::Banned)),
+        ];
+
+        for (name, expected) in cases {
+            let (_client, mut conn) = fake_connection(name).await;
+            let result = handle_login_start(
+                &state,
+                &mut conn,
+                LOCALHOST,
+                PROTOCOL_VERSION,
+                TIMEOUT,
+                PacketBudget::default(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(result.map(|_| ()), expected, "{name}");
+
+            state.remove_online_player(name).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_lock_rejects_other_ips() {
+        let mut state = test_global_state().await;
+        let other = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7));
+
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate!().run(&pool).await.unwrap();
+
+        let session_lock = SessionLock::new(
+            WriteBehindKeyValue::new(SqlxKeyValueRepository::new(pool)),
+            Duration::from_secs(60),
+            [],
+            Message::from_str("Locked"),
+        );
+        session_lock
+            .record_disconnect("Notch", LOCALHOST)
+            .await
+            .unwrap();
+        state.session_lock = Some(session_lock);
+
+        let (_client, mut conn) = fake_connection("Notch").await;
+        let result = handle_login_start(
+            &state,
+            &mut conn,
+            other,
+            PROTOCOL_VERSION,
+            TIMEOUT,
+            PacketBudget::default(),
+        )
+        .await
+        .unwrap();
+        assert!(result.is_err());
+        assert!(state.try_reserve_player("Notch", &Uuid::nil()).await);
+        state.remove_online_player("Notch").await;
+
+        let (_client, mut conn) = fake_connection("Notch").await;
+        let result = handle_login_start(
+            &state,
+            &mut conn,
+            LOCALHOST,
+            PROTOCOL_VERSION,
+            TIMEOUT,
+            PacketBudget::default(),
+        )
+        .await
+        .unwrap();
+        assert!(result.is_ok());
+    }
+
+    /// Reads the reason of the login disconnect sent to the client
+    async fn disconnect_reason(client: &mut DuplexStream) -> String {
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        String::from_utf8_lossy(&buf).into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_user_ip_ban() {
+        let state = test_global_state().await;
+        state
+            .user_ip_bans
+            .add_ban("Notch", "127.0.0.0/8".parse().unwrap(), None, None)
+            .await
+            .unwrap();
+
+        let (_client, mut conn) = fake_connection("Notch").await;
+        let result = handle_login_start(
+            &state,
+            &mut conn,
+            LOCALHOST,
+            PROTOCOL_VERSION,
+            TIMEOUT,
+            PacketBudget::default(),
+        )
+        .await
+        .unwrap();
+        assert!(result.is_err());
+
+        let other = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7));
+        let (_client, mut conn) = fake_connection("Notch").await;
+        let result = handle_login_start(
+            &state,
+            &mut conn,
+            other,
+            PROTOCOL_VERSION,
+            TIMEOUT,
+            PacketBudget::default(),
+        )
+        .await
+        .unwrap();
+        assert!(result.is_ok());
+
+        let (_client, mut conn) = fake_connection("jeb_").await;
+        let result = handle_login_start(
+            &state,
+            &mut conn,
+            LOCALHOST,
+            PROTOCOL_VERSION,
+            TIMEOUT,
+            PacketBudget::default(),
+        )
+        .await
+        .unwrap();
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_user_ban_message_comes_first() {
+        let state = test_global_state().await;
+        state
+            .user_bans
+            .add_ban("Notch", None, Some("Cheating".into()))
+            .await
+            .unwrap();
+        state
+            .user_ip_bans
+            .add_ban(
+                "Notch",
+                "127.0.0.0/8".parse().unwrap(),
+                None,
+                Some("Griefing".into()),
+            )
+            .await
+            .unwrap();
+
+        let (mut client, mut conn) = fake_connection("Notch").await;
+        let result = handle_login_start(
+            &state,
+            &mut conn,
+            LOCALHOST,
+            PROTOCOL_VERSION,
+            TIMEOUT,
+            PacketBudget::default(),
+        )
+        .await
+        .unwrap();
+        assert!(result.is_err());
+
+        drop(conn);
+        let reason = disconnect_reason(&mut client).await;
+        assert!(reason.contains("Cheating"));
+        assert!(!reason.contains("Griefing"));
+    }
+}
@@ -0,0 +1,227 @@
+use crate::repository::{ip_bans::IpBanData, user_bans::UserBanData, user_ip_bans::UserIpBanData};
+use chrono::{DateTime, Utc};
+use minecraft_protocol::data::chat::Message;
+
+/// Replaced by the reason of the ban in [`DisconnectMessages::banned`].
+pub const REASON_PLACEHOLDER: &str = "%reason%";
+/// Replaced by the time left until the ban expires, like `3 days`.
+pub const EXPIRES_PLACEHOLDER: &str = "%expires%";
+/// Replaced by the date the ban was created on, like `2024-05-17`.
+pub const CREATED_PLACEHOLDER: &str = "%created%";
+
+/// Used in place of the reason of bans that don't have one.
+const NO_REASON: &str = "No reason given";
+/// Used in place of the time left of permanent bans.
+const NEVER_EXPIRES: &str = "never";
+
+/// The metadata of a ban that can be shown to the banned player.
+#[derive(Debug, Clone, Copy)]
+pub struct BanDetails<'a> {
+    pub reason: Option<&'a str>,
+    pub created_at: DateTime<Utc>,
+    pub expiration: Option<DateTime<Utc>>,
+}
+
+impl<'a> From<&'a UserBanData> for BanDetails<'a> {
+    fn from(ban: &'a UserBanData) -> Self {
+        Self {
+            reason: ban.reason.as_deref(),
+            created_at: ban.created_at,
+            expiration: ban.expiration,
+        }
+    }
+}
+
+impl<'a> From<&'a IpBanData> for BanDetails<'a> {
+    fn from(ban: &'a IpBanData) -> Self {
+        Self {
+            reason: ban.reason.as_deref(),
+            created_at: ban.created_at,
+            expiration: ban.expiration,
+        }
+    }
+}
+
+impl<'a> From<&'a UserIpBanData> for BanDetails<'a> {
+    fn from(ban: &'a UserIpBanData) -> Self {
+        Self {
+            reason: ban.reason.as_deref(),
+            created_at: ban.created_at,
+            expiration: ban.expiration,
+        }
+    }
+}
+
+/// The reasons shown to the players disconnected by the proxy, configurable
+/// so that they can be localized.
+#[derive(Debug, Clone)]
+pub struct DisconnectMessages {
+    pub already_logged_in: Message,
+    /// May contain [`REASON_PLACEHOLDER`], [`EXPIRES_PLACEHOLDER`] and
+    /// [`CREATED_PLACEHOLDER`]
+    pub banned: Message,
+    pub not_whitelisted: Message,
+    pub version_rejected: Message,
+    /// Sent to transferred players older than 1.20.5, which must reconnect to
+    /// join the backend
+    pub transfer: Message,
+    /// Sent to players logging in while their backend can't be reached
+    pub backend_unavailable: Message,
+}
+
+impl Default for DisconnectMessages {
+    fn default() -> Self {
+        Self {
+            already_logged_in: Message::from_str(
+                "There is already a logged in player with this username",
+            ),
+            banned: Message::from_str("You are banned from this server\nReason: %reason%"),
+            not_whitelisted: Message::from_str("You are not whitelisted on this server"),
+            version_rejected: Message::from_str("Your minecraft version is not accepted"),
+            transfer: Message::from_str("Please reconnect to join the other server"),
+            backend_unavailable: Message::from_str(
+                "The server is unavailable, please try again shortly",
+            ),
+        }
+    }
+}
+
+impl DisconnectMessages {
+    /// The ban message, with the placeholders replaced by the details of
+    /// `ban`.
+    pub fn banned<'a>(&self, ban: impl Into<BanDetails<'a>>) -> Message {
+        let ban = ban.into();
+        let expires = match ban.expiration {
+            Some(expiration) => format_remaining(expiration - Utc::now()),
+            None => NEVER_EXPIRES.into(),
+        };
+        let created = ban.created_at.format("%Y-%m-%d").to_string();
+
+        let json = to_json(&self.banned)
+            .replace(REASON_PLACEHOLDER, &escape(ban.reason.unwrap_or(NO_REASON)))
+            .replace(EXPIRES_PLACEHOLDER, &expires)
+            .replace(CREATED_PLACEHOLDER, &created);
+        Message::from_json(&json).unwrap_or_else(|error| {
+            tracing::warn!(%error, "Failed to replace the placeholders of the ban message");
+            self.banned.clone()
+        })
+    }
+}
+
+/// Escapes the value as a json string, without the surrounding quotes.
+fn escape(value: &str) -> String {
+    let value = serde_json::to_string(value).unwrap_or_default();
+    value
+        .get(1..value.len().saturating_sub(1))
+        .unwrap_or("")
+        .to_owned()
+}
+
+/// Formats the time left in its largest whole unit, rounded up so that a ban
+/// never reads as shorter than it is.
+fn format_remaining(remaining: chrono::Duration) -> String {
+    let minutes = (remaining.num_seconds().max(0) + 59) / 60;
+    let (amount, unit) = if minutes >= 24 * 60 {
+        ((minutes + 24 * 60 - 1) / (24 * 60), "day")
+    } else if minutes >= 60 {
+        ((minutes + 59) / 60, "hour")
+    } else {
+        (minutes.max(1), "minute")
+    };
+
+    match amount {
+        1 => format!("1 {unit}"),
+        amount => format!("{amount} {unit}s"),
+    }
+}
+
+/// Encodes the message as json, to replace placeholders in it.
+pub fn to_json(message: &Message) -> String {
+    message.to_json().unwrap_or_else(|error| {
+        tracing::warn!(%error, "Failed to encode disconnect message");
+        r#"{"text":""}"#.into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_remaining, BanDetails, DisconnectMessages};
+    use chrono::{Duration, TimeZone, Utc};
+    use minecraft_protocol::data::chat::Message;
+
+    fn text(message: &Message) -> String {
+        let message = serde_json::to_value(message).unwrap();
+        message["text"].as_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn test_reason_placeholder() {
+        let messages = DisconnectMessages {
+            banned: Message::from_str("Banido: %reason%"),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            text(&messages.banned(details(Some("Hacks")))),
+            "Banido: Hacks"
+        );
+        assert_eq!(
+            text(&messages.banned(details(None))),
+            "Banido: No reason given"
+        );
+    }
+
+    fn details(reason: Option<&str>) -> BanDetails<'_> {
+        BanDetails {
+            reason,
+            created_at: Utc::now(),
+            expiration: None,
+        }
+    }
+
+    #[test]
+    fn test_ban_metadata_placeholders() {
+        let messages = DisconnectMessages {
+            banned: Message::from_str("Banned on %created% for %expires%"),
+            ..Default::default()
+        };
+        let created_at = Utc.with_ymd_and_hms(2024, 5, 17, 12, 0, 0).unwrap();
+
+        let ban = BanDetails {
+            reason: None,
+            created_at,
+            expiration: Some(Utc::now() + Duration::days(3) - Duration::minutes(1)),
+        };
+        assert_eq!(
+            text(&messages.banned(ban)),
+            "Banned on 2024-05-17 for 3 days"
+        );
+
+        let ban = BanDetails {
+            expiration: None,
+            ..ban
+        };
+        assert_eq!(
+            text(&messages.banned(ban)),
+            "Banned on 2024-05-17 for never"
+        );
+    }
+
+    #[test]
+    fn test_format_remaining() {
+        assert_eq!(format_remaining(Duration::seconds(-5)), "1 minute");
+        assert_eq!(format_remaining(Duration::seconds(90)), "2 minutes");
+        assert_eq!(format_remaining(Duration::minutes(60)), "1 hour");
+        assert_eq!(format_remaining(Duration::minutes(61)), "2 hours");
+        assert_eq!(format_remaining(Duration::hours(24)), "1 day");
+        assert_eq!(format_remaining(Duration::hours(49)), "3 days");
+    }
+
+    #[test]
+    fn test_reason_is_escaped() {
+        let messages = DisconnectMessages::default();
+
+        let message = messages.banned(details(Some(r#"Said "hi" \o/"#)));
+        assert!(text(&message).ends_with(r#"Reason: Said "hi" \o/"#));
+    }
+}
@@ -1,4 +1,8 @@
+pub mod brand;
+pub mod channels;
 pub mod handshake;
 pub mod login;
+pub mod messages;
 pub mod proxy;
+pub mod queue;
 pub mod status;
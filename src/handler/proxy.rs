@@ -1,28 +1,118 @@
+use super::{
+    brand::{BrandRewrite, BRAND_CHANNEL},
+    channels::ChannelFilter,
+    login::is_whitelisted,
+};
 use crate::{
+    actions::PlayerAction,
     state::{ConnectionSharedState, GlobalSharedState, PostLoginInformation},
     utils::{read_packet, write_packet},
 };
+use mc_proxy_protocol::CHANNEL;
 use minecraft_protocol::{
-    codec::{client::ClientPacket, server::ServerPacket, ProtocolState},
+    codec::{
+        client::{ClientPacket, ClientPacketCodec},
+        server::{ServerPacket, ServerPacketCodec},
+        ProtocolState, STATE_PROTOCOL_VERSION, TRANSFER_PROTOCOL_VERSION,
+    },
+    data::{chat::Message, identifier::Identifier},
     error::DecodeError,
     packet::{
-        configuration::{ConfigClientBoundPaket, ConfigServerBoundPacket},
-        game::{GameClientBoundPacket, GameServerBoundPacket, PlayPluginMessage},
-        login::{LoginClientBoundPacket, LoginServerBoundPacket},
+        configuration::{
+            ClientBoundPluginMessage, ConfigClientBoundPaket, ConfigDisconnect,
+            ConfigServerBoundPacket, ServerBoundPluginMessage, Transfer,
+        },
+        game::{GameClientBoundPacket, GameServerBoundPacket, PlayDisconnect, PlayPluginMessage},
+        login::{LoginClientBoundPacket, LoginDisconnect, LoginServerBoundPacket},
+        registry::{Direction, PacketRegistry},
     },
 };
+use std::sync::Arc;
 use tokio::{
     io::{AsyncRead, AsyncWrite, AsyncWriteExt},
     select,
-    sync::mpsc,
+    sync::{mpsc, watch},
 };
 
+/// The movement and interaction packets frozen players can't send, with the
+/// ids of 1.20.4 and 1.20.5. Confirm Teleportation, Chunk Batch Received and
+/// Message Acknowledgment keep the client in sync and are still forwarded.
+const FROZEN_ACTIONS: [u8; 19] = [
+    0x0C, 0x0D, 0x13, 0x17, 0x18, 0x19, 0x1A, 0x1B, 0x1C, 0x1D, 0x21, 0x22, 0x23, 0x2C, 0x2F, 0x33,
+    0x34, 0x35, 0x36,
+];
+const FROZEN_ACTIONS_1_20_5: [u8; 19] = [
+    0x0D, 0x0E, 0x16, 0x1A, 0x1B, 0x1C, 0x1D, 0x1E, 0x1F, 0x20, 0x24, 0x25, 0x26, 0x2F, 0x32, 0x36,
+    0x37, 0x38, 0x39,
+];
+
+/// A movement or interaction packet, decoded without its fields.
+struct FrozenAction;
+
+/// The packets the proxy decodes besides the ones of the packet enums, with
+/// the type ids of 1.20.4 and 1.20.5.
+#[derive(Debug)]
+pub struct RelayPackets {
+    client: Arc<PacketRegistry>,
+    client_1_20_5: Arc<PacketRegistry>,
+}
+
+impl Default for RelayPackets {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RelayPackets {
+    pub fn new() -> Self {
+        Self {
+            client: Arc::new(frozen_actions(&FROZEN_ACTIONS)),
+            client_1_20_5: Arc::new(frozen_actions(&FROZEN_ACTIONS_1_20_5)),
+        }
+    }
+
+    /// The registry of the packets sent by clients of `protocol_version`.
+    fn client(&self, protocol_version: i32) -> Arc<PacketRegistry> {
+        if protocol_version >= TRANSFER_PROTOCOL_VERSION {
+            self.client_1_20_5.clone()
+        } else {
+            self.client.clone()
+        }
+    }
+}
+
+fn frozen_actions(type_ids: &[u8]) -> PacketRegistry {
+    let mut registry = PacketRegistry::new();
+    for &type_id in type_ids {
+        registry.register_with(ProtocolState::Play, Direction::ServerBound, type_id, |_| {
+            Ok(FrozenAction)
+        });
+    }
+    registry
+}
+
+/// How plugin messages are handled while relaying a connection.
+#[derive(Debug, Default)]
+pub struct RelayOptions {
+    pub channels: ChannelFilter,
+    /// Rewrites the brand of the backend seen by the clients
+    pub server_brand: BrandRewrite,
+    /// Rewrites the brand of the clients seen by the backend
+    pub client_brand: BrandRewrite,
+    pub packets: RelayPackets,
+}
+
 pub async fn handle_client(
     state: &ConnectionSharedState,
+    mut codec: ClientPacketCodec,
+    options: &RelayOptions,
     mut response_receiver: mpsc::Receiver<Vec<u8>>,
     mut client_read: impl AsyncRead + Unpin + Send,
     mut srv_write: impl AsyncWrite + Unpin + Send,
 ) -> Result<(), DecodeError> {
+    codec.set_registry(options.packets.client(state.protocol_version));
+
     loop {
         select! {
             msg = response_receiver.recv() => {
@@ -32,7 +122,7 @@ pub async fn handle_client(
                 };
 
                 let _ = write_packet(&mut srv_write, &GameServerBoundPacket::ServerBoundPluginMessage(PlayPluginMessage {
-                    channel: "basileia:proxy".into(),
+                    channel: Identifier::from_static(CHANNEL),
                     data: msg
                 })).await.map_err(|error| {
                     tracing::error!(%error, "Failed to send command response to proxied server");
@@ -44,27 +134,82 @@ pub async fn handle_client(
                     None => break,
                 };
 
-                let packet_result = state.decode_client(&vec).await;
-                let current_state = state.current_state().await;
+                state.sync_client_codec(&mut codec);
+                let packet_result = codec.decode(&vec);
+                let current_state = codec.state();
 
                 match packet_result {
+                    // Kept alive and in sync with the world, but can't act on it
+                    Ok(Some(ClientPacket::Registered(packet)))
+                        if state.is_frozen() && packet.is::<FrozenAction>() =>
+                    {
+                        tracing::trace!(type_id = packet.type_id(), "Dropped packet of frozen client");
+                        continue;
+                    }
                     Ok(Some(packet)) => {
                         tracing::trace!(?current_state, ?packet, "Incomming client packet");
 
                         match packet {
                             ClientPacket::Login(LoginServerBoundPacket::LoginAcknowledged) => {
-                                state.set_state(ProtocolState::Configuration).await;
+                                state.set_state(ProtocolState::Configuration);
                                 tracing::debug!("Entered configuration state");
                             }
                             ClientPacket::Configuration(
                                 ConfigServerBoundPacket::AcknowledgeFinishConfiguration,
                             ) => {
-                                state.set_state(ProtocolState::Play).await;
+                                state.set_state(ProtocolState::Play);
                                 tracing::debug!("Entered play state");
                             }
+                            ClientPacket::Configuration(
+                                ConfigServerBoundPacket::ServerBoundPluginMessage(
+                                    ServerBoundPluginMessage { channel, .. },
+                                ),
+                            )
+                            | ClientPacket::Game(GameServerBoundPacket::ServerBoundPluginMessage(
+                                PlayPluginMessage { channel, .. },
+                            )) if !options.channels.is_allowed(channel.as_str()) => {
+                                tracing::debug!(%channel, "Dropped client plugin message");
+                                continue;
+                            }
+                            ClientPacket::Configuration(
+                                ConfigServerBoundPacket::ServerBoundPluginMessage(
+                                    ServerBoundPluginMessage { channel, data },
+                                ),
+                            ) if channel == BRAND_CHANNEL => {
+                                if let Some(data) = options.client_brand.rewrite_payload(&data) {
+                                    let packet = ConfigServerBoundPacket::ServerBoundPluginMessage(
+                                        ServerBoundPluginMessage { channel, data },
+                                    );
+                                    let mut buffer = Vec::new();
+                                    codec.encode(&packet.into(), &mut buffer);
+                                    srv_write.write_all(&buffer).await?;
+                                    tracing::debug!("Rewrote client brand");
+                                    continue;
+                                }
+                            }
                             _ => {}
                         }
                     }
+                    // Only plugin message channels are identifiers, and they
+                    // can't be checked against the channel filter
+                    Err(error @ DecodeError::InvalidIdentifier { .. }) => {
+                        tracing::debug!(%error, "Dropped client plugin message");
+                        continue;
+                    }
+                    // The stream can't be followed once it is out of sync
+                    // with the state, so the connection is closed. The ids
+                    // of other versions are only known to the backend
+                    Err(error @ DecodeError::UnexpectedPacket { .. })
+                        if state.protocol_version == STATE_PROTOCOL_VERSION =>
+                    {
+                        return Err(error)
+                    }
+                    // Packets of other versions the enums have no variant for
+                    Err(error @ DecodeError::UnknownPacketType { .. })
+                        if state.protocol_version != STATE_PROTOCOL_VERSION =>
+                    {
+                        tracing::trace!(?current_state, %error, "Incomming client packet");
+                    }
                     Err(error) => {
                         tracing::warn!(
                             ?current_state,
@@ -72,11 +217,8 @@ pub async fn handle_client(
                             "Incomming client packet could not be decoded"
                         );
                     }
-                    _ => {
-                        tracing::warn!(
-                            ?current_state,
-                            "Incomming client packet could not be decoded"
-                        );
+                    Ok(None) => {
+                        tracing::trace!(?current_state, "Incomming client packet is incomplete");
                     }
                 }
 
@@ -88,21 +230,101 @@ pub async fn handle_client(
     Ok(())
 }
 
+/// Why [`handle_server`] stopped relaying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerEnd {
+    /// The backend closed the connection, or the client was kicked
+    Closed,
+    /// The proxy is shutting down
+    Shutdown,
+    /// The client was transferred to another backend, or told to reconnect
+    Transferred,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_server(
     global_state: &GlobalSharedState,
     state: &ConnectionSharedState,
-    request_sender: mpsc::Sender<Vec<u8>>,
+    mut codec: ServerPacketCodec,
+    options: &RelayOptions,
+    connection_id: u64,
+    mut shutdown: watch::Receiver<Option<Message>>,
+    mut actions: mpsc::Receiver<PlayerAction>,
     mut srv_read: impl AsyncRead + Unpin + Send,
     mut client_write: impl AsyncWrite + Unpin + Send,
-) -> Result<(), DecodeError> {
+) -> Result<ServerEnd, DecodeError> {
     loop {
-        let vec = match read_packet(&mut srv_read, true).await? {
-            Some(v) => v,
-            None => break,
+        let vec = select! {
+            vec = read_packet(&mut srv_read, true) => match vec? {
+                Some(v) => v,
+                None => break,
+            },
+            reason = wait_shutdown(&mut shutdown) => {
+                let reason = match reason {
+                    Some(v) => v,
+                    // The server was dropped, just close the connection
+                    None => return Ok(ServerEnd::Shutdown),
+                };
+
+                state.sync_server_codec(&mut codec);
+                if let Some(packet) = encode_disconnect(&mut codec, &reason) {
+                    client_write.write_all(&packet).await?;
+                    client_write.flush().await?;
+                }
+
+                tracing::info!("Disconnected client due to shutdown");
+                return Ok(ServerEnd::Shutdown);
+            }
+            Some(action) = actions.recv() => match action {
+                PlayerAction::Transfer => {
+                    state.sync_server_codec(&mut codec);
+
+                    // Older clients must reconnect by themselves
+                    let transfer = state
+                        .transfer_address
+                        .clone()
+                        .filter(|_| state.protocol_version >= TRANSFER_PROTOCOL_VERSION)
+                        .and_then(|transfer| encode_transfer(&mut codec, transfer));
+                    let packet = match transfer {
+                        Some(packet) => {
+                            tracing::info!("Transferred client");
+                            Some(packet)
+                        }
+                        None => {
+                            tracing::info!("Disconnected client to be transferred");
+                            encode_disconnect(&mut codec, &global_state.messages.transfer)
+                        }
+                    };
+
+                    if let Some(packet) = packet {
+                        client_write.write_all(&packet).await?;
+                        client_write.flush().await?;
+                    }
+                    return Ok(ServerEnd::Transferred);
+                }
+                PlayerAction::Disconnect(reason) => {
+                    state.sync_server_codec(&mut codec);
+                    if let Some(packet) = encode_disconnect(&mut codec, &reason) {
+                        client_write.write_all(&packet).await?;
+                        client_write.flush().await?;
+                    }
+
+                    tracing::info!(reason = reason.to_plain_text(), "Kicked client");
+                    *state.disconnect_reason.write().await = Some(*reason);
+                    break;
+                }
+                PlayerAction::Freeze(frozen) => {
+                    state.set_frozen(frozen);
+                    tracing::info!(frozen, "Changed frozen state of client");
+                    continue;
+                }
+            },
         };
 
-        let packet_result = state.decode_server(&vec).await;
-        let current_state = state.current_state().await;
+        state.sync_server_codec(&mut codec);
+        let packet_result = codec.decode(&vec);
+        let current_state = codec.state();
+        let mut disconnected = false;
 
         match packet_result {
             Ok(Some(packet)) => {
@@ -110,47 +332,163 @@ pub async fn handle_server(
 
                 match packet {
                     ServerPacket::Login(LoginClientBoundPacket::LoginSuccess(packet)) => {
+                        // The login start was checked with the uuid sent by
+                        // the client, only the backend one can be trusted
+                        let whitelisted =
+                            is_whitelisted(global_state, &packet.username, packet.uuid)
+                                .await
+                                .unwrap_or_else(|error| {
+                                    tracing::error!(%error, "Failed to check the whitelist");
+                                    false
+                                });
+                        if !whitelisted {
+                            tracing::info!(
+                                username = %packet.username,
+                                uuid = %packet.uuid,
+                                "Player is not whitelisted"
+                            );
+
+                            let packet = LoginClientBoundPacket::LoginDisconnect(LoginDisconnect {
+                                reason: global_state.messages.not_whitelisted.clone(),
+                            });
+                            client_write
+                                .write_all(&encode_server(&mut codec, &packet.into()))
+                                .await?;
+                            client_write.flush().await?;
+                            break;
+                        }
+
+                        if !global_state
+                            .add_online_player(packet.username.clone(), packet.uuid)
+                            .await
+                        {
+                            tracing::info!(
+                                username = %packet.username,
+                                uuid = %packet.uuid,
+                                "A player with this uuid is already connected"
+                            );
+
+                            let packet = LoginClientBoundPacket::LoginDisconnect(LoginDisconnect {
+                                reason: global_state.messages.already_logged_in.clone(),
+                            });
+                            client_write
+                                .write_all(&encode_server(&mut codec, &packet.into()))
+                                .await?;
+                            client_write.flush().await?;
+                            break;
+                        }
+
                         tracing::info!(
                             username = %packet.username,
                             uuid = %packet.uuid,
                             "Login success"
                         );
+                        // The player was added with the username of the
+                        // backend, which may differ from the reserved one
+                        if let Some(reservation) = state.take_reservation() {
+                            global_state.release_reservation(reservation).await;
+                        }
+                        let guard = global_state.guard_online_player(packet.username.clone());
                         let mut lock = state.login_info.write().await;
                         *lock = Some(PostLoginInformation {
                             username: packet.username.clone(),
                             uuid: packet.uuid,
+                            guard,
                         });
                         drop(lock);
-
-                        global_state
-                            .add_online_player(packet.username, packet.uuid)
-                            .await;
+                    }
+                    ServerPacket::Login(LoginClientBoundPacket::LoginDisconnect(packet)) => {
+                        record_disconnect(state, packet.reason).await;
+                        disconnected = true;
+                    }
+                    ServerPacket::Configuration(ConfigClientBoundPaket::ConfigDisconnect(
+                        packet,
+                    )) => {
+                        record_disconnect(state, packet.reason).await;
+                        disconnected = true;
+                    }
+                    ServerPacket::Play(GameClientBoundPacket::Disconnect(packet)) => {
+                        record_disconnect(state, packet.reason).await;
+                        disconnected = true;
                     }
                     ServerPacket::Login(LoginClientBoundPacket::SetCompression(packet)) => {
-                        tracing::debug!(threshold = packet.threshold, "Set compression");
-                        if 0 > packet.threshold {
-                            break;
+                        // Negative thresholds turn compression off, which
+                        // backends may do at any point of the login
+                        if packet.threshold < 0 {
+                            tracing::debug!(threshold = packet.threshold, "Disabled compression");
+                        } else {
+                            tracing::debug!(threshold = packet.threshold, "Set compression");
                         }
-                        state.set_compression(packet.threshold as usize).await;
+                        state.set_compression(packet.threshold);
                     }
                     ServerPacket::Configuration(ConfigClientBoundPaket::FinishConfiguration) => {
-                        state.set_state(ProtocolState::Play).await;
+                        state.set_state(ProtocolState::Play);
                         tracing::debug!("Entered play state");
                     }
                     ServerPacket::Play(GameClientBoundPacket::ClientBoundPluginMessage(
                         plugin_message,
                     )) => {
-                        if plugin_message.channel == "basileia:proxy" {
-                            if request_sender.send(plugin_message.data).await.is_err() {
-                                tracing::error!("Command data sender closed earlier than expected");
+                        if plugin_message.channel == CHANNEL {
+                            if !global_state
+                                .command_dispatcher
+                                .submit(connection_id, plugin_message.data)
+                                .await
+                            {
+                                tracing::error!("Command dispatcher closed earlier than expected");
                                 break;
                             }
                             continue;
                         }
+
+                        if !options.channels.is_allowed(plugin_message.channel.as_str()) {
+                            tracing::debug!(
+                                channel = %plugin_message.channel,
+                                "Dropped server plugin message",
+                            );
+                            continue;
+                        }
+                    }
+                    ServerPacket::Configuration(
+                        ConfigClientBoundPaket::ClientBoundPluginMessage(
+                            ClientBoundPluginMessage { channel, .. },
+                        ),
+                    ) if !options.channels.is_allowed(channel.as_str()) => {
+                        tracing::debug!(%channel, "Dropped server plugin message");
+                        continue;
+                    }
+                    ServerPacket::Configuration(
+                        ConfigClientBoundPaket::ClientBoundPluginMessage(
+                            ClientBoundPluginMessage { channel, data },
+                        ),
+                    ) if channel == BRAND_CHANNEL => {
+                        if let Some(data) = options.server_brand.rewrite_payload(&data) {
+                            let packet = ConfigClientBoundPaket::ClientBoundPluginMessage(
+                                ClientBoundPluginMessage { channel, data },
+                            );
+                            client_write
+                                .write_all(&encode_server(&mut codec, &packet.into()))
+                                .await?;
+                            tracing::debug!("Rewrote server brand");
+                            continue;
+                        }
                     }
                     _ => {}
                 }
             }
+            Err(error @ DecodeError::InvalidIdentifier { .. }) => {
+                tracing::debug!(%error, "Dropped server plugin message");
+                continue;
+            }
+            Err(error @ DecodeError::UnexpectedPacket { .. })
+                if state.protocol_version == STATE_PROTOCOL_VERSION =>
+            {
+                return Err(error)
+            }
+            Err(error @ DecodeError::UnknownPacketType { .. })
+                if state.protocol_version != STATE_PROTOCOL_VERSION =>
+            {
+                tracing::trace!(?current_state, %error, "Incomming server packet");
+            }
             Err(error) => {
                 tracing::warn!(
                     ?current_state,
@@ -158,16 +496,822 @@ pub async fn handle_server(
                     "Incomming server packet could not be decoded"
                 );
             }
-            _ => {
-                tracing::warn!(
-                    ?current_state,
-                    "Incomming server packet could not be decoded"
-                );
+            Ok(None) => {
+                tracing::trace!(?current_state, "Incomming server packet is incomplete");
             }
         }
 
         client_write.write_all(&vec).await?;
+
+        // The relay ends with the disconnect, so that the player is removed
+        // even if neither side closes the connection
+        if disconnected {
+            client_write.flush().await?;
+            break;
+        }
     }
 
-    Ok(())
+    Ok(ServerEnd::Closed)
+}
+
+async fn record_disconnect(state: &ConnectionSharedState, reason: Message) {
+    tracing::info!(
+        reason = reason.to_plain_text(),
+        "Disconnected by the backend"
+    );
+    *state.disconnect_reason.write().await = Some(reason);
+}
+
+fn encode_server(codec: &mut ServerPacketCodec, packet: &ServerPacket) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    codec.encode(packet, &mut buffer);
+    buffer
+}
+
+/// Encodes the disconnect packet of the current state, to be sent to the
+/// client. Returns `None` if the client can't be disconnected with a reason.
+fn encode_disconnect(codec: &mut ServerPacketCodec, reason: &Message) -> Option<Vec<u8>> {
+    let packet = match codec.state() {
+        ProtocolState::Login => LoginClientBoundPacket::LoginDisconnect(LoginDisconnect {
+            reason: reason.clone(),
+        })
+        .into(),
+        ProtocolState::Configuration => {
+            ConfigClientBoundPaket::ConfigDisconnect(ConfigDisconnect {
+                reason: reason.clone(),
+            })
+            .into()
+        }
+        ProtocolState::Play => GameClientBoundPacket::Disconnect(PlayDisconnect {
+            reason: reason.clone(),
+        })
+        .into(),
+        ProtocolState::Handshake | ProtocolState::Status => return None,
+    };
+
+    Some(encode_server(codec, &packet))
+}
+
+/// Encodes the transfer packet of the current state, to be sent to the
+/// client. Returns `None` if the client can't be transferred in this state.
+pub fn encode_transfer(codec: &mut ServerPacketCodec, transfer: Transfer) -> Option<Vec<u8>> {
+    let packet = match codec.state() {
+        ProtocolState::Configuration => ConfigClientBoundPaket::Transfer(transfer).into(),
+        ProtocolState::Play => GameClientBoundPacket::Transfer(transfer).into(),
+        ProtocolState::Handshake | ProtocolState::Status | ProtocolState::Login => return None,
+    };
+
+    Some(encode_server(codec, &packet))
+}
+
+pub(crate) async fn wait_shutdown(
+    shutdown: &mut watch::Receiver<Option<Message>>,
+) -> Option<Message> {
+    shutdown
+        .wait_for(Option::is_some)
+        .await
+        .ok()
+        .and_then(|v| v.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{handle_client, handle_server, RelayOptions, ServerEnd};
+    use crate::{
+        actions::PlayerAction,
+        handler::{brand::BrandRewrite, channels::ChannelFilter},
+        repository::whitelist::WhitelistRepository,
+        state::{test_global_state, ConnectionSharedState},
+        utils::write_packet,
+    };
+    use minecraft_protocol::{
+        codec::{
+            client::ClientPacketCodec,
+            server::{ServerPacket, ServerPacketCodec},
+            ProtocolState,
+        },
+        data::{chat::Message, identifier::Identifier},
+        decoder::{DecoderReadExt, EnumDecoder},
+        encoder::EncoderWriteExt,
+        error::DecodeError,
+        packet::{
+            configuration::{ClientBoundPluginMessage, ConfigClientBoundPaket, ConfigDisconnect},
+            game::{
+                GameClientBoundPacket, GameServerBoundPacket, PlayDisconnect, PlayKeepAlive,
+                PlayPluginMessage,
+            },
+            login::{LoginClientBoundPacket, LoginDisconnect, LoginSuccess},
+        },
+    };
+    use std::io::Cursor;
+    use tokio::{
+        io::{duplex, AsyncReadExt, AsyncWriteExt},
+        sync::{mpsc, watch},
+    };
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_disconnect_on_shutdown() {
+        let global_state = test_global_state().await;
+        let state = ConnectionSharedState::new(765);
+        state.set_state(ProtocolState::Play);
+
+        let (shutdown, shutdown_recv) = watch::channel(None);
+        let (_srv, srv_read) = duplex(1024);
+        let (client_write, mut client_read) = duplex(1024);
+
+        let reason = Message::from_str("Server restarting");
+        shutdown.send_replace(Some(reason.clone()));
+
+        let end = handle_server(
+            &global_state,
+            &state,
+            ServerPacketCodec::new(),
+            &RelayOptions::default(),
+            0,
+            shutdown_recv,
+            mpsc::channel(1).1,
+            srv_read,
+            client_write,
+        )
+        .await
+        .unwrap();
+        assert_eq!(end, ServerEnd::Shutdown);
+
+        let mut vec = Vec::new();
+        client_read.read_to_end(&mut vec).await.unwrap();
+
+        // Length, packet id and the packet itself
+        let mut cursor = Cursor::new(&vec[2..]);
+        match GameClientBoundPacket::decode(vec[1], &mut cursor).unwrap() {
+            GameClientBoundPacket::Disconnect(packet) => assert_eq!(packet.reason, reason),
+            packet => panic!("Unexpected packet {packet:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_on_transfer() {
+        let global_state = test_global_state().await;
+        let state = ConnectionSharedState::new(765);
+        state.set_state(ProtocolState::Play);
+
+        let (_shutdown, shutdown_recv) = watch::channel(None);
+        let (actions, actions_recv) = mpsc::channel(1);
+        let (_srv, srv_read) = duplex(1024);
+        let (client_write, mut client_read) = duplex(1024);
+
+        actions.send(PlayerAction::Transfer).await.unwrap();
+
+        let end = handle_server(
+            &global_state,
+            &state,
+            ServerPacketCodec::new(),
+            &RelayOptions::default(),
+            0,
+            shutdown_recv,
+            actions_recv,
+            srv_read,
+            client_write,
+        )
+        .await
+        .unwrap();
+        assert_eq!(end, ServerEnd::Transferred);
+
+        let mut vec = Vec::new();
+        client_read.read_to_end(&mut vec).await.unwrap();
+
+        let mut cursor = Cursor::new(&vec[2..]);
+        match GameClientBoundPacket::decode(vec[1], &mut cursor).unwrap() {
+            GameClientBoundPacket::Disconnect(packet) => {
+                assert_eq!(packet.reason, global_state.messages.transfer)
+            }
+            packet => panic!("Unexpected packet {packet:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_kick() {
+        let global_state = test_global_state().await;
+        let state = ConnectionSharedState::new(765);
+        state.set_state(ProtocolState::Login);
+
+        let (_shutdown, shutdown_recv) = watch::channel(None);
+        let (actions, actions_recv) = mpsc::channel(1);
+        let (_srv, srv_read) = duplex(1024);
+        let (client_write, mut client_read) = duplex(1024);
+
+        let reason = Message::from_str("You are banned");
+        actions
+            .send(PlayerAction::Disconnect(Box::new(reason.clone())))
+            .await
+            .unwrap();
+
+        handle_server(
+            &global_state,
+            &state,
+            ServerPacketCodec::new(),
+            &RelayOptions::default(),
+            0,
+            shutdown_recv,
+            actions_recv,
+            srv_read,
+            client_write,
+        )
+        .await
+        .unwrap();
+
+        let mut vec = Vec::new();
+        client_read.read_to_end(&mut vec).await.unwrap();
+
+        let mut cursor = Cursor::new(&vec[2..]);
+        match LoginClientBoundPacket::decode(vec[1], &mut cursor).unwrap() {
+            LoginClientBoundPacket::LoginDisconnect(packet) => assert_eq!(packet.reason, reason),
+            packet => panic!("Unexpected packet {packet:?}"),
+        }
+        assert_eq!(*state.disconnect_reason.read().await, Some(reason));
+    }
+
+    #[tokio::test]
+    async fn test_packets_use_ids_of_the_session() {
+        let global_state = test_global_state().await;
+        let state = ConnectionSharedState::new(766);
+        state.set_state(ProtocolState::Play);
+
+        let (_shutdown, shutdown_recv) = watch::channel(None);
+        let (actions, actions_recv) = mpsc::channel(1);
+        let (_srv, srv_read) = duplex(1024);
+        let (client_write, mut client_read) = duplex(1024);
+
+        let reason = Message::from_str("You are banned");
+        actions
+            .send(PlayerAction::Disconnect(Box::new(reason.clone())))
+            .await
+            .unwrap();
+
+        handle_server(
+            &global_state,
+            &state,
+            ServerPacketCodec::new(),
+            &RelayOptions::default(),
+            0,
+            shutdown_recv,
+            actions_recv,
+            srv_read,
+            client_write,
+        )
+        .await
+        .unwrap();
+
+        let mut vec = Vec::new();
+        client_read.read_to_end(&mut vec).await.unwrap();
+        assert_eq!(vec[1], 0x1D);
+
+        let mut codec = ServerPacketCodec::new();
+        state.sync_server_codec(&mut codec);
+        match codec.decode(&vec).unwrap() {
+            Some(ServerPacket::Play(GameClientBoundPacket::Disconnect(packet))) => {
+                assert_eq!(packet.reason, reason)
+            }
+            packet => panic!("Unexpected packet {packet:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transfer_packet() {
+        let global_state = test_global_state().await;
+        let state =
+            ConnectionSharedState::new(766).with_transfer_address("mc.example.com".into(), 25565);
+        state.set_state(ProtocolState::Configuration);
+
+        let (_shutdown, shutdown_recv) = watch::channel(None);
+        let (actions, actions_recv) = mpsc::channel(1);
+        let (_srv, srv_read) = duplex(1024);
+        let (client_write, mut client_read) = duplex(1024);
+
+        actions.send(PlayerAction::Transfer).await.unwrap();
+
+        handle_server(
+            &global_state,
+            &state,
+            ServerPacketCodec::new(),
+            &RelayOptions::default(),
+            0,
+            shutdown_recv,
+            actions_recv,
+            srv_read,
+            client_write,
+        )
+        .await
+        .unwrap();
+
+        let mut vec = Vec::new();
+        client_read.read_to_end(&mut vec).await.unwrap();
+
+        let mut cursor = Cursor::new(&vec[2..]);
+        match ConfigClientBoundPaket::decode(vec[1], &mut cursor).unwrap() {
+            ConfigClientBoundPaket::Transfer(packet) => {
+                assert_eq!(packet.host, "mc.example.com");
+                assert_eq!(packet.port, 25565);
+            }
+            packet => panic!("Unexpected packet {packet:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_blocked_channels_are_dropped() {
+        let state = ConnectionSharedState::new(765);
+        state.set_state(ProtocolState::Play);
+
+        let options = RelayOptions {
+            channels: ChannelFilter::new(None, vec!["fml:*".into()]),
+            ..Default::default()
+        };
+        let (_responses, response_receiver) = mpsc::channel(1);
+        let (mut client, client_read) = duplex(1024);
+        let (srv_write, mut srv_read) = duplex(1024);
+
+        for channel in ["fml:handshake", "minecraft:brand"] {
+            let packet = GameServerBoundPacket::ServerBoundPluginMessage(PlayPluginMessage {
+                channel: Identifier::new(channel).unwrap(),
+                data: vec![1, 2, 3],
+            });
+            write_packet(&mut client, &packet).await.unwrap();
+        }
+
+        // Can't be matched against the filter, so it's dropped too
+        let mut invalid = Vec::new();
+        let packet = GameServerBoundPacket::ServerBoundPluginMessage(PlayPluginMessage {
+            channel: Identifier::from_static("fml:handshake"),
+            data: vec![1, 2, 3],
+        });
+        write_packet(&mut invalid, &packet).await.unwrap();
+        let position = invalid.iter().position(|&b| b == b'f').unwrap();
+        invalid[position] = b'F';
+        client.write_all(&invalid).await.unwrap();
+        drop(client);
+
+        // Fails once the client closes the connection
+        let _ = handle_client(
+            &state,
+            ClientPacketCodec::new(),
+            &options,
+            response_receiver,
+            client_read,
+            srv_write,
+        )
+        .await;
+
+        let mut vec = Vec::new();
+        srv_read.read_to_end(&mut vec).await.unwrap();
+
+        let mut cursor = Cursor::new(&vec[2..]);
+        match GameServerBoundPacket::decode(vec[1], &mut cursor).unwrap() {
+            GameServerBoundPacket::ServerBoundPluginMessage(packet) => {
+                assert_eq!(packet.channel, "minecraft:brand")
+            }
+            packet => panic!("Unexpected packet {packet:?}"),
+        }
+        assert_eq!(vec.len(), vec[0] as usize + 1);
+    }
+
+    #[tokio::test]
+    async fn test_frozen_client_packets_are_dropped() {
+        let state = ConnectionSharedState::new(765);
+        state.set_state(ProtocolState::Play);
+        state.set_frozen(true);
+
+        let (_responses, response_receiver) = mpsc::channel(1);
+        let (mut client, client_read) = duplex(1024);
+        let (srv_write, mut srv_read) = duplex(1024);
+
+        // Movement, use item, then the chunk batch acknowledgement
+        let packets = [
+            GameServerBoundPacket::Other { type_id: 0x17 },
+            GameServerBoundPacket::Other { type_id: 0x36 },
+            GameServerBoundPacket::Other { type_id: 0x07 },
+            GameServerBoundPacket::KeepAlive(PlayKeepAlive { id: 42 }),
+        ];
+        for packet in &packets {
+            write_packet(&mut client, packet).await.unwrap();
+        }
+        drop(client);
+
+        let mut expected = Vec::new();
+        for packet in &packets[2..] {
+            write_packet(&mut expected, packet).await.unwrap();
+        }
+
+        let _ = handle_client(
+            &state,
+            ClientPacketCodec::new(),
+            &RelayOptions::default(),
+            response_receiver,
+            client_read,
+            srv_write,
+        )
+        .await;
+
+        let mut vec = Vec::new();
+        srv_read.read_to_end(&mut vec).await.unwrap();
+        assert_eq!(vec, expected);
+    }
+
+    #[tokio::test]
+    async fn test_frozen_client_packets_of_1_20_5() {
+        let state = ConnectionSharedState::new(766);
+        state.set_state(ProtocolState::Play);
+        state.set_frozen(true);
+
+        let (_responses, response_receiver) = mpsc::channel(1);
+        let (mut client, client_read) = duplex(1024);
+        let (srv_write, mut srv_read) = duplex(1024);
+
+        // Set Player Position, Chunk Batch Received, then Confirm Teleportation
+        for type_id in [0x1A, 0x08, 0x00] {
+            write_packet(&mut client, &GameServerBoundPacket::Other { type_id })
+                .await
+                .unwrap();
+        }
+        drop(client);
+
+        let _ = handle_client(
+            &state,
+            ClientPacketCodec::new(),
+            &RelayOptions::default(),
+            response_receiver,
+            client_read,
+            srv_write,
+        )
+        .await;
+
+        let mut vec = Vec::new();
+        srv_read.read_to_end(&mut vec).await.unwrap();
+        assert_eq!(vec, [1, 0x08, 1, 0x00]);
+    }
+
+    #[tokio::test]
+    async fn test_play_packet_during_login_closes_relay() {
+        let state = ConnectionSharedState::new(765);
+        state.set_state(ProtocolState::Login);
+
+        let (_responses, response_receiver) = mpsc::channel(1);
+        let (mut client, client_read) = duplex(1024);
+        let (srv_write, mut srv_read) = duplex(1024);
+
+        let packet = GameServerBoundPacket::KeepAlive(PlayKeepAlive { id: 42 });
+        write_packet(&mut client, &packet).await.unwrap();
+
+        let result = handle_client(
+            &state,
+            ClientPacketCodec::new(),
+            &RelayOptions::default(),
+            response_receiver,
+            client_read,
+            srv_write,
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(DecodeError::UnexpectedPacket {
+                state: ProtocolState::Login,
+                type_id: 0x15
+            })
+        ));
+        let mut vec = Vec::new();
+        srv_read.read_to_end(&mut vec).await.unwrap();
+        assert!(vec.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unexpected_packet_of_other_versions_is_forwarded() {
+        let state = ConnectionSharedState::new(47);
+        state.set_state(ProtocolState::Login);
+
+        let (_responses, response_receiver) = mpsc::channel(1);
+        let (mut client, client_read) = duplex(1024);
+        let (srv_write, mut srv_read) = duplex(1024);
+
+        let packet = GameServerBoundPacket::KeepAlive(PlayKeepAlive { id: 42 });
+        write_packet(&mut client, &packet).await.unwrap();
+        drop(client);
+
+        let mut expected = Vec::new();
+        write_packet(&mut expected, &packet).await.unwrap();
+
+        handle_client(
+            &state,
+            ClientPacketCodec::new(),
+            &RelayOptions::default(),
+            response_receiver,
+            client_read,
+            srv_write,
+        )
+        .await
+        .ok();
+
+        let mut vec = Vec::new();
+        srv_read.read_to_end(&mut vec).await.unwrap();
+        assert_eq!(vec, expected);
+    }
+
+    #[tokio::test]
+    async fn test_server_brand_is_rewritten() {
+        let global_state = test_global_state().await;
+        let state = ConnectionSharedState::new(765);
+        state.set_state(ProtocolState::Configuration);
+
+        let options = RelayOptions {
+            server_brand: BrandRewrite::Append(" via Basileia".into()),
+            ..Default::default()
+        };
+        let (_shutdown, shutdown_recv) = watch::channel(None);
+        let (mut srv, srv_read) = duplex(1024);
+        let (client_write, mut client_read) = duplex(1024);
+
+        let mut data = Vec::new();
+        data.write_string("Paper", 32767).unwrap();
+        let packet = ConfigClientBoundPaket::ClientBoundPluginMessage(ClientBoundPluginMessage {
+            channel: Identifier::from_static("minecraft:brand"),
+            data,
+        });
+        write_packet(&mut srv, &packet).await.unwrap();
+        drop(srv);
+
+        let _ = handle_server(
+            &global_state,
+            &state,
+            ServerPacketCodec::new(),
+            &options,
+            0,
+            shutdown_recv,
+            mpsc::channel(1).1,
+            srv_read,
+            client_write,
+        )
+        .await;
+
+        let mut vec = Vec::new();
+        client_read.read_to_end(&mut vec).await.unwrap();
+
+        let mut cursor = Cursor::new(&vec[2..]);
+        match ConfigClientBoundPaket::decode(vec[1], &mut cursor).unwrap() {
+            ConfigClientBoundPaket::ClientBoundPluginMessage(packet) => {
+                let brand = Cursor::new(packet.data).read_string(32767).unwrap();
+                assert_eq!(brand, "Paper via Basileia");
+            }
+            packet => panic!("Unexpected packet {packet:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_backend_disconnect_reason_is_recorded() {
+        let global_state = test_global_state().await;
+        let reason = Message::from_str("Server is full");
+
+        let packets: [(ProtocolState, ServerPacket); 3] = [
+            (
+                ProtocolState::Login,
+                LoginClientBoundPacket::LoginDisconnect(LoginDisconnect {
+                    reason: reason.clone(),
+                })
+                .into(),
+            ),
+            (
+                ProtocolState::Configuration,
+                ConfigClientBoundPaket::ConfigDisconnect(ConfigDisconnect {
+                    reason: reason.clone(),
+                })
+                .into(),
+            ),
+            (
+                ProtocolState::Play,
+                GameClientBoundPacket::Disconnect(PlayDisconnect {
+                    reason: reason.clone(),
+                })
+                .into(),
+            ),
+        ];
+
+        for (protocol_state, packet) in packets {
+            let state = ConnectionSharedState::new(765);
+            state.set_state(protocol_state);
+
+            let mut sent = Vec::new();
+            ServerPacketCodec::new().encode(&packet, &mut sent);
+
+            let (_shutdown, shutdown_recv) = watch::channel(None);
+            let (mut srv, srv_read) = duplex(1024);
+            let (client_write, mut client_read) = duplex(1024);
+            srv.write_all(&sent).await.unwrap();
+            drop(srv);
+
+            let _ = handle_server(
+                &global_state,
+                &state,
+                ServerPacketCodec::new(),
+                &RelayOptions::default(),
+                0,
+                shutdown_recv,
+                mpsc::channel(1).1,
+                srv_read,
+                client_write,
+            )
+            .await;
+
+            // Relayed as is
+            let mut vec = Vec::new();
+            client_read.read_to_end(&mut vec).await.unwrap();
+            assert_eq!(vec, sent);
+
+            assert_eq!(
+                state.disconnect_reason.read().await.as_ref(),
+                Some(&reason),
+                "{protocol_state:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_uuid_after_login_success() {
+        let global_state = test_global_state().await;
+        let uuid = Uuid::new_v4();
+        assert!(global_state.add_online_player("Notch".into(), uuid).await);
+
+        let state = ConnectionSharedState::new(765);
+        state.set_state(ProtocolState::Login);
+
+        let (_shutdown, shutdown_recv) = watch::channel(None);
+        let (mut srv, srv_read) = duplex(1024);
+        let (client_write, mut client_read) = duplex(1024);
+
+        let packet = LoginClientBoundPacket::LoginSuccess(LoginSuccess {
+            uuid,
+            username: "notch".into(),
+        });
+        write_packet(&mut srv, &packet).await.unwrap();
+
+        handle_server(
+            &global_state,
+            &state,
+            ServerPacketCodec::new(),
+            &RelayOptions::default(),
+            0,
+            shutdown_recv,
+            mpsc::channel(1).1,
+            srv_read,
+            client_write,
+        )
+        .await
+        .unwrap();
+
+        let mut vec = Vec::new();
+        client_read.read_to_end(&mut vec).await.unwrap();
+
+        let mut cursor = Cursor::new(&vec[2..]);
+        assert!(matches!(
+            LoginClientBoundPacket::decode(vec[1], &mut cursor).unwrap(),
+            LoginClientBoundPacket::LoginDisconnect(_)
+        ));
+        assert!(state.login_info.read().await.is_none());
+        assert_eq!(
+            global_state.read_online_players().await.get("Notch"),
+            Some(&uuid)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_whitelist_checked_with_backend_uuid() {
+        let global_state = test_global_state().await;
+        global_state.whitelist.set_enabled(true).await.unwrap();
+        global_state
+            .whitelist
+            .add("Notch", Some(Uuid::new_v4()), None)
+            .await
+            .unwrap();
+
+        let state = ConnectionSharedState::new(765);
+        state.set_state(ProtocolState::Login);
+
+        let (_shutdown, shutdown_recv) = watch::channel(None);
+        let (mut srv, srv_read) = duplex(1024);
+        let (client_write, mut client_read) = duplex(1024);
+
+        // The client claimed the pinned uuid, but the backend knows better
+        let packet = LoginClientBoundPacket::LoginSuccess(LoginSuccess {
+            uuid: Uuid::new_v4(),
+            username: "Notch".into(),
+        });
+        write_packet(&mut srv, &packet).await.unwrap();
+
+        handle_server(
+            &global_state,
+            &state,
+            ServerPacketCodec::new(),
+            &RelayOptions::default(),
+            0,
+            shutdown_recv,
+            mpsc::channel(1).1,
+            srv_read,
+            client_write,
+        )
+        .await
+        .unwrap();
+
+        let mut vec = Vec::new();
+        client_read.read_to_end(&mut vec).await.unwrap();
+
+        let mut cursor = Cursor::new(&vec[2..]);
+        assert!(matches!(
+            LoginClientBoundPacket::decode(vec[1], &mut cursor).unwrap(),
+            LoginClientBoundPacket::LoginDisconnect(_)
+        ));
+        assert!(state.login_info.read().await.is_none());
+        assert!(global_state.read_online_players().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_bypass_checked_with_backend_uuid() {
+        let global_state = test_global_state().await;
+        global_state.whitelist.set_enabled(true).await.unwrap();
+        let uuid = Uuid::new_v4();
+        global_state.whitelist_bypass.add(&uuid.to_string());
+
+        for (backend_uuid, allowed) in [(Uuid::new_v4(), false), (uuid, true)] {
+            let state = ConnectionSharedState::new(765);
+            state.set_state(ProtocolState::Login);
+
+            let (_shutdown, shutdown_recv) = watch::channel(None);
+            let (mut srv, srv_read) = duplex(1024);
+            let (client_write, _client_read) = duplex(1024);
+
+            let packet = LoginClientBoundPacket::LoginSuccess(LoginSuccess {
+                uuid: backend_uuid,
+                username: "Admin".into(),
+            });
+            write_packet(&mut srv, &packet).await.unwrap();
+            // Ends the relay once the login success is handled
+            drop(srv);
+
+            handle_server(
+                &global_state,
+                &state,
+                ServerPacketCodec::new(),
+                &RelayOptions::default(),
+                0,
+                shutdown_recv,
+                mpsc::channel(1).1,
+                srv_read,
+                client_write,
+            )
+            .await
+            .ok();
+
+            assert_eq!(state.login_info.read().await.is_some(), allowed);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reservation_released_on_login_success() {
+        let global_state = test_global_state().await;
+        let uuid = Uuid::new_v4();
+        assert!(global_state.try_reserve_player("notch", &uuid).await);
+        let reservation = global_state.guard_online_player("notch".into());
+
+        let state = ConnectionSharedState::new(765).with_reservation(Some(reservation));
+        state.set_state(ProtocolState::Login);
+
+        let (_shutdown, shutdown_recv) = watch::channel(None);
+        let (mut srv, srv_read) = duplex(1024);
+        let (client_write, _client_read) = duplex(1024);
+
+        // The backend capitalizes the username as the account does
+        let packet = LoginClientBoundPacket::LoginSuccess(LoginSuccess {
+            uuid,
+            username: "Notch".into(),
+        });
+        write_packet(&mut srv, &packet).await.unwrap();
+        drop(srv);
+
+        handle_server(
+            &global_state,
+            &state,
+            ServerPacketCodec::new(),
+            &RelayOptions::default(),
+            0,
+            shutdown_recv,
+            mpsc::channel(1).1,
+            srv_read,
+            client_write,
+        )
+        .await
+        .ok();
+
+        assert!(state.take_reservation().is_none());
+        let login_info = state.login_info.write().await.take().unwrap();
+        global_state.release_online_player(login_info.guard).await;
+
+        assert!(global_state.read_online_players().await.is_empty());
+        assert!(global_state.try_reserve_player("notch", &uuid).await);
+    }
 }
@@ -0,0 +1,226 @@
+use super::proxy::wait_shutdown;
+use crate::{
+    errors::AppError,
+    queue::{QueueManager, QueueSlot},
+    utils::{read_packet, write_packet},
+};
+use minecraft_protocol::{
+    data::chat::Message,
+    packet::login::{
+        LoginClientBoundPacket, LoginDisconnect, LoginPluginRequest, LoginServerBoundPacket,
+    },
+};
+use std::{io::Cursor, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::watch,
+    time::Instant,
+};
+
+/// The login plugin channel the position updates are sent on.
+pub const QUEUE_CHANNEL: &str = "mc-proxy:queue";
+
+/// Login plugin requests were added in 1.13.
+const LOGIN_PLUGIN_PROTOCOL_VERSION: i32 = 393;
+
+/// Waits for a slot in the queue, sending the position to the client
+/// whenever it changes or the update interval elapses.
+///
+/// The login screen has no way to show text, so the position is sent as a
+/// login plugin request on [`QUEUE_CHANNEL`], which vanilla clients answer
+/// without showing it. These packets also keep the client from timing out,
+/// older clients that don't support them are kept waiting silently.
+///
+/// The answers are read before returning so that the backend never sees
+/// them, only that is subject to `timeout`. `None` is returned if the client
+/// left the queue or the proxy is shutting down.
+pub async fn handle_queue<'q, C: AsyncRead + AsyncWrite + Unpin + Send>(
+    queue: &'q QueueManager,
+    conn: &mut C,
+    protocol_version: i32,
+    mut shutdown: watch::Receiver<Option<Message>>,
+    timeout: Duration,
+) -> Result<Option<QueueSlot<'q>>, AppError> {
+    if let Some(slot) = queue.try_acquire() {
+        return Ok(Some(slot));
+    }
+
+    let ticket = queue.enqueue();
+    let send_updates = protocol_version >= LOGIN_PLUGIN_PROTOCOL_VERSION;
+
+    let mut sent = 0;
+    let mut last_position = 0;
+    let mut next_update = Instant::now();
+
+    let slot = loop {
+        // Listening before checking, so that a slot freed in between isn't missed
+        let changed = ticket.changed();
+        tokio::pin!(changed);
+        changed.as_mut().enable();
+
+        if let Some(slot) = ticket.try_acquire() {
+            break slot;
+        }
+
+        let position = ticket.position();
+        if position != last_position || Instant::now() >= next_update {
+            tracing::debug!(position, waiting = queue.waiting(), "Waiting in queue");
+
+            if send_updates {
+                let packet = LoginClientBoundPacket::LoginPluginRequest(LoginPluginRequest {
+                    message_id: sent,
+                    channel: QUEUE_CHANNEL.into(),
+                    data: queue.message_json(position).into_bytes(),
+                });
+                if let Err(error) = write_packet(conn, &packet).await {
+                    tracing::debug!(%error, "Client left the queue");
+                    return Ok(None);
+                }
+                sent += 1;
+            }
+
+            last_position = position;
+            next_update = Instant::now() + queue.update_interval();
+        }
+
+        tokio::select! {
+            _ = tokio::time::timeout(next_update - Instant::now(), changed) => {}
+            reason = wait_shutdown(&mut shutdown) => {
+                if let Some(reason) = reason {
+                    let packet = LoginClientBoundPacket::LoginDisconnect(LoginDisconnect {
+                        reason,
+                    });
+                    let _ = write_packet(conn, &packet).await;
+                    tracing::info!("Disconnected queued client due to shutdown");
+                }
+                return Ok(None);
+            }
+        }
+    };
+
+    for _ in 0..sent {
+        let vec = match tokio::time::timeout(timeout, read_packet(conn, false)).await {
+            Ok(v) => match v? {
+                Some(v) => v,
+                None => return Ok(None),
+            },
+            Err(_) => return Err(AppError::Timeout),
+        };
+
+        let packet =
+            LoginServerBoundPacket::decode_versioned(&mut Cursor::new(vec), protocol_version)?;
+        if !matches!(packet, LoginServerBoundPacket::LoginPluginResponse(_)) {
+            tracing::warn!(?packet, "Unexpected packet while waiting in queue");
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(slot))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{handle_queue, QUEUE_CHANNEL};
+    use crate::{
+        queue::QueueManager,
+        utils::{read_packet, write_packet},
+    };
+    use minecraft_protocol::{
+        data::chat::Message,
+        decoder::Decoder,
+        packet::login::{LoginClientBoundPacket, LoginPluginResponse, LoginServerBoundPacket},
+    };
+    use std::{io::Cursor, time::Duration};
+    use tokio::{io::duplex, sync::watch};
+
+    const PROTOCOL_VERSION: i32 = 765;
+
+    fn queue() -> QueueManager {
+        QueueManager::new(
+            1,
+            Duration::from_secs(5),
+            Message::from_str("You are #%position% in the queue"),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_slot() {
+        let queue = queue();
+        let slot = queue.try_acquire().unwrap();
+        let (mut client, mut conn) = duplex(4096);
+        let (_shutdown, shutdown_recv) = watch::channel(None);
+
+        let client = async {
+            let vec = read_packet(&mut client, false).await.unwrap().unwrap();
+            let packet = LoginClientBoundPacket::decode(&mut Cursor::new(vec)).unwrap();
+            let LoginClientBoundPacket::LoginPluginRequest(request) = packet else {
+                panic!("expected a login plugin request, got {packet:?}");
+            };
+            assert_eq!(request.channel, QUEUE_CHANNEL);
+            assert!(String::from_utf8(request.data).unwrap().contains("#1 "));
+
+            drop(slot);
+
+            let packet = LoginServerBoundPacket::LoginPluginResponse(LoginPluginResponse {
+                message_id: request.message_id,
+                successful: false,
+                data: Vec::new(),
+            });
+            write_packet(&mut client, &packet).await.unwrap();
+            client
+        };
+
+        let (slot, mut client) = tokio::join!(
+            handle_queue(
+                &queue,
+                &mut conn,
+                PROTOCOL_VERSION,
+                shutdown_recv,
+                Duration::from_secs(5),
+            ),
+            client,
+        );
+        assert!(slot.unwrap().is_some());
+        assert_eq!(queue.waiting(), 0);
+
+        // The answer was consumed
+        drop(conn);
+        assert!(read_packet(&mut client, false).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_while_waiting() {
+        let queue = queue();
+        let _slot = queue.try_acquire().unwrap();
+        let (mut client, mut conn) = duplex(4096);
+        let (shutdown, shutdown_recv) = watch::channel(None);
+        shutdown.send_replace(Some(Message::from_str("Server restarting")));
+
+        let slot = handle_queue(
+            &queue,
+            &mut conn,
+            PROTOCOL_VERSION,
+            shutdown_recv,
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        assert!(slot.is_none());
+        assert_eq!(queue.waiting(), 0);
+
+        let mut packets = Vec::new();
+        while let Ok(Some(vec)) = read_packet(&mut client, false).await {
+            packets.push(LoginClientBoundPacket::decode(&mut Cursor::new(vec)).unwrap());
+            if packets.len() == 2 {
+                break;
+            }
+        }
+        assert!(matches!(
+            packets.as_slice(),
+            [
+                LoginClientBoundPacket::LoginPluginRequest(_),
+                LoginClientBoundPacket::LoginDisconnect(_)
+            ]
+        ));
+    }
+}
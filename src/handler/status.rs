@@ -1,6 +1,6 @@
 use crate::{
     state::GlobalSharedState,
-    utils::{read_packet, write_packet},
+    utils::{write_packet, PacketBudget},
 };
 use minecraft_protocol::{
     codec::ProtocolState,
@@ -15,17 +15,33 @@ use minecraft_protocol::{
 use std::io::Cursor;
 use tokio::io::{AsyncRead, AsyncWrite};
 
+/// The optional fields of the status responses, left out when `None`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StatusOptions {
+    pub enforces_secure_chat: Option<bool>,
+    pub previews_chat: Option<bool>,
+}
+
 pub async fn handle_status<C: AsyncRead + AsyncWrite + Unpin + Send>(
     global_state: &GlobalSharedState,
+    options: &StatusOptions,
     handshake_data: &Handshake,
     conn: &mut C,
+    mut budget: PacketBudget,
 ) -> Result<(), DecodeError> {
     let current_state = ProtocolState::Status;
+    let mut responded = false;
 
     loop {
-        let vec = match read_packet(conn, false).await? {
-            Some(v) => v,
-            None => break,
+        let vec = match budget.read_packet(conn).await {
+            Ok(Some(v)) => v,
+            Ok(None) => break,
+            // Some server list clients only want the status
+            Err(error) if error.is_eof_error() && responded => {
+                tracing::debug!("Status connection closed without ping request");
+                break;
+            }
+            Err(error) => return Err(error),
         };
         let mut cursor = Cursor::new(vec);
 
@@ -50,22 +66,24 @@ pub async fn handle_status<C: AsyncRead + AsyncWrite + Unpin + Send>(
 
                 drop(online_players);
 
-                let packet = StatusClientBoundPacket::StatusResponse(StatusResponse {
-                    server_status: ServerStatus {
-                        description,
-                        players: OnlinePlayers {
-                            max: 0,
-                            online: online_count.try_into().unwrap(),
-                            sample: online_sample,
-                        },
-                        version: ServerVersion {
-                            name: format!("Basileia Proxy {}", env!("CARGO_PKG_VERSION")),
-                            protocol: handshake_data.protocol_version.try_into().unwrap(),
-                        },
+                let packet = StatusResponse::new(ServerStatus {
+                    description,
+                    players: OnlinePlayers {
+                        max: 0,
+                        online: online_count.try_into().unwrap_or(u32::MAX),
+                        sample: online_sample,
+                    },
+                    version: ServerVersion {
+                        name: format!("Basileia Proxy {}", env!("CARGO_PKG_VERSION")),
+                        // Negative versions sent by the client are shown as 0
+                        protocol: handshake_data.protocol_version.try_into().unwrap_or(0),
                     },
+                    enforces_secure_chat: options.enforces_secure_chat,
+                    previews_chat: options.previews_chat,
                 });
 
                 write_packet(conn, &packet).await?;
+                responded = true;
                 tracing::debug!("Status connection responded");
             }
             StatusServerBoundPacket::PingRequest(req) => {
@@ -82,3 +100,203 @@ pub async fn handle_status<C: AsyncRead + AsyncWrite + Unpin + Send>(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{handle_status, StatusOptions};
+    use crate::{
+        state::test_global_state,
+        utils::{read_packet, write_packet, PacketBudget},
+    };
+    use minecraft_protocol::{
+        decoder::Decoder,
+        error::DecodeError,
+        packet::{
+            handshake::{Handshake, NextState},
+            status::{PingRequest, StatusClientBoundPacket, StatusServerBoundPacket},
+        },
+    };
+    use std::io::Cursor;
+    use tokio::io::{duplex, AsyncWriteExt, DuplexStream};
+
+    fn handshake() -> Handshake {
+        Handshake {
+            protocol_version: 765,
+            server_addr: "localhost".into(),
+            server_port: 25565,
+            next_state: NextState::Status,
+        }
+    }
+
+    async fn read_response(client: &mut DuplexStream) -> StatusClientBoundPacket {
+        let data = read_packet(client, false).await.unwrap().unwrap();
+        StatusClientBoundPacket::decode(&mut Cursor::new(data)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_coalesced_status_and_ping() {
+        let state = test_global_state().await;
+        let (mut client, mut conn) = duplex(4096);
+
+        // Both requests arrive in a single read
+        let mut data = Vec::new();
+        write_packet(&mut data, &StatusServerBoundPacket::StatusRequest)
+            .await
+            .unwrap();
+        write_packet(&mut data, &PingRequest::new(42))
+            .await
+            .unwrap();
+        client.write_all(&data).await.unwrap();
+
+        handle_status(
+            &state,
+            &StatusOptions::default(),
+            &handshake(),
+            &mut conn,
+            PacketBudget::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            read_response(&mut client).await,
+            StatusClientBoundPacket::StatusResponse(_)
+        ));
+        assert!(matches!(
+            read_response(&mut client).await,
+            StatusClientBoundPacket::PingResponse(v) if v.time == 42
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_negative_protocol_version() {
+        let state = test_global_state().await;
+        let (mut client, mut conn) = duplex(4096);
+
+        write_packet(&mut client, &StatusServerBoundPacket::StatusRequest)
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+
+        let handshake = Handshake {
+            protocol_version: -1,
+            ..handshake()
+        };
+        handle_status(
+            &state,
+            &StatusOptions::default(),
+            &handshake,
+            &mut conn,
+            PacketBudget::default(),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            read_response(&mut client).await,
+            StatusClientBoundPacket::StatusResponse(v) if v.server_status.version.protocol == 0
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_status_options() {
+        let state = test_global_state().await;
+        let (mut client, mut conn) = duplex(4096);
+
+        write_packet(&mut client, &StatusServerBoundPacket::StatusRequest)
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+
+        let options = StatusOptions {
+            enforces_secure_chat: Some(false),
+            previews_chat: None,
+        };
+        handle_status(
+            &state,
+            &options,
+            &handshake(),
+            &mut conn,
+            PacketBudget::default(),
+        )
+        .await
+        .unwrap();
+
+        let StatusClientBoundPacket::StatusResponse(response) = read_response(&mut client).await
+        else {
+            panic!("expected a status response");
+        };
+        assert_eq!(response.server_status.enforces_secure_chat, Some(false));
+        assert_eq!(response.server_status.previews_chat, None);
+    }
+
+    #[tokio::test]
+    async fn test_packet_budget() {
+        let state = test_global_state().await;
+        let (mut client, mut conn) = duplex(4096);
+
+        for _ in 0..3 {
+            write_packet(&mut client, &StatusServerBoundPacket::StatusRequest)
+                .await
+                .unwrap();
+        }
+
+        let result = handle_status(
+            &state,
+            &StatusOptions::default(),
+            &handshake(),
+            &mut conn,
+            PacketBudget::new(2, 8192),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(DecodeError::TooManyPackets { max_packets: 2 })
+        ));
+
+        // Both requests within the budget were answered
+        for _ in 0..2 {
+            assert!(matches!(
+                read_response(&mut client).await,
+                StatusClientBoundPacket::StatusResponse(_)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_closed_without_ping() {
+        let state = test_global_state().await;
+        let (mut client, mut conn) = duplex(4096);
+
+        write_packet(&mut client, &StatusServerBoundPacket::StatusRequest)
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+
+        handle_status(
+            &state,
+            &StatusOptions::default(),
+            &handshake(),
+            &mut conn,
+            PacketBudget::default(),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            read_response(&mut client).await,
+            StatusClientBoundPacket::StatusResponse(_)
+        ));
+
+        // Closing before anything was served is still reported
+        let (client, mut conn) = duplex(4096);
+        drop(client);
+        let result = handle_status(
+            &state,
+            &StatusOptions::default(),
+            &handshake(),
+            &mut conn,
+            PacketBudget::default(),
+        )
+        .await;
+        assert!(result.is_err_and(|v| v.is_eof_error()));
+    }
+}
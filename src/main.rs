@@ -1,54 +1,310 @@
-use crate::{config::Config, state::GlobalSharedState, utils::touch_file};
+use crate::{
+    backend::{
+        health::{BackendHealthMap, CircuitBreaker, HealthChecker},
+        pool::BackendPool,
+        route::{RetryBackoff, Route, Router},
+        Backend,
+    },
+    bypass::WhitelistBypass,
+    commands::{
+        admin::serve_admin_api,
+        auth::{CommandAuth, CommandCredentials},
+        dispatcher::CommandDispatcher,
+        handler::{proxy_command_events, CommandLimits},
+    },
+    config::Config,
+    handler::{
+        channels::ChannelFilter,
+        handshake::HostAllowlist,
+        messages::DisconnectMessages,
+        proxy::{RelayOptions, RelayPackets},
+        status::StatusOptions,
+    },
+    presence::SharedPresence,
+    queue::QueueManager,
+    resolver::{CachedResolver, MojangResolver, UsernameResolver},
+    session::SessionLock,
+    state::GlobalSharedState,
+    stats::StatsCollector,
+    utils::touch_file,
+};
+use futures_util::future::join_all;
+#[cfg(feature = "geoip")]
+use geoip::GeoFilter;
 use repository::{
-    ip_bans::SqlxIpBansRepository, kv::SqlxKeyValueRepository, user_bans::SqlxUserBansRepository,
+    ban_cache::{CachedIpBansRepository, CachedUserBansRepository},
+    ip_bans::SqlxIpBansRepository,
+    kv::SqlxKeyValueRepository,
+    stats::SqlxStatsRepository,
+    user_bans::SqlxUserBansRepository,
+    user_ip_bans::{parse_network, SqlxUserIpBansRepository},
     whitelist::SqlxWhitelistRepository,
+    write_behind::WriteBehindKeyValue,
+    DB,
+};
+use server::{IpAllowlist, PhaseTimeouts, Server};
+use sqlx::{
+    migrate,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions},
+};
+use std::{
+    io::Error,
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use server::Server;
-use sqlx::{migrate, SqlitePool};
-use std::{io::Error, sync::Arc, time::Instant};
-use tokio::net::TcpListener;
+use tokio::{net::TcpListener, time::MissedTickBehavior};
 use tracing::{Instrument, Level};
+#[cfg(unix)]
+use utils::socket::bind_unix_listener;
 use utils::{
     service::{config_and_init_service, graceful_shutdown},
-    BoxDynError,
+    socket::{bind_listener, Listener, SocketOptions},
+    BoxDynError, PacketBudget,
 };
 
+mod actions;
+mod backend;
+mod build_info;
+mod bypass;
 mod commands;
 mod config;
 mod errors;
+#[cfg(feature = "geoip")]
+mod geoip;
 mod handler;
+mod presence;
+mod queue;
 mod repository;
+mod resolver;
 mod server;
+mod session;
 mod state;
+mod stats;
 mod utils;
 
-async fn listen_loop(listener: TcpListener, srv: Arc<Server>) -> Error {
+/// How long a connection waits for the sqlite write lock before failing.
+const SQLITE_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn listen_loop<L: Listener>(
+    listener: L,
+    label: String,
+    proxy_protocol: bool,
+    srv: Arc<Server>,
+) -> Result<(), Error> {
+    let mut shutdown = srv.subscribe_shutdown();
+
     loop {
-        let (conn, address) = match listener.accept().await {
-            Ok(v) => v,
-            Err(err) => return err,
+        // Slowed down while the database is failing, instead of failing the
+        // ban check of every connection
+        if let Some(delay) = srv.global_state().database_health.accept_delay() {
+            tracing::debug!(?delay, listener = label, "Delaying accept");
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = shutdown.wait_for(Option::is_some) => return Ok(()),
+            }
+        }
+
+        let (conn, address) = tokio::select! {
+            v = listener.accept() => v?,
+            _ = shutdown.wait_for(Option::is_some) => return Ok(()),
         };
 
-        let srv = srv.clone();
-        tokio::task::spawn(async move {
-            let _ = srv
-                .handle_conn(conn, address)
-                .instrument(tracing::span!(Level::ERROR, "connection", %address))
-                .await;
+        if let Err(error) = listener.apply_options(&conn, srv.socket_options()) {
+            tracing::warn!(%error, %address, "Failed to set socket options");
+        }
+
+        let task_srv = srv.clone();
+        let label = label.clone();
+        srv.spawn_connection(async move {
+            let span = tracing::span!(
+                Level::ERROR,
+                "connection",
+                listener = label,
+                %address,
+                client = tracing::field::Empty,
+                username = tracing::field::Empty,
+            );
+            if proxy_protocol {
+                task_srv
+                    .handle_proxied_conn(conn, address)
+                    .instrument(span)
+                    .await;
+            } else {
+                task_srv.handle_conn(conn, address).instrument(span).await;
+            }
         });
     }
 }
 
+/// Accepts the connections of `listener`, which start with a PROXY protocol
+/// header if `proxy_protocol` is set.
+async fn run_listener<L: Listener>(listener: L, proxy_protocol: bool, srv: Arc<Server>) {
+    let label = listener.label();
+
+    if let Err(error) = listen_loop(listener, label.clone(), proxy_protocol, srv).await {
+        tracing::error!(%error, listener = label, "Listener stopped accepting connections");
+    }
+}
+
+async fn flush_stats_loop(state: &GlobalSharedState, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(error) = state.stats.flush().await {
+            tracing::error!(%error, "Failed to flush stats");
+        }
+    }
+}
+
+async fn refresh_presence_loop(state: &GlobalSharedState, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+        state.refresh_presence().await;
+    }
+}
+
+async fn flush_writes_loop(
+    write_behind: &WriteBehindKeyValue<SqlxKeyValueRepository<DB>>,
+    interval: Duration,
+) {
+    let mut interval = tokio::time::interval(interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(error) = write_behind.flush().await {
+            tracing::error!(%error, "Failed to flush buffered writes");
+        }
+    }
+}
+
+fn build_router(config: &Config, socket_options: SocketOptions) -> Result<Router, BoxDynError> {
+    let mut addresses: Vec<String> = Vec::new();
+    let mut index_of = |address: &String| match addresses.iter().position(|v| v == address) {
+        Some(i) => i,
+        None => {
+            addresses.push(address.clone());
+            addresses.len() - 1
+        }
+    };
+
+    let default_backends: Vec<_> = config
+        .proxied_addr
+        .clone()
+        .into_vec()
+        .iter()
+        .map(&mut index_of)
+        .collect();
+    if default_backends.is_empty() {
+        return Err("At least one proxied server address must be configured".into());
+    }
+    let default = Route::new(Vec::new(), default_backends, config.balance_strategy)
+        .with_versions(config.protocol_versions.clone(), None);
+
+    let mut routes = Vec::with_capacity(config.routes.len());
+    for route in &config.routes {
+        let backends: Vec<_> = route
+            .backends
+            .clone()
+            .into_vec()
+            .iter()
+            .map(&mut index_of)
+            .collect();
+        if backends.is_empty() || route.hosts.is_empty() {
+            return Err("Routes must have at least one host and one backend".into());
+        }
+        let versions = route
+            .protocol_versions
+            .clone()
+            .unwrap_or_else(|| config.protocol_versions.clone());
+        routes.push(
+            Route::new(route.hosts.clone(), backends, route.strategy)
+                .with_versions(versions, route.version_rejected_message.clone()),
+        );
+    }
+
+    let backends = addresses
+        .into_iter()
+        .map(|address| {
+            Backend::new(BackendPool::new(
+                address,
+                config.backend_pool_size,
+                Duration::from_secs(config.backend_pool_idle_secs),
+                socket_options,
+            ))
+        })
+        .collect();
+
+    let backoff = RetryBackoff {
+        base: Duration::from_millis(config.backend_retry_backoff_ms),
+        max: Duration::from_millis(config.backend_retry_backoff_max_ms),
+    };
+    Ok(Router::new(backends, routes, default).with_retry_backoff(backoff))
+}
+
 async fn run_service(config: Config) -> Result<(), BoxDynError> {
     touch_file(&config.sqlite_file).await?;
 
-    let listener = TcpListener::bind(config.listen_addr).await?;
-    tracing::info!(
-        port = config.listen_addr.port(),
-        "Listening for connections"
-    );
+    let socket_options = SocketOptions {
+        nodelay: config.tcp_nodelay,
+        keepalive: (config.tcp_keepalive_secs > 0)
+            .then(|| Duration::from_secs(config.tcp_keepalive_secs)),
+        send_buffer_size: (config.tcp_send_buffer_size > 0).then_some(config.tcp_send_buffer_size),
+        recv_buffer_size: (config.tcp_recv_buffer_size > 0).then_some(config.tcp_recv_buffer_size),
+    };
+    let router = build_router(&config, socket_options)?;
+    let server_description = config.server_description();
 
-    let pool = SqlitePool::connect(&format!("sqlite:{}", config.sqlite_file)).await?;
+    let mut listeners = Vec::new();
+    for addr in config.listen_addrs.into_vec() {
+        listeners.push(bind_listener(addr, config.listen_backlog)?);
+        tracing::info!(%addr, "Listening for connections");
+    }
+    #[cfg(unix)]
+    let unix_listener = match &config.listen_unix {
+        Some(path) => {
+            let listener = bind_unix_listener(Path::new(path))?;
+            tracing::info!(path, "Listening for connections");
+            Some(listener)
+        }
+        None => None,
+    };
+    #[cfg(not(unix))]
+    if config.listen_unix.is_some() {
+        return Err("Unix sockets are not supported on this platform".into());
+    }
+    if listeners.is_empty() && config.listen_unix.is_none() {
+        return Err("At least one listen address must be configured".into());
+    }
+
+    // WAL lets the ban and whitelist reads run while the buffered writes are
+    // flushed, and the busy timeout makes writers wait instead of failing
+    let connect_options =
+        SqliteConnectOptions::from_str(&format!("sqlite:{}", config.sqlite_file))?
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(SQLITE_BUSY_TIMEOUT);
+    let admin_listener = match config.admin_addr {
+        Some(addr) => {
+            let listener = TcpListener::bind(addr).await?;
+            tracing::info!(%addr, "Listening for admin API requests");
+            Some(listener)
+        }
+        None => None,
+    };
+
+    let pool = SqlitePoolOptions::new()
+        .max_connections(config.sqlite_max_connections)
+        .connect_with(connect_options)
+        .await?;
 
     let migration_start = Instant::now();
     migrate!().run(&pool).await?;
@@ -60,27 +316,393 @@ async fn run_service(config: Config) -> Result<(), BoxDynError> {
     );
 
     let key_value = SqlxKeyValueRepository::new(pool.clone());
+    let write_behind = WriteBehindKeyValue::new(key_value.clone());
 
-    let ip_bans = SqlxIpBansRepository::new(pool.clone());
-    let user_bans = SqlxUserBansRepository::new(pool.clone());
+    let ban_cache_ttl = Duration::from_secs(config.ban_cache_ttl);
+    let ip_bans = CachedIpBansRepository::new(
+        SqlxIpBansRepository::new(pool.clone()),
+        config.ban_cache_size,
+        ban_cache_ttl,
+    );
+    let user_bans = CachedUserBansRepository::new(
+        SqlxUserBansRepository::new(pool.clone()),
+        config.ban_cache_size,
+        ban_cache_ttl,
+    );
+    let user_ip_bans = SqlxUserIpBansRepository::new(pool.clone());
+
+    let (command_dispatcher, command_receiver) =
+        CommandDispatcher::new(Duration::from_secs(config.command_response_buffer_time));
+
+    let grace_period = Duration::from_secs(config.command_secret_grace_period);
+    let mut command_auth = CommandAuth::new(CommandCredentials::new(
+        config.command_secret,
+        config.command_previous_secret,
+        grace_period,
+        config.command_permission,
+    ));
+    for (address, backend) in config.command_backends {
+        let credentials = CommandCredentials::new(
+            Some(backend.secret),
+            backend.previous_secret,
+            grace_period,
+            backend.permission,
+        );
+        command_auth = command_auth.with_backend(address, credentials);
+    }
+    if !command_auth.is_enabled() {
+        tracing::warn!("No command secret configured, commands won't be authenticated");
+    } else if !command_auth.is_default_enabled() {
+        tracing::warn!(
+            "No command secret configured, commands of backends without one won't be authenticated",
+        );
+    }
+
+    let username_resolver = if config.online_mode {
+        let resolver = CachedResolver::new(
+            MojangResolver::new(Duration::from_secs(5))?,
+            key_value.clone(),
+            Duration::from_secs(config.username_cache_ttl),
+            config.username_lookup_rate_limit,
+        );
+        Some(Box::new(resolver) as Box<dyn UsernameResolver>)
+    } else {
+        None
+    };
+
+    let session_lock = config.session_ip_lock_secs.map(|secs| {
+        SessionLock::new(
+            write_behind.clone(),
+            Duration::from_secs(secs),
+            config.session_ip_lock_bypass,
+            config.session_ip_lock_message,
+        )
+    });
+    let presence = config
+        .presence_ttl_secs
+        .map(|secs| SharedPresence::new(key_value.clone(), Duration::from_secs(secs)));
 
     let global_state = GlobalSharedState::new(
-        config.server_status,
+        server_description,
+        key_value.clone(),
         ip_bans,
         user_bans,
+        user_ip_bans,
         SqlxWhitelistRepository::new(pool.clone(), key_value),
-    );
+        command_auth,
+        command_dispatcher,
+        StatsCollector::new(SqlxStatsRepository::new(pool.clone())),
+        BackendHealthMap::new(router.backends().iter().map(Backend::address)).with_circuit_breaker(
+            CircuitBreaker {
+                threshold: config.backend_circuit_breaker_threshold,
+                cooldown: Duration::from_secs(config.backend_circuit_breaker_cooldown),
+            },
+        ),
+        username_resolver,
+        session_lock,
+        WhitelistBypass::new(config.whitelist_bypass),
+        DisconnectMessages {
+            already_logged_in: config.msg_already_logged_in,
+            banned: config.msg_banned,
+            not_whitelisted: config.msg_not_whitelisted,
+            version_rejected: config.msg_version_rejected,
+            transfer: config.msg_transfer,
+            backend_unavailable: config.msg_backend_unavailable,
+        },
+        presence,
+        config.queue_slots.map(|slots| {
+            QueueManager::new(
+                slots,
+                Duration::from_secs(config.queue_update_interval),
+                config.queue_message,
+            )
+        }),
+    )
+    .with_ban_check_failure(config.ban_check_failure);
+
+    match global_state.load_server_description().await {
+        Ok(true) => tracing::info!("Using the server description set at runtime"),
+        Ok(false) => {}
+        Err(error) => tracing::warn!(%error, "Failed to load stored server description"),
+    }
+
+    let health_checker = HealthChecker {
+        interval: Duration::from_secs(config.health_check_interval),
+        timeout: Duration::from_secs(5),
+        failure_threshold: config.health_check_failure_threshold,
+        protocol_version: 765,
+    };
 
-    let srv = Arc::new(Server::new(config.proxied_addr, global_state));
-    let tcp_end = tokio::spawn(listen_loop(listener, srv));
+    let allowed_hosts = match (config.allowed_hostnames, config.expected_port) {
+        (None, None) => None,
+        (hosts, port) => Some(HostAllowlist::new(
+            hosts,
+            port,
+            config.hostname_rejected_message,
+        )),
+    };
 
-    graceful_shutdown(tcp_end).await?;
+    let srv = Server::new(
+        router,
+        socket_options,
+        global_state,
+        allowed_hosts,
+        RelayOptions {
+            channels: ChannelFilter::new(
+                config.allowed_plugin_channels,
+                config.denied_plugin_channels,
+            ),
+            server_brand: config.server_brand,
+            client_brand: config.client_brand,
+            packets: RelayPackets::new(),
+        },
+        PhaseTimeouts {
+            handshake: Duration::from_secs(config.handshake_timeout),
+            status: Duration::from_secs(config.status_timeout),
+            login_start: Duration::from_secs(config.login_start_timeout),
+            backend_connect: Duration::from_secs(config.backend_connect_timeout),
+        },
+    )
+    .with_status_options(StatusOptions {
+        enforces_secure_chat: config.status_enforces_secure_chat,
+        previews_chat: config.status_previews_chat,
+    })
+    .with_pre_login_budget(PacketBudget::new(
+        config.pre_login_max_packets,
+        config.pre_login_max_bytes,
+    ));
+    let srv = match &config.ip_allowlist {
+        Some(networks) => srv.with_ip_allowlist(IpAllowlist::new(
+            // Validated with the config
+            networks.iter().filter_map(|v| parse_network(v).ok()),
+        )),
+        None => srv,
+    };
+    #[cfg(feature = "geoip")]
+    let srv = match &config.geoip_db_path {
+        Some(path) => {
+            let filter = GeoFilter::open(
+                path,
+                config.geoip_blocked_countries,
+                config.geoip_blocked_asns,
+            )?;
+            tracing::info!(path, "Loaded GeoIP database");
+            srv.with_geo_filter(filter)
+        }
+        None => srv,
+    };
+    let srv = Arc::new(srv);
+    let pool_end = tokio::spawn({
+        let srv = srv.clone();
+        async move {
+            join_all(srv.router().backends().iter().map(|v| v.pool().run())).await;
+        }
+    });
+    let health_end = tokio::spawn({
+        let srv = srv.clone();
+        async move {
+            health_checker
+                .run(srv.router().backends(), &srv.global_state().backend_health)
+                .await
+        }
+    });
+    let command_end = tokio::spawn({
+        let srv = srv.clone();
+        let limits = CommandLimits {
+            max_size: config.command_max_size,
+            per_second: config.command_rate_limit,
+        };
+        async move { proxy_command_events(srv.global_state(), command_receiver, limits).await }
+    });
+    let stats_end = tokio::spawn({
+        let srv = srv.clone();
+        let interval = Duration::from_secs(config.stats_flush_interval);
+        async move { flush_stats_loop(srv.global_state(), interval).await }
+    });
+    let writes_end = tokio::spawn({
+        let write_behind = write_behind.clone();
+        let interval = Duration::from_secs(config.write_flush_interval);
+        async move { flush_writes_loop(&write_behind, interval).await }
+    });
+    let presence_end = srv
+        .global_state()
+        .presence_refresh_interval()
+        .map(|interval| {
+            let srv = srv.clone();
+            tokio::spawn(async move { refresh_presence_loop(srv.global_state(), interval).await })
+        });
+    let admin_end = admin_listener.map(|listener| {
+        let token = config.admin_token.unwrap_or_default();
+        tokio::spawn(serve_admin_api(listener, token, srv.clone()))
+    });
+    let mut tcp_ends: Vec<_> = listeners
+        .into_iter()
+        .map(|listener| {
+            tokio::spawn(run_listener(
+                listener,
+                config.receive_proxy_protocol,
+                srv.clone(),
+            ))
+        })
+        .collect();
+    #[cfg(unix)]
+    if let Some(listener) = unix_listener {
+        tcp_ends.push(tokio::spawn(run_listener(
+            listener,
+            config.listen_unix_proxy_protocol,
+            srv.clone(),
+        )));
+    }
+
+    graceful_shutdown(join_all(tcp_ends.iter_mut())).await?;
     tracing::info!("Shutting down service ...");
+    srv.shutdown(
+        config.shutdown_message,
+        Duration::from_secs(config.shutdown_timeout),
+    )
+    .await;
+    command_end.abort();
+    if let Some(admin_end) = admin_end {
+        admin_end.abort();
+    }
+    if let Some(presence_end) = presence_end {
+        presence_end.abort();
+    }
+    stats_end.abort();
+    writes_end.abort();
+    pool_end.abort();
+    health_end.abort();
+    tcp_ends.iter().for_each(|v| v.abort());
+    #[cfg(unix)]
+    if let Some(path) = &config.listen_unix {
+        if let Err(error) = std::fs::remove_file(path) {
+            tracing::warn!(%error, path, "Failed to remove the unix socket");
+        }
+    }
+    if let Err(error) = srv.global_state().stats.flush().await {
+        tracing::error!(%error, "Failed to flush stats");
+    }
+    if let Err(error) = write_behind.flush().await {
+        tracing::error!(%error, "Failed to flush buffered writes");
+    }
     pool.close().await;
 
     Ok(())
 }
 
 fn main() {
+    // Handled before loading the config, which may not be set
+    match std::env::args().nth(1).as_deref() {
+        None => {}
+        Some("-V" | "--version") => return println!("{}", build_info::version()),
+        Some("-h" | "--help") => return println!("{}", build_info::USAGE),
+        Some(arg) => {
+            eprintln!("Unknown argument `{arg}`\n\n{}", build_info::USAGE);
+            std::process::exit(2);
+        }
+    }
+
     config_and_init_service(run_service)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::run_listener;
+    use crate::{
+        backend::route::{Route, Router},
+        server::Server,
+        state::test_global_state,
+        utils::socket::SocketOptions,
+    };
+    use minecraft_protocol::client::ping_stream;
+    use std::sync::Arc;
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn options() -> SocketOptions {
+        SocketOptions {
+            nodelay: true,
+            keepalive: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_multiple_listeners() {
+        let router = Router::new(
+            Vec::new(),
+            Vec::new(),
+            Route::new(Vec::new(), Vec::new(), Default::default()),
+        );
+        let srv = Arc::new(Server::new(
+            router,
+            options(),
+            test_global_state().await,
+            None,
+            Default::default(),
+            Default::default(),
+        ));
+
+        let mut addrs = Vec::new();
+        let mut tasks = Vec::new();
+        for _ in 0..2 {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            addrs.push(listener.local_addr().unwrap());
+            tasks.push(tokio::spawn(run_listener(listener, false, srv.clone())));
+        }
+
+        let pings = addrs.iter().map(|addr| async move {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            ping_stream(stream, "127.0.0.1", addr.port(), 765).await
+        });
+
+        for result in futures_util::future::join_all(pings).await {
+            result.unwrap();
+        }
+
+        tasks.iter().for_each(|v| v.abort());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_listener() {
+        use crate::utils::socket::bind_unix_listener;
+        use tokio::{io::AsyncWriteExt, net::UnixStream};
+
+        let srv = Arc::new(Server::new(
+            Router::new(
+                Vec::new(),
+                Vec::new(),
+                Route::new(Vec::new(), Vec::new(), Default::default()),
+            ),
+            options(),
+            test_global_state().await,
+            None,
+            Default::default(),
+            Default::default(),
+        ));
+
+        let path = std::env::temp_dir().join(format!("mc-proxy-{}.sock", std::process::id()));
+        // A socket file left behind is replaced
+        drop(bind_unix_listener(&path).unwrap());
+        let task = tokio::spawn(run_listener(
+            bind_unix_listener(&path).unwrap(),
+            true,
+            srv.clone(),
+        ));
+
+        // Not replaced while it is listening
+        let error = bind_unix_listener(&path).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"PROXY TCP4 203.0.113.7 127.0.0.1 51234 25565\r\n")
+            .await
+            .unwrap();
+        ping_stream(stream, "127.0.0.1", 25565, 765).await.unwrap();
+
+        task.abort();
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,200 @@
+use crate::repository::{
+    kv::{KeyValueRepository, KeyValueWrite},
+    RepositoryError,
+};
+use chrono::Utc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Shares the online players with the other proxies using the same database,
+/// so that a player can't join twice through two of them.
+///
+/// Each proxy claims the usernames and uuids of its players until the TTL
+/// elapses and refreshes them regularly, the entries of a proxy that crashed
+/// expire on their own.
+pub struct SharedPresence<KV> {
+    key_value: KV,
+    ttl: Duration,
+    /// Identifies the entries of this proxy
+    owner: String,
+}
+
+impl<KV: KeyValueRepository> SharedPresence<KV> {
+    pub fn new(key_value: KV, ttl: Duration) -> Self {
+        Self {
+            key_value,
+            ttl,
+            owner: Uuid::new_v4().to_string(),
+        }
+    }
+
+    /// How often the entries should be refreshed to not expire in between.
+    #[inline]
+    pub fn refresh_interval(&self) -> Duration {
+        self.ttl / 3
+    }
+
+    /// Whether the username or uuid is online through another proxy.
+    pub async fn is_online_elsewhere(
+        &self,
+        username: &str,
+        uuid: &Uuid,
+    ) -> Result<bool, RepositoryError> {
+        for key in [username_key(username), uuid_key(uuid)] {
+            if let Some(owner) = self.key_value.get(&key).await? {
+                if owner != self.owner {
+                    return Ok(true);
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Claims the player for this proxy until the TTL elapses, unless another
+    /// proxy claimed its username or uuid. The check and the claim are a
+    /// single conditional write, so that two proxies can't both claim it.
+    pub async fn claim(&self, username: &str, uuid: &Uuid) -> Result<bool, RepositoryError> {
+        self.key_value
+            .claim_many(&self.writes([(username, uuid)]))
+            .await
+    }
+
+    /// Claims all the players again, in a single write.
+    pub async fn refresh<'a>(
+        &self,
+        players: impl IntoIterator<Item = (&'a str, &'a Uuid)>,
+    ) -> Result<(), RepositoryError> {
+        let writes = self.writes(players);
+        if writes.is_empty() {
+            return Ok(());
+        }
+
+        self.key_value.set_many(&writes).await
+    }
+
+    fn writes<'a>(
+        &self,
+        players: impl IntoIterator<Item = (&'a str, &'a Uuid)>,
+    ) -> Vec<KeyValueWrite> {
+        let expiration = Some(Utc::now() + self.ttl);
+
+        players
+            .into_iter()
+            .flat_map(|(username, uuid)| [username_key(username), uuid_key(uuid)])
+            .map(|key| KeyValueWrite {
+                key,
+                value: self.owner.clone(),
+                expiration,
+            })
+            .collect()
+    }
+
+    /// Releases the player, unless another proxy claimed it since.
+    pub async fn remove(&self, username: &str, uuid: &Uuid) -> Result<(), RepositoryError> {
+        for key in [username_key(username), uuid_key(uuid)] {
+            if self.key_value.get(&key).await?.as_deref() == Some(self.owner.as_str()) {
+                self.key_value.delete(&key).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[inline]
+fn username_key(username: &str) -> String {
+    format!("presence.username.{}", username.to_lowercase())
+}
+
+#[inline]
+fn uuid_key(uuid: &Uuid) -> String {
+    format!("presence.uuid.{uuid}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SharedPresence;
+    use crate::repository::kv::SqlxKeyValueRepository;
+    use sqlx::{migrate, Sqlite, SqlitePool};
+    use std::time::Duration;
+    use uuid::Uuid;
+
+    type Presence = SharedPresence<SqlxKeyValueRepository<Sqlite>>;
+
+    /// Two proxies sharing the same database.
+    async fn proxies(ttl: Duration) -> (Presence, Presence) {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate!().run(&pool).await.unwrap();
+
+        (
+            SharedPresence::new(SqlxKeyValueRepository::new(pool.clone()), ttl),
+            SharedPresence::new(SqlxKeyValueRepository::new(pool), ttl),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_online_elsewhere() {
+        let (first, second) = proxies(Duration::from_secs(60)).await;
+        let uuid = Uuid::new_v4();
+
+        assert!(first.claim("Notch", &uuid).await.unwrap());
+        assert!(!first.is_online_elsewhere("Notch", &uuid).await.unwrap());
+        assert!(second.is_online_elsewhere("Notch", &uuid).await.unwrap());
+        assert!(second
+            .is_online_elsewhere("notch", &Uuid::new_v4())
+            .await
+            .unwrap());
+        assert!(second.is_online_elsewhere("Renamed", &uuid).await.unwrap());
+        assert!(!second
+            .is_online_elsewhere("jeb_", &Uuid::new_v4())
+            .await
+            .unwrap());
+
+        // Only the proxy that claimed the player releases it
+        second.remove("Notch", &uuid).await.unwrap();
+        assert!(second.is_online_elsewhere("Notch", &uuid).await.unwrap());
+        first.remove("Notch", &uuid).await.unwrap();
+        assert!(!second.is_online_elsewhere("Notch", &uuid).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_entries_expire() {
+        let (first, second) = proxies(Duration::from_millis(200)).await;
+        let uuid = Uuid::new_v4();
+
+        assert!(first.claim("Notch", &uuid).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        first.refresh([("Notch", &uuid)]).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(120)).await;
+        assert!(second.is_online_elsewhere("Notch", &uuid).await.unwrap());
+
+        // The first proxy stopped refreshing, like after a crash
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(!second.is_online_elsewhere("Notch", &uuid).await.unwrap());
+        assert!(second.claim("Notch", &uuid).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_claim_is_exclusive() {
+        let (first, second) = proxies(Duration::from_secs(60)).await;
+        let uuid = Uuid::new_v4();
+
+        let (a, b) = tokio::join!(first.claim("Notch", &uuid), second.claim("Notch", &uuid));
+        assert!(a.unwrap() ^ b.unwrap());
+
+        // Claiming again is a refresh, and a taken uuid fails the whole claim
+        let (owner, other) = if first.is_online_elsewhere("Notch", &uuid).await.unwrap() {
+            (second, first)
+        } else {
+            (first, second)
+        };
+        assert!(owner.claim("Notch", &uuid).await.unwrap());
+        assert!(!other.claim("jeb_", &uuid).await.unwrap());
+        assert!(!owner
+            .is_online_elsewhere("jeb_", &Uuid::new_v4())
+            .await
+            .unwrap());
+    }
+}
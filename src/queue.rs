@@ -0,0 +1,240 @@
+use crate::handler::messages;
+use minecraft_protocol::data::chat::Message;
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+use tokio::sync::{futures::Notified, Notify};
+
+/// Replaced by the position in the queue in the queue message.
+pub const POSITION_PLACEHOLDER: &str = "%position%";
+
+/// Limits how many players are relayed at once, the others wait for a slot
+/// in the order they logged in.
+pub struct QueueManager {
+    slots: usize,
+    /// How often waiting players are sent their position
+    update_interval: Duration,
+    /// May contain [`POSITION_PLACEHOLDER`]
+    message: Message,
+    state: Mutex<QueueState>,
+    /// Woken up when a slot frees or a waiting player leaves
+    changed: Notify,
+}
+
+#[derive(Default)]
+struct QueueState {
+    active: usize,
+    waiting: VecDeque<u64>,
+    next_ticket: u64,
+}
+
+impl QueueManager {
+    pub fn new(slots: usize, update_interval: Duration, message: Message) -> Self {
+        Self {
+            slots,
+            update_interval,
+            message,
+            state: Mutex::default(),
+            changed: Notify::new(),
+        }
+    }
+
+    #[inline]
+    pub fn update_interval(&self) -> Duration {
+        self.update_interval
+    }
+
+    /// The json of the message sent to a player waiting at `position`.
+    pub fn message_json(&self, position: usize) -> String {
+        messages::to_json(&self.message).replace(POSITION_PLACEHOLDER, &position.to_string())
+    }
+
+    /// Takes a slot right away, only if nobody is waiting for one.
+    pub fn try_acquire(&self) -> Option<QueueSlot<'_>> {
+        let mut state = self.lock();
+        if !state.waiting.is_empty() || state.active >= self.slots {
+            return None;
+        }
+
+        state.active += 1;
+        Some(QueueSlot { queue: self })
+    }
+
+    /// Joins the end of the queue.
+    pub fn enqueue(&self) -> QueueTicket<'_> {
+        let mut state = self.lock();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.waiting.push_back(ticket);
+
+        QueueTicket {
+            queue: self,
+            ticket,
+        }
+    }
+
+    /// Players waiting for a slot.
+    pub fn waiting(&self) -> usize {
+        self.lock().waiting.len()
+    }
+
+    #[inline]
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A relayed player, the slot is freed when dropped.
+pub struct QueueSlot<'a> {
+    queue: &'a QueueManager,
+}
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        self.queue.lock().active -= 1;
+        self.queue.changed.notify_waiters();
+    }
+}
+
+/// A waiting player, leaving the queue when dropped.
+pub struct QueueTicket<'a> {
+    queue: &'a QueueManager,
+    ticket: u64,
+}
+
+impl<'a> QueueTicket<'a> {
+    /// The position in the queue, starting at 1.
+    pub fn position(&self) -> usize {
+        let state = self.queue.lock();
+        state
+            .waiting
+            .iter()
+            .position(|v| *v == self.ticket)
+            .map_or(0, |v| v + 1)
+    }
+
+    /// Takes a slot if it's the turn of this player.
+    pub fn try_acquire(&self) -> Option<QueueSlot<'a>> {
+        let mut state = self.queue.lock();
+        if state.waiting.front() != Some(&self.ticket) || state.active >= self.queue.slots {
+            return None;
+        }
+
+        state.waiting.pop_front();
+        state.active += 1;
+        drop(state);
+
+        // The next player may move up
+        self.queue.changed.notify_waiters();
+        Some(QueueSlot { queue: self.queue })
+    }
+
+    /// Completes once the queue changes. Changes are only seen after the
+    /// future is enabled or first polled, so it must be enabled before
+    /// checking the queue with [`try_acquire`](Self::try_acquire).
+    pub fn changed(&self) -> Notified<'_> {
+        self.queue.changed.notified()
+    }
+}
+
+impl Drop for QueueTicket<'_> {
+    fn drop(&mut self) {
+        let mut state = self.queue.lock();
+        let Some(index) = state.waiting.iter().position(|v| *v == self.ticket) else {
+            return;
+        };
+        state.waiting.remove(index);
+        drop(state);
+
+        self.queue.changed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QueueManager;
+    use minecraft_protocol::data::chat::Message;
+    use std::time::Duration;
+
+    fn queue(slots: usize) -> QueueManager {
+        QueueManager::new(
+            slots,
+            Duration::from_secs(5),
+            Message::from_str("You are #%position% in the queue"),
+        )
+    }
+
+    #[test]
+    fn test_slots() {
+        let queue = queue(2);
+
+        let first = queue.try_acquire().unwrap();
+        let _second = queue.try_acquire().unwrap();
+        assert!(queue.try_acquire().is_none());
+
+        drop(first);
+        assert!(queue.try_acquire().is_some());
+    }
+
+    #[test]
+    fn test_waiting_order() {
+        let queue = queue(1);
+        let slot = queue.try_acquire().unwrap();
+
+        let first = queue.enqueue();
+        let second = queue.enqueue();
+        let third = queue.enqueue();
+        assert_eq!(
+            [first.position(), second.position(), third.position()],
+            [1, 2, 3]
+        );
+        assert_eq!(queue.waiting(), 3);
+
+        // Players can't skip the ones waiting before them
+        drop(slot);
+        assert!(queue.try_acquire().is_none());
+        assert!(second.try_acquire().is_none());
+
+        let slot = first.try_acquire().unwrap();
+        assert_eq!(second.position(), 1);
+
+        // Leaving the queue lets the next players move up
+        drop(second);
+        assert_eq!(third.position(), 1);
+        drop(slot);
+        assert!(third.try_acquire().is_some());
+        assert_eq!(queue.waiting(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_freed_slot_wakes_up_waiting() {
+        let queue = queue(1);
+        let slot = queue.try_acquire().unwrap();
+        let ticket = queue.enqueue();
+
+        let changed = ticket.changed();
+        tokio::pin!(changed);
+        changed.as_mut().enable();
+        assert!(ticket.try_acquire().is_none());
+
+        // Freed after the check, before waiting
+        drop(slot);
+        tokio::time::timeout(Duration::from_secs(5), changed)
+            .await
+            .unwrap();
+        assert!(ticket.try_acquire().is_some());
+    }
+
+    #[test]
+    fn test_message() {
+        let queue = queue(1);
+        assert_eq!(
+            queue.message_json(3),
+            Message::from_str("You are #3 in the queue")
+                .to_json()
+                .unwrap()
+        );
+    }
+}
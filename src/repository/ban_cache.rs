@@ -0,0 +1,375 @@
+use super::{
+    ip_bans::{IpBanData, IpBansRepository},
+    user_bans::{UserBanData, UserBansRepository},
+    RepositoryError,
+};
+use chrono::{DateTime, Utc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    hash::Hash,
+    net::IpAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+struct CacheEntry<V> {
+    value: V,
+    inserted_at: Instant,
+    used_at: u64,
+}
+
+/// A bounded map evicting the least recently used entries, and the ones
+/// older than its ttl.
+struct LruCache<K, V> {
+    capacity: usize,
+    ttl: Duration,
+    entries: HashMap<K, CacheEntry<V>>,
+    /// The keys by the tick they were last used at
+    order: BTreeMap<u64, K>,
+    tick: u64,
+    /// Bumped by every invalidation, so that lookups started before it don't
+    /// cache what they read
+    generation: u64,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> LruCache<K, V> {
+    fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            generation: 0,
+        }
+    }
+
+    fn get(&mut self, key: &K) -> Option<V> {
+        let entry = self.entries.get_mut(key)?;
+        self.order.remove(&entry.used_at);
+
+        if entry.inserted_at.elapsed() >= self.ttl {
+            self.entries.remove(key);
+            return None;
+        }
+
+        self.tick += 1;
+        entry.used_at = self.tick;
+        self.order.insert(self.tick, key.clone());
+        Some(entry.value.clone())
+    }
+
+    /// Does nothing if the cache was invalidated since `generation`.
+    fn insert(&mut self, key: K, value: V, generation: u64) {
+        if self.capacity == 0 || generation != self.generation {
+            return;
+        }
+
+        self.remove(&key);
+        if self.entries.len() >= self.capacity {
+            if let Some((_, oldest)) = self.order.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(
+            key,
+            CacheEntry {
+                value,
+                inserted_at: Instant::now(),
+                used_at: self.tick,
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.used_at);
+        }
+    }
+
+    fn invalidate(&mut self, key: &K) {
+        self.generation += 1;
+        self.remove(key);
+    }
+}
+
+/// The ban lookups of a repository, both positive and negative, shared by
+/// the clones of the repository.
+struct BanCache<K, V> {
+    cache: Arc<Mutex<LruCache<K, Option<V>>>>,
+    expiration: fn(&V) -> Option<DateTime<Utc>>,
+}
+
+impl<K, V> Clone for BanCache<K, V> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            cache: self.cache.clone(),
+            expiration: self.expiration,
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> BanCache<K, V> {
+    fn new(capacity: usize, ttl: Duration, expiration: fn(&V) -> Option<DateTime<Utc>>) -> Self {
+        Self {
+            cache: Arc::new(Mutex::new(LruCache::new(capacity, ttl))),
+            expiration,
+        }
+    }
+
+    /// Returns the cached ban, or the one returned by `load` otherwise.
+    /// Cached bans that expired in the meantime are loaded again.
+    async fn get_or_load<F>(&self, key: K, load: F) -> Result<Option<V>, RepositoryError>
+    where
+        F: Future<Output = Result<Option<V>, RepositoryError>>,
+    {
+        let generation = {
+            let mut cache = self.lock();
+            match cache.get(&key) {
+                Some(Some(ban)) if is_expired((self.expiration)(&ban)) => {}
+                Some(ban) => return Ok(ban),
+                None => {}
+            }
+            cache.generation
+        };
+
+        let ban = load.await?;
+        self.lock().insert(key, ban.clone(), generation);
+
+        Ok(ban)
+    }
+
+    #[inline]
+    fn invalidate(&self, key: &K) {
+        self.lock().invalidate(key);
+    }
+
+    #[inline]
+    fn lock(&self) -> MutexGuard<'_, LruCache<K, Option<V>>> {
+        self.cache.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[inline]
+fn is_expired(expiration: Option<DateTime<Utc>>) -> bool {
+    matches!(expiration, Some(expiration) if Utc::now() > expiration)
+}
+
+/// Caches the results of [`IpBansRepository::is_banned`] in memory, which
+/// are checked on every connection.
+///
+/// The bans added or removed through this repository are seen right away,
+/// the ones changed by other proxies sharing the database only once the
+/// cached result is older than the ttl.
+pub struct CachedIpBansRepository<R> {
+    inner: R,
+    cache: BanCache<IpAddr, IpBanData>,
+}
+
+impl<R: Clone> Clone for CachedIpBansRepository<R> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<R: IpBansRepository> CachedIpBansRepository<R> {
+    /// A `capacity` of `0` disables the cache.
+    pub fn new(inner: R, capacity: usize, ttl: Duration) -> Self {
+        Self {
+            inner,
+            cache: BanCache::new(capacity, ttl, |ban| ban.expiration),
+        }
+    }
+}
+
+impl<R: IpBansRepository> IpBansRepository for CachedIpBansRepository<R> {
+    async fn add_ban(
+        &self,
+        ip: IpAddr,
+        duration: Option<Duration>,
+        reason: Option<String>,
+    ) -> Result<IpBanData, RepositoryError> {
+        let result = self.inner.add_ban(ip, duration, reason).await;
+        self.cache.invalidate(&ip.to_canonical());
+        result
+    }
+
+    async fn is_banned(&self, ip: IpAddr) -> Result<Option<IpBanData>, RepositoryError> {
+        self.cache
+            .get_or_load(ip.to_canonical(), self.inner.is_banned(ip))
+            .await
+    }
+
+    async fn remove_ban(&self, ip: IpAddr) -> Result<Option<IpBanData>, RepositoryError> {
+        let result = self.inner.remove_ban(ip).await;
+        self.cache.invalidate(&ip.to_canonical());
+        result
+    }
+
+    #[inline]
+    async fn get_bans(&self) -> Result<Vec<IpBanData>, RepositoryError> {
+        self.inner.get_bans().await
+    }
+}
+
+/// Caches the results of [`UserBansRepository::is_banned`] in memory, which
+/// are checked on every login. Bans changed by other proxies are seen late
+/// the same way as with [`CachedIpBansRepository`].
+pub struct CachedUserBansRepository<R> {
+    inner: R,
+    cache: BanCache<String, UserBanData>,
+}
+
+impl<R: Clone> Clone for CachedUserBansRepository<R> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<R: UserBansRepository> CachedUserBansRepository<R> {
+    /// A `capacity` of `0` disables the cache.
+    pub fn new(inner: R, capacity: usize, ttl: Duration) -> Self {
+        Self {
+            inner,
+            cache: BanCache::new(capacity, ttl, |ban| ban.expiration),
+        }
+    }
+}
+
+impl<R: UserBansRepository> UserBansRepository for CachedUserBansRepository<R> {
+    async fn add_ban(
+        &self,
+        username: &str,
+        expiration: Option<Duration>,
+        reason: Option<String>,
+    ) -> Result<UserBanData, RepositoryError> {
+        let result = self.inner.add_ban(username, expiration, reason).await;
+        self.cache.invalidate(&username.to_owned());
+        result
+    }
+
+    async fn is_banned(&self, username: &str) -> Result<Option<UserBanData>, RepositoryError> {
+        self.cache
+            .get_or_load(username.to_owned(), self.inner.is_banned(username))
+            .await
+    }
+
+    async fn remove_ban(&self, username: &str) -> Result<Option<UserBanData>, RepositoryError> {
+        let result = self.inner.remove_ban(username).await;
+        self.cache.invalidate(&username.to_owned());
+        result
+    }
+
+    #[inline]
+    async fn get_bans(&self) -> Result<Vec<UserBanData>, RepositoryError> {
+        self.inner.get_bans().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CachedIpBansRepository, CachedUserBansRepository, LruCache};
+    use crate::repository::{
+        ip_bans::{IpBansRepository, SqlxIpBansRepository},
+        user_bans::{SqlxUserBansRepository, UserBansRepository},
+    };
+    use sqlx::{migrate, SqlitePool};
+    use std::{
+        net::{IpAddr, Ipv4Addr, Ipv6Addr},
+        time::Duration,
+    };
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_lru_eviction() {
+        let mut cache = LruCache::new(2, TTL);
+        cache.insert("a", 1, 0);
+        cache.insert("b", 2, 0);
+        assert_eq!(cache.get(&"a"), Some(1));
+
+        // "b" is the least recently used
+        cache.insert("c", 3, 0);
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.get(&"c"), Some(3));
+
+        let mut cache = LruCache::new(2, Duration::ZERO);
+        cache.insert("a", 1, 0);
+        assert_eq!(cache.get(&"a"), None);
+    }
+
+    #[test]
+    fn test_stale_insert_is_dropped() {
+        let mut cache = LruCache::new(2, TTL);
+        let generation = cache.generation;
+        cache.invalidate(&"a");
+
+        cache.insert("a", 1, generation);
+        assert_eq!(cache.get(&"a"), None);
+    }
+
+    #[tokio::test]
+    async fn test_ip_ban_invalidation() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate!().run(&pool).await.unwrap();
+        let inner = SqlxIpBansRepository::new(pool);
+        let cached = CachedIpBansRepository::new(inner.clone(), 16, TTL);
+
+        let ip = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+        assert!(cached.is_banned(ip).await.unwrap().is_none());
+
+        // Not seen until the cached result expires
+        inner.add_ban(ip, None, None).await.unwrap();
+        assert!(cached.is_banned(ip).await.unwrap().is_none());
+
+        inner.remove_ban(ip).await.unwrap();
+        cached.add_ban(ip, None, Some("Spam".into())).await.unwrap();
+        let mapped = IpAddr::V6(Ipv4Addr::new(203, 0, 113, 7).to_ipv6_mapped());
+        let ban = cached.is_banned(mapped).await.unwrap().unwrap();
+        assert_eq!(ban.reason.as_deref(), Some("Spam"));
+
+        cached.remove_ban(ip).await.unwrap();
+        assert!(cached.is_banned(ip).await.unwrap().is_none());
+
+        let other = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        assert!(cached.is_banned(other).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_user_ban_invalidation() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate!().run(&pool).await.unwrap();
+        let inner = SqlxUserBansRepository::new(pool);
+        let cached = CachedUserBansRepository::new(inner.clone(), 16, TTL);
+
+        cached.add_ban("Notch", None, None).await.unwrap();
+        assert!(cached.is_banned("Notch").await.unwrap().is_some());
+
+        // Cached, even though the ban is gone
+        inner.remove_ban("Notch").await.unwrap();
+        assert!(cached.is_banned("Notch").await.unwrap().is_some());
+
+        cached.remove_ban("Notch").await.unwrap();
+        assert!(cached.is_banned("Notch").await.unwrap().is_none());
+
+        // Disabled
+        let cached = CachedUserBansRepository::new(inner.clone(), 0, TTL);
+        assert!(cached.is_banned("jeb_").await.unwrap().is_none());
+        inner.add_ban("jeb_", None, None).await.unwrap();
+        assert!(cached.is_banned("jeb_").await.unwrap().is_some());
+    }
+}
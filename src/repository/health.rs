@@ -0,0 +1,163 @@
+use super::RepositoryError;
+use serde::Deserialize;
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+/// Ban lookups failing in a row after which new connections are accepted
+/// more slowly.
+const BACKOFF_THRESHOLD: u32 = 5;
+
+/// The delay between accepted connections once the threshold is reached,
+/// doubled by every further failure up to [`MAX_ACCEPT_DELAY`].
+const MIN_ACCEPT_DELAY: Duration = Duration::from_millis(50);
+const MAX_ACCEPT_DELAY: Duration = Duration::from_secs(2);
+
+/// What happens to the connections whose bans can't be checked because the
+/// database is unavailable. Lookups failing with other errors, which retrying
+/// wouldn't fix, always reject the connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BanCheckFailure {
+    /// The connection is closed
+    #[default]
+    Reject,
+    /// The connection is let through as if the player wasn't banned
+    Allow,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid ban check failure policy `{0}`")]
+pub struct ParseBanCheckFailureError(String);
+
+impl FromStr for BanCheckFailure {
+    type Err = ParseBanCheckFailureError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(BanCheckFailure::Reject),
+            "allow" => Ok(BanCheckFailure::Allow),
+            _ => Err(ParseBanCheckFailureError(s.into())),
+        }
+    }
+}
+
+/// Counts the ban lookups failing in a row because the database is
+/// unavailable, so that connections are accepted more slowly meanwhile
+/// instead of failing one by one at full rate.
+#[derive(Debug, Default)]
+pub struct DatabaseHealth {
+    on_failure: BanCheckFailure,
+    failures: AtomicU32,
+}
+
+impl DatabaseHealth {
+    pub fn new(on_failure: BanCheckFailure) -> Self {
+        Self {
+            on_failure,
+            failures: AtomicU32::new(0),
+        }
+    }
+
+    /// Records the result of a ban lookup. Lookups that failed because the
+    /// database is unavailable return `None` when they are allowed, failed
+    /// lookups return the error otherwise.
+    pub fn check_ban<T>(
+        &self,
+        result: Result<Option<T>, RepositoryError>,
+    ) -> Result<Option<T>, RepositoryError> {
+        let error = match result {
+            Ok(ban) => {
+                self.failures.store(0, Ordering::Relaxed);
+                return Ok(ban);
+            }
+            Err(error) if error.is_transient() => error,
+            Err(error) => return Err(error),
+        };
+
+        let failures = self
+            .failures
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
+                Some(v.saturating_add(1))
+            })
+            .unwrap_or_default()
+            .saturating_add(1);
+
+        match self.on_failure {
+            BanCheckFailure::Reject => Err(error),
+            BanCheckFailure::Allow => {
+                tracing::warn!(%error, failures, "Ban check failed, letting the connection through");
+                Ok(None)
+            }
+        }
+    }
+
+    /// How long to wait before accepting the next connection, `None` while
+    /// the ban lookups succeed.
+    pub fn accept_delay(&self) -> Option<Duration> {
+        let failures = self.failures.load(Ordering::Relaxed);
+        let doublings = failures.checked_sub(BACKOFF_THRESHOLD)?.min(16);
+
+        Some(
+            MIN_ACCEPT_DELAY
+                .saturating_mul(1 << doublings)
+                .min(MAX_ACCEPT_DELAY),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BanCheckFailure, DatabaseHealth, BACKOFF_THRESHOLD, MAX_ACCEPT_DELAY};
+    use crate::repository::RepositoryError;
+    use std::time::Duration;
+
+    fn failure() -> Result<Option<()>, RepositoryError> {
+        Err(sqlx::Error::PoolTimedOut.into())
+    }
+
+    #[test]
+    fn test_failure_policy() {
+        let health = DatabaseHealth::new(BanCheckFailure::Reject);
+        assert!(health.check_ban(failure()).is_err());
+        assert_eq!(health.check_ban(Ok(Some(()))).unwrap(), Some(()));
+
+        let health = DatabaseHealth::new(BanCheckFailure::Allow);
+        assert_eq!(health.check_ban(failure()).unwrap(), None);
+
+        // Retrying wouldn't help, so the connection is not let through, and
+        // the database is not considered unavailable
+        let health = DatabaseHealth::new(BanCheckFailure::Allow);
+        for _ in 0..=BACKOFF_THRESHOLD {
+            let error = health
+                .check_ban(Err::<Option<()>, _>(sqlx::Error::RowNotFound.into()))
+                .unwrap_err();
+            assert!(matches!(error, RepositoryError::NotFound));
+        }
+        assert_eq!(health.accept_delay(), None);
+    }
+
+    #[test]
+    fn test_accept_delay() {
+        let health = DatabaseHealth::default();
+        for _ in 0..BACKOFF_THRESHOLD {
+            assert_eq!(health.accept_delay(), None);
+            let _ = health.check_ban(failure());
+        }
+        assert_eq!(health.accept_delay(), Some(Duration::from_millis(50)));
+
+        let _ = health.check_ban(failure());
+        assert_eq!(health.accept_delay(), Some(Duration::from_millis(100)));
+
+        for _ in 0..64 {
+            let _ = health.check_ban(failure());
+        }
+        assert_eq!(health.accept_delay(), Some(MAX_ACCEPT_DELAY));
+
+        // Reset by the first lookup that succeeds
+        health.check_ban(Ok(None::<()>)).unwrap();
+        assert_eq!(health.accept_delay(), None);
+    }
+}
@@ -40,9 +40,18 @@ pub trait IpBansRepository: Clone + Send + Sync {
     fn get_bans(&self) -> impl Future<Output = Result<Vec<IpBanData>, RepositoryError>> + Send;
 }
 
+/// IPv4-mapped IPv6 addresses, as seen by dual-stack listeners, are stored
+/// as plain IPv4 so that bans match regardless of how the client connected.
 #[derive(Copy, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
 struct IpBinaryData(IpAddr);
 
+impl IpBinaryData {
+    #[inline]
+    fn new(ip: IpAddr) -> Self {
+        Self(ip.to_canonical())
+    }
+}
+
 impl<DB: Database> Type<DB> for IpBinaryData
 where
     Vec<u8>: Type<DB>,
@@ -63,7 +72,7 @@ where
     ) -> Result<IsNull, BoxDynError> {
         let mut vec = Vec::new();
 
-        match self.0 {
+        match self.0.to_canonical() {
             IpAddr::V4(ip) => {
                 vec.push(4_u8);
                 vec.extend(ip.octets());
@@ -98,7 +107,7 @@ where
                 value[16],
             ]);
 
-            Ok(IpBinaryData(IpAddr::V6(ip)))
+            Ok(IpBinaryData::new(IpAddr::V6(ip)))
         } else {
             Err("Unexpected value IP type".into())
         }
@@ -195,7 +204,7 @@ where
                 )
                 .bind(exp)
                 .bind(reason)
-                .bind(IpBinaryData(ip))
+                .bind(IpBinaryData::new(ip))
                 .fetch_one(&self.db)
                 .await
                 .map_err(|error| {
@@ -214,7 +223,7 @@ where
                 VALUES ($1, $2, $3, $4) \
                 RETURNING *",
             )
-            .bind(IpBinaryData(ip))
+            .bind(IpBinaryData::new(ip))
             .bind(now)
             .bind(duration.map(|exp| now + exp))
             .bind(reason)
@@ -230,7 +239,7 @@ where
     }
 
     async fn is_banned(&self, ip: IpAddr) -> Result<Option<IpBanData>, RepositoryError> {
-        let ip = IpBinaryData(ip);
+        let ip = IpBinaryData::new(ip);
 
         let row: Option<IpBanRow> = sqlx::query_as("SELECT * FROM ip_bans WHERE ip = $1")
             .bind(ip)
@@ -262,7 +271,7 @@ where
 
     async fn remove_ban(&self, ip: IpAddr) -> Result<Option<IpBanData>, RepositoryError> {
         sqlx::query_as("DELETE FROM ip_bans WHERE ip = $1 RETURNING *")
-            .bind(IpBinaryData(ip))
+            .bind(IpBinaryData::new(ip))
             .fetch_optional(&self.db)
             .await
             .map(|v| v.map(IpBanData::from_row))
@@ -407,4 +416,87 @@ mod tests {
 
         assert_eq!(all_adds.len(), 0);
     }
+
+    #[tokio::test]
+    async fn test_mapped_ban_matches_plain() {
+        let repo = get_repository().await;
+
+        let plain = IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4));
+        let mapped = IpAddr::V6(Ipv4Addr::new(1, 2, 3, 4).to_ipv6_mapped());
+
+        let ban = repo.add_ban(mapped, None, None).await.unwrap();
+        assert_eq!(ban.ip, plain);
+
+        assert!(repo.is_banned(plain).await.unwrap().is_some());
+        assert!(repo.is_banned(mapped).await.unwrap().is_some());
+
+        assert!(repo.remove_ban(plain).await.unwrap().is_some());
+        assert!(repo.is_banned(mapped).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_plain_ban_matches_mapped() {
+        let repo = get_repository().await;
+
+        let plain = IpAddr::V4(Ipv4Addr::new(5, 6, 7, 8));
+        let mapped = IpAddr::V6(Ipv4Addr::new(5, 6, 7, 8).to_ipv6_mapped());
+
+        repo.add_ban(plain, None, None).await.unwrap();
+
+        let ban = repo.is_banned(mapped).await.unwrap().unwrap();
+        assert_eq!(ban.ip, plain);
+
+        assert!(repo.remove_ban(mapped).await.unwrap().is_some());
+        assert!(repo.is_banned(plain).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mapped_rows_migration() {
+        let repo = get_repository().await;
+
+        let insert = "INSERT INTO ip_bans (ip, created_at) VALUES ($1, $2)";
+        let mapped = |v: [u8; 4]| {
+            let mut vec = vec![6, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF];
+            vec.extend(v);
+            vec
+        };
+
+        // Stored by older versions, one of them is also banned as plain IPv4
+        for ip in [
+            mapped([1, 1, 1, 1]),
+            mapped([2, 2, 2, 2]),
+            vec![4, 2, 2, 2, 2],
+        ] {
+            sqlx::query(insert)
+                .bind(ip)
+                .bind(Utc::now())
+                .execute(&repo.db)
+                .await
+                .unwrap();
+        }
+
+        sqlx::raw_sql(include_str!(
+            "../../migrations/0005_normalize_mapped_ips.up.sql"
+        ))
+        .execute(&repo.db)
+        .await
+        .unwrap();
+
+        let mut bans: Vec<_> = repo
+            .get_bans()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|v| v.ip)
+            .collect();
+        bans.sort();
+
+        assert_eq!(
+            bans,
+            [
+                IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+                IpAddr::V4(Ipv4Addr::new(2, 2, 2, 2)),
+            ]
+        );
+    }
 }
@@ -1,10 +1,18 @@
 use super::RepositoryError;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use sqlx::{
     ColumnIndex, Database, Decode, Encode, Executor, FromRow, IntoArguments, Pool, Row, Type,
 };
 use std::{future::Future, time::Duration};
 
+/// A value to be written by [`KeyValueRepository::set_many`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyValueWrite {
+    pub key: String,
+    pub value: String,
+    pub expiration: Option<DateTime<Utc>>,
+}
+
 pub trait KeyValueRepository: Send + Sync + Clone {
     fn get_ttl(
         &self,
@@ -24,6 +32,19 @@ pub trait KeyValueRepository: Send + Sync + Clone {
         key: &str,
     ) -> impl Future<Output = Result<Option<String>, RepositoryError>> + Send;
 
+    /// Writes all the values at once, in a single transaction.
+    fn set_many(
+        &self,
+        writes: &[KeyValueWrite],
+    ) -> impl Future<Output = Result<(), RepositoryError>> + Send;
+
+    /// Writes all the values at once, unless one of the keys holds another
+    /// value that didn't expire. Returns whether the values were written.
+    fn claim_many(
+        &self,
+        writes: &[KeyValueWrite],
+    ) -> impl Future<Output = Result<bool, RepositoryError>> + Send;
+
     #[inline]
     fn get(
         &self,
@@ -94,6 +115,7 @@ where
     DB: Database,
     for<'a> <DB as sqlx::Database>::Arguments<'a>: IntoArguments<'a, DB>,
     for<'a> &'a Pool<DB>: Executor<'a, Database = DB>,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
 
     for<'r> KeyValueRow: FromRow<'r, <DB as Database>::Row>,
 
@@ -199,6 +221,85 @@ where
         }
     }
 
+    async fn set_many(&self, writes: &[KeyValueWrite]) -> Result<(), RepositoryError> {
+        let now = Utc::now().timestamp_millis();
+
+        let mut tx = self.db.begin().await.map_err(|error| {
+            tracing::error!(%error, "Failed to begin key-value transaction: sqlx error");
+            error
+        })?;
+
+        for write in writes {
+            sqlx::query(
+                "INSERT INTO key_value \
+                (key, created_at, expiration, value) \
+                VALUES ($1, $2, $3, $4) \
+                ON CONFLICT (key) DO UPDATE \
+                SET expiration = excluded.expiration, value = excluded.value",
+            )
+            .bind(write.key.as_str())
+            .bind(now)
+            .bind(write.expiration.map(|v| v.timestamp_millis()))
+            .bind(write.value.as_str())
+            .execute(&mut *tx)
+            .await
+            .map_err(|error| {
+                tracing::error!(%error, "Failed to write key-value registry: sqlx error");
+                error
+            })?;
+        }
+
+        tx.commit().await.map_err(|error| {
+            tracing::error!(%error, "Failed to commit key-value transaction: sqlx error");
+            error.into()
+        })
+    }
+
+    async fn claim_many(&self, writes: &[KeyValueWrite]) -> Result<bool, RepositoryError> {
+        let now = Utc::now().timestamp_millis();
+
+        let mut tx = self.db.begin().await.map_err(|error| {
+            tracing::error!(%error, "Failed to begin key-value transaction: sqlx error");
+            error
+        })?;
+
+        for write in writes {
+            // Conflicting keys are only overwritten if they hold the same
+            // value or expired, in which case nothing is returned
+            let claimed = sqlx::query(
+                "INSERT INTO key_value \
+                (key, created_at, expiration, value) \
+                VALUES ($1, $2, $3, $4) \
+                ON CONFLICT (key) DO UPDATE \
+                SET expiration = excluded.expiration, value = excluded.value \
+                WHERE key_value.value = excluded.value OR key_value.expiration < $2 \
+                RETURNING key",
+            )
+            .bind(write.key.as_str())
+            .bind(now)
+            .bind(write.expiration.map(|v| v.timestamp_millis()))
+            .bind(write.value.as_str())
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|error| {
+                tracing::error!(%error, "Failed to write key-value registry: sqlx error");
+                error
+            })?;
+
+            if claimed.is_none() {
+                // Dropping the transaction rolls it back
+                return Ok(false);
+            }
+        }
+
+        tx.commit().await.map_err(|error| {
+            tracing::error!(%error, "Failed to commit key-value transaction: sqlx error");
+            error
+        })?;
+
+        Ok(true)
+    }
+
     async fn delete(&self, key: &str) -> Result<Option<String>, RepositoryError> {
         let now = Utc::now();
 
@@ -1,7 +1,15 @@
+use mc_proxy_protocol::ErrorCode;
+use sqlx::error::ErrorKind;
+
+pub mod ban_cache;
+pub mod health;
 pub mod ip_bans;
 pub mod kv;
+pub mod stats;
 pub mod user_bans;
+pub mod user_ip_bans;
 pub mod whitelist;
+pub mod write_behind;
 
 mod private {
     pub trait SealedRepository: Send + Sync {}
@@ -11,9 +19,105 @@ pub type DB = sqlx::Sqlite;
 
 #[derive(Debug, thiserror::Error)]
 pub enum RepositoryError {
+    /// A query expecting a row didn't return any
+    #[error("Record not found")]
+    NotFound,
+
+    /// A constraint of the database was violated, such as a duplicate key
+    #[error("Conflicting record: {0}")]
+    Conflict(sqlx::Error),
+
+    /// The database can't be reached or is locked at the moment, the operation
+    /// may be retried
+    #[error("Database unavailable: {0}")]
+    Connection(sqlx::Error),
+
     #[error("Sqlx error: {0}")]
-    Sqlx(#[from] sqlx::Error),
+    Other(sqlx::Error),
 
     #[error("Failed to deserialize value: {0}")]
     Json(#[from] serde_json::Error),
 }
+
+impl From<sqlx::Error> for RepositoryError {
+    fn from(error: sqlx::Error) -> Self {
+        match &error {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound,
+            sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::WorkerCrashed => RepositoryError::Connection(error),
+            sqlx::Error::Database(db_error) => match db_error.kind() {
+                ErrorKind::UniqueViolation | ErrorKind::ForeignKeyViolation => {
+                    RepositoryError::Conflict(error)
+                }
+                _ if is_sqlite_busy(db_error.code().as_deref()) => {
+                    RepositoryError::Connection(error)
+                }
+                _ => RepositoryError::Other(error),
+            },
+            _ => RepositoryError::Other(error),
+        }
+    }
+}
+
+/// Whether the sqlite error code, possibly extended, is `SQLITE_BUSY` or
+/// `SQLITE_LOCKED`, returned once the busy timeout elapsed.
+fn is_sqlite_busy(code: Option<&str>) -> bool {
+    code.and_then(|code| code.parse::<i32>().ok())
+        .is_some_and(|code| matches!(code & 0xff, 5 | 6))
+}
+
+impl RepositoryError {
+    /// Whether the operation may succeed if retried.
+    #[inline]
+    pub fn is_transient(&self) -> bool {
+        matches!(self, RepositoryError::Connection(_))
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            RepositoryError::Connection(_) => ErrorCode::DatabaseUnavailable,
+            RepositoryError::NotFound => ErrorCode::NotFound,
+            RepositoryError::Conflict(_) => ErrorCode::Conflict,
+            RepositoryError::Other(_) => ErrorCode::DatabaseError,
+            RepositoryError::Json(_) => ErrorCode::InvalidData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RepositoryError;
+    use sqlx::SqlitePool;
+
+    #[test]
+    fn test_classification() {
+        let error = RepositoryError::from(sqlx::Error::PoolTimedOut);
+        assert!(matches!(error, RepositoryError::Connection(_)));
+        assert!(error.is_transient());
+
+        let error = RepositoryError::from(sqlx::Error::RowNotFound);
+        assert!(matches!(error, RepositoryError::NotFound));
+        assert!(!error.is_transient());
+
+        let error = RepositoryError::from(sqlx::Error::Protocol("Unexpected".into()));
+        assert!(matches!(error, RepositoryError::Other(_)));
+    }
+
+    #[tokio::test]
+    async fn test_conflict() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE records (id INTEGER PRIMARY KEY)")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let insert = || sqlx::query("INSERT INTO records (id) VALUES (1)").execute(&pool);
+        insert().await.unwrap();
+
+        let error = RepositoryError::from(insert().await.unwrap_err());
+        assert!(matches!(error, RepositoryError::Conflict(_)), "{error:?}");
+    }
+}
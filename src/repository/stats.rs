@@ -0,0 +1,281 @@
+use super::RepositoryError;
+use chrono::{DateTime, NaiveDate, Utc};
+use futures_util::TryStreamExt;
+use sqlx::{
+    ColumnIndex, Database, Decode, Encode, Executor, FromRow, IntoArguments, Pool, Row, Type,
+};
+use std::future::Future;
+use uuid::Uuid;
+
+/// How many of the most recent days are returned by [`StatsRepository::get_stats`].
+const RECENT_DAYS: i64 = 30;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DayStats {
+    pub day: NaiveDate,
+    pub logins: u64,
+    pub unique_players: u64,
+    pub peak_players: u64,
+    pub peak_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StatsSummary {
+    pub unique_players: u64,
+    pub peak_players: u64,
+    pub peak_at: Option<DateTime<Utc>>,
+    /// The most recent days, newest first
+    pub days: Vec<DayStats>,
+}
+
+pub trait StatsRepository: Clone + Send + Sync {
+    /// Merges the stats into the stored ones of the same day. Logins are added
+    /// while unique players and peaks keep the greatest value.
+    fn merge_day(
+        &self,
+        stats: &DayStats,
+    ) -> impl Future<Output = Result<(), RepositoryError>> + Send;
+
+    /// Stores the players that were not seen before.
+    fn add_seen_players(
+        &self,
+        players: &[(Uuid, String)],
+    ) -> impl Future<Output = Result<(), RepositoryError>> + Send;
+
+    fn get_stats(&self) -> impl Future<Output = Result<StatsSummary, RepositoryError>> + Send;
+}
+
+struct DayStatsRow {
+    day: String,
+    logins: i64,
+    unique_players: i64,
+    peak_players: i64,
+    peak_at: Option<i64>,
+}
+
+impl<'r, R: Row> FromRow<'r, R> for DayStatsRow
+where
+    &'static str: ColumnIndex<R>,
+    i64: Decode<'r, R::Database> + Type<R::Database>,
+    String: Decode<'r, R::Database> + Type<R::Database>,
+{
+    fn from_row(row: &'r R) -> Result<Self, sqlx::Error> {
+        let data = DayStatsRow {
+            day: row.try_get("day")?,
+            logins: row.try_get("logins")?,
+            unique_players: row.try_get("unique_players")?,
+            peak_players: row.try_get("peak_players")?,
+            peak_at: row.try_get("peak_at")?,
+        };
+
+        Ok(data)
+    }
+}
+
+impl DayStats {
+    fn from_row(row: DayStatsRow) -> Result<Self, RepositoryError> {
+        Ok(Self {
+            day: row
+                .day
+                .parse()
+                .map_err(|error| sqlx::Error::Decode(Box::new(error)))?,
+            logins: row.logins as u64,
+            unique_players: row.unique_players as u64,
+            peak_players: row.peak_players as u64,
+            peak_at: row.peak_at.and_then(DateTime::from_timestamp_millis),
+        })
+    }
+}
+
+pub struct SqlxStatsRepository<DB: Database> {
+    db: Pool<DB>,
+}
+
+impl<DB: Database> Clone for SqlxStatsRepository<DB> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+        }
+    }
+}
+
+impl<DB: Database> SqlxStatsRepository<DB> {
+    #[inline]
+    pub fn new(db: Pool<DB>) -> Self {
+        Self { db }
+    }
+}
+
+impl<DB> StatsRepository for SqlxStatsRepository<DB>
+where
+    DB: Database,
+    for<'a> <DB as sqlx::Database>::Arguments<'a>: IntoArguments<'a, DB>,
+    for<'a> &'a Pool<DB>: Executor<'a, Database = DB>,
+    for<'c> &'c mut DB::Connection: Executor<'c, Database = DB>,
+
+    for<'r> DayStatsRow: FromRow<'r, DB::Row>,
+    for<'r> (i64,): FromRow<'r, DB::Row>,
+
+    for<'e> i64: Encode<'e, DB> + Type<DB>,
+    for<'e> Option<i64>: Encode<'e, DB> + Type<DB>,
+    for<'e> String: Encode<'e, DB> + Type<DB>,
+    for<'e> &'e str: Encode<'e, DB> + Type<DB>,
+{
+    async fn merge_day(&self, stats: &DayStats) -> Result<(), RepositoryError> {
+        sqlx::query(
+            "INSERT INTO daily_stats (day, logins, unique_players, peak_players, peak_at) \
+            VALUES ($1, $2, $3, $4, $5) \
+            ON CONFLICT (day) DO UPDATE SET \
+            logins = logins + excluded.logins, \
+            unique_players = max(unique_players, excluded.unique_players), \
+            peak_at = CASE WHEN excluded.peak_players > peak_players \
+                THEN excluded.peak_at ELSE peak_at END, \
+            peak_players = max(peak_players, excluded.peak_players)",
+        )
+        .bind(stats.day.to_string())
+        .bind(stats.logins as i64)
+        .bind(stats.unique_players as i64)
+        .bind(stats.peak_players as i64)
+        .bind(stats.peak_at.map(|v| v.timestamp_millis()))
+        .execute(&self.db)
+        .await
+        .map(|_| ())
+        .map_err(|error| {
+            tracing::error!(%error, "Failed to merge daily stats: sqlx error");
+            error.into()
+        })
+    }
+
+    async fn add_seen_players(&self, players: &[(Uuid, String)]) -> Result<(), RepositoryError> {
+        let now = Utc::now().timestamp_millis();
+
+        // A single transaction, so that a join wave costs one commit
+        let mut tx = self.db.begin().await.map_err(|error| {
+            tracing::error!(%error, "Failed to begin seen players transaction: sqlx error");
+            error
+        })?;
+
+        for (uuid, username) in players {
+            sqlx::query(
+                "INSERT INTO seen_players (uuid, username, first_seen) VALUES ($1, $2, $3) \
+                ON CONFLICT (uuid) DO NOTHING",
+            )
+            .bind(uuid.to_string())
+            .bind(username.as_str())
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            .map_err(|error| {
+                tracing::error!(%error, "Failed to add seen player: sqlx error");
+                error
+            })?;
+        }
+
+        tx.commit().await.map_err(|error| {
+            tracing::error!(%error, "Failed to commit seen players transaction: sqlx error");
+            error.into()
+        })
+    }
+
+    async fn get_stats(&self) -> Result<StatsSummary, RepositoryError> {
+        let (unique_players,): (i64,) = sqlx::query_as("SELECT count(*) FROM seen_players")
+            .fetch_one(&self.db)
+            .await
+            .map_err(|error| {
+                tracing::error!(%error, "Failed to count seen players: sqlx error");
+                error
+            })?;
+
+        let peak = sqlx::query_as(
+            "SELECT * FROM daily_stats ORDER BY peak_players DESC, peak_at ASC LIMIT 1",
+        )
+        .fetch_optional(&self.db)
+        .await
+        .map_err(|error| {
+            tracing::error!(%error, "Failed to get peak stats: sqlx error");
+            error
+        })?
+        .map(DayStats::from_row)
+        .transpose()?;
+
+        let days = sqlx::query_as("SELECT * FROM daily_stats ORDER BY day DESC LIMIT $1")
+            .bind(RECENT_DAYS)
+            .fetch(&self.db)
+            .map_err(RepositoryError::from)
+            .and_then(|row: DayStatsRow| async move { DayStats::from_row(row) })
+            .try_collect()
+            .await?;
+
+        Ok(StatsSummary {
+            unique_players: unique_players as u64,
+            peak_players: peak.as_ref().map_or(0, |v| v.peak_players),
+            peak_at: peak.and_then(|v| v.peak_at),
+            days,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DayStats, SqlxStatsRepository, StatsRepository};
+    use chrono::{DateTime, NaiveDate};
+    use sqlx::{migrate, Sqlite, SqlitePool};
+    use uuid::Uuid;
+
+    async fn get_repository() -> SqlxStatsRepository<Sqlite> {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate!().run(&pool).await.unwrap();
+
+        SqlxStatsRepository::new(pool)
+    }
+
+    fn day(day: u32, logins: u64, peak_players: u64, peak_at: i64) -> DayStats {
+        DayStats {
+            day: NaiveDate::from_ymd_opt(2024, 1, day).unwrap(),
+            logins,
+            unique_players: logins,
+            peak_players,
+            peak_at: DateTime::from_timestamp_millis(peak_at),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_merge_day() {
+        let repo = get_repository().await;
+
+        repo.merge_day(&day(1, 3, 2, 100)).await.unwrap();
+        repo.merge_day(&day(1, 2, 5, 200)).await.unwrap();
+        repo.merge_day(&day(1, 1, 4, 300)).await.unwrap();
+
+        let stats = repo.get_stats().await.unwrap();
+        assert_eq!(stats.days.len(), 1);
+        assert_eq!(stats.days[0].logins, 6);
+        assert_eq!(stats.days[0].unique_players, 3);
+        assert_eq!(stats.days[0].peak_players, 5);
+        assert_eq!(stats.days[0].peak_at, DateTime::from_timestamp_millis(200));
+    }
+
+    #[tokio::test]
+    async fn test_get_stats() {
+        let repo = get_repository().await;
+
+        repo.merge_day(&day(1, 3, 2, 100)).await.unwrap();
+        repo.merge_day(&day(2, 2, 7, 200)).await.unwrap();
+        repo.merge_day(&day(3, 1, 4, 300)).await.unwrap();
+
+        let players: Vec<_> = (0..3)
+            .map(|i| (Uuid::new_v4(), format!("Player{i}")))
+            .collect();
+        repo.add_seen_players(&players).await.unwrap();
+        repo.add_seen_players(&players[..1]).await.unwrap();
+
+        let stats = repo.get_stats().await.unwrap();
+        assert_eq!(stats.unique_players, 3);
+        assert_eq!(stats.peak_players, 7);
+        assert_eq!(stats.peak_at, DateTime::from_timestamp_millis(200));
+
+        let days: Vec<_> = stats.days.iter().map(|v| v.day.to_string()).collect();
+        assert_eq!(days, vec!["2024-01-03", "2024-01-02", "2024-01-01"]);
+    }
+}
@@ -0,0 +1,361 @@
+use super::RepositoryError;
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use ipnet::{IpNet, Ipv4Net};
+use sqlx::{
+    prelude::FromRow, ColumnIndex, Database, Decode, Encode, Executor, IntoArguments, Pool, Row,
+    Type,
+};
+use std::{future::Future, net::IpAddr, time::Duration};
+
+/// Bans a username only when it connects from the network, leaving both the
+/// account and the network free to be used otherwise.
+#[derive(Debug, Clone)]
+pub struct UserIpBanData {
+    pub username: String,
+    pub network: IpNet,
+    pub created_at: DateTime<Utc>,
+    pub expiration: Option<DateTime<Utc>>,
+    pub reason: Option<String>,
+}
+
+pub trait UserIpBansRepository: Clone + Send + Sync {
+    fn add_ban(
+        &self,
+        username: &str,
+        network: IpNet,
+        expiration: Option<Duration>,
+        reason: Option<String>,
+    ) -> impl Future<Output = Result<UserIpBanData, RepositoryError>> + Send;
+
+    /// Returns the oldest ban of the username whose network contains `ip`.
+    fn is_banned(
+        &self,
+        username: &str,
+        ip: IpAddr,
+    ) -> impl Future<Output = Result<Option<UserIpBanData>, RepositoryError>> + Send;
+
+    fn remove_ban(
+        &self,
+        username: &str,
+        network: IpNet,
+    ) -> impl Future<Output = Result<Option<UserIpBanData>, RepositoryError>> + Send;
+
+    fn get_bans(&self) -> impl Future<Output = Result<Vec<UserIpBanData>, RepositoryError>> + Send;
+}
+
+/// Accepts both CIDR networks and single IPs, which match only that address.
+pub fn parse_network(network: &str) -> Result<IpNet, ipnet::AddrParseError> {
+    network.parse().or_else(|error| {
+        network
+            .parse::<IpAddr>()
+            .map(IpNet::from)
+            .map_err(|_| error)
+    })
+}
+
+/// Truncates the host bits and turns IPv4-mapped IPv6 networks into plain
+/// IPv4, so that the same network is always stored under the same key.
+pub fn canonical_network(network: IpNet) -> IpNet {
+    if let IpNet::V6(net) = network {
+        if let Some(ip) = net.addr().to_ipv4_mapped() {
+            if net.prefix_len() >= 96 {
+                if let Ok(net) = Ipv4Net::new(ip, net.prefix_len() - 96) {
+                    return IpNet::V4(net.trunc());
+                }
+            }
+        }
+    }
+
+    network.trunc()
+}
+
+impl<'r, R: Row> FromRow<'r, R> for UserIpBanData
+where
+    &'static str: ColumnIndex<R>,
+    String: Decode<'r, R::Database> + Type<R::Database>,
+    DateTime<Utc>: Decode<'r, R::Database> + Type<R::Database>,
+{
+    fn from_row(row: &'r R) -> Result<Self, sqlx::Error> {
+        let network: String = row.try_get("network")?;
+        let network = network.parse().map_err(|error| sqlx::Error::ColumnDecode {
+            index: "network".into(),
+            source: Box::new(error),
+        })?;
+
+        let data = Self {
+            username: row.try_get("username")?,
+            network,
+            created_at: row.try_get("created_at")?,
+            expiration: row.try_get("expiration")?,
+            reason: row.try_get("reason")?,
+        };
+
+        Ok(data)
+    }
+}
+
+pub struct SqlxUserIpBansRepository<DB: Database> {
+    db: Pool<DB>,
+}
+
+impl<DB: Database> Clone for SqlxUserIpBansRepository<DB> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+        }
+    }
+}
+
+impl<DB: Database> SqlxUserIpBansRepository<DB> {
+    #[inline]
+    pub fn new(db: Pool<DB>) -> Self {
+        Self { db }
+    }
+}
+
+impl<DB> UserIpBansRepository for SqlxUserIpBansRepository<DB>
+where
+    DB: Database,
+    for<'a> <DB as sqlx::Database>::Arguments<'a>: IntoArguments<'a, DB>,
+    for<'a> &'a Pool<DB>: Executor<'a, Database = DB>,
+
+    for<'r> UserIpBanData: FromRow<'r, DB::Row>,
+
+    for<'e> DateTime<Utc>: Encode<'e, DB> + Type<DB>,
+    for<'e> Option<DateTime<Utc>>: Encode<'e, DB> + Type<DB>,
+    for<'e> &'e str: Encode<'e, DB> + Type<DB>,
+    for<'e> String: Encode<'e, DB> + Type<DB>,
+    for<'e> Option<String>: Encode<'e, DB> + Type<DB>,
+{
+    async fn add_ban(
+        &self,
+        username: &str,
+        network: IpNet,
+        expiration: Option<Duration>,
+        reason: Option<String>,
+    ) -> Result<UserIpBanData, RepositoryError> {
+        let now = Utc::now();
+
+        sqlx::query_as(
+            "INSERT INTO user_ip_bans \
+            (username, network, created_at, expiration, reason) \
+            VALUES ($1, $2, $3, $4, $5) \
+            ON CONFLICT (username, network) DO UPDATE \
+            SET expiration = excluded.expiration, reason = excluded.reason \
+            RETURNING *",
+        )
+        .bind(username)
+        .bind(canonical_network(network).to_string())
+        .bind(now)
+        .bind(expiration.map(|exp| now + exp))
+        .bind(reason)
+        .fetch_one(&self.db)
+        .await
+        .map_err(|error| {
+            tracing::error!(%error, "Failed to create user IP ban registry: sqlx error");
+            error.into()
+        })
+    }
+
+    async fn is_banned(
+        &self,
+        username: &str,
+        ip: IpAddr,
+    ) -> Result<Option<UserIpBanData>, RepositoryError> {
+        let rows: Vec<UserIpBanData> = sqlx::query_as(
+            "SELECT * FROM user_ip_bans \
+            WHERE username = $1 \
+            ORDER BY created_at, network",
+        )
+        .bind(username)
+        .fetch_all(&self.db)
+        .await
+        .map_err(|error| {
+            tracing::error!(%error, "Failed to get user IP ban registries: sqlx error");
+            error
+        })?;
+
+        let ip = ip.to_canonical();
+        let now = Utc::now();
+        let mut found = None;
+
+        for row in rows {
+            if matches!(row.expiration, Some(expiration) if now > expiration) {
+                let _ = sqlx::query(
+                    "DELETE FROM user_ip_bans WHERE username = $1 AND network = $2",
+                )
+                .bind(username)
+                .bind(row.network.to_string())
+                .execute(&self.db)
+                .await
+                .map_err(|error| {
+                    tracing::error!(%error, "Failed to delete expired user IP ban registry: sqlx error");
+                });
+            } else if found.is_none() && row.network.contains(&ip) {
+                found = Some(row);
+            }
+        }
+
+        Ok(found)
+    }
+
+    async fn remove_ban(
+        &self,
+        username: &str,
+        network: IpNet,
+    ) -> Result<Option<UserIpBanData>, RepositoryError> {
+        sqlx::query_as(
+            "DELETE FROM user_ip_bans \
+            WHERE username = $1 AND network = $2 \
+            RETURNING *",
+        )
+        .bind(username)
+        .bind(canonical_network(network).to_string())
+        .fetch_optional(&self.db)
+        .await
+        .map_err(|error| {
+            tracing::error!(%error, "Failed to delete user IP ban registry: sqlx error");
+            error.into()
+        })
+    }
+
+    async fn get_bans(&self) -> Result<Vec<UserIpBanData>, RepositoryError> {
+        sqlx::query_as("SELECT * FROM user_ip_bans")
+            .fetch(&self.db)
+            .try_collect()
+            .await
+            .map_err(|error| {
+                tracing::error!(%error, "Failed to get all user IP ban registries: sqlx error");
+                error.into()
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{canonical_network, SqlxUserIpBansRepository, UserIpBansRepository};
+    use ipnet::IpNet;
+    use sqlx::{migrate, Sqlite, SqlitePool};
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::Duration,
+    };
+    use tokio::time::sleep;
+
+    async fn get_repository() -> SqlxUserIpBansRepository<Sqlite> {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate!().run(&pool).await.unwrap();
+
+        SqlxUserIpBansRepository::new(pool)
+    }
+
+    fn net(s: &str) -> IpNet {
+        s.parse().unwrap()
+    }
+
+    fn ip(a: u8, b: u8, c: u8, d: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(a, b, c, d))
+    }
+
+    #[test]
+    fn test_canonical_network() {
+        assert_eq!(canonical_network(net("10.1.2.3/8")), net("10.0.0.0/8"));
+        assert_eq!(
+            canonical_network(net("::ffff:192.0.2.7/120")),
+            net("192.0.2.0/24")
+        );
+        assert_eq!(
+            canonical_network(net("2001:db8::1/32")),
+            net("2001:db8::/32")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ban_matches_network_and_username() {
+        let repo = get_repository().await;
+
+        repo.add_ban("Notch", net("10.0.0.0/8"), None, Some("Alt".into()))
+            .await
+            .unwrap();
+
+        let ban = repo
+            .is_banned("Notch", ip(10, 20, 30, 40))
+            .await
+            .unwrap()
+            .expect("The added ban was not registrered properly");
+        assert_eq!(ban.network, net("10.0.0.0/8"));
+        assert_eq!(ban.reason.unwrap(), "Alt");
+
+        let mapped = IpAddr::V6(Ipv4Addr::new(10, 0, 0, 1).to_ipv6_mapped());
+        assert!(repo.is_banned("Notch", mapped).await.unwrap().is_some());
+
+        assert!(repo
+            .is_banned("Notch", ip(11, 0, 0, 1))
+            .await
+            .unwrap()
+            .is_none());
+        assert!(repo
+            .is_banned("jeb_", ip(10, 0, 0, 1))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_add_ban_updates_existing() {
+        let repo = get_repository().await;
+
+        repo.add_ban("Notch", net("10.0.0.0/8"), None, None)
+            .await
+            .unwrap();
+        repo.add_ban("Notch", net("10.9.9.9/8"), None, Some("Updated".into()))
+            .await
+            .unwrap();
+
+        let bans = repo.get_bans().await.unwrap();
+        assert_eq!(bans.len(), 1);
+        assert_eq!(bans[0].reason.as_deref(), Some("Updated"));
+    }
+
+    #[tokio::test]
+    async fn test_remove_ban() {
+        let repo = get_repository().await;
+
+        let result = repo.remove_ban("Notch", net("10.0.0.0/8")).await.unwrap();
+        assert!(result.is_none());
+
+        repo.add_ban("Notch", net("10.0.0.0/8"), None, None)
+            .await
+            .unwrap();
+
+        let result = repo.remove_ban("Notch", net("10.1.1.1/8")).await.unwrap();
+        assert!(result.is_some());
+
+        let result = repo.is_banned("Notch", ip(10, 0, 0, 1)).await.unwrap();
+        assert!(result.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ban_expiration() {
+        let repo = get_repository().await;
+
+        repo.add_ban(
+            "Notch",
+            net("10.0.0.0/8"),
+            Some(Duration::from_millis(100)),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let result = repo.is_banned("Notch", ip(10, 0, 0, 1)).await.unwrap();
+        assert!(result.is_some());
+
+        sleep(Duration::from_millis(200)).await;
+        let result = repo.is_banned("Notch", ip(10, 0, 0, 1)).await.unwrap();
+        assert!(result.is_none());
+        assert!(repo.get_bans().await.unwrap().is_empty());
+    }
+}
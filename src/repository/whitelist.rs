@@ -1,10 +1,20 @@
 use super::{kv::KeyValueRepository, private::SealedRepository, RepositoryError};
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use futures_util::TryStreamExt;
 use sqlx::{
     ColumnIndex, Database, Decode, Encode, Executor, FromRow, IntoArguments, Pool, Row, Type,
 };
-use std::future::Future;
+use std::{
+    fmt,
+    future::Future,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use uuid::Uuid;
+
+/// Longest pattern accepted, usernames themselves are at most 16 characters.
+const MAX_PATTERN_LENGTH: usize = 64;
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum WhitelistResult {
@@ -22,10 +32,101 @@ impl WhitelistResult {
     }
 }
 
+/// A whitelist entry matching usernames case insensitively, where `*` matches
+/// any characters and `?` a single one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WhitelistPattern(String);
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid whitelist pattern `{0}`")]
+pub struct InvalidWhitelistPattern(String);
+
+impl FromStr for WhitelistPattern {
+    type Err = InvalidWhitelistPattern;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let valid = !s.is_empty()
+            && s.len() <= MAX_PATTERN_LENGTH
+            && s.bytes()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, b'_' | b'*' | b'?'));
+
+        if valid {
+            Ok(Self(s.to_ascii_lowercase()))
+        } else {
+            Err(InvalidWhitelistPattern(s.into()))
+        }
+    }
+}
+
+impl fmt::Display for WhitelistPattern {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl WhitelistPattern {
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn matches(&self, username: &str) -> bool {
+        let pattern = self.0.as_bytes();
+        let name = username.as_bytes();
+
+        let (mut p, mut n) = (0, 0);
+        // The last `*` and the name position it was tried at, to backtrack
+        let mut star = None;
+
+        while n < name.len() {
+            let c = name[n].to_ascii_lowercase();
+
+            if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == c) {
+                p += 1;
+                n += 1;
+            } else if p < pattern.len() && pattern[p] == b'*' {
+                star = Some((p, n));
+                p += 1;
+            } else if let Some((star_p, star_n)) = star {
+                // Lets the `*` match one more character
+                star = Some((star_p, star_n + 1));
+                p = star_p + 1;
+                n = star_n + 1;
+            } else {
+                return false;
+            }
+        }
+
+        pattern[p..].iter().all(|&c| c == b'*')
+    }
+}
+
+/// A pattern and the named group it was added to, if any. The patterns of a
+/// group can be removed together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WhitelistPatternEntry {
+    pub pattern: WhitelistPattern,
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WhitelistEntry {
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+    pub expiration: Option<DateTime<Utc>>,
+}
+
 pub trait WhitelistRepository: SealedRepository {
+    /// Whitelists the username, pinning it to the account `uuid` if provided,
+    /// for `duration` or permanently. Adding an uuid that is already
+    /// whitelisted updates its username, and adding an existing entry again
+    /// replaces its expiration.
     fn add(
         &self,
         username: &str,
+        uuid: Option<Uuid>,
+        duration: Option<Duration>,
     ) -> impl Future<Output = Result<WhitelistResult, RepositoryError>> + Send;
 
     fn is_enabled(&self) -> impl Future<Output = Result<bool, RepositoryError>> + Send;
@@ -35,43 +136,111 @@ pub trait WhitelistRepository: SealedRepository {
         enabled: bool,
     ) -> impl Future<Output = Result<(), RepositoryError>> + Send;
 
+    /// Whether the username is whitelisted, either exactly or by a pattern.
     fn is_whitelisted(
         &self,
         username: &str,
     ) -> impl Future<Output = Result<bool, RepositoryError>> + Send;
 
+    /// Whether a joining player is whitelisted. Entries pinned to an uuid only
+    /// match that uuid, the username is only compared with unpinned entries
+    /// and then with the patterns.
+    fn is_player_whitelisted(
+        &self,
+        username: &str,
+        uuid: Uuid,
+    ) -> impl Future<Output = Result<bool, RepositoryError>> + Send;
+
     fn remove(
         &self,
         username: &str,
     ) -> impl Future<Output = Result<WhitelistResult, RepositoryError>> + Send;
 
-    fn get_all(&self) -> impl Future<Output = Result<Vec<String>, RepositoryError>> + Send;
+    fn get_all(&self) -> impl Future<Output = Result<Vec<WhitelistEntry>, RepositoryError>> + Send;
+
+    /// Adds a pattern, or moves it to `tag` if it already exists.
+    fn add_pattern(
+        &self,
+        pattern: &WhitelistPattern,
+        tag: Option<&str>,
+    ) -> impl Future<Output = Result<WhitelistResult, RepositoryError>> + Send;
+
+    fn remove_pattern(
+        &self,
+        pattern: &WhitelistPattern,
+    ) -> impl Future<Output = Result<WhitelistResult, RepositoryError>> + Send;
+
+    /// Removes every pattern of the named group.
+    fn remove_pattern_group(
+        &self,
+        tag: &str,
+    ) -> impl Future<Output = Result<WhitelistResult, RepositoryError>> + Send;
+
+    fn get_patterns(
+        &self,
+    ) -> impl Future<Output = Result<Vec<WhitelistPatternEntry>, RepositoryError>> + Send;
 }
 
 struct WhitelistRow {
     username: String,
     created_at: i64,
+    expiration: Option<i64>,
 }
 
 impl<'r, R: Row> FromRow<'r, R> for WhitelistRow
 where
     &'static str: ColumnIndex<R>,
     i64: Decode<'r, R::Database> + Type<R::Database>,
+    Option<i64>: Decode<'r, R::Database> + Type<R::Database>,
     String: Decode<'r, R::Database> + Type<R::Database>,
 {
     fn from_row(row: &'r R) -> Result<Self, sqlx::Error> {
         let data = WhitelistRow {
             username: row.try_get("username")?,
             created_at: row.try_get("created_at")?,
+            expiration: row.try_get("expiration")?,
+        };
+
+        Ok(data)
+    }
+}
+
+struct WhitelistPatternRow {
+    pattern: String,
+    tag: Option<String>,
+}
+
+impl<'r, R: Row> FromRow<'r, R> for WhitelistPatternRow
+where
+    &'static str: ColumnIndex<R>,
+    String: Decode<'r, R::Database> + Type<R::Database>,
+    Option<String>: Decode<'r, R::Database> + Type<R::Database>,
+{
+    fn from_row(row: &'r R) -> Result<Self, sqlx::Error> {
+        let data = WhitelistPatternRow {
+            pattern: row.try_get("pattern")?,
+            tag: row.try_get("tag")?,
         };
 
         Ok(data)
     }
 }
 
+#[derive(Default)]
+struct PatternCache {
+    patterns: Option<Arc<[WhitelistPattern]>>,
+    /// Bumped when the patterns change, so that patterns loaded before the
+    /// change are not cached after it
+    generation: u64,
+}
+
+/// The patterns are cached in memory once loaded, so that logins don't scan
+/// the whole table. The cache is shared by the clones of the repository and
+/// cleared when the patterns change.
 pub struct SqlxWhitelistRepository<DB: Database, KV> {
     db: Pool<DB>,
     kv: KV,
+    patterns: Arc<RwLock<PatternCache>>,
 }
 
 impl<DB: Database, KV: Clone> Clone for SqlxWhitelistRepository<DB, KV> {
@@ -80,6 +249,7 @@ impl<DB: Database, KV: Clone> Clone for SqlxWhitelistRepository<DB, KV> {
         Self {
             db: self.db.clone(),
             kv: self.kv.clone(),
+            patterns: self.patterns.clone(),
         }
     }
 }
@@ -87,7 +257,34 @@ impl<DB: Database, KV: Clone> Clone for SqlxWhitelistRepository<DB, KV> {
 impl<DB: Database, KV: KeyValueRepository> SqlxWhitelistRepository<DB, KV> {
     #[inline]
     pub fn new(db: Pool<DB>, kv: KV) -> Self {
-        Self { db, kv }
+        Self {
+            db,
+            kv,
+            patterns: Arc::default(),
+        }
+    }
+
+    /// The cached patterns, or the generation to cache them at once loaded.
+    #[inline]
+    fn cached_patterns(&self) -> Result<Arc<[WhitelistPattern]>, u64> {
+        let cache = self.patterns.read().unwrap_or_else(|e| e.into_inner());
+        cache.patterns.clone().ok_or(cache.generation)
+    }
+
+    /// Does nothing if the patterns changed since `generation`.
+    #[inline]
+    fn set_cached_patterns(&self, patterns: Arc<[WhitelistPattern]>, generation: u64) {
+        let mut cache = self.patterns.write().unwrap_or_else(|e| e.into_inner());
+        if cache.generation == generation {
+            cache.patterns = Some(patterns);
+        }
+    }
+
+    #[inline]
+    fn invalidate_patterns(&self) {
+        let mut cache = self.patterns.write().unwrap_or_else(|e| e.into_inner());
+        cache.generation += 1;
+        cache.patterns = None;
     }
 }
 
@@ -101,29 +298,130 @@ where
     for<'a> &'a Pool<DB>: Executor<'a, Database = DB>,
 
     for<'r> WhitelistRow: FromRow<'r, DB::Row>,
+    for<'r> WhitelistPatternRow: FromRow<'r, DB::Row>,
+    for<'r> String: Decode<'r, DB> + Type<DB>,
+    usize: ColumnIndex<DB::Row>,
 
     for<'e> i64: Encode<'e, DB> + Type<DB>,
+    for<'e> Option<i64>: Encode<'e, DB> + Type<DB>,
     for<'e> &'e str: Encode<'e, DB> + Type<DB>,
+    for<'e> Option<&'e str>: Encode<'e, DB> + Type<DB>,
 {
-    async fn add(&self, username: &str) -> Result<WhitelistResult, RepositoryError> {
+    async fn add(
+        &self,
+        username: &str,
+        uuid: Option<Uuid>,
+        duration: Option<Duration>,
+    ) -> Result<WhitelistResult, RepositoryError> {
         let now = Utc::now();
+        let expiration = duration.map(|v| (now + v).timestamp_millis());
 
-        if !self.is_whitelisted(username).await? {
-            sqlx::query("INSERT INTO whitelist (username, created_at) VALUES ($1, $2)")
+        // Expired entries would otherwise conflict with the new one
+        self.remove_expired(now).await?;
+
+        let Some(uuid) = uuid else {
+            let updated = sqlx::query(
+                "UPDATE whitelist SET expiration = $1 \
+                WHERE username = $2 AND expiration IS NOT $3 RETURNING created_at",
+            )
+            .bind(expiration)
+            .bind(username)
+            .bind(expiration)
+            .fetch_optional(&self.db)
+            .await
+            .map_err(|error| {
+                tracing::error!(%error, "Failed to update whitelist registry: sqlx error");
+                error
+            })?;
+
+            if updated.is_some() {
+                return Ok(WhitelistResult::Changed);
+            }
+            if self.has_username(username, now).await? {
+                return Ok(WhitelistResult::Unchanged);
+            }
+
+            sqlx::query(
+                "INSERT INTO whitelist (username, created_at, expiration) VALUES ($1, $2, $3)",
+            )
+            .bind(username)
+            .bind(now.timestamp_millis())
+            .bind(expiration)
+            .execute(&self.db)
+            .await
+            .map_err(|error| {
+                tracing::error!(%error, "Failed to create whitelist registry: sqlx error");
+                error
+            })?;
+
+            return Ok(WhitelistResult::Changed);
+        };
+        let uuid = uuid.to_string();
+
+        let pinned: Option<WhitelistRow> =
+            sqlx::query_as("SELECT * FROM whitelist WHERE uuid = $1")
+                .bind(uuid.as_str())
+                .fetch_optional(&self.db)
+                .await
+                .map_err(|error| {
+                    tracing::error!(%error, "Failed to get whitelist registry: sqlx error");
+                    error
+                })?;
+
+        match pinned {
+            Some(row) if row.username == username && row.expiration == expiration => {
+                return Ok(WhitelistResult::Unchanged)
+            }
+            // The player renamed since it was whitelisted, or the expiration
+            // was replaced
+            Some(_) => {
+                sqlx::query("UPDATE whitelist SET username = $1, expiration = $2 WHERE uuid = $3")
+                    .bind(username)
+                    .bind(expiration)
+                    .bind(uuid.as_str())
+                    .execute(&self.db)
+                    .await
+                    .map_err(|error| {
+                        tracing::error!(%error, "Failed to update whitelist registry: sqlx error");
+                        error
+                    })?;
+            }
+            None => {
+                // Pins the entry that was whitelisted by username only, if any
+                let updated = sqlx::query(
+                    "UPDATE whitelist SET uuid = $1, expiration = $2 \
+                    WHERE username = $3 AND uuid IS NULL RETURNING created_at",
+                )
+                .bind(uuid.as_str())
+                .bind(expiration)
                 .bind(username)
-                .bind(now.timestamp_millis())
-                .execute(&self.db)
+                .fetch_optional(&self.db)
                 .await
-                .map(|_| ())
                 .map_err(|error| {
-                    tracing::error!(%error, "Failed to create whitelist registry: sqlx error");
+                    tracing::error!(%error, "Failed to update whitelist registry: sqlx error");
                     error
                 })?;
 
-            Ok(WhitelistResult::Changed)
-        } else {
-            Ok(WhitelistResult::Unchanged)
+                if updated.is_none() {
+                    sqlx::query(
+                        "INSERT INTO whitelist (username, uuid, created_at, expiration) \
+                        VALUES ($1, $2, $3, $4)",
+                    )
+                    .bind(username)
+                    .bind(uuid.as_str())
+                    .bind(now.timestamp_millis())
+                    .bind(expiration)
+                    .execute(&self.db)
+                    .await
+                    .map_err(|error| {
+                        tracing::error!(%error, "Failed to create whitelist registry: sqlx error");
+                        error
+                    })?;
+                }
+            }
         }
+
+        Ok(WhitelistResult::Changed)
     }
 
     async fn is_enabled(&self) -> Result<bool, RepositoryError> {
@@ -144,15 +442,40 @@ where
     }
 
     async fn is_whitelisted(&self, username: &str) -> Result<bool, RepositoryError> {
-        sqlx::query("SELECT created_at FROM whitelist WHERE username = $1")
-            .bind(username)
-            .fetch_optional(&self.db)
-            .await
-            .map(|v| v.is_some())
-            .map_err(|error| {
-                tracing::error!(%error, "Failed to get whitelist registry: sqlx error");
-                error.into()
-            })
+        if self.has_username(username, Utc::now()).await? {
+            return Ok(true);
+        }
+
+        self.matches_pattern(username).await
+    }
+
+    async fn is_player_whitelisted(
+        &self,
+        username: &str,
+        uuid: Uuid,
+    ) -> Result<bool, RepositoryError> {
+        let uuid = uuid.to_string();
+
+        let exact = sqlx::query(
+            "SELECT created_at FROM whitelist \
+            WHERE (uuid = $1 OR (uuid IS NULL AND username = $2)) \
+            AND (expiration IS NULL OR expiration > $3) LIMIT 1",
+        )
+        .bind(uuid.as_str())
+        .bind(username)
+        .bind(Utc::now().timestamp_millis())
+        .fetch_optional(&self.db)
+        .await
+        .map_err(|error| {
+            tracing::error!(%error, "Failed to get whitelist registry: sqlx error");
+            error
+        })?;
+
+        if exact.is_some() {
+            return Ok(true);
+        }
+
+        self.matches_pattern(username).await
     }
 
     async fn remove(&self, username: &str) -> Result<WhitelistResult, RepositoryError> {
@@ -170,10 +493,19 @@ where
             })
     }
 
-    async fn get_all(&self) -> Result<Vec<String>, RepositoryError> {
+    async fn get_all(&self) -> Result<Vec<WhitelistEntry>, RepositoryError> {
+        let now = Utc::now();
+        self.remove_expired(now).await?;
+
         sqlx::query_as("SELECT * FROM whitelist")
             .fetch(&self.db)
-            .try_filter_map(|v: WhitelistRow| async move { Ok(Some(v.username)) })
+            .try_filter_map(|v: WhitelistRow| async move {
+                Ok(Some(WhitelistEntry {
+                    username: v.username,
+                    created_at: DateTime::from_timestamp_millis(v.created_at).unwrap_or_default(),
+                    expiration: v.expiration.and_then(DateTime::from_timestamp_millis),
+                }))
+            })
             .try_collect()
             .await
             .map_err(|error| {
@@ -181,17 +513,180 @@ where
                 error.into()
             })
     }
+
+    async fn add_pattern(
+        &self,
+        pattern: &WhitelistPattern,
+        tag: Option<&str>,
+    ) -> Result<WhitelistResult, RepositoryError> {
+        let updated = sqlx::query(
+            "INSERT INTO whitelist_patterns (pattern, tag, created_at) VALUES ($1, $2, $3) \
+            ON CONFLICT (pattern) DO UPDATE SET tag = excluded.tag \
+            WHERE tag IS NOT excluded.tag RETURNING pattern",
+        )
+        .bind(pattern.as_str())
+        .bind(tag)
+        .bind(Utc::now().timestamp_millis())
+        .fetch_optional(&self.db)
+        .await
+        .map_err(|error| {
+            tracing::error!(%error, "Failed to create whitelist pattern: sqlx error");
+            error
+        })?;
+
+        self.invalidate_patterns();
+
+        if updated.is_some() {
+            Ok(WhitelistResult::Changed)
+        } else {
+            Ok(WhitelistResult::Unchanged)
+        }
+    }
+
+    async fn remove_pattern(
+        &self,
+        pattern: &WhitelistPattern,
+    ) -> Result<WhitelistResult, RepositoryError> {
+        let deleted =
+            sqlx::query("DELETE FROM whitelist_patterns WHERE pattern = $1 RETURNING pattern")
+                .bind(pattern.as_str())
+                .fetch_optional(&self.db)
+                .await
+                .map_err(|error| {
+                    tracing::error!(%error, "Failed to delete whitelist pattern: sqlx error");
+                    error
+                })?;
+
+        self.invalidate_patterns();
+
+        if deleted.is_some() {
+            Ok(WhitelistResult::Changed)
+        } else {
+            Ok(WhitelistResult::Unchanged)
+        }
+    }
+
+    async fn remove_pattern_group(&self, tag: &str) -> Result<WhitelistResult, RepositoryError> {
+        let deleted =
+            sqlx::query("DELETE FROM whitelist_patterns WHERE tag = $1 RETURNING pattern")
+                .bind(tag)
+                .fetch_all(&self.db)
+                .await
+                .map_err(|error| {
+                    tracing::error!(%error, "Failed to delete whitelist patterns: sqlx error");
+                    error
+                })?;
+
+        self.invalidate_patterns();
+
+        if !deleted.is_empty() {
+            Ok(WhitelistResult::Changed)
+        } else {
+            Ok(WhitelistResult::Unchanged)
+        }
+    }
+
+    async fn get_patterns(&self) -> Result<Vec<WhitelistPatternEntry>, RepositoryError> {
+        sqlx::query_as("SELECT pattern, tag FROM whitelist_patterns ORDER BY pattern")
+            .fetch(&self.db)
+            .try_filter_map(|v: WhitelistPatternRow| async move {
+                // Stored patterns were validated when added
+                Ok(Some(WhitelistPatternEntry {
+                    pattern: WhitelistPattern(v.pattern),
+                    tag: v.tag,
+                }))
+            })
+            .try_collect()
+            .await
+            .map_err(|error| {
+                tracing::error!(%error, "Failed to get all whitelist patterns: sqlx error");
+                error.into()
+            })
+    }
+}
+
+impl<DB, KV> SqlxWhitelistRepository<DB, KV>
+where
+    DB: Database,
+    KV: KeyValueRepository,
+    for<'a> <DB as sqlx::Database>::Arguments<'a>: IntoArguments<'a, DB>,
+    for<'a> &'a Pool<DB>: Executor<'a, Database = DB>,
+
+    for<'r> String: Decode<'r, DB> + Type<DB>,
+    usize: ColumnIndex<DB::Row>,
+
+    for<'e> i64: Encode<'e, DB> + Type<DB>,
+    for<'e> &'e str: Encode<'e, DB> + Type<DB>,
+{
+    /// Whether the username has an exact entry that didn't expire, pinned or
+    /// not.
+    async fn has_username(
+        &self,
+        username: &str,
+        now: DateTime<Utc>,
+    ) -> Result<bool, RepositoryError> {
+        sqlx::query(
+            "SELECT created_at FROM whitelist \
+            WHERE username = $1 AND (expiration IS NULL OR expiration > $2)",
+        )
+        .bind(username)
+        .bind(now.timestamp_millis())
+        .fetch_optional(&self.db)
+        .await
+        .map(|v| v.is_some())
+        .map_err(|error| {
+            tracing::error!(%error, "Failed to get whitelist registry: sqlx error");
+            error.into()
+        })
+    }
+
+    async fn remove_expired(&self, now: DateTime<Utc>) -> Result<(), RepositoryError> {
+        sqlx::query("DELETE FROM whitelist WHERE expiration <= $1")
+            .bind(now.timestamp_millis())
+            .execute(&self.db)
+            .await
+            .map(|_| ())
+            .map_err(|error| {
+                tracing::error!(%error, "Failed to delete expired whitelist registries: sqlx error");
+                error.into()
+            })
+    }
+
+    async fn matches_pattern(&self, username: &str) -> Result<bool, RepositoryError> {
+        let patterns = match self.cached_patterns() {
+            Ok(v) => v,
+            Err(generation) => {
+                let patterns: Vec<String> = sqlx::query_scalar(
+                    "SELECT pattern FROM whitelist_patterns",
+                )
+                .fetch_all(&self.db)
+                .await
+                .map_err(|error| {
+                    tracing::error!(%error, "Failed to get all whitelist patterns: sqlx error");
+                    error
+                })?;
+                // Stored patterns were validated when added
+                let patterns: Arc<[WhitelistPattern]> =
+                    patterns.into_iter().map(WhitelistPattern).collect();
+                self.set_cached_patterns(patterns.clone(), generation);
+                patterns
+            }
+        };
+
+        Ok(patterns.iter().any(|v| v.matches(username)))
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::SqlxWhitelistRepository;
+    use super::{SqlxWhitelistRepository, WhitelistPattern};
     use crate::repository::{
         kv::SqlxKeyValueRepository,
         whitelist::{WhitelistRepository, WhitelistResult},
     };
     use sqlx::{migrate, Sqlite, SqlitePool};
-    use std::collections::HashSet;
+    use std::{collections::HashSet, sync::Arc, time::Duration};
+    use tokio::time::sleep;
     use uuid::Uuid;
 
     async fn get_repository() -> SqlxWhitelistRepository<Sqlite, SqlxKeyValueRepository<Sqlite>> {
@@ -213,13 +708,50 @@ mod tests {
 
         let username = rand_string();
 
-        let result = repo.add(&username).await.unwrap();
+        let result = repo.add(&username, None, None).await.unwrap();
         assert_eq!(result, WhitelistResult::Changed);
 
         let result = repo.is_whitelisted(&username).await.unwrap();
         assert_eq!(result, true);
 
-        let result = repo.add(&username).await.unwrap();
+        let result = repo.add(&username, None, None).await.unwrap();
+        assert_eq!(result, WhitelistResult::Unchanged);
+    }
+
+    #[tokio::test]
+    async fn test_whitelist_expiration() {
+        let repo = get_repository().await;
+
+        let uuid = Uuid::new_v4();
+        let duration = Some(Duration::from_millis(100));
+
+        let result = repo.add("Notch", None, duration).await.unwrap();
+        assert_eq!(result, WhitelistResult::Changed);
+        let result = repo.add("jeb_", Some(uuid), duration).await.unwrap();
+        assert_eq!(result, WhitelistResult::Changed);
+
+        assert!(repo.is_whitelisted("Notch").await.unwrap());
+        assert!(repo.is_player_whitelisted("jeb_", uuid).await.unwrap());
+        let all = repo.get_all().await.unwrap();
+        assert!(all.iter().all(|v| v.expiration > Some(v.created_at)));
+
+        sleep(Duration::from_millis(200)).await;
+        assert!(!repo.is_whitelisted("Notch").await.unwrap());
+        assert!(!repo.is_player_whitelisted("jeb_", uuid).await.unwrap());
+        assert!(repo.get_all().await.unwrap().is_empty());
+
+        // Expired entries don't block adding them again
+        let result = repo.add("Notch", None, None).await.unwrap();
+        assert_eq!(result, WhitelistResult::Changed);
+        let result = repo.add("jeb_", Some(uuid), None).await.unwrap();
+        assert_eq!(result, WhitelistResult::Changed);
+
+        // Adding again replaces the expiration
+        let result = repo.add("Notch", None, duration).await.unwrap();
+        assert_eq!(result, WhitelistResult::Changed);
+        let result = repo.add("Notch", None, None).await.unwrap();
+        assert_eq!(result, WhitelistResult::Changed);
+        let result = repo.add("Notch", None, None).await.unwrap();
         assert_eq!(result, WhitelistResult::Unchanged);
     }
 
@@ -253,7 +785,7 @@ mod tests {
         let result = repo.remove(&username).await.unwrap();
         assert_eq!(result, WhitelistResult::Unchanged);
 
-        let result = repo.add(&username).await.unwrap();
+        let result = repo.add(&username, None, None).await.unwrap();
         assert_eq!(result, WhitelistResult::Changed);
 
         let result = repo.remove(&username).await.unwrap();
@@ -275,16 +807,142 @@ mod tests {
         for _ in 0..10 {
             let username = rand_string();
 
-            let result = repo.add(&username).await.unwrap();
+            let result = repo.add(&username, None, None).await.unwrap();
             assert_eq!(result, WhitelistResult::Changed);
 
             all_adds.insert(username);
         }
 
-        for username in repo.get_all().await.unwrap() {
-            assert!(all_adds.remove(&username));
+        for entry in repo.get_all().await.unwrap() {
+            assert!(all_adds.remove(&entry.username));
+            assert_eq!(entry.expiration, None);
         }
 
         assert_eq!(all_adds.len(), 0);
     }
+
+    #[tokio::test]
+    async fn test_pinned_whitelist() {
+        let repo = get_repository().await;
+
+        let uuid = Uuid::new_v4();
+        let other = Uuid::new_v4();
+
+        let result = repo.add("Notch", None, None).await.unwrap();
+        assert_eq!(result, WhitelistResult::Changed);
+        assert!(repo.is_player_whitelisted("Notch", other).await.unwrap());
+
+        // Pins the existing entry
+        let result = repo.add("Notch", Some(uuid), None).await.unwrap();
+        assert_eq!(result, WhitelistResult::Changed);
+        let result = repo.add("Notch", Some(uuid), None).await.unwrap();
+        assert_eq!(result, WhitelistResult::Unchanged);
+
+        assert!(repo.is_player_whitelisted("Notch", uuid).await.unwrap());
+        assert!(repo.is_player_whitelisted("Renamed", uuid).await.unwrap());
+        assert!(!repo.is_player_whitelisted("Notch", other).await.unwrap());
+
+        let result = repo.add("Renamed", Some(uuid), None).await.unwrap();
+        assert_eq!(result, WhitelistResult::Changed);
+        let all = repo.get_all().await.unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].username, "Renamed");
+
+        // Someone else took the old username
+        let result = repo.add("Notch", Some(other), None).await.unwrap();
+        assert_eq!(result, WhitelistResult::Changed);
+        assert!(repo.is_player_whitelisted("Notch", other).await.unwrap());
+        assert!(repo.is_player_whitelisted("Renamed", uuid).await.unwrap());
+    }
+
+    #[test]
+    fn test_pattern_matching() {
+        let pattern = |v: &str| v.parse::<WhitelistPattern>().unwrap();
+
+        assert!(pattern("Event_*").matches("event_Notch"));
+        assert!(pattern("event_*").matches("EVENT_"));
+        assert!(!pattern("event_*").matches("notch_event_"));
+        assert!(pattern("*_staff").matches("Notch_staff"));
+        assert!(pattern("a*b*c").matches("aXXbYYbZc"));
+        assert!(!pattern("a*b*c").matches("aXXbYY"));
+        assert!(pattern("team?").matches("team1"));
+        assert!(!pattern("team?").matches("team12"));
+        assert!(pattern("notch").matches("Notch"));
+
+        for invalid in ["", "event-*", "a b", &"*".repeat(65)] {
+            assert!(invalid.parse::<WhitelistPattern>().is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pattern_whitelist() {
+        let repo = get_repository().await;
+        let pattern: WhitelistPattern = "event_*".parse().unwrap();
+
+        assert!(!repo.is_whitelisted("event_Notch").await.unwrap());
+
+        let result = repo.add_pattern(&pattern, Some("event")).await.unwrap();
+        assert_eq!(result, WhitelistResult::Changed);
+        let result = repo.add_pattern(&pattern, Some("event")).await.unwrap();
+        assert_eq!(result, WhitelistResult::Unchanged);
+        let result = repo.add_pattern(&pattern, None).await.unwrap();
+        assert_eq!(result, WhitelistResult::Changed);
+
+        assert!(repo.is_whitelisted("event_Notch").await.unwrap());
+        assert!(repo
+            .is_player_whitelisted("Event_Herobrine", Uuid::new_v4())
+            .await
+            .unwrap());
+        assert!(!repo.is_whitelisted("Notch").await.unwrap());
+
+        // Matching usernames can still be added as exact entries
+        let result = repo.add("event_Notch", None, None).await.unwrap();
+        assert_eq!(result, WhitelistResult::Changed);
+
+        let patterns = repo.get_patterns().await.unwrap();
+        assert_eq!(patterns.len(), 1);
+        assert_eq!(patterns[0].pattern, pattern);
+        assert_eq!(patterns[0].tag, None);
+
+        let result = repo.remove_pattern(&pattern).await.unwrap();
+        assert_eq!(result, WhitelistResult::Changed);
+        assert!(!repo.is_whitelisted("event_Herobrine").await.unwrap());
+        assert!(repo.is_whitelisted("event_Notch").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_pattern_group() {
+        let repo = get_repository().await;
+        for pattern in ["event_*", "team?"] {
+            let pattern = pattern.parse().unwrap();
+            repo.add_pattern(&pattern, Some("event")).await.unwrap();
+        }
+        let staff = "*_staff".parse().unwrap();
+        repo.add_pattern(&staff, None).await.unwrap();
+        assert!(repo.is_whitelisted("team1").await.unwrap());
+
+        let result = repo.remove_pattern_group("event").await.unwrap();
+        assert_eq!(result, WhitelistResult::Changed);
+        let result = repo.remove_pattern_group("event").await.unwrap();
+        assert_eq!(result, WhitelistResult::Unchanged);
+
+        assert!(!repo.is_whitelisted("team1").await.unwrap());
+        assert!(repo.is_whitelisted("Notch_staff").await.unwrap());
+        let patterns = repo.get_patterns().await.unwrap();
+        assert_eq!(patterns.len(), 1);
+        assert_eq!(patterns[0].pattern, staff);
+    }
+
+    #[tokio::test]
+    async fn test_stale_patterns_not_cached() {
+        let repo = get_repository().await;
+        let pattern: WhitelistPattern = "event_*".parse().unwrap();
+
+        // A reader that loaded the patterns before they changed
+        let generation = repo.cached_patterns().unwrap_err();
+        repo.add_pattern(&pattern, None).await.unwrap();
+        repo.set_cached_patterns(Arc::new([]), generation);
+
+        assert!(repo.is_whitelisted("event_Notch").await.unwrap());
+    }
 }
@@ -0,0 +1,267 @@
+use super::{
+    kv::{KeyValueRepository, KeyValueWrite},
+    RepositoryError,
+};
+use chrono::{DateTime, Utc};
+use std::{
+    collections::HashMap,
+    mem,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+struct PendingValue {
+    value: String,
+    expiration: Option<DateTime<Utc>>,
+}
+
+impl PendingValue {
+    #[inline]
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        matches!(self.expiration, Some(expiration) if now > expiration)
+    }
+}
+
+/// Buffers the writes of non-critical values, such as session records, and
+/// writes them in a single transaction by [`WriteBehindKeyValue::flush`], so
+/// that join waves don't queue one commit per player.
+///
+/// Reads see the buffered values, but the values are lost if the proxy dies
+/// before flushing.
+pub struct WriteBehindKeyValue<KV> {
+    inner: KV,
+    pending: Arc<Mutex<HashMap<String, PendingValue>>>,
+}
+
+impl<KV: Clone> Clone for WriteBehindKeyValue<KV> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            pending: self.pending.clone(),
+        }
+    }
+}
+
+impl<KV: KeyValueRepository> WriteBehindKeyValue<KV> {
+    pub fn new(inner: KV) -> Self {
+        Self {
+            inner,
+            pending: Arc::default(),
+        }
+    }
+
+    /// Writes the buffered values. They are buffered again if the write fails,
+    /// unless they were overwritten in the meantime.
+    pub async fn flush(&self) -> Result<usize, RepositoryError> {
+        let pending = mem::take(&mut *self.lock_pending());
+        if pending.is_empty() {
+            return Ok(0);
+        }
+
+        let writes: Vec<_> = pending
+            .iter()
+            .map(|(key, v)| KeyValueWrite {
+                key: key.clone(),
+                value: v.value.clone(),
+                expiration: v.expiration,
+            })
+            .collect();
+
+        if let Err(error) = self.inner.set_many(&writes).await {
+            let mut current = self.lock_pending();
+            for (key, value) in pending {
+                current.entry(key).or_insert(value);
+            }
+
+            return Err(error);
+        }
+
+        Ok(writes.len())
+    }
+
+    #[inline]
+    fn lock_pending(&self) -> std::sync::MutexGuard<'_, HashMap<String, PendingValue>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<KV: KeyValueRepository> KeyValueRepository for WriteBehindKeyValue<KV> {
+    async fn get_ttl(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+    ) -> Result<Option<String>, RepositoryError> {
+        {
+            let now = Utc::now();
+            let mut pending = self.lock_pending();
+
+            if let Some(v) = pending.get_mut(key) {
+                if v.is_expired(now) {
+                    return Ok(None);
+                }
+                if let Some(ttl) = ttl {
+                    v.expiration = Some(now + ttl);
+                }
+                return Ok(Some(v.value.clone()));
+            }
+        }
+
+        self.inner.get_ttl(key, ttl).await
+    }
+
+    async fn set_ttl(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Option<Duration>,
+    ) -> Result<(), RepositoryError> {
+        let value = PendingValue {
+            value: value.to_owned(),
+            expiration: ttl.map(|ttl| Utc::now() + ttl),
+        };
+        self.lock_pending().insert(key.to_owned(), value);
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<Option<String>, RepositoryError> {
+        let pending = self.lock_pending().remove(key);
+        let stored = self.inner.delete(key).await?;
+
+        match pending {
+            Some(v) if !v.is_expired(Utc::now()) => Ok(Some(v.value)),
+            _ => Ok(stored),
+        }
+    }
+
+    #[inline]
+    async fn set_many(&self, writes: &[KeyValueWrite]) -> Result<(), RepositoryError> {
+        {
+            let mut pending = self.lock_pending();
+            for write in writes {
+                pending.remove(&write.key);
+            }
+        }
+
+        self.inner.set_many(writes).await
+    }
+
+    async fn claim_many(&self, writes: &[KeyValueWrite]) -> Result<bool, RepositoryError> {
+        {
+            let now = Utc::now();
+            let mut pending = self.lock_pending();
+
+            let taken = writes.iter().any(|write| {
+                pending
+                    .get(&write.key)
+                    .is_some_and(|v| !v.is_expired(now) && v.value != write.value)
+            });
+            if taken {
+                return Ok(false);
+            }
+            for write in writes {
+                pending.remove(&write.key);
+            }
+        }
+
+        self.inner.claim_many(writes).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WriteBehindKeyValue;
+    use crate::repository::kv::{KeyValueRepository, SqlxKeyValueRepository};
+    use sqlx::{migrate, Sqlite, SqlitePool};
+    use std::time::Duration;
+
+    async fn write_behind() -> (
+        WriteBehindKeyValue<SqlxKeyValueRepository<Sqlite>>,
+        SqlxKeyValueRepository<Sqlite>,
+    ) {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate!().run(&pool).await.unwrap();
+
+        let inner = SqlxKeyValueRepository::new(pool);
+        (WriteBehindKeyValue::new(inner.clone()), inner)
+    }
+
+    #[tokio::test]
+    async fn test_batched_writes_land_after_flush() {
+        let (kv, inner) = write_behind().await;
+
+        let writes = (0..500).map(|i| {
+            let kv = kv.clone();
+            async move {
+                kv.set_ttl(&format!("session.{i}"), &i.to_string(), None)
+                    .await
+                    .unwrap()
+            }
+        });
+        futures_util::future::join_all(writes).await;
+
+        assert_eq!(kv.get("session.42").await.unwrap().unwrap(), "42");
+        assert!(inner.get("session.42").await.unwrap().is_none());
+
+        assert_eq!(kv.flush().await.unwrap(), 500);
+        for i in 0..500 {
+            let value = inner.get(&format!("session.{i}")).await.unwrap();
+            assert_eq!(value.unwrap(), i.to_string());
+        }
+
+        assert_eq!(kv.flush().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_flush_on_shutdown() {
+        let (kv, inner) = write_behind().await;
+
+        kv.set("session.notch", "old").await.unwrap();
+        kv.flush().await.unwrap();
+        kv.set("session.notch", "new").await.unwrap();
+        kv.set_ttl("session.jeb_", "1.2.3.4", Some(Duration::from_secs(60)))
+            .await
+            .unwrap();
+
+        // As done by the service once the connections are closed
+        let handle = kv.clone();
+        drop(kv);
+        handle.flush().await.unwrap();
+
+        assert_eq!(inner.get("session.notch").await.unwrap().unwrap(), "new");
+        assert_eq!(inner.get("session.jeb_").await.unwrap().unwrap(), "1.2.3.4");
+    }
+
+    #[tokio::test]
+    async fn test_pending_values_expire() {
+        let (kv, inner) = write_behind().await;
+
+        kv.set("session.notch", "stored").await.unwrap();
+        kv.flush().await.unwrap();
+
+        kv.set_ttl("session.notch", "pending", Some(Duration::from_millis(20)))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert!(kv.get("session.notch").await.unwrap().is_none());
+
+        kv.flush().await.unwrap();
+        assert!(inner.get("session.notch").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_delete_drops_pending() {
+        let (kv, inner) = write_behind().await;
+
+        kv.set("session.notch", "1.2.3.4").await.unwrap();
+        assert_eq!(
+            kv.delete("session.notch").await.unwrap().unwrap(),
+            "1.2.3.4"
+        );
+        assert!(kv.delete("session.notch").await.unwrap().is_none());
+
+        kv.flush().await.unwrap();
+        assert!(inner.get("session.notch").await.unwrap().is_none());
+    }
+}
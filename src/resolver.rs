@@ -0,0 +1,249 @@
+//! Resolution of usernames to the uuid of their Mojang account, used to pin
+//! whitelist entries so that they survive renames.
+
+use crate::{repository::kv::KeyValueRepository, utils::rate_limit::RateLimiter};
+use futures_util::future::BoxFuture;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::time::Duration;
+use uuid::Uuid;
+
+const MOJANG_PROFILE_URL: &str = "https://api.mojang.com/users/profiles/minecraft/";
+
+/// Cached in place of the uuid of usernames that don't belong to any account.
+const NOT_FOUND: &str = "none";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ResolveError {
+    #[error("No Mojang account is named `{0}`")]
+    NotFound(String),
+    #[error("Too many username lookups, try again later")]
+    RateLimited,
+    #[error("Mojang API request failed: {0}")]
+    Http(#[from] reqwest::Error),
+}
+
+/// Resolves usernames to the uuid of the account currently using them.
+///
+/// The future is boxed so that the resolver can be stored as a trait object
+/// and replaced by a mock in tests.
+pub trait UsernameResolver: Send + Sync {
+    fn resolve<'a>(&'a self, username: &'a str) -> BoxFuture<'a, Result<Uuid, ResolveError>>;
+}
+
+#[derive(Deserialize)]
+struct MojangProfile {
+    id: Uuid,
+}
+
+/// Resolves usernames with the Mojang profile API.
+pub struct MojangResolver {
+    client: reqwest::Client,
+}
+
+impl MojangResolver {
+    pub fn new(timeout: Duration) -> Result<Self, reqwest::Error> {
+        let client = reqwest::Client::builder().timeout(timeout).build()?;
+        Ok(Self { client })
+    }
+
+    async fn fetch(&self, username: &str) -> Result<Uuid, ResolveError> {
+        if !is_valid_username(username) {
+            return Err(ResolveError::NotFound(username.into()));
+        }
+
+        let res = self
+            .client
+            .get(format!("{MOJANG_PROFILE_URL}{username}"))
+            .send()
+            .await?;
+
+        match res.status() {
+            StatusCode::NO_CONTENT | StatusCode::NOT_FOUND => {
+                Err(ResolveError::NotFound(username.into()))
+            }
+            StatusCode::TOO_MANY_REQUESTS => Err(ResolveError::RateLimited),
+            _ => {
+                let profile: MojangProfile = res.error_for_status()?.json().await?;
+                Ok(profile.id)
+            }
+        }
+    }
+}
+
+impl UsernameResolver for MojangResolver {
+    #[inline]
+    fn resolve<'a>(&'a self, username: &'a str) -> BoxFuture<'a, Result<Uuid, ResolveError>> {
+        Box::pin(self.fetch(username))
+    }
+}
+
+/// Usernames are 1 to 16 ascii letters, digits or underscores, anything else
+/// can't be resolved and must not reach the request url.
+fn is_valid_username(username: &str) -> bool {
+    (1..=16).contains(&username.len())
+        && username
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'_')
+}
+
+/// Caches the results of another resolver in the key-value repository and
+/// limits how many lookups it receives per minute.
+pub struct CachedResolver<R, KV> {
+    inner: R,
+    kv: KV,
+    ttl: Duration,
+    limiter: RateLimiter,
+}
+
+impl<R: UsernameResolver, KV: KeyValueRepository> CachedResolver<R, KV> {
+    pub fn new(inner: R, kv: KV, ttl: Duration, max_requests_per_minute: u32) -> Self {
+        Self {
+            inner,
+            kv,
+            ttl,
+            limiter: RateLimiter::new(max_requests_per_minute, Duration::from_secs(60)),
+        }
+    }
+
+    async fn cached_resolve(&self, username: &str) -> Result<Uuid, ResolveError> {
+        let key = format!("resolver.username.{}", username.to_lowercase());
+
+        match self.kv.get(&key).await {
+            Ok(Some(v)) if v == NOT_FOUND => return Err(ResolveError::NotFound(username.into())),
+            Ok(Some(v)) => match v.parse() {
+                Ok(uuid) => return Ok(uuid),
+                Err(error) => tracing::warn!(%error, username, "Invalid cached username uuid"),
+            },
+            Ok(None) => {}
+            Err(error) => tracing::warn!(%error, username, "Failed to get cached username uuid"),
+        }
+
+        if !self.limiter.try_acquire() {
+            tracing::warn!(username, "Username lookup rate limited");
+            return Err(ResolveError::RateLimited);
+        }
+
+        let result = self.inner.resolve(username).await;
+
+        let value = match &result {
+            Ok(uuid) => uuid.to_string(),
+            Err(ResolveError::NotFound(_)) => NOT_FOUND.into(),
+            Err(error) => {
+                tracing::warn!(%error, username, "Failed to resolve username");
+                return result;
+            }
+        };
+
+        if let Err(error) = self.kv.set_ttl(&key, &value, Some(self.ttl)).await {
+            tracing::warn!(%error, username, "Failed to cache username uuid");
+        }
+
+        result
+    }
+}
+
+impl<R: UsernameResolver, KV: KeyValueRepository> UsernameResolver for CachedResolver<R, KV> {
+    #[inline]
+    fn resolve<'a>(&'a self, username: &'a str) -> BoxFuture<'a, Result<Uuid, ResolveError>> {
+        Box::pin(self.cached_resolve(username))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{is_valid_username, CachedResolver, ResolveError, UsernameResolver};
+    use crate::repository::kv::SqlxKeyValueRepository;
+    use futures_util::future::BoxFuture;
+    use sqlx::{migrate, Sqlite, SqlitePool};
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+    use uuid::Uuid;
+
+    /// Resolves the usernames in the map, counting every lookup
+    #[derive(Default)]
+    pub struct MockResolver {
+        pub players: HashMap<String, Uuid>,
+        pub lookups: AtomicUsize,
+    }
+
+    impl UsernameResolver for MockResolver {
+        fn resolve<'a>(&'a self, username: &'a str) -> BoxFuture<'a, Result<Uuid, ResolveError>> {
+            self.lookups.fetch_add(1, Ordering::Relaxed);
+
+            let result = self
+                .players
+                .get(username)
+                .copied()
+                .ok_or_else(|| ResolveError::NotFound(username.into()));
+
+            Box::pin(async move { result })
+        }
+    }
+
+    async fn cached(
+        players: &[(&str, Uuid)],
+        max_requests: u32,
+    ) -> CachedResolver<MockResolver, SqlxKeyValueRepository<Sqlite>> {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate!().run(&pool).await.unwrap();
+
+        let mock = MockResolver {
+            players: players.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+            ..Default::default()
+        };
+
+        CachedResolver::new(
+            mock,
+            SqlxKeyValueRepository::new(pool),
+            Duration::from_secs(3600),
+            max_requests,
+        )
+    }
+
+    #[test]
+    fn test_valid_usernames() {
+        assert!(is_valid_username("Notch"));
+        assert!(is_valid_username("jeb_"));
+        assert!(!is_valid_username(""));
+        assert!(!is_valid_username("ThisNameIsTooLong"));
+        assert!(!is_valid_username("../../sessions"));
+    }
+
+    #[tokio::test]
+    async fn test_results_are_cached() {
+        let uuid = Uuid::new_v4();
+        let resolver = cached(&[("Notch", uuid)], 10).await;
+
+        assert_eq!(resolver.resolve("Notch").await.unwrap(), uuid);
+        assert_eq!(resolver.resolve("notch").await.unwrap(), uuid);
+        assert_eq!(resolver.inner.lookups.load(Ordering::Relaxed), 1);
+
+        for _ in 0..2 {
+            let result = resolver.resolve("Herobrine").await;
+            assert!(matches!(result, Err(ResolveError::NotFound(_))));
+        }
+        assert_eq!(resolver.inner.lookups.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_lookups_are_rate_limited() {
+        let resolver = cached(&[], 2).await;
+
+        for name in ["a", "b"] {
+            let result = resolver.resolve(name).await;
+            assert!(matches!(result, Err(ResolveError::NotFound(_))));
+        }
+
+        let result = resolver.resolve("c").await;
+        assert!(matches!(result, Err(ResolveError::RateLimited)));
+
+        // Cached lookups are not limited
+        let result = resolver.resolve("a").await;
+        assert!(matches!(result, Err(ResolveError::NotFound(_))));
+        assert_eq!(resolver.inner.lookups.load(Ordering::Relaxed), 2);
+    }
+}
@@ -1,229 +1,306 @@
+mod connection;
+mod ip_allowlist;
+mod proxy_protocol;
+#[cfg(test)]
+mod tests;
+
+pub use connection::{ConnectionFsm, ConnectionOutcome, PhaseTimeouts};
+pub use ip_allowlist::IpAllowlist;
+
+use connection::Phase;
+
+#[cfg(feature = "geoip")]
+use crate::geoip::GeoFilter;
 use crate::{
-    commands::handler::proxy_command_events,
-    errors::AppError,
+    backend::{route::Router, BackendConnection},
     handler::{
-        handshake::handle_handshake,
-        login::handle_login_start,
-        proxy::{handle_client, handle_server},
-        status::handle_status,
-    },
-    repository::ip_bans::IpBansRepository,
-    state::{ConnectionSharedState, GlobalSharedState},
-    utils::write_packet,
-};
-use minecraft_protocol::{
-    codec::ProtocolState,
-    packet::{
-        handshake::{Handshake, HandshakeServerBoundPacket, NextState},
-        login::{LoginClientBoundPacket, LoginDisconnect, LoginServerBoundPacket, LoginStart},
+        handshake::{HandshakeRejections, HostAllowlist},
+        proxy::RelayOptions,
+        status::StatusOptions,
     },
+    state::GlobalSharedState,
+    utils::{socket::SocketOptions, PacketBudget},
 };
+use minecraft_protocol::data::chat::Message;
 use std::{
     io::{self},
     net::SocketAddr,
+    time::{Duration, Instant},
 };
 use tokio::{
-    net::{lookup_host, TcpStream},
-    sync::mpsc,
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    sync::watch,
 };
+use tokio_util::task::TaskTracker;
 
 pub struct Server {
-    proxied_address: String,
+    router: Router,
+    socket_options: SocketOptions,
     global_state: GlobalSharedState,
+    handshake_rejections: HandshakeRejections,
+    allowed_hosts: Option<HostAllowlist>,
+    /// `None` when connections from any address are accepted
+    ip_allowlist: Option<IpAllowlist>,
+    #[cfg(feature = "geoip")]
+    geo_filter: Option<GeoFilter>,
+    relay: RelayOptions,
+    status: StatusOptions,
+    timeouts: PhaseTimeouts,
+    /// What a client can send between the handshake and the login start, or
+    /// during the status exchange
+    pre_login: PacketBudget,
+    /// Set to the disconnect reason once the server starts shutting down
+    shutdown: watch::Sender<Option<Message>>,
+    connections: TaskTracker,
 }
 
 impl Server {
-    pub fn new(addr: String, global_state: GlobalSharedState) -> Self {
+    pub fn new(
+        router: Router,
+        socket_options: SocketOptions,
+        global_state: GlobalSharedState,
+        allowed_hosts: Option<HostAllowlist>,
+        relay: RelayOptions,
+        timeouts: PhaseTimeouts,
+    ) -> Self {
         Self {
-            proxied_address: addr,
+            router,
+            socket_options,
             global_state,
+            handshake_rejections: HandshakeRejections::new(),
+            allowed_hosts,
+            ip_allowlist: None,
+            #[cfg(feature = "geoip")]
+            geo_filter: None,
+            relay,
+            status: StatusOptions::default(),
+            timeouts,
+            pre_login: PacketBudget::default(),
+            shutdown: watch::Sender::new(None),
+            connections: TaskTracker::new(),
         }
     }
 
-    pub async fn handle_conn(
-        &self,
-        mut incomming: TcpStream,
-        address: SocketAddr,
-    ) -> Result<(), AppError> {
-        let ban = self.global_state.ip_bans.is_banned(address.ip()).await?;
-
-        if let Some(ban) = ban {
-            tracing::info!(
-                reason = ban.reason,
-                banned_at = ?ban.created_at,
-                banned_until = ?ban.expiration,
-                "Connection rejected: IP banned",
-            );
+    /// Rejects the connections from addresses outside of `allowlist`, before
+    /// the IP bans are checked.
+    #[inline]
+    pub fn with_ip_allowlist(mut self, allowlist: IpAllowlist) -> Self {
+        self.ip_allowlist = Some(allowlist);
+        self
+    }
 
-            return Ok(());
-        }
+    /// Rejects the connections from the countries and networks blocked by
+    /// `filter`, checked right after the IP bans.
+    #[cfg(feature = "geoip")]
+    #[inline]
+    pub fn with_geo_filter(mut self, filter: GeoFilter) -> Self {
+        self.geo_filter = Some(filter);
+        self
+    }
 
-        tracing::info!("Incomming connection");
+    /// Sets the optional fields of the status responses.
+    #[inline]
+    pub fn with_status_options(mut self, status: StatusOptions) -> Self {
+        self.status = status;
+        self
+    }
 
-        let handshake = match handle_handshake(&mut incomming).await {
-            Ok(v) => v,
-            Err(error) => {
-                tracing::warn!(%error, "Client didn't send handshake properly");
-                return Ok(());
-            }
-        };
+    /// Limits the packets and bytes a client can send before logging in or
+    /// being served the status, the connection is closed past them.
+    #[inline]
+    pub fn with_pre_login_budget(mut self, budget: PacketBudget) -> Self {
+        self.pre_login = budget;
+        self
+    }
 
-        tracing::debug!(
-            protocol = handshake.protocol_version,
-            next_state = ?handshake.next_state,
-            "Connection finished handshake",
-        );
+    /// Tracks the connection task so that shutdown can wait for it.
+    #[inline]
+    pub fn spawn_connection<F>(&self, task: F)
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        self.connections.spawn(task);
+    }
 
-        tracing::info!("Connection is of {:?} type", handshake.next_state);
+    #[inline]
+    pub fn subscribe_shutdown(&self) -> watch::Receiver<Option<Message>> {
+        self.shutdown.subscribe()
+    }
 
-        match handshake.next_state {
-            NextState::Status => {
-                let _ = handle_status(&self.global_state, &handshake, &mut incomming)
-                    .await
-                    .map_err(|error| {
-                        if !error.is_eof_error() {
-                            tracing::warn!(%error, "Client error on status connection");
-                        }
-                    });
+    /// Stops accepting connections and disconnects the proxied players with
+    /// `reason`, waiting up to `timeout` for their connections to close.
+    pub async fn shutdown(&self, reason: Message, timeout: Duration) {
+        self.shutdown.send_replace(Some(reason));
+        self.connections.close();
 
-                tracing::info!(
-                    protocol = handshake.protocol_version,
-                    "Status connection closed"
-                );
-            }
-            NextState::Login => {
-                if !self.check_protocol_version(handshake.protocol_version) {
-                    let _ = write_packet(
-                        &mut incomming,
-                        &LoginClientBoundPacket::LoginDisconnect(LoginDisconnect {
-                            reason: r#"{"text":"Your minecraft version is not accepted"}"#.into(),
-                        }),
-                    )
-                    .await
-                    .map_err(|error| {
-                        tracing::warn!(%error, "Failed to send login disconnect message");
-                    });
-
-                    tracing::info!(
-                        protocol = handshake.protocol_version,
-                        "Connection closed: invalid protocol version"
-                    );
-                } else {
-                    let login_start =
-                        match handle_login_start(&self.global_state, &mut incomming).await {
-                            Ok(Some(v)) => v,
-                            _ => {
-                                tracing::info!(
-                                    protocol = handshake.protocol_version,
-                                    "Connection closed during login start",
-                                );
-                                return Ok(());
-                            }
-                        };
-
-                    self.handle_proxy(incomming, login_start, handshake).await?;
-                }
-            }
+        let remaining = self.connections.len();
+        if remaining > 0 {
+            tracing::info!(remaining, "Waiting for connections to close");
         }
 
-        Ok(())
+        if tokio::time::timeout(timeout, self.connections.wait())
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                remaining = self.connections.len(),
+                "Connections didn't close in time, dropping them",
+            );
+        }
     }
 
-    pub async fn handle_proxy(
+    /// Drives the connection until it closes, returning how it ended.
+    #[inline]
+    pub async fn handle_conn<S: AsyncRead + AsyncWrite + Unpin + Send>(
         &self,
-        mut incomming: TcpStream,
-        login_start: LoginStart,
-        handshake: Handshake,
-    ) -> Result<(), AppError> {
-        let mut srv = self.connect_to_server().await?;
-
-        let result1 = write_packet(
-            &mut srv,
-            &HandshakeServerBoundPacket::Handshake(handshake.clone()),
-        )
-        .await
-        .map_err(|error| {
-            tracing::error!(%error, "Failed to send handshake packet to proxied server");
-        });
-
-        let result2 = write_packet(&mut srv, &LoginServerBoundPacket::LoginStart(login_start))
-            .await
-            .map_err(|error| {
-                tracing::error!(%error, "Failed to send login start packt to proxied server");
-            });
-
-        if result1.is_err() || result2.is_err() {
-            tracing::info!(protocol = handshake.protocol_version, "Connection closed");
-            return Ok(());
-        }
+        incomming: S,
+        address: SocketAddr,
+    ) -> ConnectionOutcome {
+        self.serve(incomming, address, false).await
+    }
 
-        let (srv_read, srv_write) = srv.split();
-        let (client_read, client_write) = incomming.split();
+    /// Like [`handle_conn`](Self::handle_conn), for the listeners whose
+    /// connections start with a PROXY protocol header carrying the client
+    /// address. Connections without it are rejected.
+    #[inline]
+    pub async fn handle_proxied_conn<S: AsyncRead + AsyncWrite + Unpin + Send>(
+        &self,
+        incomming: S,
+        address: SocketAddr,
+    ) -> ConnectionOutcome {
+        self.serve(incomming, address, true).await
+    }
 
-        let state = ConnectionSharedState::new(handshake.protocol_version);
-        state.set_state(ProtocolState::Login).await;
+    async fn serve<S: AsyncRead + AsyncWrite + Unpin + Send>(
+        &self,
+        mut incomming: S,
+        address: SocketAddr,
+        proxy_protocol: bool,
+    ) -> ConnectionOutcome {
+        let started_at = Instant::now();
+        let address = if proxy_protocol {
+            self.client_address(&mut incomming, address).await
+        } else {
+            Ok(address)
+        };
+        let outcome = match address {
+            Ok(address) => ConnectionFsm::new(self, incomming, address).run().await,
+            Err(outcome) => outcome,
+        };
 
-        let (request_sender, request_receiver) = mpsc::channel(3);
-        let (response_sender, response_receiver) = mpsc::channel(3);
+        self.global_state
+            .stats
+            .record_connection(outcome.close_reason());
 
-        tokio::select! {
-            r = handle_server(&self.global_state, &state, request_sender, srv_read, client_write) => {
-                if let Err(error) = r {
-                    if !error.is_eof_error() {
-                        tracing::warn!(%error, "Server error");
-                    }
-                }
+        let elapsed_ms = started_at.elapsed().as_millis() as u64;
+        match &outcome {
+            ConnectionOutcome::Failed(..) => {
+                tracing::warn!(%outcome, elapsed_ms, "Connection closed");
             }
-            r = handle_client(&state, response_receiver, client_read, srv_write) => {
-                if let Err(error) = r {
-                    if !error.is_eof_error() {
-                        tracing::warn!(%error, "Client error");
-                    }
-                }
+            ConnectionOutcome::Relayed {
+                username, traffic, ..
+            } => {
+                tracing::info!(
+                    %outcome,
+                    username,
+                    bytes_to_client = traffic.to_client,
+                    bytes_to_backend = traffic.to_backend,
+                    elapsed_ms,
+                    "Connection closed",
+                );
             }
-            _ = proxy_command_events(&self.global_state, request_receiver, response_sender) => {}
-        }
-
-        match state.login_username().await {
-            Some(username) => {
-                self.global_state.remove_online_player(&username).await;
+            ConnectionOutcome::Kicked {
+                username,
+                reason,
+                traffic,
+            } => {
                 tracing::info!(
+                    %outcome,
                     username,
-                    protocol = state.protocol_version,
-                    "Connection closed"
+                    reason,
+                    bytes_to_client = traffic.to_client,
+                    bytes_to_backend = traffic.to_backend,
+                    elapsed_ms,
+                    "Connection closed",
                 );
             }
-            None => {
-                tracing::info!(protocol = state.protocol_version, "Connection closed");
+            _ if outcome.is_noise() => {
+                tracing::debug!(%outcome, elapsed_ms, "Connection closed");
+            }
+            _ => {
+                tracing::info!(%outcome, elapsed_ms, "Connection closed");
             }
         }
 
-        Ok(())
+        outcome
     }
 
-    fn check_protocol_version(&self, protocol_version: i32) -> bool {
-        protocol_version == 765
+    /// The address of the client, read from the PROXY protocol header,
+    /// `address` being the one of the socket.
+    async fn client_address<S: AsyncRead + Unpin>(
+        &self,
+        incomming: &mut S,
+        address: SocketAddr,
+    ) -> Result<SocketAddr, ConnectionOutcome> {
+        let header = tokio::time::timeout(
+            self.timeouts.handshake,
+            proxy_protocol::read_proxy_header(incomming),
+        )
+        .await
+        .map_err(|_| ConnectionOutcome::TimedOut(Phase::Handshaking))?;
+
+        match header {
+            Ok(client) => {
+                let client = client.unwrap_or(address);
+                tracing::Span::current().record("client", tracing::field::display(client));
+                Ok(client)
+            }
+            Err(error) => {
+                tracing::warn!(%error, "Connection rejected: invalid PROXY protocol header");
+                Err(ConnectionOutcome::InvalidProxyHeader)
+            }
+        }
+    }
+
+    #[inline]
+    pub fn global_state(&self) -> &GlobalSharedState {
+        &self.global_state
+    }
+
+    #[inline]
+    pub fn socket_options(&self) -> &SocketOptions {
+        &self.socket_options
     }
 
-    async fn resolve_dns(&self) -> Result<SocketAddr, io::Error> {
-        lookup_host(&self.proxied_address)
-            .await?
-            .next()
-            .ok_or(io::Error::new(
-                io::ErrorKind::ConnectionRefused,
-                "Failed to resolve proxied server address",
-            ))
+    #[inline]
+    pub fn router(&self) -> &Router {
+        &self.router
     }
 
-    async fn connect_to_server(&self) -> Result<TcpStream, io::Error> {
-        let host = self.resolve_dns().await.map_err(|error| {
-            tracing::error!(%error, "Failed to resolve proxied server address");
-            error
-        })?;
+    /// Connects to the `backend` players were transferred to, to the backend
+    /// of the route resolved from the `host` otherwise. Sticky routes pick the
+    /// backend from the `username` of the player.
+    async fn connect_to_server(
+        &self,
+        host: &str,
+        username: &str,
+        backend: Option<&str>,
+    ) -> Result<(TcpStream, BackendConnection<'_>), io::Error> {
+        if let Some(address) = backend {
+            match self.router.backend(address) {
+                Some(backend) => {
+                    tracing::debug!(address, "Connecting to the backend of the transfer");
+                    let stream = backend.pool().get().await?;
+                    return Ok((stream, backend.track_connection()));
+                }
+                None => tracing::warn!(address, "Transferred to an unknown backend"),
+            }
+        }
 
-        TcpStream::connect(host).await.map_err(|error| {
-            tracing::error!(%error, "Failed to connect to proxied server");
-            error
-        })
+        let route = self.router.resolve(host);
+        self.router
+            .connect(route, &self.global_state.backend_health, username)
+            .await
     }
 }
@@ -0,0 +1,1202 @@
+//! The lifecycle of a client connection as an explicit state machine:
+//!
+//! ```text
+//! Handshaking ─┬─> StatusLoop
+//!              └─> LoginStart ─> Relaying
+//! ```
+//!
+//! Every phase but the relaying itself has a deadline, and every connection
+//! ends with a [`ConnectionOutcome`] used for the access log and metrics.
+
+use super::Server;
+use crate::{
+    errors::AppError,
+    handler::{
+        handshake::{
+            handle_handshake, normalize_host, HandshakeError, HostAllowlist, HostRejection,
+        },
+        login::{handle_login_start, LoginRejection},
+        proxy::{handle_client, handle_server, ServerEnd},
+        queue::handle_queue,
+        status::handle_status,
+    },
+    repository::ip_bans::{IpBanData, IpBansRepository},
+    state::{ConnectionSharedState, OnlinePlayerGuard},
+    utils::{counting::CountingWriter, write_packet},
+};
+use minecraft_protocol::{
+    codec::{client::ClientPacketCodec, server::ServerPacketCodec, ProtocolState},
+    packet::{
+        handshake::{Handshake, HandshakeServerBoundPacket, NextState},
+        login::{LoginClientBoundPacket, LoginDisconnect, LoginStart, VersionedLoginStart},
+    },
+};
+use std::{fmt, net::SocketAddr, sync::atomic::Ordering, time::Duration};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Handshaking,
+    StatusLoop,
+    LoginStart,
+    Relaying,
+}
+
+impl Phase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Handshaking => "handshaking",
+            Phase::StatusLoop => "status_loop",
+            Phase::LoginStart => "login_start",
+            Phase::Relaying => "relaying",
+        }
+    }
+}
+
+/// The deadlines of each phase. Relaying has no deadline once the backend
+/// connection is established, `backend_connect` only bounds opening it.
+#[derive(Debug, Clone, Copy)]
+pub struct PhaseTimeouts {
+    pub handshake: Duration,
+    pub status: Duration,
+    pub login_start: Duration,
+    pub backend_connect: Duration,
+}
+
+impl Default for PhaseTimeouts {
+    fn default() -> Self {
+        Self {
+            handshake: crate::handler::handshake::HANDSHAKE_TIMEOUT,
+            status: Duration::from_secs(10),
+            login_start: Duration::from_secs(10),
+            backend_connect: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConnectionOutcome {
+    /// The connection didn't start with the expected PROXY protocol header
+    InvalidProxyHeader,
+    /// The client address is not in the IP allowlist
+    IpNotAllowed,
+    /// The client IP is banned, only checked for logins so that banned
+    /// players are told the reason
+    IpBanned,
+    /// The client address is in a blocked country or network
+    #[cfg(feature = "geoip")]
+    GeoBlocked,
+    InvalidHandshake,
+    HostRejected(HostRejection),
+    StatusServed,
+    UnsupportedVersion,
+    /// The client was disconnected during the login start
+    LoginRejected(LoginRejection),
+    BackendUnavailable,
+    /// The client left the queue, or was disconnected from it by the shutdown
+    LeftQueue,
+    /// The connection was proxied until either side, or the proxy, closed it
+    Relayed {
+        username: Option<String>,
+        closed_by: ClosedBy,
+        traffic: Traffic,
+    },
+    /// The connection was proxied until the backend, or a kick, disconnected
+    /// the client with a reason
+    Kicked {
+        username: Option<String>,
+        /// The chat component as plain text
+        reason: String,
+        traffic: Traffic,
+    },
+    /// The client closed the connection before being relayed
+    ClientLeft(Phase),
+    TimedOut(Phase),
+    Failed(Phase, AppError),
+}
+
+/// What closed a relayed connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClosedBy {
+    Client,
+    Backend,
+    /// The proxy disconnected the client as it shut down
+    Shutdown,
+    /// The client was transferred to another backend, or told to reconnect
+    Transfer,
+}
+
+/// The bytes relayed in each direction by a proxied connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Traffic {
+    pub to_client: u64,
+    pub to_backend: u64,
+}
+
+impl ConnectionOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionOutcome::InvalidProxyHeader => "invalid_proxy_header",
+            ConnectionOutcome::IpNotAllowed => "ip_not_allowed",
+            ConnectionOutcome::IpBanned => "ip_banned",
+            #[cfg(feature = "geoip")]
+            ConnectionOutcome::GeoBlocked => "geo_blocked",
+            ConnectionOutcome::InvalidHandshake => "invalid_handshake",
+            ConnectionOutcome::HostRejected(_) => "host_rejected",
+            ConnectionOutcome::StatusServed => "status_served",
+            ConnectionOutcome::UnsupportedVersion => "unsupported_version",
+            ConnectionOutcome::LoginRejected(_) => "login_rejected",
+            ConnectionOutcome::BackendUnavailable => "backend_unavailable",
+            ConnectionOutcome::LeftQueue => "left_queue",
+            ConnectionOutcome::Relayed { .. } => "relayed",
+            ConnectionOutcome::Kicked { .. } => "kicked",
+            ConnectionOutcome::ClientLeft(_) => "client_left",
+            ConnectionOutcome::TimedOut(_) => "timed_out",
+            ConnectionOutcome::Failed(..) => "failed",
+        }
+    }
+
+    /// A finer category than [`ConnectionOutcome::as_str`], telling apart why
+    /// logins were rejected and what closed relayed connections. Used to
+    /// categorize disconnects in the metrics.
+    pub fn close_reason(&self) -> &'static str {
+        match self {
+            ConnectionOutcome::LoginRejected(rejection) => rejection.as_str(),
+            ConnectionOutcome::Relayed { closed_by, .. } => match closed_by {
+                ClosedBy::Client => "client_eof",
+                ClosedBy::Backend => "backend_eof",
+                ClosedBy::Shutdown => "shutdown",
+                ClosedBy::Transfer => "transferred",
+            },
+            _ => self.as_str(),
+        }
+    }
+
+    /// Whether the connection likely didn't come from a minecraft client,
+    /// or from an address that was never meant to connect, these are not
+    /// worth an access log line.
+    pub fn is_noise(&self) -> bool {
+        matches!(
+            self,
+            ConnectionOutcome::IpNotAllowed
+                | ConnectionOutcome::InvalidHandshake
+                | ConnectionOutcome::TimedOut(Phase::Handshaking)
+        )
+    }
+}
+
+impl fmt::Display for ConnectionOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionOutcome::HostRejected(cause) => {
+                write!(f, "host_rejected({})", cause.as_str())
+            }
+            ConnectionOutcome::LoginRejected(_) | ConnectionOutcome::Relayed { .. } => {
+                write!(f, "{}({})", self.as_str(), self.close_reason())
+            }
+            ConnectionOutcome::ClientLeft(phase) => {
+                write!(f, "client_left({})", phase.as_str())
+            }
+            ConnectionOutcome::TimedOut(phase) => write!(f, "timed_out({})", phase.as_str()),
+            ConnectionOutcome::Failed(phase, error) => {
+                write!(f, "failed({}): {error}", phase.as_str())
+            }
+            _ => f.write_str(self.as_str()),
+        }
+    }
+}
+
+enum State {
+    Handshaking,
+    StatusLoop(Handshake),
+    LoginStart(Handshake),
+    Relaying(Handshake, LoginStart),
+}
+
+impl State {
+    fn phase(&self) -> Phase {
+        match self {
+            State::Handshaking => Phase::Handshaking,
+            State::StatusLoop(_) => Phase::StatusLoop,
+            State::LoginStart(_) => Phase::LoginStart,
+            State::Relaying(..) => Phase::Relaying,
+        }
+    }
+}
+
+enum Transition {
+    Next(State),
+    Done(ConnectionOutcome),
+}
+
+pub struct ConnectionFsm<'a, S> {
+    server: &'a Server,
+    stream: S,
+    address: SocketAddr,
+    /// The username reserved by the login start, handed over to the relay or
+    /// released when the connection ends whatever the outcome is
+    reserved: Option<OnlinePlayerGuard>,
+}
+
+impl<'a, S: AsyncRead + AsyncWrite + Unpin + Send> ConnectionFsm<'a, S> {
+    pub fn new(server: &'a Server, stream: S, address: SocketAddr) -> Self {
+        Self {
+            server,
+            stream,
+            address,
+            reserved: None,
+        }
+    }
+
+    pub async fn run(mut self) -> ConnectionOutcome {
+        let outcome = self.drive().await;
+
+        if let Some(guard) = self.reserved.take() {
+            self.server.global_state.release_online_player(guard).await;
+        }
+
+        outcome
+    }
+
+    async fn drive(&mut self) -> ConnectionOutcome {
+        let mut state = State::Handshaking;
+
+        loop {
+            let phase = state.phase();
+
+            let result = match state {
+                State::Handshaking => self.handshaking().await,
+                State::StatusLoop(handshake) => self.status_loop(handshake).await,
+                State::LoginStart(handshake) => self.login_start(handshake).await,
+                State::Relaying(handshake, login_start) => {
+                    self.relaying(handshake, login_start).await
+                }
+            };
+
+            state = match result {
+                Ok(Transition::Next(next)) => next,
+                Ok(Transition::Done(outcome)) => return outcome,
+                Err(AppError::Timeout) => return ConnectionOutcome::TimedOut(phase),
+                Err(error) if error.is_eof_error() => return ConnectionOutcome::ClientLeft(phase),
+                Err(error) => return ConnectionOutcome::Failed(phase, error),
+            };
+        }
+    }
+
+    async fn handshaking(&mut self) -> Result<Transition, AppError> {
+        let ip = self.address.ip();
+
+        if let Some(allowlist) = &self.server.ip_allowlist {
+            if !allowlist.contains(ip) {
+                tracing::debug!("Connection rejected: IP not allowed");
+                return Ok(Transition::Done(ConnectionOutcome::IpNotAllowed));
+            }
+        }
+
+        #[cfg(feature = "geoip")]
+        if let Some(filter) = &self.server.geo_filter {
+            if let Some(rejection) = filter.check(ip) {
+                tracing::info!(blocked = %rejection, "Connection rejected: blocked network");
+                return Ok(Transition::Done(ConnectionOutcome::GeoBlocked));
+            }
+        }
+
+        tracing::debug!("Incomming connection");
+
+        let timeout = self.server.timeouts.handshake;
+        let handshake = match handle_handshake(&mut self.stream, timeout).await {
+            Ok(v) => v,
+            Err(error) if error.is_noise() => {
+                if let Some(count) = self.server.handshake_rejections.record(ip) {
+                    tracing::debug!(%error, count, "Rejected connection without handshake");
+                }
+
+                return Ok(Transition::Done(match error {
+                    HandshakeError::Timeout => ConnectionOutcome::TimedOut(Phase::Handshaking),
+                    _ => ConnectionOutcome::InvalidHandshake,
+                }));
+            }
+            Err(error) => {
+                tracing::warn!(%error, "Client didn't send handshake properly");
+                return Ok(Transition::Done(ConnectionOutcome::InvalidHandshake));
+            }
+        };
+
+        tracing::debug!(
+            protocol = handshake.protocol_version,
+            next_state = ?handshake.next_state,
+            "Connection finished handshake",
+        );
+
+        if let Some(allowlist) = &self.server.allowed_hosts {
+            if let Err(cause) = allowlist.check(&handshake.server_addr, handshake.server_port) {
+                self.reject_host(&handshake, allowlist, cause).await;
+                return Ok(Transition::Done(ConnectionOutcome::HostRejected(cause)));
+            }
+        }
+
+        // Only logins are checked against the IP bans, which keeps the
+        // database out of status pings but lets banned IPs read the MOTD. It
+        // also lets the banned players be told why they can't join.
+        if let NextState::Login | NextState::Transfer = handshake.next_state {
+            let global_state = &self.server.global_state;
+            let ban = global_state
+                .database_health
+                .check_ban(global_state.ip_bans.is_banned(ip).await)?;
+
+            if let Some(ban) = ban {
+                tracing::info!(
+                    reason = ban.reason,
+                    banned_at = ?ban.created_at,
+                    banned_until = ?ban.expiration,
+                    "Connection rejected: IP banned",
+                );
+
+                self.reject_banned(&ban).await;
+                return Ok(Transition::Done(ConnectionOutcome::IpBanned));
+            }
+        }
+
+        tracing::info!("Connection is of {:?} type", handshake.next_state);
+
+        // Transfers are logins as well, the handshake is relayed unchanged so
+        // the backend decides whether it accepts them
+        Ok(Transition::Next(match handshake.next_state {
+            NextState::Status => State::StatusLoop(handshake),
+            NextState::Login | NextState::Transfer => State::LoginStart(handshake),
+        }))
+    }
+
+    /// Disconnects logins with the configured message, status requests are
+    /// dropped silently since they mostly come from scanners.
+    async fn reject_host(
+        &mut self,
+        handshake: &Handshake,
+        allowlist: &HostAllowlist,
+        cause: HostRejection,
+    ) {
+        match handshake.next_state {
+            NextState::Status => {
+                let ip = self.address.ip();
+                if let Some(count) = self.server.handshake_rejections.record(ip) {
+                    tracing::debug!(
+                        host = handshake.server_addr,
+                        port = handshake.server_port,
+                        cause = cause.as_str(),
+                        count,
+                        "Status connection rejected: host not allowed",
+                    );
+                }
+            }
+            NextState::Login | NextState::Transfer => {
+                tracing::info!(
+                    host = handshake.server_addr,
+                    port = handshake.server_port,
+                    cause = cause.as_str(),
+                    "Login connection rejected: host not allowed",
+                );
+
+                let packet = LoginClientBoundPacket::LoginDisconnect(LoginDisconnect {
+                    reason: allowlist.rejected_message().clone(),
+                });
+                let _ = write_packet(&mut self.stream, &packet)
+                    .await
+                    .map_err(|error| {
+                        tracing::warn!(%error, "Failed to send login disconnect message");
+                    });
+            }
+        }
+    }
+
+    async fn reject_banned(&mut self, ban: &IpBanData) {
+        let packet = LoginClientBoundPacket::LoginDisconnect(LoginDisconnect {
+            reason: self.server.global_state.messages.banned(ban),
+        });
+        let _ = write_packet(&mut self.stream, &packet)
+            .await
+            .map_err(|error| {
+                tracing::warn!(%error, "Failed to send login disconnect message");
+            });
+    }
+
+    async fn status_loop(&mut self, handshake: Handshake) -> Result<Transition, AppError> {
+        let status = handle_status(
+            &self.server.global_state,
+            &self.server.status,
+            &handshake,
+            &mut self.stream,
+            self.server.pre_login,
+        );
+
+        match tokio::time::timeout(self.server.timeouts.status, status).await {
+            Err(_) => Err(AppError::Timeout),
+            Ok(Err(error)) if !error.is_eof_error() => Err(error.into()),
+            Ok(_) => {
+                self.server.global_state.stats.record_status_ping();
+                Ok(Transition::Done(ConnectionOutcome::StatusServed))
+            }
+        }
+    }
+
+    async fn login_start(&mut self, handshake: Handshake) -> Result<Transition, AppError> {
+        let route = self.server.router.resolve(&handshake.server_addr);
+
+        if !route.accepts_version(handshake.protocol_version) {
+            tracing::info!(
+                host = handshake.server_addr,
+                protocol = handshake.protocol_version,
+                "Login connection rejected: protocol version not accepted",
+            );
+
+            let message = route
+                .version_rejected_message()
+                .unwrap_or(&self.server.global_state.messages.version_rejected);
+            let _ = write_packet(
+                &mut self.stream,
+                &LoginClientBoundPacket::LoginDisconnect(LoginDisconnect {
+                    reason: message.clone(),
+                }),
+            )
+            .await
+            .map_err(|error| {
+                tracing::warn!(%error, "Failed to send login disconnect message");
+            });
+
+            return Ok(Transition::Done(ConnectionOutcome::UnsupportedVersion));
+        }
+
+        let login_start = handle_login_start(
+            &self.server.global_state,
+            &mut self.stream,
+            self.address.ip(),
+            handshake.protocol_version,
+            self.server.timeouts.login_start,
+            self.server.pre_login,
+        )
+        .await?;
+
+        match login_start {
+            Ok((login_start, guard)) => {
+                tracing::Span::current().record("username", &login_start.name);
+                self.reserved = Some(guard);
+                Ok(Transition::Next(State::Relaying(handshake, login_start)))
+            }
+            Err(rejection) => Ok(Transition::Done(ConnectionOutcome::LoginRejected(
+                rejection,
+            ))),
+        }
+    }
+
+    /// Tells the client, still in the login state, that the backend can't be
+    /// reached instead of just closing the connection.
+    async fn disconnect_backend_unavailable(&mut self) {
+        let packet = LoginClientBoundPacket::LoginDisconnect(LoginDisconnect {
+            reason: self
+                .server
+                .global_state
+                .messages
+                .backend_unavailable
+                .clone(),
+        });
+        let _ = write_packet(&mut self.stream, &packet)
+            .await
+            .map_err(|error| {
+                tracing::warn!(%error, "Failed to send login disconnect message");
+            });
+    }
+
+    async fn relaying(
+        &mut self,
+        mut handshake: Handshake,
+        login_start: LoginStart,
+    ) -> Result<Transition, AppError> {
+        // Held until the relay ends
+        let _slot = match &self.server.global_state.queue {
+            Some(queue) => {
+                let slot = handle_queue(
+                    queue,
+                    &mut self.stream,
+                    handshake.protocol_version,
+                    self.server.subscribe_shutdown(),
+                    self.server.timeouts.login_start,
+                )
+                .await?;
+
+                match slot {
+                    Some(v) => Some(v),
+                    None => return Ok(Transition::Done(ConnectionOutcome::LeftQueue)),
+                }
+            }
+            None => None,
+        };
+
+        let transfer = self
+            .server
+            .global_state
+            .player_actions
+            .take_transfer(&login_start.name);
+        // Transfers of the proxy are regular logins for the backend, which
+        // may not accept transfers
+        if transfer.is_some() && matches!(handshake.next_state, NextState::Transfer) {
+            handshake.next_state = NextState::Login;
+        }
+        let connect = self.server.connect_to_server(
+            &handshake.server_addr,
+            &login_start.name,
+            transfer.as_deref(),
+        );
+
+        let (mut srv, backend) =
+            match tokio::time::timeout(self.server.timeouts.backend_connect, connect).await {
+                Ok(Ok(v)) => v,
+                Ok(Err(error)) => {
+                    tracing::warn!(%error, "Failed to connect to proxied server");
+                    self.disconnect_backend_unavailable().await;
+                    return Ok(Transition::Done(ConnectionOutcome::BackendUnavailable));
+                }
+                Err(_) => {
+                    self.disconnect_backend_unavailable().await;
+                    return Err(AppError::Timeout);
+                }
+            };
+
+        let result1 = write_packet(
+            &mut srv,
+            &HandshakeServerBoundPacket::Handshake(handshake.clone()),
+        )
+        .await
+        .map_err(|error| {
+            tracing::error!(%error, "Failed to send handshake packet to proxied server");
+        });
+
+        // Same version as the client, so the backend expects the same shape
+        let packet = VersionedLoginStart {
+            login_start: &login_start,
+            protocol_version: handshake.protocol_version,
+        };
+        let result2 = write_packet(&mut srv, &packet).await.map_err(|error| {
+            tracing::error!(%error, "Failed to send login start packt to proxied server");
+        });
+
+        if result1.is_err() || result2.is_err() {
+            self.disconnect_backend_unavailable().await;
+            return Ok(Transition::Done(ConnectionOutcome::BackendUnavailable));
+        }
+
+        let (srv_read, srv_write) = srv.split();
+        let (client_read, client_write) = tokio::io::split(&mut self.stream);
+
+        let state = ConnectionSharedState::new(handshake.protocol_version)
+            .with_transfer_address(
+                normalize_host(&handshake.server_addr),
+                handshake.server_port,
+            )
+            .with_reservation(self.reserved.take());
+        state.set_state(ProtocolState::Login);
+
+        let global_state = &self.server.global_state;
+        let (connection_id, response_receiver) =
+            global_state.command_dispatcher.register(backend.address());
+        let actions = global_state.player_actions.register(&login_start.name);
+
+        let bytes_proxied = global_state.stats.bytes_proxied();
+        let client_write = CountingWriter::new(
+            CountingWriter::new(client_write, &state.bytes_to_client),
+            bytes_proxied,
+        );
+        let srv_write = CountingWriter::new(
+            CountingWriter::new(srv_write, &state.bytes_to_backend),
+            bytes_proxied,
+        );
+
+        let closed_by = tokio::select! {
+            r = handle_server(
+                global_state,
+                &state,
+                ServerPacketCodec::new(),
+                &self.server.relay,
+                connection_id,
+                self.server.subscribe_shutdown(),
+                actions,
+                srv_read,
+                client_write,
+            ) => match r {
+                Ok(ServerEnd::Closed) => ClosedBy::Backend,
+                Ok(ServerEnd::Shutdown) => ClosedBy::Shutdown,
+                Ok(ServerEnd::Transferred) => ClosedBy::Transfer,
+                Err(error) => {
+                    if !error.is_eof_error() {
+                        tracing::warn!(%error, "Server error");
+                    }
+                    ClosedBy::Backend
+                }
+            },
+            r = handle_client(
+                &state,
+                ClientPacketCodec::new(),
+                &self.server.relay,
+                response_receiver,
+                client_read,
+                srv_write,
+            ) => {
+                if let Err(error) = r {
+                    if !error.is_eof_error() {
+                        tracing::warn!(%error, "Client error");
+                    }
+                }
+                ClosedBy::Client
+            }
+        };
+
+        global_state.command_dispatcher.unregister(connection_id);
+        global_state.player_actions.unregister(&login_start.name);
+        tracing::debug!(protocol = state.protocol_version, "Relay finished");
+
+        // Still held when the backend didn't accept the login
+        if let Some(guard) = state.take_reservation() {
+            global_state.release_online_player(guard).await;
+        }
+
+        let login_info = state.login_info.write().await.take();
+        let username = login_info.as_ref().map(|v| v.username.clone());
+
+        if let Some(login_info) = login_info {
+            let username = &login_info.username;
+            global_state.release_online_player(login_info.guard).await;
+
+            if let Some(session_lock) = &global_state.session_lock {
+                if let Err(error) = session_lock
+                    .record_disconnect(username, self.address.ip())
+                    .await
+                {
+                    tracing::warn!(%error, "Failed to record session lock");
+                }
+            }
+        }
+
+        let traffic = Traffic {
+            to_client: state.bytes_to_client.load(Ordering::Relaxed),
+            to_backend: state.bytes_to_backend.load(Ordering::Relaxed),
+        };
+        let outcome = match state.disconnect_reason.write().await.take() {
+            Some(reason) => ConnectionOutcome::Kicked {
+                username,
+                reason: reason.to_plain_text(),
+                traffic,
+            },
+            None => ConnectionOutcome::Relayed {
+                username,
+                closed_by,
+                traffic,
+            },
+        };
+
+        Ok(Transition::Done(outcome))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClosedBy, ConnectionOutcome, Phase, PhaseTimeouts, Traffic};
+    use crate::{
+        backend::{
+            pool::BackendPool,
+            route::{Route, Router},
+            Backend,
+        },
+        handler::login::LoginRejection,
+        repository::{
+            health::BanCheckFailure, ip_bans::IpBansRepository, user_bans::UserBansRepository,
+        },
+        server::{IpAllowlist, Server},
+        state::{test_global_state, test_global_state_with_pool},
+        utils::{read_packet, socket::SocketOptions, write_packet},
+    };
+    use minecraft_protocol::{
+        client::ping_stream,
+        data::chat::{Message, Payload},
+        decoder::Decoder,
+        packet::{
+            handshake::{Handshake, HandshakeServerBoundPacket, NextState},
+            login::{LoginClientBoundPacket, LoginDisconnect, LoginServerBoundPacket, LoginStart},
+        },
+    };
+    use sqlx::{migrate, SqlitePool};
+    use std::{io::Cursor, net::SocketAddr, time::Duration};
+    use tokio::{
+        io::{duplex, AsyncWriteExt, DuplexStream},
+        net::TcpListener,
+    };
+    use uuid::Uuid;
+
+    const SHORT: Duration = Duration::from_millis(50);
+
+    async fn server(backend: &str, timeouts: PhaseTimeouts) -> Server {
+        let options = SocketOptions {
+            nodelay: true,
+            keepalive: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        };
+        let backends = vec![Backend::new(BackendPool::new(
+            backend.into(),
+            0,
+            Duration::ZERO,
+            options,
+        ))];
+        let router = Router::new(
+            backends,
+            Vec::new(),
+            Route::new(Vec::new(), vec![0], Default::default()),
+        );
+
+        Server::new(
+            router,
+            options,
+            test_global_state().await,
+            None,
+            Default::default(),
+            timeouts,
+        )
+    }
+
+    fn address() -> SocketAddr {
+        "127.0.0.1:50000".parse().unwrap()
+    }
+
+    async fn send_handshake(client: &mut DuplexStream, next_state: NextState) {
+        let packet = HandshakeServerBoundPacket::Handshake(Handshake {
+            protocol_version: 765,
+            server_addr: "localhost".into(),
+            server_port: 25565,
+            next_state,
+        });
+        write_packet(client, &packet).await.unwrap();
+    }
+
+    async fn send_login_start(client: &mut DuplexStream, name: &str) {
+        let packet = LoginServerBoundPacket::LoginStart(LoginStart {
+            name: name.into(),
+            uuid: Uuid::new_v4(),
+        });
+        write_packet(client, &packet).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_status_happy_path() {
+        let srv = server("127.0.0.1:1", PhaseTimeouts::default()).await;
+        let (client, conn) = duplex(4096);
+
+        let (outcome, ping) = tokio::join!(
+            srv.handle_conn(conn, address()),
+            ping_stream(client, "localhost", 25565, 765),
+        );
+
+        ping.unwrap();
+        assert!(matches!(outcome, ConnectionOutcome::StatusServed));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_protocol_address() {
+        let srv = server("127.0.0.1:1", PhaseTimeouts::default()).await;
+        srv.global_state
+            .ip_bans
+            .add_ban("203.0.113.7".parse().unwrap(), None, None)
+            .await
+            .unwrap();
+
+        // The socket address isn't banned, but the client one is
+        let (mut client, conn) = duplex(4096);
+        client
+            .write_all(b"PROXY TCP4 203.0.113.7 127.0.0.1 51234 25565\r\n")
+            .await
+            .unwrap();
+        send_handshake(&mut client, NextState::Login).await;
+
+        let outcome = srv.handle_proxied_conn(conn, address()).await;
+        assert!(matches!(outcome, ConnectionOutcome::IpBanned));
+
+        let (mut client, conn) = duplex(4096);
+        send_handshake(&mut client, NextState::Status).await;
+
+        let outcome = srv.handle_proxied_conn(conn, address()).await;
+        assert!(matches!(outcome, ConnectionOutcome::InvalidProxyHeader));
+    }
+
+    #[tokio::test]
+    async fn test_ip_allowlist() {
+        let srv = server("127.0.0.1:1", PhaseTimeouts::default())
+            .await
+            .with_ip_allowlist(IpAllowlist::new(["10.0.0.0/8".parse().unwrap()]));
+
+        let (mut client, conn) = duplex(4096);
+        send_handshake(&mut client, NextState::Status).await;
+
+        let outcome = srv.handle_conn(conn, address()).await;
+        assert!(matches!(outcome, ConnectionOutcome::IpNotAllowed));
+
+        let (client, conn) = duplex(4096);
+        let (outcome, ping) = tokio::join!(
+            srv.handle_conn(conn, "10.0.0.1:50000".parse().unwrap()),
+            ping_stream(client, "localhost", 25565, 765),
+        );
+
+        ping.unwrap();
+        assert!(matches!(outcome, ConnectionOutcome::StatusServed));
+    }
+
+    #[tokio::test]
+    async fn test_ip_banned_login_is_disconnected() {
+        let srv = server("127.0.0.1:1", PhaseTimeouts::default()).await;
+        srv.global_state
+            .ip_bans
+            .add_ban(address().ip(), None, Some("Griefing".into()))
+            .await
+            .unwrap();
+
+        let (mut client, conn) = duplex(4096);
+        send_handshake(&mut client, NextState::Login).await;
+        send_login_start(&mut client, "Notch").await;
+
+        let outcome = srv.handle_conn(conn, address()).await;
+        assert!(matches!(outcome, ConnectionOutcome::IpBanned));
+
+        let vec = read_packet(&mut client, false).await.unwrap().unwrap();
+        let packet = LoginClientBoundPacket::decode(&mut Cursor::new(vec)).unwrap();
+        let LoginClientBoundPacket::LoginDisconnect(disconnect) = packet else {
+            panic!("Unexpected packet {packet:?}");
+        };
+        assert!(disconnect.reason.to_json().unwrap().contains("Griefing"));
+
+        // Status requests aren't checked, so banned IPs can still read the MOTD
+        let (client, conn) = duplex(4096);
+        let (outcome, ping) = tokio::join!(
+            srv.handle_conn(conn, address()),
+            ping_stream(client, "localhost", 25565, 765),
+        );
+
+        ping.unwrap();
+        assert!(matches!(outcome, ConnectionOutcome::StatusServed));
+    }
+
+    #[tokio::test]
+    async fn test_ban_check_failure() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate!().run(&pool).await.unwrap();
+        let mut srv = server("127.0.0.1:1", PhaseTimeouts::default()).await;
+        srv.global_state = test_global_state_with_pool(pool.clone());
+        pool.close().await;
+
+        let (mut client, conn) = duplex(4096);
+        send_handshake(&mut client, NextState::Login).await;
+
+        let outcome = srv.handle_conn(conn, address()).await;
+        assert!(matches!(
+            outcome,
+            ConnectionOutcome::Failed(Phase::Handshaking, _)
+        ));
+
+        // The bans are let through, the whitelist still fails
+        srv.global_state =
+            test_global_state_with_pool(pool).with_ban_check_failure(BanCheckFailure::Allow);
+        let (mut client, conn) = duplex(4096);
+        send_handshake(&mut client, NextState::Login).await;
+        send_login_start(&mut client, "Notch").await;
+
+        let outcome = srv.handle_conn(conn, address()).await;
+        assert!(matches!(
+            outcome,
+            ConnectionOutcome::Failed(Phase::LoginStart, _)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_relaying_happy_path() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backend = listener.local_addr().unwrap().to_string();
+        let srv = server(&backend, PhaseTimeouts::default()).await;
+
+        let (mut client, conn) = duplex(4096);
+        send_handshake(&mut client, NextState::Login).await;
+        send_login_start(&mut client, "Notch").await;
+
+        // The backend receives the forwarded login and closes the connection
+        let backend = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_packet(&mut stream, false).await.unwrap().unwrap();
+
+            let vec = read_packet(&mut stream, false).await.unwrap().unwrap();
+            let packet = LoginServerBoundPacket::decode(&mut Cursor::new(vec)).unwrap();
+            assert!(matches!(
+                packet,
+                LoginServerBoundPacket::LoginStart(LoginStart { name, .. }) if name == "Notch"
+            ));
+        });
+
+        let outcome = srv.handle_conn(conn, address()).await;
+        backend.await.unwrap();
+
+        assert!(matches!(
+            outcome,
+            ConnectionOutcome::Relayed {
+                username: None,
+                closed_by: ClosedBy::Backend,
+                ..
+            }
+        ));
+        assert!(
+            srv.global_state
+                .try_reserve_player("Notch", &Uuid::nil())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_relayed_traffic() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backend = listener.local_addr().unwrap().to_string();
+        let srv = server(&backend, PhaseTimeouts::default()).await;
+
+        let (mut client, conn) = duplex(4096);
+        send_handshake(&mut client, NextState::Login).await;
+        send_login_start(&mut client, "Notch").await;
+
+        let mut disconnect = Vec::new();
+        let packet = LoginClientBoundPacket::LoginDisconnect(LoginDisconnect {
+            reason: Message::new(Payload::text("Server full")),
+        });
+        write_packet(&mut disconnect, &packet).await.unwrap();
+        let length = disconnect.len() as u64;
+
+        let backend = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_packet(&mut stream, false).await.unwrap().unwrap();
+            read_packet(&mut stream, false).await.unwrap().unwrap();
+            stream.write_all(&disconnect).await.unwrap();
+        });
+
+        let outcome = srv.handle_conn(conn, address()).await;
+        backend.await.unwrap();
+
+        let ConnectionOutcome::Kicked { traffic, .. } = outcome else {
+            panic!("expected a kick, got {outcome}");
+        };
+        // The handshake and login start are sent before relaying
+        assert_eq!(
+            traffic,
+            Traffic {
+                to_client: length,
+                to_backend: 0
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transfer_is_relayed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backend = listener.local_addr().unwrap().to_string();
+        let srv = server(&backend, PhaseTimeouts::default()).await;
+
+        let (mut client, conn) = duplex(4096);
+        send_handshake(&mut client, NextState::Transfer).await;
+        send_login_start(&mut client, "Notch").await;
+
+        let backend = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let vec = read_packet(&mut stream, false).await.unwrap().unwrap();
+            let packet = HandshakeServerBoundPacket::decode(&mut Cursor::new(vec)).unwrap();
+            let HandshakeServerBoundPacket::Handshake(handshake) = packet;
+            assert!(matches!(handshake.next_state, NextState::Transfer));
+
+            read_packet(&mut stream, false).await.unwrap().unwrap();
+        });
+
+        let outcome = srv.handle_conn(conn, address()).await;
+        backend.await.unwrap();
+
+        assert!(matches!(outcome, ConnectionOutcome::Relayed { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_backend_unavailable_releases_username() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let backend = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let srv = server(&backend, PhaseTimeouts::default()).await;
+
+        let (mut client, conn) = duplex(4096);
+        send_handshake(&mut client, NextState::Login).await;
+        send_login_start(&mut client, "Notch").await;
+
+        let outcome = srv.handle_conn(conn, address()).await;
+
+        assert!(matches!(outcome, ConnectionOutcome::BackendUnavailable));
+        let vec = read_packet(&mut client, false).await.unwrap().unwrap();
+        let packet = LoginClientBoundPacket::decode(&mut Cursor::new(vec)).unwrap();
+        assert!(matches!(
+            packet,
+            LoginClientBoundPacket::LoginDisconnect(v)
+                if v.reason == srv.global_state.messages.backend_unavailable
+        ));
+        assert!(
+            srv.global_state
+                .try_reserve_player("Notch", &Uuid::nil())
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handshake_timeout() {
+        let timeouts = PhaseTimeouts {
+            handshake: SHORT,
+            ..Default::default()
+        };
+        let srv = server("127.0.0.1:1", timeouts).await;
+        let (_client, conn) = duplex(4096);
+
+        let outcome = srv.handle_conn(conn, address()).await;
+        assert!(matches!(
+            outcome,
+            ConnectionOutcome::TimedOut(Phase::Handshaking)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_status_timeout() {
+        let timeouts = PhaseTimeouts {
+            status: SHORT,
+            ..Default::default()
+        };
+        let srv = server("127.0.0.1:1", timeouts).await;
+
+        let (mut client, conn) = duplex(4096);
+        send_handshake(&mut client, NextState::Status).await;
+
+        let outcome = srv.handle_conn(conn, address()).await;
+        assert!(matches!(
+            outcome,
+            ConnectionOutcome::TimedOut(Phase::StatusLoop)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_banned_login_close_reason() {
+        let srv = server("127.0.0.1:1", PhaseTimeouts::default()).await;
+        srv.global_state
+            .user_bans
+            .add_ban("Notch", None, None)
+            .await
+            .unwrap();
+
+        let (mut client, conn) = duplex(4096);
+        send_handshake(&mut client, NextState::Login).await;
+        send_login_start(&mut client, "Notch").await;
+
+        let outcome = srv.handle_conn(conn, address()).await;
+        assert!(matches!(
+            outcome,
+            ConnectionOutcome::LoginRejected(LoginRejection::Banned)
+        ));
+        assert_eq!(outcome.to_string(), "login_rejected(banned)");
+        assert_eq!(srv.global_state.stats.connections()["banned"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_login_start_timeout() {
+        let timeouts = PhaseTimeouts {
+            login_start: SHORT,
+            ..Default::default()
+        };
+        let srv = server("127.0.0.1:1", timeouts).await;
+
+        let (mut client, conn) = duplex(4096);
+        send_handshake(&mut client, NextState::Login).await;
+
+        let outcome = srv.handle_conn(conn, address()).await;
+        assert!(matches!(
+            outcome,
+            ConnectionOutcome::TimedOut(Phase::LoginStart)
+        ));
+        assert_eq!(srv.global_state.stats.connections()["timed_out"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_client_left_before_login_start() {
+        let srv = server("127.0.0.1:1", PhaseTimeouts::default()).await;
+
+        let (mut client, conn) = duplex(4096);
+        send_handshake(&mut client, NextState::Login).await;
+        drop(client);
+
+        let outcome = srv.handle_conn(conn, address()).await;
+        assert!(matches!(
+            outcome,
+            ConnectionOutcome::ClientLeft(Phase::LoginStart)
+        ));
+        assert_eq!(srv.global_state.stats.connections()["client_left"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_route_protocol_versions() {
+        let options = SocketOptions {
+            nodelay: true,
+            keepalive: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        };
+        let backends = vec![Backend::new(BackendPool::new(
+            "127.0.0.1:1".into(),
+            0,
+            Duration::ZERO,
+            options,
+        ))];
+        let minigames = Route::new(
+            vec!["minigames.example.com".into()],
+            vec![0],
+            Default::default(),
+        )
+        .with_versions("47".parse().unwrap(), Some(Message::from_str("Use 1.8")));
+        let router = Router::new(
+            backends,
+            vec![minigames],
+            Route::new(Vec::new(), vec![0], Default::default()),
+        );
+        let srv = Server::new(
+            router,
+            options,
+            test_global_state().await,
+            None,
+            Default::default(),
+            Default::default(),
+        );
+
+        let cases = [
+            ("minigames.example.com", 765, "Use 1.8"),
+            ("localhost", 47, "Your minecraft version is not accepted"),
+        ];
+
+        for (host, protocol_version, expected) in cases {
+            let (mut client, conn) = duplex(4096);
+            let packet = HandshakeServerBoundPacket::Handshake(Handshake {
+                protocol_version,
+                server_addr: host.into(),
+                server_port: 25565,
+                next_state: NextState::Login,
+            });
+            write_packet(&mut client, &packet).await.unwrap();
+
+            let outcome = srv.handle_conn(conn, address()).await;
+            assert!(matches!(outcome, ConnectionOutcome::UnsupportedVersion));
+
+            let vec = read_packet(&mut client, false).await.unwrap().unwrap();
+            let packet = LoginClientBoundPacket::decode(&mut Cursor::new(vec)).unwrap();
+            let LoginClientBoundPacket::LoginDisconnect(disconnect) = packet else {
+                panic!("Unexpected packet {packet:?}");
+            };
+            let reason = disconnect.reason.to_json().unwrap();
+            assert!(reason.contains(expected), "{host}");
+        }
+    }
+}
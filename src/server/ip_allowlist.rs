@@ -0,0 +1,49 @@
+use crate::repository::user_ip_bans::canonical_network;
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+/// Only lets through the connections coming from the listed networks, every
+/// other address is rejected before the handshake.
+#[derive(Debug, Clone)]
+pub struct IpAllowlist {
+    networks: Vec<IpNet>,
+}
+
+impl IpAllowlist {
+    pub fn new(networks: impl IntoIterator<Item = IpNet>) -> Self {
+        Self {
+            networks: networks.into_iter().map(canonical_network).collect(),
+        }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.networks.iter().any(|v| v.contains(&ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IpAllowlist;
+    use crate::repository::user_ip_bans::parse_network;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn test_allowlist() {
+        let allowlist = IpAllowlist::new(
+            ["10.0.0.0/8", "203.0.113.7", "2001:db8::/32"]
+                .into_iter()
+                .map(|v| parse_network(v).unwrap()),
+        );
+
+        assert!(allowlist.contains("10.1.2.3".parse().unwrap()));
+        assert!(allowlist.contains("203.0.113.7".parse().unwrap()));
+        assert!(!allowlist.contains("203.0.113.8".parse().unwrap()));
+        assert!(allowlist.contains("2001:db8::1".parse().unwrap()));
+        assert!(!allowlist.contains("2001:db9::1".parse().unwrap()));
+
+        // Clients connecting to a dual stack socket
+        let mapped = IpAddr::V6(Ipv4Addr::new(10, 0, 0, 1).to_ipv6_mapped());
+        assert!(allowlist.contains(mapped));
+    }
+}
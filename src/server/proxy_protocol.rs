@@ -0,0 +1,200 @@
+//! Reads the [PROXY protocol] header prepended by TCP load balancers, which
+//! carries the address of the client they accepted the connection from.
+//!
+//! [PROXY protocol]: https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// The longest a v1 header can be, including the `\r\n`
+const V1_MAX_LENGTH: usize = 107;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProxyHeaderError {
+    #[error("Failed to read PROXY protocol header: {0}")]
+    Io(#[from] io::Error),
+    #[error("Connection didn't start with a PROXY protocol header")]
+    Missing,
+    #[error("Invalid PROXY protocol header: {0}")]
+    Invalid(&'static str),
+}
+
+/// Reads the header at the start of `stream`, without reading past it.
+///
+/// Returns the source address it carries, or `None` when the load balancer
+/// doesn't know it, such as with health checks, in which case the address of
+/// the socket should be used.
+pub async fn read_proxy_header<R: AsyncRead + Unpin>(
+    stream: &mut R,
+) -> Result<Option<SocketAddr>, ProxyHeaderError> {
+    // Shorter than the smallest header of both versions
+    let mut start = [0u8; 12];
+    stream.read_exact(&mut start).await?;
+
+    if start == V2_SIGNATURE {
+        read_v2(stream).await
+    } else if start.starts_with(b"PROXY ") {
+        read_v1(stream, &start).await
+    } else {
+        Err(ProxyHeaderError::Missing)
+    }
+}
+
+async fn read_v1<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    start: &[u8],
+) -> Result<Option<SocketAddr>, ProxyHeaderError> {
+    let mut line = start.to_vec();
+
+    // Read byte by byte, the client data right after the header must be left
+    // in the stream
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(ProxyHeaderError::Invalid("v1 header is too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| ProxyHeaderError::Invalid("v1 header is not ascii"))?;
+    parse_v1(line)
+}
+
+fn parse_v1(line: &str) -> Result<Option<SocketAddr>, ProxyHeaderError> {
+    let mut parts = line.split(' ').skip(1);
+
+    match parts.next() {
+        Some("TCP4" | "TCP6") => {}
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(ProxyHeaderError::Invalid("unknown v1 protocol")),
+    }
+
+    let source: IpAddr = parts
+        .next()
+        .and_then(|v| v.parse().ok())
+        .ok_or(ProxyHeaderError::Invalid("invalid v1 source address"))?;
+    let _destination = parts.next();
+    let port: u16 = parts
+        .next()
+        .and_then(|v| v.parse().ok())
+        .ok_or(ProxyHeaderError::Invalid("invalid v1 source port"))?;
+
+    Ok(Some(SocketAddr::new(source, port)))
+}
+
+async fn read_v2<R: AsyncRead + Unpin>(
+    stream: &mut R,
+) -> Result<Option<SocketAddr>, ProxyHeaderError> {
+    let version_command = stream.read_u8().await?;
+    let family = stream.read_u8().await?;
+    let length = stream.read_u16().await?;
+
+    let mut addresses = vec![0u8; length as usize];
+    stream.read_exact(&mut addresses).await?;
+
+    if version_command >> 4 != 2 {
+        return Err(ProxyHeaderError::Invalid("unsupported version"));
+    }
+    match version_command & 0x0F {
+        // LOCAL, sent by the load balancer itself
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => {}
+        _ => return Err(ProxyHeaderError::Invalid("unknown v2 command")),
+    }
+
+    match family {
+        // TCP over IPv4
+        0x11 if addresses.len() >= 12 => {
+            let ip: [u8; 4] = addresses[..4].try_into().unwrap();
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(Ipv4Addr::from(ip).into(), port)))
+        }
+        // TCP over IPv6
+        0x21 if addresses.len() >= 36 => {
+            let ip: [u8; 16] = addresses[..16].try_into().unwrap();
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port)))
+        }
+        0x11 | 0x21 => Err(ProxyHeaderError::Invalid("v2 addresses are too short")),
+        // UDP, unix sockets or unspecified
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_proxy_header, ProxyHeaderError, V2_SIGNATURE};
+    use std::net::SocketAddr;
+    use tokio::io::AsyncReadExt;
+
+    async fn read(data: &[u8]) -> (Result<Option<SocketAddr>, ProxyHeaderError>, Vec<u8>) {
+        let mut stream = data;
+        let result = read_proxy_header(&mut stream).await;
+
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        (result, rest)
+    }
+
+    #[tokio::test]
+    async fn test_v1() {
+        let (result, rest) =
+            read(b"PROXY TCP4 203.0.113.7 192.0.2.1 51234 25565\r\n\x10\x00").await;
+        assert_eq!(result.unwrap(), Some("203.0.113.7:51234".parse().unwrap()));
+        assert_eq!(rest, [0x10, 0x00]);
+
+        let (result, _) = read(b"PROXY TCP6 2001:db8::1 2001:db8::2 51234 25565\r\n").await;
+        assert_eq!(
+            result.unwrap(),
+            Some("[2001:db8::1]:51234".parse().unwrap())
+        );
+
+        let (result, _) = read(b"PROXY UNKNOWN\r\n").await;
+        assert_eq!(result.unwrap(), None);
+
+        let (result, _) = read(b"PROXY TCP4 localhost 192.0.2.1 51234 25565\r\n").await;
+        assert!(matches!(result, Err(ProxyHeaderError::Invalid(_))));
+
+        let (result, _) = read(&[b'P'; 200]).await;
+        assert!(matches!(result, Err(ProxyHeaderError::Missing)));
+    }
+
+    #[tokio::test]
+    async fn test_v2() {
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend_from_slice(&[0x21, 0x11, 0x00, 0x0C]);
+        data.extend_from_slice(&[203, 0, 113, 7, 192, 0, 2, 1]);
+        data.extend_from_slice(&51234u16.to_be_bytes());
+        data.extend_from_slice(&25565u16.to_be_bytes());
+        data.extend_from_slice(&[0x10, 0x00]);
+
+        let (result, rest) = read(&data).await;
+        assert_eq!(result.unwrap(), Some("203.0.113.7:51234".parse().unwrap()));
+        assert_eq!(rest, [0x10, 0x00]);
+
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend_from_slice(&[0x20, 0x00, 0x00, 0x00, 0x10]);
+
+        let (result, rest) = read(&data).await;
+        assert_eq!(result.unwrap(), None);
+        assert_eq!(rest, [0x10]);
+    }
+
+    #[tokio::test]
+    async fn test_missing_header() {
+        // A handshake sent straight by a client
+        let (result, _) = read(&[
+            0x10, 0x00, 0xFD, 0x05, 0x09, b'l', b'o', b'c', b'a', b'l', b'h', b'o', b's', b't',
+        ])
+        .await;
+        assert!(matches!(result, Err(ProxyHeaderError::Missing)));
+
+        let (result, _) = read(b"PROXY").await;
+        assert!(matches!(result, Err(ProxyHeaderError::Io(_))));
+    }
+}
@@ -0,0 +1,311 @@
+//! End to end tests of the proxy, relaying a fake client to a fake backend
+//! through a [`Server`] listening on an ephemeral port.
+
+use super::{PhaseTimeouts, Server};
+use crate::{
+    backend::{
+        pool::BackendPool,
+        route::{Route, Router},
+        Backend,
+    },
+    state::test_global_state,
+    utils::{read_packet, socket::SocketOptions, write_packet},
+};
+use minecraft_protocol::{
+    data::{chat::Message, identifier::Identifier},
+    decoder::Decoder,
+    encoder::Encoder,
+    packet::{
+        configuration::{ConfigClientBoundPaket, ConfigDisconnect, ConfigServerBoundPacket},
+        game::{GameClientBoundPacket, GameServerBoundPacket, PlayPluginMessage},
+        handshake::{Handshake, HandshakeServerBoundPacket, NextState},
+        login::{LoginClientBoundPacket, LoginServerBoundPacket, LoginStart, LoginSuccess},
+    },
+};
+use std::{io::Cursor, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::net::{TcpListener, TcpStream};
+use uuid::Uuid;
+
+const PROTOCOL_VERSION: i32 = 765;
+
+/// How long the assertions on the shared state wait for it to change.
+const WAIT: Duration = Duration::from_secs(5);
+
+fn socket_options() -> SocketOptions {
+    SocketOptions {
+        nodelay: true,
+        keepalive: None,
+        send_buffer_size: None,
+        recv_buffer_size: None,
+    }
+}
+
+/// Starts a proxy relaying every player to `backend`, returning the address
+/// it listens on.
+async fn spawn_proxy(backend: SocketAddr) -> (Arc<Server>, SocketAddr) {
+    let backends = vec![Backend::new(BackendPool::new(
+        backend.to_string(),
+        0,
+        Duration::ZERO,
+        socket_options(),
+    ))];
+    let router = Router::new(
+        backends,
+        Vec::new(),
+        Route::new(Vec::new(), vec![0], Default::default()),
+    );
+    let srv = Arc::new(Server::new(
+        router,
+        socket_options(),
+        test_global_state().await,
+        None,
+        Default::default(),
+        PhaseTimeouts::default(),
+    ));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let accept_srv = srv.clone();
+    tokio::spawn(async move {
+        while let Ok((conn, address)) = listener.accept().await {
+            let task_srv = accept_srv.clone();
+            accept_srv.spawn_connection(async move {
+                task_srv.handle_conn(conn, address).await;
+            });
+        }
+    });
+
+    (srv, address)
+}
+
+async fn send<T: Encoder + Sync>(stream: &mut TcpStream, packet: &T) {
+    write_packet(stream, packet).await.unwrap();
+}
+
+async fn receive<T: Decoder>(stream: &mut TcpStream) -> T::Output {
+    let vec = read_packet(stream, false).await.unwrap().unwrap();
+    T::decode(&mut Cursor::new(vec)).unwrap()
+}
+
+/// A backend accepting a single player, which it logs in and takes to the
+/// play state without sending any of the packets a real server would.
+struct FakeBackend {
+    listener: TcpListener,
+}
+
+impl FakeBackend {
+    async fn bind() -> Self {
+        Self {
+            listener: TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        }
+    }
+
+    fn address(&self) -> SocketAddr {
+        self.listener.local_addr().unwrap()
+    }
+
+    /// Returns the connection of the player once it entered the play state.
+    async fn accept(self, uuid: Uuid) -> TcpStream {
+        let mut stream = self.accept_login(uuid).await;
+
+        send(&mut stream, &ConfigClientBoundPaket::FinishConfiguration).await;
+        let packet = receive::<ConfigServerBoundPacket>(&mut stream).await;
+        assert!(matches!(
+            packet,
+            ConfigServerBoundPacket::AcknowledgeFinishConfiguration
+        ));
+
+        stream
+    }
+
+    /// Returns the connection of the player once it entered the
+    /// configuration state.
+    async fn accept_login(self, uuid: Uuid) -> TcpStream {
+        let (mut stream, _) = self.listener.accept().await.unwrap();
+
+        let HandshakeServerBoundPacket::Handshake(handshake) =
+            receive::<HandshakeServerBoundPacket>(&mut stream).await;
+        assert_eq!(handshake.protocol_version, PROTOCOL_VERSION);
+        assert!(matches!(handshake.next_state, NextState::Login));
+
+        let packet = receive::<LoginServerBoundPacket>(&mut stream).await;
+        let LoginServerBoundPacket::LoginStart(login_start) = packet else {
+            panic!("expected a login start, got {packet:?}");
+        };
+
+        let packet = LoginClientBoundPacket::LoginSuccess(LoginSuccess {
+            uuid,
+            username: login_start.name,
+        });
+        send(&mut stream, &packet).await;
+        let packet = receive::<LoginServerBoundPacket>(&mut stream).await;
+        assert!(matches!(packet, LoginServerBoundPacket::LoginAcknowledged));
+
+        stream
+    }
+}
+
+/// A client that logs in as `name`, returning its connection once it entered
+/// the play state.
+async fn login(proxy: SocketAddr, name: &str, uuid: Uuid) -> TcpStream {
+    let mut stream = start_login(proxy, name, uuid).await;
+
+    let packet = receive::<ConfigClientBoundPaket>(&mut stream).await;
+    assert!(matches!(
+        packet,
+        ConfigClientBoundPaket::FinishConfiguration
+    ));
+    send(
+        &mut stream,
+        &ConfigServerBoundPacket::AcknowledgeFinishConfiguration,
+    )
+    .await;
+
+    stream
+}
+
+/// A client that logs in as `name`, returning its connection once it entered
+/// the configuration state.
+async fn start_login(proxy: SocketAddr, name: &str, uuid: Uuid) -> TcpStream {
+    let mut stream = TcpStream::connect(proxy).await.unwrap();
+
+    let packet = HandshakeServerBoundPacket::Handshake(Handshake {
+        protocol_version: PROTOCOL_VERSION,
+        server_addr: "localhost".into(),
+        server_port: proxy.port(),
+        next_state: NextState::Login,
+    });
+    send(&mut stream, &packet).await;
+    let packet = LoginServerBoundPacket::LoginStart(LoginStart {
+        name: name.into(),
+        uuid,
+    });
+    send(&mut stream, &packet).await;
+
+    let packet = receive::<LoginClientBoundPacket>(&mut stream).await;
+    let LoginClientBoundPacket::LoginSuccess(success) = packet else {
+        panic!("expected a login success, got {packet:?}");
+    };
+    assert_eq!(success.username, name);
+    send(&mut stream, &LoginServerBoundPacket::LoginAcknowledged).await;
+
+    stream
+}
+
+/// Waits until the player is, or isn't, online on the proxy.
+async fn wait_online(srv: &Server, name: &str, online: bool) {
+    let wait = async {
+        while srv
+            .global_state()
+            .read_online_players()
+            .await
+            .contains_key(name)
+            != online
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+
+    if tokio::time::timeout(WAIT, wait).await.is_err() {
+        panic!("{name} online should be {online}");
+    }
+}
+
+#[tokio::test]
+async fn test_relay_both_ways() {
+    let backend = FakeBackend::bind().await;
+    let (srv, proxy) = spawn_proxy(backend.address()).await;
+    let uuid = Uuid::new_v4();
+
+    let (mut backend, mut client) = tokio::join!(backend.accept(uuid), login(proxy, "Notch", uuid));
+    wait_online(&srv, "Notch", true).await;
+    assert_eq!(
+        srv.global_state().read_online_players().await["Notch"],
+        uuid
+    );
+
+    let packet = GameServerBoundPacket::ServerBoundPluginMessage(PlayPluginMessage {
+        channel: Identifier::from_static("example:ping"),
+        data: b"ping".to_vec(),
+    });
+    send(&mut client, &packet).await;
+    match receive::<GameServerBoundPacket>(&mut backend).await {
+        GameServerBoundPacket::ServerBoundPluginMessage(message) => {
+            assert_eq!(message.channel.as_str(), "example:ping");
+            assert_eq!(message.data, b"ping");
+        }
+        packet => panic!("expected a plugin message, got {packet:?}"),
+    }
+
+    let packet = GameClientBoundPacket::ClientBoundPluginMessage(PlayPluginMessage {
+        channel: Identifier::from_static("example:pong"),
+        data: b"pong".to_vec(),
+    });
+    send(&mut backend, &packet).await;
+    match receive::<GameClientBoundPacket>(&mut client).await {
+        GameClientBoundPacket::ClientBoundPluginMessage(message) => {
+            assert_eq!(message.channel.as_str(), "example:pong");
+            assert_eq!(message.data, b"pong");
+        }
+        packet => panic!("expected a plugin message, got {packet:?}"),
+    }
+
+    drop(client);
+    wait_online(&srv, "Notch", false).await;
+    // The backend connection is closed along with the client one
+    assert!(read_packet(&mut backend, false).await.is_err());
+}
+
+#[tokio::test]
+async fn test_config_disconnect() {
+    let backend = FakeBackend::bind().await;
+    let (srv, proxy) = spawn_proxy(backend.address()).await;
+    let uuid = Uuid::new_v4();
+
+    let (mut backend, mut client) = tokio::join!(
+        backend.accept_login(uuid),
+        start_login(proxy, "Notch", uuid)
+    );
+    wait_online(&srv, "Notch", true).await;
+
+    let reason = Message::from_str("Server is full");
+    let packet = ConfigClientBoundPaket::ConfigDisconnect(ConfigDisconnect {
+        reason: reason.clone(),
+    });
+    send(&mut backend, &packet).await;
+    match receive::<ConfigClientBoundPaket>(&mut client).await {
+        ConfigClientBoundPaket::ConfigDisconnect(packet) => assert_eq!(packet.reason, reason),
+        packet => panic!("expected a disconnect, got {packet:?}"),
+    }
+
+    // Removed while both connections are still open
+    wait_online(&srv, "Notch", false).await;
+}
+
+#[tokio::test]
+async fn test_cancelled_relay_removes_player() {
+    let backend = FakeBackend::bind().await;
+    let (srv, _) = spawn_proxy(backend.address()).await;
+
+    // Served outside of the tracker, so that it can be aborted
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy = listener.local_addr().unwrap();
+    let task_srv = srv.clone();
+    let task = tokio::spawn(async move {
+        let (conn, address) = listener.accept().await.unwrap();
+        task_srv.handle_conn(conn, address).await
+    });
+
+    let uuid = Uuid::new_v4();
+    let (_backend, _client) = tokio::join!(backend.accept(uuid), login(proxy, "Notch", uuid));
+    wait_online(&srv, "Notch", true).await;
+
+    task.abort();
+    wait_online(&srv, "Notch", false).await;
+    assert!(
+        srv.global_state()
+            .try_reserve_player("Notch", &Uuid::nil())
+            .await
+    );
+}
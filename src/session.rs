@@ -0,0 +1,148 @@
+use crate::repository::{kv::KeyValueRepository, RepositoryError};
+use minecraft_protocol::data::chat::Message;
+use std::{collections::HashSet, net::IpAddr, time::Duration};
+
+/// Rejects players that reconnect from another IP shortly after leaving,
+/// which usually means the account is being shared.
+pub struct SessionLock<KV> {
+    key_value: KV,
+    window: Duration,
+    /// Lowercase usernames the rule doesn't apply to
+    bypass: HashSet<String>,
+    message: Message,
+}
+
+impl<KV: KeyValueRepository> SessionLock<KV> {
+    pub fn new(
+        key_value: KV,
+        window: Duration,
+        bypass: impl IntoIterator<Item = String>,
+        message: Message,
+    ) -> Self {
+        Self {
+            key_value,
+            window,
+            bypass: bypass.into_iter().map(|v| v.to_lowercase()).collect(),
+            message,
+        }
+    }
+
+    /// The disconnect reason of rejected logins.
+    #[inline]
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    /// Locks the username to `ip` until the window elapses.
+    pub async fn record_disconnect(
+        &self,
+        username: &str,
+        ip: IpAddr,
+    ) -> Result<(), RepositoryError> {
+        self.key_value
+            .set_ttl(
+                &key(username),
+                &ip.to_canonical().to_string(),
+                Some(self.window),
+            )
+            .await
+    }
+
+    /// Whether the player can log in from `ip`.
+    pub async fn check(&self, username: &str, ip: IpAddr) -> Result<bool, RepositoryError> {
+        if self.bypass.contains(&username.to_lowercase()) {
+            return Ok(true);
+        }
+
+        let locked = self.key_value.get(&key(username)).await?;
+
+        Ok(locked.is_none_or(|v| v.parse() == Ok(ip.to_canonical())))
+    }
+
+    /// Removes the lock of the username, returning whether there was one.
+    pub async fn clear(&self, username: &str) -> Result<bool, RepositoryError> {
+        self.key_value
+            .delete(&key(username))
+            .await
+            .map(|v| v.is_some())
+    }
+}
+
+#[inline]
+fn key(username: &str) -> String {
+    format!("session.ip.{}", username.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SessionLock;
+    use crate::repository::kv::SqlxKeyValueRepository;
+    use minecraft_protocol::data::chat::Message;
+    use sqlx::{migrate, Sqlite, SqlitePool};
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::Duration,
+    };
+
+    const HOME: IpAddr = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 1));
+    const ABROAD: IpAddr = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 7));
+
+    async fn session_lock(window: Duration) -> SessionLock<SqlxKeyValueRepository<Sqlite>> {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate!().run(&pool).await.unwrap();
+
+        SessionLock::new(
+            SqlxKeyValueRepository::new(pool),
+            window,
+            ["Admin".to_string()],
+            Message::from_str("Locked"),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_inside_window() {
+        let lock = session_lock(Duration::from_secs(60)).await;
+
+        assert!(lock.check("Notch", ABROAD).await.unwrap());
+
+        lock.record_disconnect("Notch", HOME).await.unwrap();
+        assert!(lock.check("Notch", HOME).await.unwrap());
+        assert!(lock.check("notch", HOME).await.unwrap());
+        assert!(!lock.check("Notch", ABROAD).await.unwrap());
+        assert!(lock.check("jeb_", ABROAD).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_outside_window() {
+        let lock = session_lock(Duration::from_millis(20)).await;
+
+        lock.record_disconnect("Notch", HOME).await.unwrap();
+        assert!(!lock.check("Notch", ABROAD).await.unwrap());
+
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert!(lock.check("Notch", ABROAD).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_mapped_address_matches() {
+        let lock = session_lock(Duration::from_secs(60)).await;
+
+        let mapped = IpAddr::V6(Ipv4Addr::new(203, 0, 113, 1).to_ipv6_mapped());
+
+        lock.record_disconnect("Notch", mapped).await.unwrap();
+        assert!(lock.check("Notch", HOME).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_bypass_and_clear() {
+        let lock = session_lock(Duration::from_secs(60)).await;
+
+        lock.record_disconnect("Admin", HOME).await.unwrap();
+        assert!(lock.check("admin", ABROAD).await.unwrap());
+
+        lock.record_disconnect("Notch", HOME).await.unwrap();
+        assert!(lock.clear("Notch").await.unwrap());
+        assert!(!lock.clear("Notch").await.unwrap());
+        assert!(lock.check("Notch", ABROAD).await.unwrap());
+    }
+}
@@ -1,84 +1,473 @@
-use crate::repository::{
-    ip_bans::SqlxIpBansRepository, kv::SqlxKeyValueRepository, user_bans::SqlxUserBansRepository,
-    whitelist::SqlxWhitelistRepository, DB,
+use crate::{
+    actions::PlayerActions,
+    backend::health::BackendHealthMap,
+    bypass::WhitelistBypass,
+    commands::{auth::CommandAuth, dispatcher::CommandDispatcher},
+    handler::messages::DisconnectMessages,
+    presence::SharedPresence,
+    queue::QueueManager,
+    repository::{
+        ban_cache::{CachedIpBansRepository, CachedUserBansRepository},
+        health::{BanCheckFailure, DatabaseHealth},
+        ip_bans::SqlxIpBansRepository,
+        kv::{KeyValueRepository, SqlxKeyValueRepository},
+        stats::SqlxStatsRepository,
+        user_bans::SqlxUserBansRepository,
+        user_ip_bans::SqlxUserIpBansRepository,
+        whitelist::SqlxWhitelistRepository,
+        write_behind::WriteBehindKeyValue,
+        RepositoryError, DB,
+    },
+    resolver::UsernameResolver,
+    session::SessionLock,
+    stats::StatsCollector,
 };
 use minecraft_protocol::{
-    codec::{
-        client::{ClientPacket, ClientPacketCodec},
-        server::{ServerPacket, ServerPacketCodec},
-        ProtocolState,
-    },
+    codec::{client::ClientPacketCodec, server::ServerPacketCodec, ProtocolState},
     data::chat::Message,
-    error::DecodeError,
+    packet::configuration::Transfer,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicU8, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    runtime::Handle,
+    sync::{RwLock, RwLockReadGuard},
 };
-use std::{collections::HashMap, future::Future};
-use tokio::sync::{RwLock, RwLockReadGuard};
 use uuid::Uuid;
 
+/// Key under which the server description set at runtime is stored.
+const SERVER_DESCRIPTION_KEY: &str = "server.description";
+
+/// Loads the server description stored by
+/// [`GlobalSharedState::set_server_description`], if any.
+async fn stored_server_description<KV: KeyValueRepository>(
+    key_value: &KV,
+) -> Result<Option<Message>, RepositoryError> {
+    match key_value.get(SERVER_DESCRIPTION_KEY).await? {
+        Some(v) => Ok(Some(serde_json::from_str(&v)?)),
+        None => Ok(None),
+    }
+}
+
 pub struct GlobalSharedState {
     server_description: RwLock<Message>,
-    pub ip_bans: SqlxIpBansRepository<DB>,
-    pub user_bans: SqlxUserBansRepository<DB>,
+    /// The configured description, used when none was set at runtime
+    default_server_description: Message,
+    key_value: SqlxKeyValueRepository<DB>,
+    pub ip_bans: CachedIpBansRepository<SqlxIpBansRepository<DB>>,
+    pub user_bans: CachedUserBansRepository<SqlxUserBansRepository<DB>>,
+    pub user_ip_bans: SqlxUserIpBansRepository<DB>,
     pub whitelist: SqlxWhitelistRepository<DB, SqlxKeyValueRepository<DB>>,
-    online_players: RwLock<HashMap<String, Uuid>>,
+    pub command_auth: CommandAuth,
+    pub command_dispatcher: CommandDispatcher,
+    pub stats: Arc<StatsCollector<SqlxStatsRepository<DB>>>,
+    pub backend_health: BackendHealthMap,
+    /// Pins whitelisted usernames to their account, `None` when online mode is disabled
+    pub username_resolver: Option<Box<dyn UsernameResolver>>,
+    /// `None` when reconnecting from other IPs is allowed
+    pub session_lock: Option<SessionLock<WriteBehindKeyValue<SqlxKeyValueRepository<DB>>>>,
+    pub whitelist_bypass: WhitelistBypass,
+    pub messages: DisconnectMessages,
+    /// `None` when the online players aren't shared with other proxies
+    presence: Option<Arc<SharedPresence<SqlxKeyValueRepository<DB>>>>,
+    /// `None` when players are relayed without waiting for a slot
+    pub queue: Option<QueueManager>,
+    pub player_actions: PlayerActions,
+    pub database_health: DatabaseHealth,
+    /// Shared with the [`OnlinePlayerGuard`]s, which outlive the borrows of
+    /// the connections
+    online_players: Arc<RwLock<OnlinePlayers>>,
+}
+
+#[derive(Default)]
+struct OnlinePlayers {
+    players: HashMap<String, Uuid>,
+    /// The usernames of the online players by uuid
+    uuids: HashMap<Uuid, String>,
+    /// Usernames of the players that are logging in
+    reserved: HashSet<String>,
+}
+
+impl OnlinePlayers {
+    /// Removes the player or its reservation, returning its uuid if it was
+    /// online.
+    fn remove(&mut self, name: &str) -> Option<Uuid> {
+        self.reserved.remove(name);
+        let uuid = self.players.remove(name)?;
+        if self.uuids.get(&uuid).is_some_and(|v| v == name) {
+            self.uuids.remove(&uuid);
+        }
+
+        Some(uuid)
+    }
+}
+
+/// Removes a reserved or online player when dropped without being released
+/// with [`GlobalSharedState::release_online_player`], so that a connection
+/// that panicked or was cancelled doesn't leave a ghost player behind, neither
+/// here nor in the shared presence.
+pub struct OnlinePlayerGuard {
+    online_players: Arc<RwLock<OnlinePlayers>>,
+    stats: Arc<StatsCollector<SqlxStatsRepository<DB>>>,
+    presence: Option<Arc<SharedPresence<SqlxKeyValueRepository<DB>>>>,
+    name: Option<String>,
+}
+
+impl OnlinePlayerGuard {
+    /// Leaves the player in place, to be removed by the caller.
+    pub fn disarm(mut self) -> String {
+        self.name.take().unwrap_or_default()
+    }
+}
+
+impl Drop for OnlinePlayerGuard {
+    fn drop(&mut self) {
+        let Some(name) = self.name.take() else {
+            return;
+        };
+        tracing::warn!(username = name, "Player was not released by its connection");
+
+        // Removed right away when possible, so that the username can be taken
+        // again as soon as the guard is gone
+        let removed = match self.online_players.try_write() {
+            Ok(mut lock) => match remove_player(&mut lock, &self.stats, &name) {
+                Some(uuid) => Some(uuid),
+                None => return,
+            },
+            Err(_) => None,
+        };
+        if removed.is_some() && self.presence.is_none() {
+            return;
+        }
+
+        let Ok(handle) = Handle::try_current() else {
+            tracing::error!(username = name, "Failed to remove player");
+            return;
+        };
+        let online_players = self.online_players.clone();
+        let stats = self.stats.clone();
+        let presence = self.presence.clone();
+        handle.spawn(async move {
+            let uuid = match removed {
+                Some(uuid) => uuid,
+                None => {
+                    let mut lock = online_players.write().await;
+                    match remove_player(&mut lock, &stats, &name) {
+                        Some(uuid) => uuid,
+                        None => return,
+                    }
+                }
+            };
+            release_presence(presence.as_deref(), &name, &uuid).await;
+        });
+    }
+}
+
+/// Removes the player or its reservation, recording the new online count if
+/// it was online.
+fn remove_player(
+    online_players: &mut OnlinePlayers,
+    stats: &StatsCollector<SqlxStatsRepository<DB>>,
+    name: &str,
+) -> Option<Uuid> {
+    let uuid = online_players.remove(name)?;
+    stats.record_online(online_players.players.len());
+
+    Some(uuid)
+}
+
+async fn release_presence(
+    presence: Option<&SharedPresence<SqlxKeyValueRepository<DB>>>,
+    name: &str,
+    uuid: &Uuid,
+) {
+    if let Some(presence) = presence {
+        if let Err(error) = presence.remove(name, uuid).await {
+            tracing::warn!(%error, "Failed to release shared presence");
+        }
+    }
 }
 
 impl GlobalSharedState {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         server_description: Message,
-        ip_bans: SqlxIpBansRepository<DB>,
-        user_bans: SqlxUserBansRepository<DB>,
+        key_value: SqlxKeyValueRepository<DB>,
+        ip_bans: CachedIpBansRepository<SqlxIpBansRepository<DB>>,
+        user_bans: CachedUserBansRepository<SqlxUserBansRepository<DB>>,
+        user_ip_bans: SqlxUserIpBansRepository<DB>,
         whitelist: SqlxWhitelistRepository<DB, SqlxKeyValueRepository<DB>>,
+        command_auth: CommandAuth,
+        command_dispatcher: CommandDispatcher,
+        stats: StatsCollector<SqlxStatsRepository<DB>>,
+        backend_health: BackendHealthMap,
+        username_resolver: Option<Box<dyn UsernameResolver>>,
+        session_lock: Option<SessionLock<WriteBehindKeyValue<SqlxKeyValueRepository<DB>>>>,
+        whitelist_bypass: WhitelistBypass,
+        messages: DisconnectMessages,
+        presence: Option<SharedPresence<SqlxKeyValueRepository<DB>>>,
+        queue: Option<QueueManager>,
     ) -> GlobalSharedState {
         GlobalSharedState {
-            server_description: RwLock::new(server_description),
+            server_description: RwLock::new(server_description.clone()),
+            default_server_description: server_description,
+            key_value,
             ip_bans,
             user_bans,
+            user_ip_bans,
             whitelist,
-            online_players: RwLock::new(HashMap::new()),
+            command_auth,
+            command_dispatcher,
+            stats: Arc::new(stats),
+            backend_health,
+            username_resolver,
+            session_lock,
+            whitelist_bypass,
+            messages,
+            presence: presence.map(Arc::new),
+            queue,
+            player_actions: PlayerActions::default(),
+            database_health: DatabaseHealth::default(),
+            online_players: Arc::new(RwLock::new(OnlinePlayers::default())),
         }
     }
 
+    /// Lets the connections through when their bans can't be checked, if
+    /// `on_failure` allows it.
+    #[inline]
+    pub fn with_ban_check_failure(mut self, on_failure: BanCheckFailure) -> Self {
+        self.database_health = DatabaseHealth::new(on_failure);
+        self
+    }
+
     pub async fn server_description(&self) -> Message {
         self.server_description.read().await.clone()
     }
 
+    /// Removes the player, or releases its username if it was only reserved.
     pub async fn remove_online_player(&self, name: &str) {
-        self.online_players.write().await.remove(name);
+        let mut lock = self.online_players.write().await;
+        let Some(uuid) = remove_player(&mut lock, &self.stats, name) else {
+            return;
+        };
+        drop(lock);
+
+        release_presence(self.presence.as_deref(), name, &uuid).await;
     }
 
-    pub async fn set_server_description(&self, server_description: Message) {
+    /// Removes the player the guard was created for, see
+    /// [`Self::remove_online_player`].
+    pub async fn release_online_player(&self, guard: OnlinePlayerGuard) {
+        self.remove_online_player(&guard.disarm()).await;
+    }
+
+    /// Frees the username reserved by the login start once the player is
+    /// online, since the backend may have changed its capitalization.
+    pub async fn release_reservation(&self, guard: OnlinePlayerGuard) {
+        let name = guard.disarm();
+        self.online_players.write().await.reserved.remove(&name);
+    }
+
+    /// Ties the removal of a reserved or online player to the returned guard.
+    pub fn guard_online_player(&self, name: String) -> OnlinePlayerGuard {
+        OnlinePlayerGuard {
+            online_players: self.online_players.clone(),
+            stats: self.stats.clone(),
+            presence: self.presence.clone(),
+            name: Some(name),
+        }
+    }
+
+    /// Changes the server description, storing it so that it's kept across
+    /// restarts.
+    pub async fn set_server_description(
+        &self,
+        server_description: Message,
+    ) -> Result<(), RepositoryError> {
+        let json = serde_json::to_string(&server_description)?;
+        self.key_value.set(SERVER_DESCRIPTION_KEY, &json).await?;
+        tracing::info!(
+            description = server_description.to_plain_text(),
+            "Server description changed"
+        );
+
         let mut lock = self.server_description.write().await;
         *lock = server_description;
+
+        Ok(())
     }
 
-    pub async fn add_online_player(&self, name: String, uuid: Uuid) {
+    /// Replaces the configured server description with the one stored by
+    /// [`Self::set_server_description`]. Returns `false` if none was stored.
+    pub async fn load_server_description(&self) -> Result<bool, RepositoryError> {
+        let Some(server_description) = stored_server_description(&self.key_value).await? else {
+            return Ok(false);
+        };
+
+        let mut lock = self.server_description.write().await;
+        *lock = server_description;
+
+        Ok(true)
+    }
+
+    /// Goes back to the configured server description, removing the stored one.
+    pub async fn reset_server_description(&self) -> Result<(), RepositoryError> {
+        self.key_value.delete(SERVER_DESCRIPTION_KEY).await?;
+
+        let mut lock = self.server_description.write().await;
+        *lock = self.default_server_description.clone();
+
+        Ok(())
+    }
+
+    /// Atomically checks that no player with this username or uuid is online,
+    /// nor with this username logging in, and reserves the username. Returns
+    /// `false` if either is taken.
+    ///
+    /// The uuid sent by the client is only trusted to reject the login early,
+    /// the one of the backend is checked again by [`Self::add_online_player`].
+    pub async fn try_reserve_player(&self, name: &str, uuid: &Uuid) -> bool {
         let mut lock = self.online_players.write().await;
-        lock.insert(name, uuid);
+        if lock.players.contains_key(name)
+            || lock.reserved.contains(name)
+            || lock.uuids.contains_key(uuid)
+        {
+            return false;
+        }
+
+        lock.reserved.insert(name.to_owned());
+        drop(lock);
+
+        if self.is_online_elsewhere(name, uuid).await {
+            self.online_players.write().await.reserved.remove(name);
+            return false;
+        }
+        true
+    }
+
+    /// Whether the player is online through another proxy. Logins are let
+    /// through when the shared presence can't be read.
+    async fn is_online_elsewhere(&self, name: &str, uuid: &Uuid) -> bool {
+        let Some(presence) = &self.presence else {
+            return false;
+        };
+
+        presence
+            .is_online_elsewhere(name, uuid)
+            .await
+            .unwrap_or_else(|error| {
+                tracing::warn!(%error, "Failed to check shared presence");
+                false
+            })
+    }
+
+    /// Marks the player as online, confirming its reservation if any. Returns
+    /// `false` if another player with the same uuid is already online, here
+    /// or through another proxy.
+    pub async fn add_online_player(&self, name: String, uuid: Uuid) -> bool {
+        let mut lock = self.online_players.write().await;
+        if lock.uuids.get(&uuid).is_some_and(|v| *v != name) {
+            return false;
+        }
+
+        lock.reserved.remove(&name);
+        lock.uuids.insert(uuid, name.clone());
+        lock.players.insert(name.clone(), uuid);
+        drop(lock);
+
+        // The uuid of the backend may differ from the one checked when the
+        // username was reserved
+        if !self.claim_presence(&name, &uuid).await {
+            self.online_players.write().await.remove(&name);
+            return false;
+        }
+
+        let lock = self.online_players.read().await;
+        self.stats.record_login(uuid, &name, lock.players.len());
+
+        true
     }
 
-    pub async fn exists_online_player(&self, name: &str) -> bool {
-        self.online_players.read().await.get(name).is_some()
+    /// Claims the player in the shared presence, if enabled. Logins are let
+    /// through when it can't be written.
+    async fn claim_presence(&self, name: &str, uuid: &Uuid) -> bool {
+        let Some(presence) = &self.presence else {
+            return true;
+        };
+
+        presence.claim(name, uuid).await.unwrap_or_else(|error| {
+            tracing::warn!(%error, "Failed to claim shared presence");
+            true
+        })
+    }
+
+    /// Claims the online players again before their shared presence expires,
+    /// does nothing when it's disabled.
+    pub async fn refresh_presence(&self) {
+        let Some(presence) = &self.presence else {
+            return;
+        };
+
+        let lock = self.online_players.read().await;
+        let players: Vec<_> = lock.players.iter().map(|(k, v)| (k.clone(), *v)).collect();
+        drop(lock);
+
+        let result = presence
+            .refresh(players.iter().map(|(name, uuid)| (name.as_str(), uuid)))
+            .await;
+        if let Err(error) = result {
+            tracing::warn!(%error, "Failed to refresh shared presence");
+        }
     }
 
+    /// How often [`Self::refresh_presence`] should run, `None` when the
+    /// presence isn't shared.
     #[inline]
-    pub fn read_online_players(
-        &self,
-    ) -> impl Future<Output = RwLockReadGuard<HashMap<String, Uuid>>> + Send {
-        self.online_players.read()
+    pub fn presence_refresh_interval(&self) -> Option<Duration> {
+        self.presence.as_ref().map(|v| v.refresh_interval())
+    }
+
+    pub async fn read_online_players(&self) -> RwLockReadGuard<'_, HashMap<String, Uuid>> {
+        RwLockReadGuard::map(self.online_players.read().await, |v| &v.players)
     }
 }
 
 pub struct PostLoginInformation {
     pub username: String,
     pub uuid: Uuid,
+    /// The guard of the reservation, which now removes the player if the
+    /// relay ends without releasing it
+    pub guard: OnlinePlayerGuard,
 }
 
+/// State shared by the two relay tasks of a connection. Each task owns its
+/// codec, the protocol state and compression threshold are copied into it with
+/// [`sync_client_codec`](Self::sync_client_codec) and
+/// [`sync_server_codec`](Self::sync_server_codec) before every packet.
 pub struct ConnectionSharedState {
     pub protocol_version: i32,
     pub login_info: RwLock<Option<PostLoginInformation>>,
-    client_codec: RwLock<ClientPacketCodec>,
-    server_codec: RwLock<ServerPacketCodec>,
+    /// The guard of the username reserved by the login start, released once
+    /// the backend accepts the login
+    reservation: Mutex<Option<OnlinePlayerGuard>>,
+    /// The reason of the disconnect packet sent by the backend, if any
+    pub disconnect_reason: RwLock<Option<Message>>,
+    /// Where the client connected to, where it's sent back to when transferred
+    pub transfer_address: Option<Transfer>,
+    /// Bytes written to the client by the relay
+    pub bytes_to_client: AtomicU64,
+    /// Bytes written to the backend by the relay
+    pub bytes_to_backend: AtomicU64,
+    /// Whether the packets of the player are dropped in the play state
+    frozen: AtomicBool,
+    protocol_state: AtomicU8,
+    compression_threshold: AtomicI32,
 }
 
 impl ConnectionSharedState {
@@ -87,38 +476,340 @@ impl ConnectionSharedState {
         Self {
             protocol_version,
             login_info: RwLock::new(None),
-            client_codec: RwLock::new(ClientPacketCodec::new()),
-            server_codec: RwLock::new(ServerPacketCodec::new()),
+            reservation: Mutex::new(None),
+            disconnect_reason: RwLock::new(None),
+            transfer_address: None,
+            bytes_to_client: AtomicU64::new(0),
+            bytes_to_backend: AtomicU64::new(0),
+            frozen: AtomicBool::new(false),
+            protocol_state: AtomicU8::new(ProtocolState::Handshake as u8),
+            compression_threshold: AtomicI32::new(-1),
         }
     }
 
-    pub async fn login_username(&self) -> Option<String> {
-        self.login_info
-            .read()
-            .await
-            .as_ref()
-            .map(|v| v.username.clone())
+    #[inline]
+    pub fn with_transfer_address(mut self, host: String, port: u16) -> Self {
+        self.transfer_address = Some(Transfer { host, port });
+        self
+    }
+
+    #[inline]
+    pub fn with_reservation(mut self, guard: Option<OnlinePlayerGuard>) -> Self {
+        self.reservation = Mutex::new(guard);
+        self
+    }
+
+    pub fn take_reservation(&self) -> Option<OnlinePlayerGuard> {
+        self.reservation
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+    }
+
+    #[inline]
+    pub fn current_state(&self) -> ProtocolState {
+        protocol_state_from_u8(self.protocol_state.load(Ordering::Acquire))
+    }
+
+    #[inline]
+    pub fn set_state(&self, state: ProtocolState) {
+        self.protocol_state.store(state as u8, Ordering::Release);
+    }
+
+    #[inline]
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub fn set_frozen(&self, frozen: bool) {
+        self.frozen.store(frozen, Ordering::Release);
     }
 
-    pub async fn current_state(&self) -> ProtocolState {
-        self.client_codec.read().await.state()
+    /// Negative thresholds disable compression.
+    #[inline]
+    pub fn set_compression(&self, threshold: i32) {
+        self.compression_threshold
+            .store(threshold, Ordering::Release);
     }
 
-    pub async fn set_state(&self, state: ProtocolState) {
-        self.client_codec.write().await.set_state(state);
-        self.server_codec.write().await.set_state(state);
+    #[inline]
+    pub fn sync_client_codec(&self, codec: &mut ClientPacketCodec) {
+        codec.set_state(self.current_state());
+        codec.set_protocol_version(self.protocol_version);
+        codec.set_compression(self.compression_threshold.load(Ordering::Acquire));
     }
 
-    pub async fn set_compression(&self, threshold: usize) {
-        self.client_codec.write().await.set_compression(threshold);
-        self.server_codec.write().await.set_compression(threshold);
+    #[inline]
+    pub fn sync_server_codec(&self, codec: &mut ServerPacketCodec) {
+        codec.set_state(self.current_state());
+        codec.set_protocol_version(self.protocol_version);
+        codec.set_compression(self.compression_threshold.load(Ordering::Acquire));
     }
+}
 
-    pub async fn decode_client(&self, data: &[u8]) -> Result<Option<ClientPacket>, DecodeError> {
-        self.client_codec.write().await.decode(data)
+fn protocol_state_from_u8(v: u8) -> ProtocolState {
+    match v {
+        v if v == ProtocolState::Status as u8 => ProtocolState::Status,
+        v if v == ProtocolState::Login as u8 => ProtocolState::Login,
+        v if v == ProtocolState::Configuration as u8 => ProtocolState::Configuration,
+        v if v == ProtocolState::Play as u8 => ProtocolState::Play,
+        _ => ProtocolState::Handshake,
     }
+}
+
+#[cfg(test)]
+pub async fn test_global_state() -> GlobalSharedState {
+    use sqlx::{migrate, SqlitePool};
+
+    let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+    migrate!().run(&pool).await.unwrap();
+
+    test_global_state_with_pool(pool)
+}
+
+#[cfg(test)]
+pub fn test_global_state_with_pool(pool: sqlx::Pool<DB>) -> GlobalSharedState {
+    use crate::commands::auth::CommandCredentials;
+    use mc_proxy_protocol::auth::Permission;
+    use minecraft_protocol::data::chat::Payload;
+
+    GlobalSharedState::new(
+        Message::new(Payload::text("Minecraft Server")),
+        SqlxKeyValueRepository::new(pool.clone()),
+        CachedIpBansRepository::new(SqlxIpBansRepository::new(pool.clone()), 0, Duration::ZERO),
+        CachedUserBansRepository::new(SqlxUserBansRepository::new(pool.clone()), 0, Duration::ZERO),
+        SqlxUserIpBansRepository::new(pool.clone()),
+        SqlxWhitelistRepository::new(pool.clone(), SqlxKeyValueRepository::new(pool.clone())),
+        CommandAuth::new(CommandCredentials::new(
+            None,
+            None,
+            Duration::ZERO,
+            Permission::Full,
+        )),
+        CommandDispatcher::new(Duration::ZERO).0,
+        StatsCollector::new(SqlxStatsRepository::new(pool.clone())),
+        BackendHealthMap::default(),
+        None,
+        None,
+        WhitelistBypass::default(),
+        DisconnectMessages::default(),
+        None,
+        None,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        stored_server_description, test_global_state, test_global_state_with_pool,
+        ConnectionSharedState,
+    };
+    use crate::{
+        presence::SharedPresence, repository::kv::SqlxKeyValueRepository, utils::write_packet,
+    };
+    use minecraft_protocol::{
+        codec::{client::ClientPacketCodec, ProtocolState},
+        data::{chat::Message, identifier::Identifier},
+        packet::game::{GameServerBoundPacket, PlayPluginMessage},
+    };
+    use sqlx::{migrate, SqlitePool};
+    use std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+    use tokio::sync::RwLock;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_reserve_player() {
+        let state = test_global_state().await;
+
+        assert!(state.try_reserve_player("Notch", &Uuid::nil()).await);
+        assert!(!state.try_reserve_player("Notch", &Uuid::nil()).await);
+        assert!(state.read_online_players().await.is_empty());
+
+        assert!(state.add_online_player("Notch".into(), Uuid::nil()).await);
+        assert!(!state.try_reserve_player("Notch", &Uuid::nil()).await);
+        assert_eq!(state.read_online_players().await.len(), 1);
+
+        state.remove_online_player("Notch").await;
+        assert!(state.try_reserve_player("Notch", &Uuid::nil()).await);
+    }
+
+    #[tokio::test]
+    async fn test_online_player_guard() {
+        let state = test_global_state().await;
+
+        assert!(state.try_reserve_player("Notch", &Uuid::nil()).await);
+        drop(state.guard_online_player("Notch".into()));
+        assert!(state.try_reserve_player("Notch", &Uuid::nil()).await);
+
+        assert!(state.add_online_player("Notch".into(), Uuid::nil()).await);
+        let guard = state.guard_online_player("Notch".into());
+        // Contended, removed once the lock is released
+        let lock = state.read_online_players().await;
+        drop(guard);
+        assert_eq!(lock.len(), 1);
+        drop(lock);
+        tokio::task::yield_now().await;
+        assert!(state.read_online_players().await.is_empty());
+
+        assert!(state.try_reserve_player("Notch", &Uuid::nil()).await);
+        let guard = state.guard_online_player("Notch".into());
+        assert_eq!(guard.disarm(), "Notch");
+        assert!(!state.try_reserve_player("Notch", &Uuid::nil()).await);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_guard_releases_shared_presence() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate!().run(&pool).await.unwrap();
+
+        let ttl = Duration::from_secs(60);
+        let mut state = test_global_state_with_pool(pool.clone());
+        state.presence = Some(Arc::new(SharedPresence::new(
+            SqlxKeyValueRepository::new(pool.clone()),
+            ttl,
+        )));
+        let other = SharedPresence::new(SqlxKeyValueRepository::new(pool), ttl);
+        let uuid = Uuid::new_v4();
+
+        assert!(state.try_reserve_player("Notch", &uuid).await);
+        assert!(state.add_online_player("Notch".into(), uuid).await);
+        assert!(other.is_online_elsewhere("Notch", &uuid).await.unwrap());
+
+        drop(state.guard_online_player("Notch".into()));
+        assert!(state.read_online_players().await.is_empty());
+
+        // Released by a task spawned on drop
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while other.is_online_elsewhere("Notch", &uuid).await.unwrap() {
+            assert!(Instant::now() < deadline, "presence was not released");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_uuid() {
+        let state = test_global_state().await;
+        let uuid = Uuid::new_v4();
+
+        assert!(state.try_reserve_player("Notch", &uuid).await);
+        assert!(state.add_online_player("Notch".into(), uuid).await);
+
+        // Same account under another name, rejected before and after auth
+        assert!(!state.try_reserve_player("notch", &uuid).await);
+        assert!(!state.add_online_player("notch".into(), uuid).await);
+        assert_eq!(state.read_online_players().await.len(), 1);
+
+        state.remove_online_player("notch").await;
+        assert!(!state.try_reserve_player("NOTCH", &uuid).await);
+
+        state.remove_online_player("Notch").await;
+        assert!(state.try_reserve_player("NOTCH", &uuid).await);
+        assert!(state.add_online_player("NOTCH".into(), uuid).await);
+    }
+
+    #[tokio::test]
+    async fn test_server_description_is_stored() {
+        let state = test_global_state().await;
+        assert!(stored_server_description(&state.key_value)
+            .await
+            .unwrap()
+            .is_none());
+
+        let description = Message::from_str("Maintenance");
+        state
+            .set_server_description(description.clone())
+            .await
+            .unwrap();
+
+        assert_eq!(state.server_description().await, description);
+        assert_eq!(
+            stored_server_description(&state.key_value).await.unwrap(),
+            Some(description)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_description_survives_restart() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate!().run(&pool).await.unwrap();
+
+        let state = test_global_state_with_pool(pool.clone());
+        let default = state.server_description().await;
+        let description = Message::from_str("Maintenance");
+        state
+            .set_server_description(description.clone())
+            .await
+            .unwrap();
+
+        let state = test_global_state_with_pool(pool.clone());
+        assert_eq!(state.server_description().await, default);
+        assert!(state.load_server_description().await.unwrap());
+        assert_eq!(state.server_description().await, description);
+
+        state.reset_server_description().await.unwrap();
+        assert_eq!(state.server_description().await, default);
+
+        let state = test_global_state_with_pool(pool);
+        assert!(!state.load_server_description().await.unwrap());
+        assert_eq!(state.server_description().await, default);
+    }
+
+    #[tokio::test]
+    async fn test_codecs_are_synced() {
+        let state = ConnectionSharedState::new(765);
+        let mut codec = ClientPacketCodec::new();
+
+        state.set_state(ProtocolState::Play);
+        state.sync_client_codec(&mut codec);
+        assert_eq!(codec.state(), ProtocolState::Play);
+        assert_eq!(state.current_state(), ProtocolState::Play);
+    }
+
+    /// Compares the relay decode loop using a locked codec, as it was before
+    /// the codecs were owned by the relay tasks, with the current one.
+    ///
+    /// `cargo test --release bench_codec_ownership -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore = "benchmark"]
+    async fn bench_codec_ownership() {
+        const PACKETS: usize = 1_000_000;
+
+        let mut frame = Vec::new();
+        let packet = GameServerBoundPacket::ServerBoundPluginMessage(PlayPluginMessage {
+            channel: Identifier::from_static("minecraft:brand"),
+            data: vec![0; 64],
+        });
+        write_packet(&mut frame, &packet).await.unwrap();
+
+        let locked = RwLock::new(ClientPacketCodec::new());
+        locked.write().await.set_state(ProtocolState::Play);
+
+        let start = Instant::now();
+        for _ in 0..PACKETS {
+            let packet = locked.write().await.decode(&frame).unwrap();
+            let _state = locked.read().await.state();
+            assert!(packet.is_some());
+        }
+        let locked_rate = PACKETS as f64 / start.elapsed().as_secs_f64();
+
+        let state = ConnectionSharedState::new(765);
+        state.set_state(ProtocolState::Play);
+        let mut codec = ClientPacketCodec::new();
+
+        let start = Instant::now();
+        for _ in 0..PACKETS {
+            state.sync_client_codec(&mut codec);
+            let packet = codec.decode(&frame).unwrap();
+            let _state = codec.state();
+            assert!(packet.is_some());
+        }
+        let owned_rate = PACKETS as f64 / start.elapsed().as_secs_f64();
 
-    pub async fn decode_server(&self, data: &[u8]) -> Result<Option<ServerPacket>, DecodeError> {
-        self.server_codec.write().await.decode(data)
+        println!("locked codec: {locked_rate:.0} packets/s");
+        println!("owned codec: {owned_rate:.0} packets/s");
     }
 }
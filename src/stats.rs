@@ -0,0 +1,481 @@
+use crate::repository::{
+    stats::{DayStats, StatsRepository},
+    RepositoryError,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    mem,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// Stats that were collected but not yet written to the repository.
+#[derive(Debug, Default)]
+pub struct PendingStats {
+    pub days: Vec<DayStats>,
+    pub seen_players: Vec<(Uuid, String)>,
+}
+
+struct Counters {
+    day: NaiveDate,
+    /// Logins since the last flush
+    logins: u64,
+    players_today: HashSet<Uuid>,
+    peak_players: u64,
+    peak_at: Option<DateTime<Utc>>,
+    online: u64,
+    /// Days that ended since the last flush
+    closed_days: Vec<DayStats>,
+    /// Players seen since the last flush
+    seen_players: HashMap<Uuid, String>,
+}
+
+impl Counters {
+    fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            day: now.date_naive(),
+            logins: 0,
+            players_today: HashSet::new(),
+            peak_players: 0,
+            peak_at: None,
+            online: 0,
+            closed_days: Vec::new(),
+            seen_players: HashMap::new(),
+        }
+    }
+
+    fn current_day(&self) -> DayStats {
+        DayStats {
+            day: self.day,
+            logins: self.logins,
+            unique_players: self.players_today.len() as u64,
+            peak_players: self.peak_players,
+            peak_at: self.peak_at,
+        }
+    }
+
+    /// Closes the current day if `now` is past it. The players that are still
+    /// online are the starting peak of the new day.
+    fn roll_over(&mut self, now: DateTime<Utc>) {
+        let today = now.date_naive();
+        if today <= self.day {
+            return;
+        }
+
+        let closed = self.current_day();
+        self.closed_days.push(closed);
+
+        self.day = today;
+        self.logins = 0;
+        self.players_today.clear();
+        self.peak_players = self.online;
+        self.peak_at = (self.online > 0).then_some(now);
+    }
+
+    fn set_online(&mut self, online: u64, now: DateTime<Utc>) {
+        self.online = online;
+        if online > self.peak_players {
+            self.peak_players = online;
+            self.peak_at = Some(now);
+        }
+    }
+}
+
+/// Counters since startup or the last [`StatsCollector::reset_live`], never
+/// persisted.
+struct LiveCounters {
+    started_at: Instant,
+    reset_at: Mutex<DateTime<Utc>>,
+    connections: AtomicU64,
+    status_pings: AtomicU64,
+    bytes_proxied: AtomicU64,
+}
+
+impl Default for LiveCounters {
+    fn default() -> Self {
+        Self {
+            started_at: Instant::now(),
+            reset_at: Mutex::new(Utc::now()),
+            connections: AtomicU64::new(0),
+            status_pings: AtomicU64::new(0),
+            bytes_proxied: AtomicU64::new(0),
+        }
+    }
+}
+
+/// A snapshot of the live counters.
+#[derive(Debug, Clone)]
+pub struct LiveStats {
+    pub connections: u64,
+    pub status_pings: u64,
+    /// Bytes sent to both the players and the backends
+    pub bytes_proxied: u64,
+    pub uptime: Duration,
+    /// When the counters started, either at startup or when they were reset
+    pub reset_at: DateTime<Utc>,
+}
+
+/// Collects the player stats in memory, so that recording events is cheap. The
+/// stats are written to the repository by [`StatsCollector::flush`].
+pub struct StatsCollector<R> {
+    repository: R,
+    counters: Mutex<Counters>,
+    /// Connections handled since startup by close reason, never persisted
+    connections: Mutex<BTreeMap<&'static str, u64>>,
+    live: LiveCounters,
+}
+
+impl<R: StatsRepository> StatsCollector<R> {
+    pub fn new(repository: R) -> Self {
+        Self {
+            repository,
+            counters: Mutex::new(Counters::new(Utc::now())),
+            connections: Mutex::default(),
+            live: LiveCounters::default(),
+        }
+    }
+
+    #[inline]
+    pub fn repository(&self) -> &R {
+        &self.repository
+    }
+
+    /// Records a successful login, `online` being the players online after it.
+    pub fn record_login(&self, uuid: Uuid, username: &str, online: usize) {
+        self.record_login_at(uuid, username, online, Utc::now())
+    }
+
+    /// Records the players online after a disconnection.
+    pub fn record_online(&self, online: usize) {
+        self.record_online_at(online, Utc::now())
+    }
+
+    /// Records the close reason of a finished connection.
+    pub fn record_connection(&self, reason: &'static str) {
+        let mut connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        *connections.entry(reason).or_default() += 1;
+        self.live.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a served server list ping.
+    pub fn record_status_ping(&self) {
+        self.live.status_pings.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The counter of the bytes sent to the players and the backends, updated
+    /// while relaying connections.
+    #[inline]
+    pub fn bytes_proxied(&self) -> &AtomicU64 {
+        &self.live.bytes_proxied
+    }
+
+    pub fn live(&self) -> LiveStats {
+        LiveStats {
+            connections: self.live.connections.load(Ordering::Relaxed),
+            status_pings: self.live.status_pings.load(Ordering::Relaxed),
+            bytes_proxied: self.live.bytes_proxied.load(Ordering::Relaxed),
+            uptime: self.live.started_at.elapsed(),
+            reset_at: *self.live.reset_at.lock().unwrap_or_else(|e| e.into_inner()),
+        }
+    }
+
+    /// Zeroes the live counters, including the connections by close reason.
+    /// The persisted stats are not affected.
+    pub fn reset_live(&self) {
+        let mut reset_at = self.live.reset_at.lock().unwrap_or_else(|e| e.into_inner());
+        self.connections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+        self.live.connections.store(0, Ordering::Relaxed);
+        self.live.status_pings.store(0, Ordering::Relaxed);
+        self.live.bytes_proxied.store(0, Ordering::Relaxed);
+        *reset_at = Utc::now();
+    }
+
+    /// The connections handled since startup by close reason.
+    pub fn connections(&self) -> BTreeMap<&'static str, u64> {
+        self.connections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn record_login_at(&self, uuid: Uuid, username: &str, online: usize, now: DateTime<Utc>) {
+        let mut counters = self.lock_counters();
+        counters.roll_over(now);
+
+        counters.logins += 1;
+        counters.players_today.insert(uuid);
+        counters.seen_players.insert(uuid, username.to_owned());
+        counters.set_online(online as u64, now);
+    }
+
+    fn record_online_at(&self, online: usize, now: DateTime<Utc>) {
+        let mut counters = self.lock_counters();
+        counters.roll_over(now);
+        counters.set_online(online as u64, now);
+    }
+
+    /// Takes the stats collected since the last call, including the partial
+    /// stats of the current day.
+    fn take_pending(&self, now: DateTime<Utc>) -> PendingStats {
+        let mut counters = self.lock_counters();
+        counters.roll_over(now);
+
+        let mut days = mem::take(&mut counters.closed_days);
+        days.push(counters.current_day());
+        counters.logins = 0;
+
+        PendingStats {
+            days,
+            seen_players: counters.seen_players.drain().collect(),
+        }
+    }
+
+    /// Writes the collected stats to the repository. The stats that could not
+    /// be written are kept for the next flush.
+    pub async fn flush(&self) -> Result<(), RepositoryError> {
+        self.flush_at(Utc::now()).await
+    }
+
+    async fn flush_at(&self, now: DateTime<Utc>) -> Result<(), RepositoryError> {
+        let PendingStats { days, seen_players } = self.take_pending(now);
+
+        // Merged days are added to the stored ones, only the days that were
+        // not written can be put back
+        for (i, day) in days.iter().enumerate() {
+            if let Err(error) = self.repository.merge_day(day).await {
+                self.restore_pending(PendingStats {
+                    days: days[i..].to_vec(),
+                    seen_players,
+                });
+                return Err(error);
+            }
+        }
+        if let Err(error) = self.repository.add_seen_players(&seen_players).await {
+            self.restore_pending(PendingStats {
+                days: Vec::new(),
+                seen_players,
+            });
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Merges stats taken by [`take_pending`](Self::take_pending) back into
+    /// the ones collected since.
+    fn restore_pending(&self, pending: PendingStats) {
+        let mut counters = self.lock_counters();
+
+        let mut closed_days = Vec::new();
+        for day in pending.days {
+            // The players and peak of the current day were never cleared
+            if day.day == counters.day {
+                counters.logins += day.logins;
+            } else {
+                closed_days.push(day);
+            }
+        }
+        closed_days.append(&mut counters.closed_days);
+        counters.closed_days = closed_days;
+
+        for (uuid, username) in pending.seen_players {
+            counters.seen_players.entry(uuid).or_insert(username);
+        }
+    }
+
+    #[inline]
+    fn lock_counters(&self) -> std::sync::MutexGuard<'_, Counters> {
+        self.counters.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Counters, LiveCounters, StatsCollector};
+    use crate::repository::stats::{SqlxStatsRepository, StatsRepository};
+    use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+    use sqlx::{migrate, Sqlite, SqlitePool};
+    use std::sync::{atomic::Ordering, Mutex};
+    use uuid::Uuid;
+
+    async fn get_collector(now: DateTime<Utc>) -> StatsCollector<SqlxStatsRepository<Sqlite>> {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate!().run(&pool).await.unwrap();
+
+        collector_with_pool(pool, now)
+    }
+
+    fn collector_with_pool(
+        pool: SqlitePool,
+        now: DateTime<Utc>,
+    ) -> StatsCollector<SqlxStatsRepository<Sqlite>> {
+        StatsCollector {
+            repository: SqlxStatsRepository::new(pool),
+            counters: Mutex::new(Counters::new(now)),
+            connections: Mutex::default(),
+            live: LiveCounters::default(),
+        }
+    }
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, day, hour, 0, 0).unwrap()
+    }
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 1, day).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rollup_across_day_boundary() {
+        let collector = get_collector(at(1, 20)).await;
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+        collector.record_login_at(a, "A", 1, at(1, 21));
+        collector.record_login_at(b, "B", 2, at(1, 22));
+        collector.record_online_at(1, at(1, 23));
+        collector.record_login_at(b, "B", 2, at(1, 23));
+
+        // Two players are still online when the day ends, so they are the
+        // starting peak of the next day
+        collector.record_online_at(1, at(2, 1));
+        collector.record_login_at(c, "C", 2, at(2, 2));
+
+        let pending = collector.take_pending(at(2, 3));
+        assert_eq!(pending.days.len(), 2);
+        assert_eq!(pending.seen_players.len(), 3);
+
+        let day1 = &pending.days[0];
+        assert_eq!(day1.day, date(1));
+        assert_eq!(day1.logins, 3);
+        assert_eq!(day1.unique_players, 2);
+        assert_eq!(day1.peak_players, 2);
+        assert_eq!(day1.peak_at, Some(at(1, 22)));
+
+        let day2 = &pending.days[1];
+        assert_eq!(day2.day, date(2));
+        assert_eq!(day2.logins, 1);
+        assert_eq!(day2.unique_players, 1);
+        assert_eq!(day2.peak_players, 2);
+        assert_eq!(day2.peak_at, Some(at(2, 1)));
+
+        for day in &pending.days {
+            collector.repository.merge_day(day).await.unwrap();
+        }
+        collector
+            .repository
+            .add_seen_players(&pending.seen_players)
+            .await
+            .unwrap();
+
+        let stats = collector.repository.get_stats().await.unwrap();
+        assert_eq!(stats.unique_players, 3);
+        assert_eq!(stats.peak_players, 2);
+        assert_eq!(stats.peak_at, Some(at(1, 22)));
+    }
+
+    #[tokio::test]
+    async fn test_day_without_events_keeps_online_peak() {
+        let collector = get_collector(at(1, 20)).await;
+
+        collector.record_login_at(Uuid::new_v4(), "A", 1, at(1, 21));
+        collector.take_pending(at(1, 22));
+
+        let pending = collector.take_pending(at(3, 0));
+        let days: Vec<_> = pending.days.iter().map(|v| v.day).collect();
+        assert_eq!(days, vec![date(1), date(3)]);
+
+        assert_eq!(pending.days[0].logins, 0);
+        assert_eq!(pending.days[1].peak_players, 1);
+        assert_eq!(pending.days[1].peak_at, Some(at(3, 0)));
+    }
+
+    #[tokio::test]
+    async fn test_incremental_flushes() {
+        let collector = get_collector(at(1, 0)).await;
+
+        collector.record_login_at(Uuid::new_v4(), "A", 1, at(1, 1));
+        let pending = collector.take_pending(at(1, 2));
+        collector
+            .repository
+            .merge_day(&pending.days[0])
+            .await
+            .unwrap();
+
+        collector.record_login_at(Uuid::new_v4(), "B", 2, at(1, 3));
+        let pending = collector.take_pending(at(1, 4));
+        collector
+            .repository
+            .merge_day(&pending.days[0])
+            .await
+            .unwrap();
+
+        let stats = collector.repository.get_stats().await.unwrap();
+        assert_eq!(stats.days[0].logins, 2);
+        assert_eq!(stats.days[0].unique_players, 2);
+        assert_eq!(stats.days[0].peak_players, 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_flush_is_kept() {
+        let pool = SqlitePool::connect("sqlite::memory:").await.unwrap();
+        migrate!().run(&pool).await.unwrap();
+        let collector = collector_with_pool(pool.clone(), at(1, 0));
+
+        collector.record_login_at(Uuid::new_v4(), "A", 1, at(1, 1));
+        collector.record_login_at(Uuid::new_v4(), "B", 2, at(2, 1));
+
+        // Neither the closed day, the current one nor the players are written
+        sqlx::query("ALTER TABLE daily_stats RENAME TO daily_stats_old")
+            .execute(&pool)
+            .await
+            .unwrap();
+        collector.flush_at(at(2, 2)).await.unwrap_err();
+        sqlx::query("ALTER TABLE daily_stats_old RENAME TO daily_stats")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let stats = collector.repository.get_stats().await.unwrap();
+        assert!(stats.days.is_empty());
+        assert_eq!(stats.unique_players, 0);
+
+        collector.record_login_at(Uuid::new_v4(), "C", 2, at(2, 3));
+        collector.flush_at(at(2, 4)).await.unwrap();
+
+        let stats = collector.repository.get_stats().await.unwrap();
+        assert_eq!(stats.unique_players, 3);
+        let logins: Vec<_> = stats.days.iter().map(|v| (v.day, v.logins)).collect();
+        assert!(logins.contains(&(date(1), 1)));
+        assert!(logins.contains(&(date(2), 2)));
+    }
+
+    #[tokio::test]
+    async fn test_reset_live_counters() {
+        let collector = get_collector(at(1, 0)).await;
+
+        collector.record_connection("status_served");
+        collector.record_status_ping();
+        collector.bytes_proxied().fetch_add(42, Ordering::Relaxed);
+
+        let live = collector.live();
+        assert_eq!(live.connections, 1);
+        assert_eq!(live.status_pings, 1);
+        assert_eq!(live.bytes_proxied, 42);
+
+        collector.reset_live();
+        let reset = collector.live();
+        assert_eq!(reset.connections, 0);
+        assert_eq!(reset.status_pings, 0);
+        assert_eq!(reset.bytes_proxied, 0);
+        assert!(reset.reset_at >= live.reset_at);
+        assert!(collector.connections().is_empty());
+    }
+}
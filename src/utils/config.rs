@@ -1,6 +1,6 @@
-use super::BoxDynError;
+use super::{logging::LogOptions, BoxDynError};
 use serde::Deserialize;
-use std::{fmt::Debug, fs};
+use std::{fmt::Debug, fs, path::Path, str::FromStr};
 
 pub trait Config
 where
@@ -29,12 +29,29 @@ where
 
     fn from_env_var() -> Result<Self, BoxDynError>;
 
+    /// Checks the loaded values before the service starts.
+    fn validate(&self) -> Result<(), BoxDynError> {
+        Ok(())
+    }
+
+    /// How the service logs, applied once the config is loaded.
+    fn log_options(&self) -> LogOptions {
+        LogOptions::default()
+    }
+
+    /// Files ending in `.toml` are read as TOML, anything else as JSON.
     fn from_file(config_file: String) -> Result<Self, BoxDynError> {
-        let string = fs::read_to_string(config_file)?;
+        let string = fs::read_to_string(&config_file)?;
 
-        let json = serde_json::from_str(&string)?;
+        let is_toml = Path::new(&config_file)
+            .extension()
+            .is_some_and(|v| v.eq_ignore_ascii_case("toml"));
 
-        Ok(json)
+        if is_toml {
+            Ok(toml::from_str(&string)?)
+        } else {
+            Ok(serde_json::from_str(&string)?)
+        }
     }
 }
 
@@ -51,3 +68,34 @@ impl Config for () {
         Ok(())
     }
 }
+
+/// A config value that can be given either as a single item or as a list.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+    pub fn into_vec(self) -> Vec<T> {
+        match self {
+            OneOrMany::One(v) => vec![v],
+            OneOrMany::Many(v) => v,
+        }
+    }
+}
+
+/// Parses a comma separated list, as used by environment variables.
+impl<T: FromStr> FromStr for OneOrMany<T> {
+    type Err = T::Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(T::from_str)
+            .collect::<Result<_, _>>()
+            .map(OneOrMany::Many)
+    }
+}
@@ -0,0 +1,64 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+use tokio::io::AsyncWrite;
+
+/// Adds the bytes written to `inner` to a shared counter.
+pub struct CountingWriter<'a, W> {
+    inner: W,
+    counter: &'a AtomicU64,
+}
+
+impl<'a, W> CountingWriter<'a, W> {
+    #[inline]
+    pub fn new(inner: W, counter: &'a AtomicU64) -> Self {
+        Self { inner, counter }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for CountingWriter<'_, W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.counter.fetch_add(written as u64, Ordering::Relaxed);
+        }
+        poll
+    }
+
+    #[inline]
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CountingWriter;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn test_counts_written_bytes() {
+        let counter = AtomicU64::new(0);
+        let mut buffer = Vec::new();
+
+        let mut writer = CountingWriter::new(&mut buffer, &counter);
+        writer.write_all(b"hello").await.unwrap();
+        writer.write_all(b" world").await.unwrap();
+
+        assert_eq!(counter.load(Ordering::Relaxed), 11);
+        assert_eq!(buffer, b"hello world");
+    }
+}
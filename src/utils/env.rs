@@ -1,5 +1,5 @@
 use super::BoxDynError;
-use std::{env::VarError, error::Error, str::FromStr};
+use std::{env::VarError, error::Error, fs, io, str::FromStr};
 
 #[derive(Debug, thiserror::Error)]
 pub enum EnvError<'a> {
@@ -9,6 +9,10 @@ pub enum EnvError<'a> {
     NotUnicode(&'a str),
     #[error("Failed to parse environment variable `{0}`: {1}")]
     ParseError(&'a str, BoxDynError),
+    #[error("Only one of `{0}` and `{0}_FILE` can be set")]
+    Conflict(&'a str),
+    #[error("Failed to read the file of environment variable `{0}_FILE`: {1}")]
+    ReadFile(&'a str, io::Error),
 }
 
 pub fn get<'a>(key: &'a str) -> Result<String, EnvError<'a>> {
@@ -18,9 +22,47 @@ pub fn get<'a>(key: &'a str) -> Result<String, EnvError<'a>> {
     })
 }
 
+/// Gets the variable either from `key` or from the file named by `{key}_FILE`,
+/// as done for secrets mounted in containers. The contents of the file are
+/// trimmed, and setting both variables is an error.
+pub fn get_maybe_file<'a>(key: &'a str) -> Result<String, EnvError<'a>> {
+    let value = match get(key) {
+        Ok(v) => Some(v),
+        Err(EnvError::NotFound(_)) => None,
+        Err(error) => return Err(error),
+    };
+
+    let path = match std::env::var(format!("{key}_FILE")) {
+        Ok(v) => Some(v),
+        Err(VarError::NotPresent) => None,
+        Err(VarError::NotUnicode(_)) => return Err(EnvError::NotUnicode(key)),
+    };
+
+    match (value, path) {
+        (Some(_), Some(_)) => Err(EnvError::Conflict(key)),
+        (Some(value), None) => Ok(value),
+        (None, Some(path)) => fs::read_to_string(path)
+            .map(|v| v.trim().to_owned())
+            .map_err(|error| EnvError::ReadFile(key, error)),
+        (None, None) => Err(EnvError::NotFound(key)),
+    }
+}
+
+/// Like [`get_maybe_file`], but a missing variable is not an error.
+pub fn get_optional<'a>(key: &'a str) -> Result<Option<String>, EnvError<'a>> {
+    match get_maybe_file(key) {
+        Ok(v) => Ok(Some(v)),
+        Err(EnvError::NotFound(_)) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
 #[inline]
-pub fn get_or(key: &str, default: String) -> String {
-    std::env::var(key).unwrap_or(default)
+pub fn get_or<'a>(key: &'a str, default: String) -> Result<String, EnvError<'a>> {
+    match get_maybe_file(key) {
+        Err(EnvError::NotFound(_)) => Ok(default),
+        result => result,
+    }
 }
 
 pub fn get_parsed<'a, T, E>(key: &'a str) -> Result<T, EnvError<'a>>
@@ -28,7 +70,7 @@ where
     T: FromStr<Err = E>,
     E: Error + Send + Sync + 'static,
 {
-    let s = get(key)?;
+    let s = get_maybe_file(key)?;
     T::from_str(&s).map_err(|error| EnvError::ParseError(key, error.into()))
 }
 
@@ -37,7 +79,7 @@ where
     T: FromStr<Err = E> + Sized,
     E: Error + Send + Sync + 'static,
 {
-    match get(key) {
+    match get_maybe_file(key) {
         Ok(s) => T::from_str(&s).map_err(|error| EnvError::ParseError(key, error.into())),
         Err(error) => match error {
             EnvError::NotFound(_) => Ok(default),
@@ -45,3 +87,62 @@ where
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{get_maybe_file, get_parsed_or, EnvError};
+    use std::{env, fs};
+
+    /// Every test uses its own variables, the environment is shared by the
+    /// tests running in parallel
+    fn set(key: &str, value: &str) {
+        env::set_var(key, value);
+    }
+
+    fn temp_file(name: &str, contents: &str) -> String {
+        let path = env::temp_dir().join(format!("mc-proxy-{}-{name}", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_value_from_file() {
+        let path = temp_file("secret", "  hunter2\n");
+        set("MC_PROXY_TEST_SECRET_FILE", &path);
+
+        assert_eq!(get_maybe_file("MC_PROXY_TEST_SECRET").unwrap(), "hunter2");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_plain_value() {
+        set("MC_PROXY_TEST_PLAIN", "25");
+
+        assert_eq!(get_maybe_file("MC_PROXY_TEST_PLAIN").unwrap(), "25");
+        assert_eq!(get_parsed_or("MC_PROXY_TEST_PLAIN", 0).unwrap(), 25);
+        assert_eq!(get_parsed_or("MC_PROXY_TEST_UNSET", 7).unwrap(), 7);
+    }
+
+    #[test]
+    fn test_both_set_is_an_error() {
+        let path = temp_file("both", "file");
+        set("MC_PROXY_TEST_BOTH", "env");
+        set("MC_PROXY_TEST_BOTH_FILE", &path);
+
+        let result = get_maybe_file("MC_PROXY_TEST_BOTH");
+        assert!(matches!(result, Err(EnvError::Conflict(_))));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_file() {
+        set("MC_PROXY_TEST_MISSING_FILE", "/does/not/exist");
+
+        let result = get_maybe_file("MC_PROXY_TEST_MISSING");
+        assert!(matches!(result, Err(EnvError::ReadFile(..))));
+
+        // Defaults only apply when neither variable is set
+        let result = get_parsed_or("MC_PROXY_TEST_MISSING", 0);
+        assert!(matches!(result, Err(EnvError::ReadFile(..))));
+    }
+}
@@ -0,0 +1,123 @@
+use serde::Deserialize;
+use std::{str::FromStr, sync::OnceLock};
+use tracing_subscriber::{
+    filter::ParseError,
+    layer::{Layered, SubscriberExt},
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+
+/// Reloads the filter of the global subscriber, set once it's installed.
+static LOG_FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl Default for LogFormat {
+    /// Json when built with the `json-log` feature, text otherwise.
+    fn default() -> Self {
+        if cfg!(feature = "json-log") {
+            LogFormat::Json
+        } else {
+            LogFormat::Text
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid log format `{0}`")]
+pub struct ParseLogFormatError(String);
+
+impl FromStr for LogFormat {
+    type Err = ParseLogFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(ParseLogFormatError(s.into())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LogOptions {
+    pub format: LogFormat,
+    /// Filter directives in the `RUST_LOG` syntax, which is used when unset
+    pub level: Option<String>,
+}
+
+impl LogOptions {
+    fn filter(&self) -> Result<EnvFilter, ParseError> {
+        match &self.level {
+            Some(level) => EnvFilter::try_new(level),
+            None => Ok(EnvFilter::from_default_env()),
+        }
+    }
+}
+
+/// The registry with the reloadable filter, which the other layers wrap.
+pub type FilteredRegistry = Layered<reload::Layer<EnvFilter, Registry>, Registry>;
+
+/// Installs the global subscriber, with `extra` added next to the formatting
+/// layer.
+pub fn init<L>(options: &LogOptions, extra: L) -> Result<(), ParseError>
+where
+    L: Layer<FilteredRegistry> + Send + Sync,
+{
+    let (filter, handle) = reload::Layer::new(options.filter()?);
+
+    let fmt_layer = match options.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer().json().boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(extra)
+        .with(fmt_layer)
+        .init();
+    let _ = LOG_FILTER.set(handle);
+
+    Ok(())
+}
+
+/// Replaces the filter of the global subscriber with `level`, in the
+/// `RUST_LOG` syntax.
+pub fn set_log_level(level: &str) -> Result<(), ParseError> {
+    let filter = EnvFilter::try_new(level)?;
+
+    if let Some(handle) = LOG_FILTER.get() {
+        if let Err(error) = handle.reload(filter) {
+            tracing::warn!(%error, "Failed to reload the log level");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{set_log_level, LogFormat, LogOptions};
+
+    #[test]
+    fn test_log_options() {
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert_eq!("text".parse::<LogFormat>().unwrap(), LogFormat::Text);
+        assert!("yaml".parse::<LogFormat>().is_err());
+
+        let options = LogOptions {
+            format: LogFormat::Text,
+            level: Some("info,mc_proxy=debug".into()),
+        };
+        assert!(options.filter().is_ok());
+
+        assert!(set_log_level("warn").is_ok());
+        assert!(set_log_level("mc_proxy=loud").is_err());
+    }
+}
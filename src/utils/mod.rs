@@ -1,53 +1,76 @@
 use minecraft_protocol::{
     encoder::{var_int, Encoder},
     error::{DecodeError, EncodeError},
-    tokio::AsyncDecoderReadExt,
+    tokio::AsyncEncoderWriteExt,
 };
 use std::{
     error::Error,
     io::{self, ErrorKind},
+    path::Path,
 };
 use tokio::{
     fs::File,
-    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
 };
 
 pub type BoxDynError = Box<dyn Error + Send + Sync>;
 
 pub mod config;
+pub mod counting;
 pub mod env;
+pub mod logging;
+pub mod rate_limit;
 pub mod service;
+pub mod socket;
 
 pub use config::Config;
 
-pub fn encode_packet<T: Encoder>(data: &T) -> Result<Vec<u8>, EncodeError> {
-    let mut buf = Vec::new();
+pub async fn write_packet<W: AsyncWrite + Unpin + Send, T: Encoder + Sync>(
+    writer: &mut W,
+    data: &T,
+) -> Result<(), io::Error> {
+    writer
+        .write_packet_async(data)
+        .await
+        .map_err(|error| match error {
+            EncodeError::IOError { io_error } => io_error,
+            error => io::Error::new(ErrorKind::InvalidData, error),
+        })
+}
 
-    data.encode(&mut buf).unwrap();
-    let mut vec = Vec::new();
+/// The largest packet length the protocol allows, the maximum value of a
+/// 3 byte VarInt.
+pub const MAX_PACKET_LENGTH: usize = (1 << 21) - 1;
 
-    var_int::encode(&(buf.len() as i32), &mut vec).unwrap();
-    vec.extend(buf);
+/// Reads a VarInt packet length, failing as soon as it exceeds `max_length`
+/// instead of waiting for the remaining bytes.
+pub async fn read_packet_length<R: AsyncRead + Unpin + Send>(
+    reader: &mut R,
+    max_length: usize,
+) -> Result<usize, DecodeError> {
+    let mut length = 0usize;
 
-    Ok(vec)
-}
+    for i in 0..3 {
+        let byte = reader.read_u8().await?;
+        length |= ((byte & 0b0111_1111) as usize) << (7 * i);
 
-pub async fn write_packet<W: AsyncWrite + Unpin + Send, T: Encoder>(
-    writer: &mut W,
-    data: &T,
-) -> Result<(), io::Error> {
-    let vec = encode_packet(data).unwrap();
+        if length > max_length {
+            return Err(DecodeError::InvalidPacketLength);
+        }
+        if byte & 0b1000_0000 == 0 {
+            return Ok(length);
+        }
+    }
 
-    writer.write_all(&vec).await?;
-    Ok(())
+    Err(DecodeError::VarIntTooLong { max_bytes: 3 })
 }
 
 pub async fn read_packet<R: AsyncRead + Unpin + Send>(
     reader: &mut R,
     encode_length: bool,
 ) -> Result<Option<Vec<u8>>, DecodeError> {
-    let length = reader.read_var_i32_async().await?;
-    if length == 0 || 0 > length {
+    let length = read_packet_length(reader, MAX_PACKET_LENGTH).await? as i32;
+    if length == 0 {
         return Ok(None);
     }
 
@@ -69,11 +92,80 @@ pub async fn read_packet<R: AsyncRead + Unpin + Send>(
     }
 }
 
+/// Bounds the packets and bytes read from a client that didn't log in or get
+/// the status yet, so that connections can't be kept busy cheaply.
+#[derive(Debug, Clone, Copy)]
+pub struct PacketBudget {
+    max_packets: u32,
+    packets: u32,
+    bytes: usize,
+}
+
+impl Default for PacketBudget {
+    #[inline]
+    fn default() -> Self {
+        Self::new(4, 8192)
+    }
+}
+
+impl PacketBudget {
+    #[inline]
+    pub fn new(max_packets: u32, max_bytes: usize) -> Self {
+        Self {
+            max_packets,
+            packets: max_packets,
+            bytes: max_bytes,
+        }
+    }
+
+    /// Same as [`read_packet`], failing once the packets or bytes read exceed
+    /// the budget.
+    pub async fn read_packet<R: AsyncRead + Unpin + Send>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<Option<Vec<u8>>, DecodeError> {
+        let Some(packets) = self.packets.checked_sub(1) else {
+            tracing::warn!(
+                max_packets = self.max_packets,
+                "Client sent too many packets before logging in"
+            );
+            return Err(DecodeError::TooManyPackets {
+                max_packets: self.max_packets,
+            });
+        };
+        self.packets = packets;
+
+        let length = match read_packet_length(reader, self.bytes.min(MAX_PACKET_LENGTH)).await {
+            Err(DecodeError::InvalidPacketLength) => {
+                tracing::warn!(
+                    remaining = self.bytes,
+                    "Client sent too many bytes before logging in"
+                );
+                return Err(DecodeError::InvalidPacketLength);
+            }
+            v => v?,
+        };
+        if length == 0 {
+            return Ok(None);
+        }
+        self.bytes -= length;
+
+        let mut buf = vec![0; length];
+        reader.read_exact(&mut buf).await?;
+
+        Ok(Some(buf))
+    }
+}
+
+/// Creates the file at `path` if missing, along with its missing directories.
 pub async fn touch_file(path: &str) -> io::Result<()> {
     let file = File::open(path).await;
 
     if let Err(err) = file {
         if err.kind() == ErrorKind::NotFound {
+            if let Some(parent) = Path::new(path).parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
             File::create(path).await?;
             Ok(())
         } else {
@@ -83,3 +175,23 @@ pub async fn touch_file(path: &str) -> io::Result<()> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::touch_file;
+    use std::env;
+
+    #[tokio::test]
+    async fn test_touch_file_creates_directories() {
+        let dir = env::temp_dir().join(format!("mc-proxy-{}-touch", std::process::id()));
+        let file = dir.join("data").join("proxy.sqlite");
+        let path = file.to_str().unwrap();
+
+        touch_file(path).await.unwrap();
+        assert!(file.is_file());
+        // Already existing
+        touch_file(path).await.unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+use std::{sync::Mutex, time::Duration};
+use tokio::time::Instant;
+
+/// Fixed window limit of how many times something can be done.
+pub struct RateLimiter {
+    max_requests: u32,
+    window: Duration,
+    /// Start of the current window and the requests made during it
+    state: Mutex<(Instant, u32)>,
+}
+
+impl RateLimiter {
+    pub fn new(max_requests: u32, window: Duration) -> Self {
+        Self {
+            max_requests,
+            window,
+            state: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    #[inline]
+    pub fn try_acquire(&self) -> bool {
+        self.try_acquire_many(1)
+    }
+
+    /// Acquires `count` requests at once, or none of them if they don't all
+    /// fit in the current window.
+    pub fn try_acquire_many(&self, count: u32) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        if now - state.0 >= self.window {
+            *state = (now, 0);
+        }
+
+        if state.1.saturating_add(count) <= self.max_requests {
+            state.1 += count;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the current window is over, so that a new limiter would behave
+    /// the same as this one.
+    pub fn is_idle(&self) -> bool {
+        Instant::now() - self.state.lock().unwrap().0 >= self.window
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimiter;
+    use std::time::Duration;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limit_window() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+        assert!(!limiter.is_idle());
+
+        tokio::time::advance(Duration::from_secs(59)).await;
+        assert!(!limiter.try_acquire());
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(limiter.is_idle());
+        assert!(limiter.try_acquire());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_acquire_many() {
+        let limiter = RateLimiter::new(3, Duration::from_secs(60));
+
+        assert!(limiter.try_acquire_many(2));
+        assert!(!limiter.try_acquire_many(2));
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+    }
+}
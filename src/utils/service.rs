@@ -1,11 +1,15 @@
-use super::{BoxDynError, Config};
+use super::{logging, BoxDynError, Config};
 use std::future::Future;
 use tokio::runtime::Builder;
 use tracing_subscriber::EnvFilter;
 
+/// Standard OpenTelemetry variable, spans are exported to it when set.
+#[cfg(feature = "otel")]
+const OTLP_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
 pub fn config_and_init_service<C, F, Fut>(service_fn: F)
 where
-    C: Config,
+    C: Config + 'static,
     F: Fn(C) -> Fut,
     Fut: Future<Output = Result<(), BoxDynError>>,
 {
@@ -16,37 +20,56 @@ where
         }
     }
 
-    #[cfg(not(feature = "json-log"))]
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .init();
-
-    #[cfg(feature = "json-log")]
-    tracing_subscriber::fmt()
-        .json()
+    // The configured format and level are only known once the config is
+    // loaded, so it's logged with the defaults
+    let bootstrap = tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
-        .init();
+        .finish();
+    let config = tracing::subscriber::with_default(bootstrap, load_config::<C>);
 
-    let config = match C::auto() {
+    #[cfg(feature = "otel")]
+    let tracer_provider = match otel_tracer_provider() {
         Ok(v) => v,
         Err(error) => {
-            tracing::error!(
-                target: "service_configuration",
-                %error,
-                "Failed to load configuration",
-            );
-            eprintln!("Failed to load service configuration: {error}");
+            eprintln!("Failed to create the OpenTelemetry exporter: {error}");
             std::process::exit(1);
         }
     };
 
+    #[cfg(feature = "otel")]
+    let otel_layer = tracer_provider.as_ref().map(|provider| {
+        use opentelemetry::trace::TracerProvider;
+
+        tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+    });
+
+    #[cfg(not(feature = "otel"))]
+    let otel_layer = tracing_subscriber::layer::Identity::new();
+
+    if let Err(error) = logging::init(&config.log_options(), otel_layer) {
+        eprintln!("Invalid log level: {error}");
+        std::process::exit(1);
+    }
+
     tracing::info!(target: "service_configuration", ?config, "Loaded configuration");
 
     let async_rt_result = Builder::new_multi_thread()
         .enable_all()
         .build()
         .expect("Failed building the Runtime")
-        .block_on(service_fn(config));
+        .block_on(async {
+            #[cfg(unix)]
+            tokio::spawn(reload_log_level_on_hangup::<C>());
+
+            service_fn(config).await
+        });
+
+    #[cfg(feature = "otel")]
+    if let Some(provider) = tracer_provider {
+        if let Err(error) = provider.shutdown() {
+            eprintln!("Failed to flush the OpenTelemetry spans: {error}");
+        }
+    }
 
     if let Err(e) = async_rt_result {
         eprintln!("Unhandled fatal error: {e}");
@@ -54,6 +77,103 @@ where
     }
 }
 
+/// Loads and validates the config, exiting the process if it fails.
+fn load_config<C: Config>() -> C {
+    let config = match C::auto() {
+        Ok(v) => v,
+        Err(error) => {
+            tracing::error!(
+                target: "service_configuration",
+                %error,
+                "Failed to load configuration",
+            );
+            eprintln!("Failed to load service configuration: {error}");
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(error) = config.validate() {
+        tracing::error!(
+            target: "service_configuration",
+            %error,
+            "Invalid configuration",
+        );
+        eprintln!("{error}");
+        std::process::exit(1);
+    }
+
+    config
+}
+
+/// Loads the config again on every SIGHUP and applies its log level, the
+/// other values are left untouched. Environment variables can't change while
+/// the process runs, so it's mostly useful with a config file.
+#[cfg(unix)]
+async fn reload_log_level_on_hangup<C: Config>() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(v) => v,
+        Err(error) => {
+            tracing::warn!(target: "service_signals", %error, "Failed to listen for SIGHUP");
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        tracing::info!(target: "service_signals", "Received SIGHUP, reloading the log level");
+
+        let options = match C::auto() {
+            Ok(config) => config.log_options(),
+            Err(error) => {
+                tracing::warn!(
+                    target: "service_configuration",
+                    %error,
+                    "Failed to load configuration, keeping the log level",
+                );
+                continue;
+            }
+        };
+        let level = options
+            .level
+            .or_else(|| std::env::var(EnvFilter::DEFAULT_ENV).ok())
+            .unwrap_or_default();
+
+        if let Err(error) = logging::set_log_level(&level) {
+            tracing::warn!(target: "service_configuration", %error, "Invalid log level");
+        }
+    }
+}
+
+/// Creates the OTLP exporter if [`OTLP_ENDPOINT_ENV`] is set. The exporter
+/// reads the endpoint and the other standard `OTEL_EXPORTER_OTLP_*` variables
+/// by itself.
+#[cfg(feature = "otel")]
+fn otel_tracer_provider() -> Result<Option<opentelemetry_sdk::trace::SdkTracerProvider>, BoxDynError>
+{
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+
+    if std::env::var_os(OTLP_ENDPOINT_ENV).is_none() {
+        return Ok(None);
+    }
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_protocol(opentelemetry_otlp::Protocol::HttpBinary)
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(env!("CARGO_PKG_NAME"))
+                .build(),
+        )
+        .build();
+
+    Ok(Some(provider))
+}
+
 #[cfg(unix)]
 pub fn shutdown_signal() -> std::io::Result<impl Future<Output = ()>> {
     use tokio::signal::unix::{signal, SignalKind};
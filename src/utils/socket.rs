@@ -0,0 +1,220 @@
+use socket2::{Domain, SockRef, Socket, TcpKeepalive, Type};
+use std::{
+    future::Future,
+    io,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    time::Duration,
+};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct SocketOptions {
+    pub nodelay: bool,
+    /// Idle time before keepalive probes are sent, disabled if `None`
+    pub keepalive: Option<Duration>,
+    /// Size of the kernel send buffer, the OS default if `None`
+    pub send_buffer_size: Option<usize>,
+    /// Size of the kernel receive buffer, the OS default if `None`
+    pub recv_buffer_size: Option<usize>,
+}
+
+impl SocketOptions {
+    pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        let socket = SockRef::from(stream);
+
+        socket.set_nodelay(self.nodelay)?;
+        if let Some(time) = self.keepalive {
+            let keepalive = TcpKeepalive::new().with_time(time).with_interval(time);
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Binds a listener with a custom connection backlog.
+pub fn bind_listener(addr: SocketAddr, backlog: u32) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(backlog.min(i32::MAX as u32) as i32)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// The address given to the connections of listeners whose peers have no IP
+/// address, replaced by the one of the PROXY protocol header.
+pub const UNKNOWN_PEER: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+
+/// A listener accepting the connections of players.
+pub trait Listener: Send + Sync + 'static {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    /// Accepts a connection, along with the address of its peer.
+    fn accept(&self) -> impl Future<Output = io::Result<(Self::Stream, SocketAddr)>> + Send;
+
+    /// The local address, used to tell the listeners apart in the logs.
+    fn label(&self) -> String;
+
+    /// Applies the `options` meaningful to the kind of stream.
+    fn apply_options(&self, stream: &Self::Stream, options: &SocketOptions) -> io::Result<()>;
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    #[inline]
+    fn accept(&self) -> impl Future<Output = io::Result<(TcpStream, SocketAddr)>> + Send {
+        TcpListener::accept(self)
+    }
+
+    fn label(&self) -> String {
+        match self.local_addr() {
+            Ok(addr) => addr.to_string(),
+            Err(_) => "unknown".into(),
+        }
+    }
+
+    #[inline]
+    fn apply_options(&self, stream: &TcpStream, options: &SocketOptions) -> io::Result<()> {
+        options.apply(stream)
+    }
+}
+
+/// Accepts the connections of a local load balancer or sidecar, the address
+/// of the players being read from the PROXY protocol header.
+#[cfg(unix)]
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+
+    async fn accept(&self) -> io::Result<(UnixStream, SocketAddr)> {
+        let (stream, _) = UnixListener::accept(self).await?;
+        Ok((stream, UNKNOWN_PEER))
+    }
+
+    fn label(&self) -> String {
+        match self
+            .local_addr()
+            .ok()
+            .and_then(|v| v.as_pathname().map(|v| v.to_owned()))
+        {
+            Some(path) => format!("unix:{}", path.display()),
+            None => "unix".into(),
+        }
+    }
+
+    #[inline]
+    fn apply_options(&self, _: &UnixStream, _: &SocketOptions) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Binds a listener on the unix socket at `path`, replacing the socket file
+/// left behind by a previous run and creating its missing directories. Fails
+/// if another process is still listening on it.
+#[cfg(unix)]
+pub fn bind_unix_listener(path: &std::path::Path) -> io::Result<UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            match std::os::unix::net::UnixStream::connect(path) {
+                Ok(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!("{} is used by a running process", path.display()),
+                    ))
+                }
+                Err(error) if error.kind() == io::ErrorKind::ConnectionRefused => {
+                    std::fs::remove_file(path)?
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ))
+        }
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+        Err(error) => return Err(error),
+    }
+
+    UnixListener::bind(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bind_listener, SocketOptions};
+    use socket2::SockRef;
+    use std::time::Duration;
+    use tokio::net::TcpStream;
+
+    async fn connected_pair() -> (TcpStream, TcpStream) {
+        let listener = bind_listener("127.0.0.1:0".parse().unwrap(), 16).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let (client, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        (client.unwrap(), accepted.unwrap().0)
+    }
+
+    #[tokio::test]
+    async fn test_apply_socket_options() {
+        let (client, accepted) = connected_pair().await;
+
+        let options = SocketOptions {
+            nodelay: true,
+            keepalive: Some(Duration::from_secs(30)),
+            send_buffer_size: Some(64 * 1024),
+            recv_buffer_size: Some(64 * 1024),
+        };
+        options.apply(&client).unwrap();
+        options.apply(&accepted).unwrap();
+
+        for stream in [&client, &accepted] {
+            let socket = SockRef::from(stream);
+            assert!(socket.nodelay().unwrap());
+            assert!(socket.keepalive().unwrap());
+            assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(30));
+            // The kernel may round the sizes up, linux doubles them
+            assert!(socket.send_buffer_size().unwrap() >= 64 * 1024);
+            assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_disabled_socket_options() {
+        let (client, _accepted) = connected_pair().await;
+
+        let options = SocketOptions {
+            nodelay: false,
+            keepalive: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        };
+        options.apply(&client).unwrap();
+
+        let socket = SockRef::from(&client);
+        assert!(!socket.nodelay().unwrap());
+        assert!(!socket.keepalive().unwrap());
+    }
+}